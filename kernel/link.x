@@ -21,4 +21,7 @@ SECTIONS {
 	{
 		*(.text*);
 	}
+
+	/* Everything after this is free to use, the heap goes after it */
+	__kernel_end = .;
 }
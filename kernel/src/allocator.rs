@@ -14,7 +14,32 @@ pub static PROGRAM_ALLOCATOR: ProgramBasicAlloc = ProgramBasicAlloc(Mutex::from(
 
 pub struct ProgramBasicAlloc(pub Mutex<BasicAlloc>);
 
+const MAX_SPARE_HEAP_REGIONS: usize = 32;
+const MAX_RETIRED_HEAP_REGIONS: usize = 16;
+
+// Memory that either allocator can grow into once it runs out, as (base, len), (null, 0) is an empty slot
+static SPARE_HEAP_REGIONS: Mutex<[(*mut u8, usize); MAX_SPARE_HEAP_REGIONS]> = Mutex::from([(null_mut(), 0); MAX_SPARE_HEAP_REGIONS]);
+
+// Returns: None if there are no free slots left, then the memory just doesn't get used
+pub fn add_spare_heap_region(base: *mut u8, len: usize) -> Option<()> {
+    let mut spare_regions = SPARE_HEAP_REGIONS.lock();
+    *spare_regions.iter_mut().find(|region| region.0.is_null())? = (base, len);
+    Some(())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub live_allocations: usize,
+    // Freed memory that couldn't be given back yet because it's under allocations that are still live, plus what has never been used
+    pub total_free: usize,
+    // The biggest allocation that fits, either after the top of the stack or in a stashed deallocation
+    pub largest_free_block: usize,
+    pub stashed_deallocations: usize,
+}
+
 // This is a bump allocator that doesn't leak as much memory as a normal bump allocator
+// Deallocations under the top of the stack are stashed, merged with the stashed deallocations right next to them,
+// and reused by allocations that fit in them, the top of the stack moves back down once the deallocations under it reach it
 #[derive(Clone)]
 pub struct BasicAlloc {
     base: *mut u8,
@@ -23,6 +48,10 @@ pub struct BasicAlloc {
     next: usize,
     is_virtual: bool, // Tells the allocator that the pointers are not real, they are virtual and should not be dereferenced
     stashed_deallocations: [(*mut u8, core::alloc::Layout); 1024],
+    // How big the heap can get by growing, counting the regions that were grown out of, 0 means it can't grow
+    max_len: usize,
+    // The regions the allocator grew out of that still have live allocations in them, as (base, len, live allocations)
+    retired_regions: [(*mut u8, usize, usize); MAX_RETIRED_HEAP_REGIONS],
 }
 
 impl Debug for BasicAlloc {
@@ -33,6 +62,8 @@ impl Debug for BasicAlloc {
             .field("alloc_count", &self.alloc_count)
             .field("next", &self.next)
             .field("stashed_deallocations (len)", &self.stashed_deallocations.iter().filter(|val| val.0 != null_mut()).count())
+            .field("max_len", &self.max_len)
+            .field("retired_regions (len)", &self.retired_regions.iter().filter(|val| val.0 != null_mut()).count())
             .finish()
     }
 }
@@ -46,6 +77,8 @@ impl BasicAlloc {
             next: 0,
             is_virtual,
             stashed_deallocations: [(null_mut(), core::alloc::Layout::new::<u8>()); 1024],
+            max_len: 0,
+            retired_regions: [(null_mut(), 0, 0); MAX_RETIRED_HEAP_REGIONS],
         }
     }
 
@@ -57,6 +90,8 @@ impl BasicAlloc {
             next: 0,
             is_virtual,
             stashed_deallocations: [(null_mut(), core::alloc::Layout::new::<u8>()); 1024],
+            max_len: 0,
+            retired_regions: [(null_mut(), 0, 0); MAX_RETIRED_HEAP_REGIONS],
         }
     }
 
@@ -73,11 +108,34 @@ impl BasicAlloc {
         self.len += len;
     }
 
+    // Lets the heap grow into the spare heap regions once it runs out, until it's max_len bytes big
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    fn get_retired_len(&self) -> usize {
+        self.retired_regions.iter().map(|region| region.1).sum()
+    }
+
+    // NOTE: The regions the heap grew out of count as fully used, since nothing can be allocated from them anymore
     pub fn get_heap_used(&self) -> usize {
-        self.next
+        self.next + self.get_retired_len()
     }
     pub fn get_heap_max(&self) -> usize {
-        self.len
+        self.len + self.get_retired_len()
+    }
+
+    // NOTE: Only the region that is being allocated from has free space, the regions the heap grew out of don't count
+    // NOTE: Deallocations that were leaked because there was no room to stash them aren't counted as free, since they can never be given back
+    pub fn get_stats(&self) -> HeapStats {
+        let stashed = self.stashed_deallocations.iter().filter(|val| val.0 != null_mut());
+        let free_after_top = self.len.saturating_sub(self.next);
+        HeapStats {
+            live_allocations: self.alloc_count + self.retired_regions.iter().map(|region| region.2).sum::<usize>(),
+            total_free: free_after_top + stashed.clone().map(|val| val.1.size()).sum::<usize>(),
+            largest_free_block: stashed.clone().map(|val| val.1.size()).fold(free_after_top, core::cmp::max),
+            stashed_deallocations: stashed.count(),
+        }
     }
 
     pub fn find_free_dealloc_ind(&self) -> Option<usize> {
@@ -89,107 +147,238 @@ impl BasicAlloc {
         return None;
     }
 
-    fn try_dealloc(&mut self, ptr: *mut u8, layout: core::alloc::Layout) -> bool {
-        // If we are asked to deallocate from the top of the stack we can do that :)
-        if unsafe { ptr.add(layout.size()).sub(self.next) } == self.base {
-            // (ptr = alloc.base) alloc.base+alloc.size-head = base <=> alloc.base+alloc.size = base+head <=> we are asked to deallocate from the top of the stack
-            self.next -= layout.size();
-            return true;
+    // Stashes the size bytes at ptr as they are, without merging them with anything
+    fn stash(&mut self, ptr: *mut u8, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let Ok(layout) = core::alloc::Layout::from_size_align(size, 1) else {
+            return;
+        };
+        if let Some(ind) = self.find_free_dealloc_ind() {
+            self.stashed_deallocations[ind] = (ptr, layout);
+        } else {
+            use core::fmt::Write;
+            let _ = writeln!(UART.lock(), "Leaking memory :)");
+            // Just leak memory idk ¯\_(ツ)_/¯
         }
+    }
 
-        // Or if we have deallocated all the allocations
-        if self.alloc_count == 0 {
-            self.next = 0;
-            for e in self.stashed_deallocations.iter_mut() {
+    // Gives back the size bytes at ptr, merging them with the stashed deallocations right before and after them,
+    // if that reaches the top of the stack then the top moves down instead of stashing anything
+    // NOTE: Because every deallocation is merged like this, no two stashed deallocations are ever next to each other and none of them end at the top of the stack,
+    // so there's at most one before and one after, and the top only has to move once
+    // NOTE: Blocks that aren't in the current region are ignored, since there's nothing there to give back
+    fn free_block(&mut self, ptr: *mut u8, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let mut start = ptr as usize;
+        let Some(mut end) = start.checked_add(size) else {
+            return;
+        };
+        if start < self.base as usize || end > self.base as usize + self.len {
+            return;
+        }
+        for e in self.stashed_deallocations.iter_mut().filter(|e| e.0 != null_mut()) {
+            let (stashed_start, stashed_end) = (e.0 as usize, e.0 as usize + e.1.size());
+            if stashed_end == start || stashed_start == end {
+                start = core::cmp::min(start, stashed_start);
+                end = core::cmp::max(end, stashed_end);
                 *e = (null_mut(), core::alloc::Layout::new::<u8>());
             }
-            return true;
         }
 
-        // Otherwise we can't deallocate
-        return false;
+        let start_offset = start - self.base as usize;
+        if end == self.base as usize + self.next {
+            self.next = start_offset;
+        } else {
+            self.stash(self.base.wrapping_add(start_offset), end - start);
+        }
     }
 
-    pub fn alloc(&mut self, layout: core::alloc::Layout) -> *mut u8 {
-        if self.next % layout.align() != 0 {
-            // If we are not aligned
-            if let Ok(padding) = core::alloc::Layout::from_size_align(
-                layout.align()
-                    - (self.next % layout.align()/* no div by 0 because align() can't return zero if the layout is constructed correctly */),
-                1,
-            ) {
-                self.next += padding.size();
-                if self.next >= self.len {
-                    self.next -= padding.size();
-                    return null_mut();
-                } // OOM :^(
-                  // Note: since we never call dealloc() on this padding allocation explicitly there is no need to inc alloc_count
-                let padding_ptr = unsafe { self.base.add(self.next).sub(padding.size()) };
-                if let Some(ind) = self.find_free_dealloc_ind() {
-                    self.stashed_deallocations[ind] = (padding_ptr, padding);
+    // Reuses the smallest stashed deallocation that layout fits in, the parts of it before and after the allocation stay stashed
+    // NOTE: Those parts don't need to be merged with anything, since the deallocation they were part of wasn't next to any other
+    fn alloc_from_stash(&mut self, layout: core::alloc::Layout) -> *mut u8 {
+        let best = self
+            .stashed_deallocations
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.0 != null_mut())
+            .filter_map(|(i, e)| {
+                let padding = (layout.align() - e.0 as usize % layout.align()) % layout.align();
+                if padding.checked_add(layout.size())? <= e.1.size() {
+                    Some((i, padding, e.1.size()))
                 } else {
-                    use core::fmt::Write;
-                    let _ = writeln!(UART.lock(), "Leaking memory :)");
-                    // Just leak memory idk ¯\_(ツ)_/¯
+                    None
                 }
-            } else {
-                return null_mut();
+            })
+            .min_by_key(|(_, _, size)| *size);
+        let Some((ind, padding, stashed_size)) = best else {
+            return null_mut();
+        };
+
+        let stashed_ptr = self.stashed_deallocations[ind].0;
+        self.stashed_deallocations[ind] = (null_mut(), core::alloc::Layout::new::<u8>());
+        let ret_ptr = stashed_ptr.wrapping_add(padding);
+        self.stash(stashed_ptr, padding);
+        self.stash(ret_ptr.wrapping_add(layout.size()), stashed_size - padding - layout.size());
+        self.alloc_count += 1;
+        ret_ptr
+    }
+
+    // Makes room for an allocation of layout, either by extending the current region, if there is a spare region right after it,
+    // or by moving to a spare region that is big enough
+    // NOTE: When moving, the allocations in the old region stay where they are, the old region is only given back once they are all deallocated
+    // Returns: None if there is no spare region that fits, or if using it would make the heap bigger than max_len
+    fn grow(&mut self, layout: core::alloc::Layout) -> Option<()> {
+        if self.is_virtual {
+            return None;
+        }
+        // Worst case the allocation needs align-1 bytes of padding, and the last byte of a region can't be allocated
+        let needed = layout.size().checked_add(layout.align())?;
+        let retired_len = self.get_retired_len();
+        let mut spare_regions = SPARE_HEAP_REGIONS.lock();
+
+        let region_end = self.base.wrapping_add(self.len);
+        if let Some(spare_region) = spare_regions.iter_mut().find(|region| !region.0.is_null() && region.0 == region_end) {
+            if retired_len + self.len + spare_region.1 <= self.max_len && self.len - self.next + spare_region.1 >= needed {
+                self.len += spare_region.1;
+                *spare_region = (null_mut(), 0);
+                return Some(());
             }
         }
 
-        self.next += layout.size();
-        if self.next >= self.len {
-            self.next -= layout.size();
+        // If nothing is allocated in the current region then it can be given back right away, instead of retiring it
+        let retired_len_after = if self.alloc_count == 0 { retired_len } else { retired_len + self.len };
+        let spare_region = spare_regions
+            .iter_mut()
+            .filter(|region| !region.0.is_null() && region.1 >= needed && retired_len_after + region.1 <= self.max_len)
+            .max_by_key(|region| region.1)?;
+
+        let (new_base, new_len) = *spare_region;
+        if self.alloc_count == 0 {
+            *spare_region = (self.base, self.len);
+        } else {
+            *self.retired_regions.iter_mut().find(|region| region.0.is_null())? = (self.base, self.len, self.alloc_count);
+            *spare_region = (null_mut(), 0);
+        }
+
+        self.base = new_base;
+        self.len = new_len;
+        self.next = 0;
+        self.alloc_count = 0;
+        // The stashed deallocations were all in the old region, which gets given back as a whole
+        for e in self.stashed_deallocations.iter_mut() {
+            *e = (null_mut(), core::alloc::Layout::new::<u8>());
+        }
+        Some(())
+    }
+
+    pub fn alloc(&mut self, layout: core::alloc::Layout) -> *mut u8 {
+        let ptr = self.alloc_from_stash(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        let ptr = self.bump_alloc(layout);
+        if ptr.is_null() && self.grow(layout).is_some() {
+            return self.bump_alloc(layout);
+        }
+        ptr
+    }
+
+    fn bump_alloc(&mut self, layout: core::alloc::Layout) -> *mut u8 {
+        // If we are not aligned
+        // NOTE: The address has to be aligned, not the offset from base, since base isn't necessarily aligned to anything
+        let top = self.base.wrapping_add(self.next) as usize;
+        let padding = (layout.align() - (top % layout.align()/* no div by 0 because align() can't return zero if the layout is constructed correctly */)) % layout.align();
+        let Some(new_next) = self.next.checked_add(padding).and_then(|next| next.checked_add(layout.size())) else {
+            return null_mut();
+        };
+        if new_next >= self.len {
             return null_mut();
         } // OOM :^(
+
+        let padding_ptr = self.base.wrapping_add(self.next);
+        self.next = new_next;
+        // Note: since we never call dealloc() on this padding allocation explicitly there is no need to inc alloc_count
+        // NOTE: This has to happen after moving the top, otherwise the padding would be at the top and just get given back
+        self.free_block(padding_ptr, padding);
         self.alloc_count += 1;
         let ret_ptr = unsafe { self.base.add(self.next).sub(layout.size()) };
         ret_ptr
     }
 
     pub fn dealloc(&mut self, ptr: *mut u8, layout: core::alloc::Layout) {
+        // Allocations in the regions the heap grew out of are only counted, the region is given back once none are left
+        if !(self.base..self.base.wrapping_add(self.len)).contains(&ptr) {
+            if let Some(region) = self.retired_regions.iter_mut().find(|region| (region.0..region.0.wrapping_add(region.1)).contains(&ptr)) {
+                region.2 -= 1;
+                if region.2 == 0 {
+                    if add_spare_heap_region(region.0, region.1).is_none() {
+                        use core::fmt::Write;
+                        let _ = writeln!(UART.lock(), "Leaking memory :)");
+                    }
+                    *region = (null_mut(), 0, 0);
+                }
+                return;
+            }
+            // It wasn't allocated by us ( for eg. a program freeing a pointer into one of its elf segments ), so there is nothing to give back
+            return;
+        }
+
         self.alloc_count -= 1; // Keeps track if we have gotten the same amount of deallocations as allocations,
                                // so we can reset everything that we leaked in that case
 
-        let did_dealloc = self.try_dealloc(ptr, layout);
-
-        if !did_dealloc {
-            // Store failed deallocation in some free spot in array
-            if let Some(i) = self.find_free_dealloc_ind() {
-                self.stashed_deallocations[i] = (ptr, layout);
-            } else {
-                use core::fmt::Write;
-                let _ = writeln!(UART.lock(), "Leaking memory :)");
-                // Just leak memory idk ¯\_(ツ)_/¯
-            }
-        } else {
-            // Maybe the deallocation we just did allows us to deallocate even more
-            // NOTE: sort_by allocates, so don't use it
-            // Sort array by allocations that are closest to the top of the stack ( a.k.a descending order, highest addresses first because we grow the allocator's stack by adding to the base address )
-            self.stashed_deallocations.sort_unstable_by(|alloc1, alloc2| alloc2.0.cmp(&alloc1.0));
-
-            for i in 0..self.stashed_deallocations.len() {
-                let stashed_dealloc = self.stashed_deallocations[i];
-                if stashed_dealloc.0 == null_mut() {
-                    break;
-                } // If the failed deallocation with the highest address is null, then obv. all other deallocations are null as well (a.k.a there are no more deallocations to consider)
-                if self.try_dealloc(stashed_dealloc.0, stashed_dealloc.1) {
-                    self.stashed_deallocations[i] = (null_mut(), core::alloc::Layout::new::<u8>());
-                } else {
-                    // If we can't deallocate the allocation with the highest address, there is no point in trying the others because they will be under it
-                    break;
-                }
+        // If we have deallocated all the allocations
+        if self.alloc_count == 0 {
+            self.next = 0;
+            for e in self.stashed_deallocations.iter_mut() {
+                *e = (null_mut(), core::alloc::Layout::new::<u8>());
             }
+            return;
         }
+
+        self.free_block(ptr, layout.size());
     }
 
 
+    // Tries to make the allocation at ptr new_size bytes big without moving it, this works when:
+    // 1. The allocation is at the top of the stack, then the top just moves, as long as there is room
+    // 2. The allocation shrinks, then the bytes after the new end are deallocated ( the same way as padding )
+    // Returns: If the allocation was resized, if it was then it has to be deallocated with new_size from now on
+    pub fn resize_in_place(&mut self, ptr: *mut u8, layout: core::alloc::Layout, new_size: usize) -> bool {
+        let is_in_current_region = (self.base..self.base.wrapping_add(self.len)).contains(&ptr);
+        if is_in_current_region && ptr.wrapping_add(layout.size()) == self.base.wrapping_add(self.next) {
+            let Some(new_next) = (self.next - layout.size()).checked_add(new_size) else {
+                return false;
+            };
+            if new_next >= self.len {
+                return false;
+            } // Not enough room :^(
+            self.next = new_next;
+            return true;
+        }
+
+        if new_size > layout.size() {
+            return false;
+        }
+        // NOTE: Regions the heap grew out of are given back as a whole, so there's no need to keep track of the end of the allocation
+        if is_in_current_region {
+            self.free_block(ptr.wrapping_add(new_size), layout.size() - new_size);
+        }
+        true
+    }
+
     pub unsafe fn realloc(&mut self, ptr: *mut u8, layout: core::alloc::Layout, new_size: usize) -> *mut u8 {
         let Ok(new_layout) = core::alloc::Layout::from_size_align(new_size, layout.align()) else {
             return null_mut();
         };
 
-        // TODO: Ability to reuse old allocation when shrinking
+        if self.resize_in_place(ptr, layout, new_size) {
+            return ptr;
+        }
+
         let new_ptr = self.alloc(new_layout);
         if !new_ptr.is_null() {
             // If we could allocate a new block
@@ -1,8 +1,8 @@
-use alloc::{rc::Rc, vec::Vec};
-use core::{cell::RefCell, mem};
+use alloc::{boxed::Box, collections::BTreeMap, rc::Rc, vec, vec::Vec};
+use core::{cell::RefCell, convert::TryFrom, mem};
 use packed_struct::prelude::PackedStruct;
 
-use crate::{vfs::IFile, virtmem::KernPointer};
+use crate::{pci, vfs::IFile, virtmem::KernPointer};
 
 #[derive(PackedStruct, PartialEq)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "1")]
@@ -85,11 +85,24 @@ impl ControlRegistersLBA28 {
         self.alt_stat_device_ctrl.write(d)
     }
 
+    // Resets both devices on the bus, used to get the bus out of a transfer that went wrong
+    // Source: https://wiki.osdev.org/ATA_PIO_Mode#Resetting_a_drive_.2F_device
+    unsafe fn soft_reset(&mut self) {
+        self.write_device_ctrl(DEVICE_CTRL_SOFTWARE_RESET);
+        // The reset bit has to be held for at least 5 microseconds, reading the alternate status takes about 100 nanoseconds
+        for _ in 0..100 {
+            self.read_alt_stat();
+        }
+        self.write_device_ctrl(0);
+    }
+
     unsafe fn read_drive_addr(&self) -> u8 {
         self.drive_addr.read()
     }
 }
 
+const DEVICE_CTRL_SOFTWARE_RESET: u8 = 1 << 2;
+
 pub const SECTOR_SIZE_IN_BYTES: usize = 256 * core::mem::size_of::<u16>();
 type Sector = [u16; SECTOR_SIZE_IN_BYTES / core::mem::size_of::<u16>()];
 
@@ -114,32 +127,160 @@ impl BUSType {
     }
 }
 
+#[derive(Clone, Copy)]
+struct ATADeviceInfo {
+    sector_count: u64,
+    supports_lba48: bool,
+}
+
+// NOTE: This is about the ide controller on the pci bus, not the ata devices
+// Source: https://wiki.osdev.org/PCI_IDE_Controller
+const PCI_CLASS_MASS_STORAGE: u8 = 0x01;
+const PCI_SUBCLASS_IDE: u8 = 0x01;
+const IDE_PROG_IF_PRIMARY_NATIVE: u8 = 1 << 0;
+const IDE_PROG_IF_SECONDARY_NATIVE: u8 = 1 << 2;
+const IDE_PROG_IF_BUS_MASTER: u8 = 1 << 7;
+// BAR4 holds the io port base of the bus master registers, the primary bus's registers come first, then the secondary bus's
+const IDE_BUS_MASTER_BAR: u8 = pci::CONFIG_BAR0 + 4 * 4;
+const BUS_MASTER_SECONDARY_OFFSET: u16 = 8;
+
+// Source: https://wiki.osdev.org/ATA/ATAPI_using_DMA
+const BUS_MASTER_COMMAND_START: u8 = 1 << 0;
+// NOTE: "Read" is from the point of view of the disk, so when set the bus master writes to memory
+const BUS_MASTER_COMMAND_READ: u8 = 1 << 3;
+const BUS_MASTER_STATUS_ACTIVE: u8 = 1 << 0;
+const BUS_MASTER_STATUS_ERROR: u8 = 1 << 1;
+const BUS_MASTER_STATUS_INTERRUPT: u8 = 1 << 2;
+// A transfer of MAX_SECTORS_PER_COMMAND sectors is done way before this, if it isn't then it's never going to be
+const DMA_MAX_TRIES: usize = 10_000_000;
+
+// The bus master moves the bytes of each region in the table in order, stopping after the one marked as the end of the table
+// NOTE: Regions can't cross a 64K boundary, and a byte count of 0 means 64K
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[repr(C)]
+struct PRDEntry {
+    region_address: u32,
+    byte_count: u16,
+    flags: u16,
+}
+
+const PRD_END_OF_TABLE: u16 = 1 << 15;
+const PRD_MAX_REGION_LEN: u64 = 64 * 1024;
+// The bounce buffer isn't necessarily aligned to 64K, so it can start in the middle of a region
+const PRDT_MAX_ENTRIES: usize = (MAX_SECTORS_PER_COMMAND * SECTOR_SIZE_IN_BYTES) / PRD_MAX_REGION_LEN as usize + 1;
+
+// NOTE: The table has to be 4 byte aligned and can't cross a 64K boundary, aligning it to a power of 2 that's at least its size guarantees both
+#[repr(C, align(32))]
+struct PRDTable([PRDEntry; PRDT_MAX_ENTRIES]);
+
+// Describes the len bytes at addr as a table of regions
+// Returns: How many entries of prdt were used, or None if the bytes can't be transferred by the bus master,
+// because they aren't in the first 4G, aren't word aligned or would need more entries than prdt has
+fn fill_prdt(prdt: &mut [PRDEntry], addr: u64, len: usize) -> Option<usize> {
+    let end = addr.checked_add(len as u64)?;
+    if len == 0 || addr % 2 != 0 || len % 2 != 0 || end > 1 << 32 {
+        return None;
+    }
+
+    let mut used = 0;
+    let mut region_start = addr;
+    while region_start < end {
+        let region_end = core::cmp::min((region_start / PRD_MAX_REGION_LEN + 1) * PRD_MAX_REGION_LEN, end);
+        // NOTE: A region of 64K has a byte count of 0, which is what the truncation gives us
+        *prdt.get_mut(used)? =
+            PRDEntry { region_address: region_start as u32, byte_count: (region_end - region_start) as u16, flags: 0 };
+        used += 1;
+        region_start = region_end;
+    }
+    prdt[used - 1].flags = PRD_END_OF_TABLE;
+    Some(used)
+}
+
+struct BusMaster {
+    command: KernPointer<u8>,
+    status: KernPointer<u8>,
+    prdt_address: KernPointer<u32>,
+    prdt: Box<PRDTable>,
+    // Transfers go through here, so that whatever memory the caller has doesn't matter
+    bounce_buffer: Vec<Sector>,
+}
+
+// Returns: The io port base of the bus master registers of the pci ide controller, after enabling bus mastering on it
+// NOTE: Only controllers with both buses in compatibility mode are used, since otherwise the buses aren't at the legacy ports
+pub unsafe fn find_bus_master_base() -> Option<u16> {
+    let controller = pci::find_by_class(PCI_CLASS_MASS_STORAGE, PCI_SUBCLASS_IDE)?;
+    let (_, _, prog_if) = pci::read_class(controller);
+    if prog_if & IDE_PROG_IF_BUS_MASTER == 0 || prog_if & (IDE_PROG_IF_PRIMARY_NATIVE | IDE_PROG_IF_SECONDARY_NATIVE) != 0 {
+        return None;
+    }
+    let bar = pci::read_config(controller, IDE_BUS_MASTER_BAR);
+    // Bit 0 is set for io space bars, the bus master registers are never memory mapped
+    if bar & 1 == 0 {
+        return None;
+    }
+    let base = u16::try_from(bar & 0xFFFC).ok().filter(|base| *base != 0)?;
+    pci::enable_command_bits(controller, pci::COMMAND_IO_SPACE | pci::COMMAND_BUS_MASTER);
+    Some(base)
+}
+
 pub struct ATABus {
     io: IORegistersLBA28,
     control: ControlRegistersLBA28,
-    master_sector_count: Option<u32>,
-    slave_sector_count: Option<u32>,
+    master_info: Option<ATADeviceInfo>,
+    slave_info: Option<ATADeviceInfo>,
     bus_type: BUSType,
+    // Only there when transfers can use dma
+    bus_master: Option<BusMaster>,
 }
 
+pub const LBA28_MAX_SECTOR_COUNT: u64 = 1 << 28;
+// NOTE: The ATA sector count register is 8 bits wide in LBA28 mode, 0 meaning 256 sectors,
+// LBA48 allows more but we limit both to the same amount
+pub const MAX_SECTORS_PER_COMMAND: usize = 256;
+
 #[derive(Clone, Copy)]
 
 pub struct LBA28 {
     pub low: u8,
     pub mid: u8,
     pub hi: u8,
+    // NOTE: Only the low 4 bits are used, they get sent in the drive select register
+    pub top: u8,
 }
 
 impl From<u32> for LBA28 {
     fn from(v: u32) -> Self {
         let data = v.to_le_bytes();
-        LBA28 { low: data[0], mid: data[1], hi: data[2] }
+        LBA28 { low: data[0], mid: data[1], hi: data[2], top: data[3] & 0x0F }
+    }
+}
+
+impl From<LBA28> for u32 {
+    fn from(lba: LBA28) -> Self {
+        u32::from_le_bytes([lba.low, lba.mid, lba.hi, lba.top & 0x0F])
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LBA48 {
+    pub low: u8,
+    pub mid: u8,
+    pub hi: u8,
+    pub ext_low: u8,
+    pub ext_mid: u8,
+    pub ext_hi: u8,
+}
+
+impl From<u64> for LBA48 {
+    fn from(v: u64) -> Self {
+        let data = v.to_le_bytes();
+        LBA48 { low: data[0], mid: data[1], hi: data[2], ext_low: data[3], ext_mid: data[4], ext_hi: data[5] }
     }
 }
 
-impl Into<u32> for LBA28 {
-    fn into(self) -> u32 {
-        u32::from_le_bytes([self.low, self.mid, self.hi, 0])
+impl From<LBA48> for u64 {
+    fn from(lba: LBA48) -> Self {
+        u64::from_le_bytes([lba.low, lba.mid, lba.hi, lba.ext_low, lba.ext_mid, lba.ext_hi, 0, 0])
     }
 }
 impl ATADevice {
@@ -155,9 +296,13 @@ impl ATADevice {
 mod ata_command {
     pub const NOP: u8 = 0x00;
     pub const READ_SECTORS: u8 = 0x20;
+    pub const READ_SECTORS_EXT: u8 = 0x24;
     pub const WRITE_SECTORS: u8 = 0x30;
+    pub const WRITE_SECTORS_EXT: u8 = 0x34;
     pub const READ_DMA: u8 = 0xC8;
+    pub const READ_DMA_EXT: u8 = 0x25;
     pub const WRITE_DMA: u8 = 0xCA;
+    pub const WRITE_DMA_EXT: u8 = 0x35;
     pub const STANDBY_IMMEDIATE: u8 = 0xE0;
     pub const IDLE_IMMEDIATE: u8 = 0xE1;
     pub const STANDBY: u8 = 0xE2;
@@ -183,9 +328,10 @@ impl ATABus {
         let bus = ATABus {
             io: IORegistersLBA28::new(io_base),
             control: ControlRegistersLBA28::new(cntrl_base),
-            master_sector_count: None,
-            slave_sector_count: None,
+            master_info: None,
+            slave_info: None,
             bus_type: typ,
+            bus_master: None,
         };
         // IO bus has pull-up resitors so 0xFF, which is normally an invalid value anyway, probs indicates no drives on the bus
         if bus.io.read_status() == ATAStatus::unpack(&[0xFF]).ok()? {
@@ -195,36 +341,85 @@ impl ATABus {
         }
     }
 
-    pub unsafe fn get_sector_count(&mut self, device: ATADevice) -> Option<u32> {
+    // Makes transfers use dma through the bus master registers at controller_base ( as returned by find_bus_master_base ), they fall back to pio if dma fails
+    // NOTE: The bus master only sees physical addresses, since memory is identity mapped those are the same as ours, but they have to be in the first 4G
+    pub unsafe fn enable_dma(&mut self, controller_base: u16) -> Option<()> {
+        let base = match self.bus_type {
+            BUSType::Primary => controller_base,
+            BUSType::Secondary => controller_base.checked_add(BUS_MASTER_SECONDARY_OFFSET)?,
+        };
+        let mut prdt = Box::new(PRDTable([PRDEntry::default(); PRDT_MAX_ENTRIES]));
+        let bounce_buffer = vec![[0u16; SECTOR_SIZE_IN_BYTES / mem::size_of::<u16>()]; MAX_SECTORS_PER_COMMAND];
+        let prdt_addr = &*prdt as *const PRDTable as u64;
+        if prdt_addr % mem::align_of::<PRDTable>() as u64 != 0 || prdt_addr + mem::size_of::<PRDTable>() as u64 > 1 << 32 {
+            return None;
+        }
+        // Make sure the whole bounce buffer can be described by the table
+        fill_prdt(&mut prdt.0, bounce_buffer.as_ptr() as u64, bounce_buffer.len() * SECTOR_SIZE_IN_BYTES)?;
+
+        self.bus_master = Some(BusMaster {
+            command: KernPointer::<u8>::from_port(base),
+            status: KernPointer::<u8>::from_port(base + 2),
+            prdt_address: KernPointer::<u32>::from_port(base + 4),
+            prdt,
+            bounce_buffer,
+        });
+        Some(())
+    }
+
+    pub fn is_dma_enabled(&self) -> bool {
+        self.bus_master.is_some()
+    }
+
+    pub fn get_bus_name(&self) -> &'static str {
+        self.bus_type.into_str()
+    }
+
+    // Reads the same sectors using dma and using pio
+    // Returns: Whether both reads gave the same data, or None if dma isn't enabled or either read failed
+    pub unsafe fn verify_dma(&mut self, device: ATADevice, sector: u64, sector_count: usize) -> Option<bool> {
+        let mut dma_data = vec![[0u16; SECTOR_SIZE_IN_BYTES / mem::size_of::<u16>()]; sector_count];
+        let mut pio_data = dma_data.clone();
+        self.read_sectors_dma(device, sector, &mut dma_data)?;
+        self.read_sectors_pio(device, sector, &mut pio_data)?;
+        Some(dma_data == pio_data)
+    }
+
+    unsafe fn get_device_info(&mut self, device: ATADevice) -> Option<ATADeviceInfo> {
+        let cached_info = match device {
+            ATADevice::MASTER => self.master_info,
+            ATADevice::SLAVE => self.slave_info,
+        };
+        if let Some(info) = cached_info {
+            return Some(info);
+        }
+
+        let id = self.identify(device)?;
+        // Word 83 bit 10 is set if the device supports the 48-bit address feature set
+        // Source: https://wiki.osdev.org/ATA_PIO_Mode#Interesting_information_returned_by_IDENTIFY
+        let supports_lba48 = id[83] & (1 << 10) != 0;
+        let sector_count = if supports_lba48 {
+            // Words 100 through 103 taken as a u64 contain the total number of 48 bit addressable sectors
+            u64::from(id[100]) | u64::from(id[101]) << 16 | u64::from(id[102]) << 32 | u64::from(id[103]) << 48
+        } else {
+            // Words 60 and 61 taken as a u32 contain the total number of 28 bit LBA addressable sectors
+            u64::from(id[60]) | u64::from(id[61]) << 16
+        };
+
+        let info = ATADeviceInfo { sector_count, supports_lba48 };
         match device {
-            ATADevice::MASTER => {
-                if let Some(sector_count) = self.master_sector_count {
-                    return Some(sector_count);
-                } else if let Some(id) = self.identify(device) {
-                    self.master_sector_count = Some(u32::from_le_bytes([
-                        id[60].to_le_bytes()[0],
-                        id[60].to_le_bytes()[1],
-                        id[61].to_le_bytes()[0],
-                        id[61].to_le_bytes()[1],
-                    ]));
-                    return self.master_sector_count;
-                }
-            }
-            ATADevice::SLAVE => {
-                if let Some(sector_count) = self.slave_sector_count {
-                    return Some(sector_count);
-                } else if let Some(id) = self.identify(device) {
-                    self.slave_sector_count = Some(u32::from_le_bytes([
-                        id[60].to_le_bytes()[0],
-                        id[60].to_le_bytes()[1],
-                        id[61].to_le_bytes()[0],
-                        id[61].to_le_bytes()[1],
-                    ]));
-                    return self.slave_sector_count;
-                }
-            }
+            ATADevice::MASTER => self.master_info = Some(info),
+            ATADevice::SLAVE => self.slave_info = Some(info),
         }
-        None
+        Some(info)
+    }
+
+    pub unsafe fn get_sector_count(&mut self, device: ATADevice) -> Option<u64> {
+        Some(self.get_device_info(device)?.sector_count)
+    }
+
+    pub unsafe fn supports_lba48(&mut self, device: ATADevice) -> Option<bool> {
+        Some(self.get_device_info(device)?.supports_lba48)
     }
 
     pub unsafe fn identify(&mut self, device: ATADevice) -> Option<Sector> {
@@ -239,7 +434,7 @@ impl ATABus {
         if self.io.read_status() == ATAStatus::unpack(&[0x0]).ok()? {
             return None;
         }
-        wait_for!(self.io.read_status().ata_busy == false); // BSY clears
+        wait_for!(!self.io.read_status().ata_busy); // BSY clears
         wait_for!({
             let status = self.io.read_status();
             status.ata_data_request || status.ata_err
@@ -252,24 +447,73 @@ impl ATABus {
         Some(a)
     }
 
-    pub unsafe fn read_sector(&mut self, device: ATADevice, sector_lba: LBA28) -> Option<Sector> {
-        // FIXME: This shouldn't be needed in theory
-        wait_for!(self.io.read_status().ata_busy == false); // BSY clears
-
+    unsafe fn send_lba28_command(&mut self, device: ATADevice, sector_lba: LBA28, sector_count: u8, command: u8) {
         self.io.drive_sel.write(
             match device {
                 ATADevice::MASTER => 0xE0,
                 ATADevice::SLAVE => 0xF0,
-            } | (sector_lba.hi >> 4),
+            } | (sector_lba.top & 0x0F),
         );
         self.io.write_features(0); // No features
-        self.io.sector_count.write(1); // Read one sector
+        self.io.sector_count.write(sector_count);
         self.io.address_low.write(sector_lba.low);
         self.io.address_mid.write(sector_lba.mid);
         self.io.address_hi.write(sector_lba.hi);
-        self.io.write_command(ata_command::READ_SECTORS); // Read sectors command
+        self.io.write_command(command);
+    }
 
-        wait_for!(self.io.read_status().ata_busy == false); // BSY clears
+    // NOTE: In LBA48 mode the sector count and address registers are 2 bytes "deep",
+    // the high order bytes have to be written first, then the low order bytes
+    // Source: https://wiki.osdev.org/ATA_PIO_Mode#48_bit_PIO
+    unsafe fn send_lba48_command(&mut self, device: ATADevice, sector_lba: LBA48, sector_count: u16, command: u8) {
+        self.io.drive_sel.write(match device {
+            ATADevice::MASTER => 0x40,
+            ATADevice::SLAVE => 0x50,
+        });
+        self.io.write_features(0); // No features
+        self.io.sector_count.write(sector_count.to_le_bytes()[1]);
+        self.io.address_low.write(sector_lba.ext_low);
+        self.io.address_mid.write(sector_lba.ext_mid);
+        self.io.address_hi.write(sector_lba.ext_hi);
+        self.io.sector_count.write(sector_count.to_le_bytes()[0]);
+        self.io.address_low.write(sector_lba.low);
+        self.io.address_mid.write(sector_lba.mid);
+        self.io.address_hi.write(sector_lba.hi);
+        self.io.write_command(command);
+    }
+
+    // Picks LBA48 if the device supports it, otherwise falls back to LBA28
+    // NOTE: Fails instead of wrapping around if the sectors can't be addressed
+    unsafe fn send_transfer_command(&mut self, device: ATADevice, sector: u64, sector_count: usize, write: bool, dma: bool) -> Option<()> {
+        if sector_count == 0 || sector_count > MAX_SECTORS_PER_COMMAND {
+            return None;
+        }
+
+        if self.supports_lba48(device)? {
+            let command = match (write, dma) {
+                (false, false) => ata_command::READ_SECTORS_EXT,
+                (true, false) => ata_command::WRITE_SECTORS_EXT,
+                (false, true) => ata_command::READ_DMA_EXT,
+                (true, true) => ata_command::WRITE_DMA_EXT,
+            };
+            self.send_lba48_command(device, LBA48::from(sector), sector_count as u16, command);
+        } else if sector + sector_count as u64 <= LBA28_MAX_SECTOR_COUNT {
+            let command = match (write, dma) {
+                (false, false) => ata_command::READ_SECTORS,
+                (true, false) => ata_command::WRITE_SECTORS,
+                (false, true) => ata_command::READ_DMA,
+                (true, true) => ata_command::WRITE_DMA,
+            };
+            // NOTE: A sector count of 0 means 256 sectors in LBA28 mode, which is what the truncation gives us
+            self.send_lba28_command(device, LBA28::from(sector as u32), sector_count as u8, command);
+        } else {
+            return None;
+        }
+        Some(())
+    }
+
+    unsafe fn wait_for_data_request(&mut self) -> Option<()> {
+        wait_for!(!self.io.read_status().ata_busy); // BSY clears
         wait_for!({
             let status = self.io.read_status();
             status.ata_data_request || status.ata_err
@@ -277,161 +521,326 @@ impl ATABus {
         if self.io.read_status().ata_err {
             return None;
         } // ERR
+        Some(())
+    }
 
-        let mut a = [0u16; 256];
-        a.iter_mut().for_each(|e| *e = self.io.data.read());
-        Some(a)
+    // Reads buf.len() contiguous sectors using one command, using dma if it's enabled
+    // NOTE: At most MAX_SECTORS_PER_COMMAND sectors can be read at once
+    pub unsafe fn read_sectors(&mut self, device: ATADevice, sector: u64, buf: &mut [Sector]) -> Option<()> {
+        if self.bus_master.is_some() {
+            if self.read_sectors_dma(device, sector, buf).is_some() {
+                return Some(());
+            }
+            self.fall_back_to_pio();
+        }
+        self.read_sectors_pio(device, sector, buf)
     }
 
-    pub unsafe fn write_sector(&mut self, device: ATADevice, sector_lba: LBA28, data: &Sector) -> Option<()> {
+    // NOTE: At most MAX_SECTORS_PER_COMMAND sectors can be written at once
+    pub unsafe fn write_sectors(&mut self, device: ATADevice, sector: u64, data: &[Sector]) -> Option<()> {
+        if self.bus_master.is_some() {
+            if self.write_sectors_dma(device, sector, data).is_some() {
+                return Some(());
+            }
+            self.fall_back_to_pio();
+        }
+        self.write_sectors_pio(device, sector, data)
+    }
+
+    // The device raises DRQ once per sector
+    pub unsafe fn read_sectors_pio(&mut self, device: ATADevice, sector: u64, buf: &mut [Sector]) -> Option<()> {
         // FIXME: This shouldn't be needed in theory
-        wait_for!(self.io.read_status().ata_busy == false); // BSY clears
+        wait_for!(!self.io.read_status().ata_busy); // BSY clears
 
-        self.io.drive_sel.write(
-            match device {
-                ATADevice::MASTER => 0xE0,
-                ATADevice::SLAVE => 0xF0,
-            } | (sector_lba.hi >> 4),
-        );
-        self.io.write_features(0); // No features
-        self.io.sector_count.write(1); // Write one sector
-        self.io.address_low.write(sector_lba.low);
-        self.io.address_mid.write(sector_lba.mid);
-        self.io.address_hi.write(sector_lba.hi);
-        self.io.write_command(ata_command::WRITE_SECTORS); // Write sectors command
+        self.send_transfer_command(device, sector, buf.len(), false, false)?;
+        for sector_data in buf.iter_mut() {
+            self.wait_for_data_request()?;
+            sector_data.iter_mut().for_each(|e| *e = self.io.data.read());
+        }
+        Some(())
+    }
 
-        wait_for!(self.io.read_status().ata_busy == false); // BSY clears
-        wait_for!({
-            let status = self.io.read_status();
-            status.ata_data_request || status.ata_err
-        }); // DRQ or ERR sets
-        if self.io.read_status().ata_err {
+    pub unsafe fn write_sectors_pio(&mut self, device: ATADevice, sector: u64, data: &[Sector]) -> Option<()> {
+        // FIXME: This shouldn't be needed in theory
+        wait_for!(!self.io.read_status().ata_busy); // BSY clears
+
+        self.send_transfer_command(device, sector, data.len(), true, false)?;
+        for sector_data in data {
+            self.wait_for_data_request()?;
+            sector_data.iter().for_each(|e| self.io.data.write(*e));
+        }
+        Some(())
+    }
+
+    // Returns: None if dma isn't enabled or the transfer failed
+    pub unsafe fn read_sectors_dma(&mut self, device: ATADevice, sector: u64, buf: &mut [Sector]) -> Option<()> {
+        let mut bus_master = self.bus_master.take()?;
+        let res = self.run_dma_command(&mut bus_master, device, sector, buf.len(), false);
+        if res.is_some() {
+            buf.copy_from_slice(&bus_master.bounce_buffer[..buf.len()]);
+        }
+        self.bus_master = Some(bus_master);
+        res
+    }
+
+    // Returns: None if dma isn't enabled or the transfer failed
+    pub unsafe fn write_sectors_dma(&mut self, device: ATADevice, sector: u64, data: &[Sector]) -> Option<()> {
+        let mut bus_master = self.bus_master.take()?;
+        let res = bus_master
+            .bounce_buffer
+            .get_mut(..data.len())
+            .map(|bounce| bounce.copy_from_slice(data))
+            .and_then(|_| self.run_dma_command(&mut bus_master, device, sector, data.len(), true));
+        self.bus_master = Some(bus_master);
+        res
+    }
+
+    // Transfers sector_count sectors between the disk and the start of the bounce buffer
+    // Source: https://wiki.osdev.org/ATA/ATAPI_using_DMA#The_Bus_Master_Register
+    unsafe fn run_dma_command(&mut self, bus_master: &mut BusMaster, device: ATADevice, sector: u64, sector_count: usize, write: bool) -> Option<()> {
+        if sector_count == 0 || sector_count > bus_master.bounce_buffer.len() {
             return None;
-        } // ERR
+        }
+        fill_prdt(&mut bus_master.prdt.0, bus_master.bounce_buffer.as_ptr() as u64, sector_count * SECTOR_SIZE_IN_BYTES)?;
+        // FIXME: This shouldn't be needed in theory
+        wait_for!(!self.io.read_status().ata_busy); // BSY clears
+
+        let direction = if write { 0 } else { BUS_MASTER_COMMAND_READ };
+        bus_master.prdt_address.write(&*bus_master.prdt as *const PRDTable as u64 as u32);
+        bus_master.command.write(direction);
+        // The interrupt and error bits are cleared by writing 1s to them
+        bus_master.status.write(BUS_MASTER_STATUS_ERROR | BUS_MASTER_STATUS_INTERRUPT);
+        self.send_transfer_command(device, sector, sector_count, write, true)?;
+        bus_master.command.write(direction | BUS_MASTER_COMMAND_START);
+
+        // NOTE: The bus master can stop being active before the device is done ( for writes it's done once the table runs out ),
+        // so the device's interrupt is what says that the transfer is over
+        let mut tries = 0;
+        while bus_master.status.read() & (BUS_MASTER_STATUS_INTERRUPT | BUS_MASTER_STATUS_ERROR) == 0 && tries < DMA_MAX_TRIES {
+            tries += 1;
+            core::hint::spin_loop();
+        }
+        while self.io.read_status().ata_busy && tries < DMA_MAX_TRIES {
+            tries += 1;
+            core::hint::spin_loop();
+        }
 
-        data.iter().for_each(|e| self.io.data.write(*e));
+        bus_master.command.write(direction);
+        let status = bus_master.status.read();
+        bus_master.status.write(BUS_MASTER_STATUS_ERROR | BUS_MASTER_STATUS_INTERRUPT);
+        if tries >= DMA_MAX_TRIES || status & (BUS_MASTER_STATUS_ERROR | BUS_MASTER_STATUS_ACTIVE) != 0 || self.io.read_status().ata_err {
+            return None;
+        }
         Some(())
     }
+
+    // Called when a dma transfer failed, it could have left the bus in the middle of a command, so the bus is reset and dma isn't used anymore
+    unsafe fn fall_back_to_pio(&mut self) {
+        use crate::UART;
+        use core::fmt::Write;
+        writeln!(UART.lock(), "DMA transfer failed on the {} ata bus, falling back to pio!", self.bus_type.into_str()).unwrap();
+        self.bus_master = None;
+        self.control.soft_reset();
+        wait_for!(!self.io.read_status().ata_busy); // BSY clears
+    }
+
+    pub unsafe fn read_sector(&mut self, device: ATADevice, sector: u64) -> Option<Sector> {
+        let mut buf = [[0u16; SECTOR_SIZE_IN_BYTES / core::mem::size_of::<u16>()]];
+        self.read_sectors(device, sector, &mut buf)?;
+        Some(buf[0])
+    }
+
+    pub unsafe fn write_sector(&mut self, device: ATADevice, sector: u64, data: &Sector) -> Option<()> {
+        self.write_sectors(device, sector, core::slice::from_ref(data))
+    }
 }
 
 pub struct ATADeviceFile {
     pub bus: Rc<RefCell<ATABus>>,
     pub bus_device: ATADevice,
+    // Sectors that were written but haven't reached the disk yet, so that writes to the same sector are coalesced,
+    // they are written back on sync, or when there are more than write_cache_max_sectors of them
+    // NOTE: When write_cache_max_sectors is 0 writes go straight to the disk
+    write_cache: BTreeMap<u64, Sector>,
+    write_cache_max_sectors: usize,
 }
 
+impl ATADeviceFile {
+    pub fn new(bus: Rc<RefCell<ATABus>>, bus_device: ATADevice, write_cache_max_sectors: usize) -> Self {
+        Self { bus, bus_device, write_cache: BTreeMap::new(), write_cache_max_sectors }
+    }
+
+    // Writes every cached sector to the disk, runs of consecutive sectors are written using as few commands as possible
+    // NOTE: If a write fails then the sectors that weren't written yet stay cached
+    pub fn flush_write_cache(&mut self) -> Option<()> {
+        let mut bus = (*self.bus).borrow_mut();
+        while let Some(first) = self.write_cache.keys().next().copied() {
+            let run: Vec<Sector> =
+                (first..).map_while(|sector| self.write_cache.get(&sector).copied()).take(MAX_SECTORS_PER_COMMAND).collect();
+            unsafe { bus.write_sectors(self.bus_device, first, &run) }?;
+            for sector in first..first + run.len() as u64 {
+                self.write_cache.remove(&sector);
+            }
+        }
+        Some(())
+    }
+
+    // Same as write, but into the write cache
+    fn write_to_cache(&mut self, offset_in_bytes: u64, data: &[u8], first_sector: u64, last_sector: u64) -> Option<usize> {
+        let mut data_left = data;
+        let mut offset_in_sector = (offset_in_bytes % SECTOR_SIZE_IN_BYTES as u64) as usize;
+        for sector in first_sector..=last_sector {
+            let bytes_in_sector = core::cmp::min(data_left.len(), SECTOR_SIZE_IN_BYTES - offset_in_sector);
+            // No need to read sectors that we know will be completly overriden
+            let mut sector_data = if bytes_in_sector == SECTOR_SIZE_IN_BYTES {
+                [0u16; SECTOR_SIZE_IN_BYTES / mem::size_of::<u16>()]
+            } else if let Some(cached) = self.write_cache.get(&sector) {
+                *cached
+            } else {
+                unsafe { (*self.bus).borrow_mut().read_sector(self.bus_device, sector) }?
+            };
+            write_bytes_to_sector(&mut sector_data, offset_in_sector, &data_left[..bytes_in_sector]);
+            self.write_cache.insert(sector, sector_data);
+            data_left = &data_left[bytes_in_sector..];
+            offset_in_sector = 0;
+        }
+
+        if self.write_cache.len() > self.write_cache_max_sectors {
+            self.flush_write_cache()?;
+        }
+        Some(data.len())
+    }
+}
+
+// Copies bytes into sector starting at offset
+// NOTE: Sectors are transferred as words, so bytes are packed into them in memory order
+fn write_bytes_to_sector(sector: &mut Sector, offset: usize, bytes: &[u8]) {
+    for (i, b) in bytes.iter().enumerate() {
+        let pos = offset + i;
+        let word = &mut sector[pos / mem::size_of::<u16>()];
+        let mut word_bytes = word.to_ne_bytes();
+        word_bytes[pos % mem::size_of::<u16>()] = *b;
+        *word = u16::from_ne_bytes(word_bytes);
+    }
+}
+
+// Returns: The first and last sector that the len bytes at offset_in_bytes are in, or None if any of them are past the end of a device with sector_count sectors
+// NOTE: len must not be 0, since then there are no sectors
+fn sectors_spanned(offset_in_bytes: u64, len: usize, sector_count: u64) -> Option<(u64, u64)> {
+    let last_byte = offset_in_bytes.checked_add(len.checked_sub(1)? as u64)?;
+    let last_sector = last_byte / SECTOR_SIZE_IN_BYTES as u64;
+    if last_sector >= sector_count {
+        return None;
+    }
+    Some((offset_in_bytes / SECTOR_SIZE_IN_BYTES as u64, last_sector))
+}
+
+// NOTE: The disk is treated as a flat array of bytes, accesses that don't start or end on a sector boundary read the whole sectors
+// and only use ( or for writes, only change ) the bytes that were asked for
 impl IFile for ATADeviceFile {
     fn read(&self, offset_in_bytes: u64, len: usize) -> Option<Vec<u8>> {
-        let offset_in_first_sector = (offset_in_bytes % SECTOR_SIZE_IN_BYTES as u64) as usize;
-        let first_sector = (offset_in_bytes / SECTOR_SIZE_IN_BYTES as u64) as usize;
-        let mut res: Vec<u8> = Vec::with_capacity(len as usize);
-
-        // Deal with first block
-        let first_block_lba = LBA28 {
-            hi: ((first_sector >> 16) & 0xFF) as u8,
-            mid: ((first_sector >> 8) & 0xFF) as u8,
-            low: (first_sector & 0xFF) as u8,
-        };
-        let first_block = unsafe { (*self.bus).borrow_mut().read_sector(self.bus_device, first_block_lba) }?;
-
-        let mut skip_first_byte = offset_in_first_sector % 2 == 1;
-        for e in &first_block[offset_in_first_sector / 2..] {
-            if skip_first_byte {
-                res.push(((e >> 8) & 0xFF) as u8);
-                skip_first_byte = false;
-                continue;
-            }
-            res.push((e & 0xFF) as u8);
-            res.push(((e >> 8) & 0xFF) as u8);
+        let mut res: Vec<u8> = Vec::with_capacity(len + 2 * SECTOR_SIZE_IN_BYTES);
+        if len == 0 {
+            return Some(res);
         }
 
-        // Read continually, until the end is included in res, overreading if necessary
-        let extra_block = if len % SECTOR_SIZE_IN_BYTES != 0 { 1 } else { 0 };
-        for sector_indx in 1..((len / SECTOR_SIZE_IN_BYTES) + extra_block) {
-            if res.len() >= len {
-                break;
-            }
-            let offset = first_sector + sector_indx;
-            let lba =
-                LBA28 { hi: ((offset >> 16) & 0xFF) as u8, mid: ((offset >> 8) & 0xFF) as u8, low: (offset & 0xFF) as u8 };
-            res.append(&mut unsafe { (*self.bus).borrow_mut().read_sector(self.bus_device, lba) }.map(|val| {
-                let mut v = Vec::with_capacity(SECTOR_SIZE_IN_BYTES);
-                for e in &val {
-                    v.extend(e.to_ne_bytes());
+        let mut bus = (*self.bus).borrow_mut();
+        let offset_in_first_sector = (offset_in_bytes % SECTOR_SIZE_IN_BYTES as u64) as usize;
+        let (first_sector, last_sector) = sectors_spanned(offset_in_bytes, len, unsafe { bus.get_sector_count(self.bus_device) }?)?;
+
+        // Read the sectors in chunks as big as the device allows, overreading at the start and end if necessary
+        let mut sector = first_sector;
+        let mut chunk: Vec<Sector> = Vec::new();
+        while sector <= last_sector {
+            let sectors_in_chunk = core::cmp::min(last_sector - sector + 1, MAX_SECTORS_PER_COMMAND as u64) as usize;
+            chunk.resize(sectors_in_chunk, [0u16; SECTOR_SIZE_IN_BYTES / core::mem::size_of::<u16>()]);
+            unsafe { bus.read_sectors(self.bus_device, sector, &mut chunk) }?;
+            for (i, sector_data) in chunk.iter().enumerate() {
+                // What's in the write cache is newer than what's on the disk
+                let sector_data = self.write_cache.get(&(sector + i as u64)).unwrap_or(sector_data);
+                for e in sector_data {
+                    res.extend(e.to_ne_bytes());
                 }
-                v
-            })?);
+            }
+            sector += sectors_in_chunk as u64;
         }
+
         // Get rid of overread bytes
-        while res.len() > len {
-            res.pop();
-        }
+        res.drain(..offset_in_first_sector);
+        res.truncate(len);
         assert!(res.len() == len, "The amount of bytes read from disk should be the same as the amount requested!");
         Some(res)
     }
 
     fn write(&mut self, offset_in_bytes: u64, data: &[u8]) -> Option<usize> {
-        let offset_in_first_sector_in_bytes = (offset_in_bytes % SECTOR_SIZE_IN_BYTES as u64) as usize;
-        let first_sector = (offset_in_bytes / SECTOR_SIZE_IN_BYTES as u64) as usize;
-        let mut iter = data.iter();
-        let mut bytes_written = 0;
+        if data.is_empty() {
+            return Some(0);
+        }
 
-        let extra_block = if data.len() % SECTOR_SIZE_IN_BYTES != 0 { 1 } else { 0 };
+        // NOTE: Nothing is written if any of it would be past the end of the device
+        let sector_count = unsafe { (*self.bus).borrow_mut().get_sector_count(self.bus_device) }?;
+        let (first_sector, last_sector) = sectors_spanned(offset_in_bytes, data.len(), sector_count)?;
+        if self.write_cache_max_sectors != 0 {
+            return self.write_to_cache(offset_in_bytes, data, first_sector, last_sector);
+        }
 
-        let mut ind = offset_in_first_sector_in_bytes / 2;
-        let mut skip_first_byte = offset_in_first_sector_in_bytes % 2 == 1;
+        let mut bus = (*self.bus).borrow_mut();
+        let mut offset_in_sector = (offset_in_bytes % SECTOR_SIZE_IN_BYTES as u64) as usize;
+        let mut data_left = data;
 
-        for sector_indx in 0..data.len() / SECTOR_SIZE_IN_BYTES + extra_block {
-            let offset = first_sector + sector_indx;
-            let lba =
-                LBA28 { hi: ((offset >> 16) & 0xFF) as u8, mid: ((offset >> 8) & 0xFF) as u8, low: (offset & 0xFF) as u8 };
+        let mut sector = first_sector;
+        while sector <= last_sector {
+            let sectors_in_chunk = core::cmp::min(last_sector - sector + 1, MAX_SECTORS_PER_COMMAND as u64) as usize;
+            let bytes_in_chunk = core::cmp::min(data_left.len(), sectors_in_chunk * SECTOR_SIZE_IN_BYTES - offset_in_sector);
+            let mut chunk = vec![[0u16; SECTOR_SIZE_IN_BYTES / core::mem::size_of::<u16>()]; sectors_in_chunk];
 
             // No need to read sectors that we know will be completly overriden
-            let mut v = if (sector_indx == data.len() / SECTOR_SIZE_IN_BYTES + extra_block - 1 && extra_block == 1)
-                || (sector_indx == 0 && offset_in_first_sector_in_bytes != 0)
-            {
-                unsafe { (*self.bus).borrow_mut().read_sector(self.bus_device, lba) }.expect("Reading device should work!")
-            } else {
-                [0u16; SECTOR_SIZE_IN_BYTES / core::mem::size_of::<u16>()]
-            };
-
-            loop {
-                if ind >= v.len() {
-                    break;
-                }
-                let mut bytes_to_write = v[ind].to_ne_bytes();
-
-                // TODO: Test this if
-                if !skip_first_byte {
-                    if let Some(val) = iter.next() {
-                        bytes_to_write[0] = *val;
-                    } else {
-                        break;
-                    }
-                } else {
-                    skip_first_byte = false;
-                }
-                if let Some(val) = iter.next() {
-                    bytes_to_write[1] = *val;
-                } // Note: Don't break here because there may be data to write in old_vals[0]
+            if offset_in_sector != 0 {
+                chunk[0] = unsafe { bus.read_sector(self.bus_device, sector) }?;
+            }
+            let end_in_last_sector = (offset_in_sector + bytes_in_chunk) % SECTOR_SIZE_IN_BYTES;
+            if end_in_last_sector != 0 && (sectors_in_chunk > 1 || offset_in_sector == 0) {
+                chunk[sectors_in_chunk - 1] =
+                    unsafe { bus.read_sector(self.bus_device, sector + sectors_in_chunk as u64 - 1) }?;
+            }
 
-                v[ind] = u16::from_ne_bytes(bytes_to_write);
-                ind += 1;
-                bytes_written += core::mem::size_of::<u16>();
+            for (i, b) in data_left[..bytes_in_chunk].iter().enumerate() {
+                let pos = offset_in_sector + i;
+                let word = &mut chunk[pos / SECTOR_SIZE_IN_BYTES][(pos % SECTOR_SIZE_IN_BYTES) / core::mem::size_of::<u16>()];
+                let mut bytes = word.to_ne_bytes();
+                bytes[pos % core::mem::size_of::<u16>()] = *b;
+                *word = u16::from_ne_bytes(bytes);
             }
-            ind = 0;
 
-            unsafe { (*self.bus).borrow_mut().write_sector(self.bus_device, lba, &v) }?;
+            unsafe { bus.write_sectors(self.bus_device, sector, &chunk) }?;
+            data_left = &data_left[bytes_in_chunk..];
+            sector += sectors_in_chunk as u64;
+            offset_in_sector = 0;
         }
-        Some(bytes_written)
+        Some(data.len())
     }
 
     fn get_size(&self) -> u64 {
         let mut ata_bus = (*self.bus).borrow_mut();
         let sector_count = unsafe { ata_bus.get_sector_count(self.bus_device) }.expect("Rading device should work!");
-        (sector_count as u64) * SECTOR_SIZE_IN_BYTES as u64
+        sector_count * SECTOR_SIZE_IN_BYTES as u64
     }
 
     fn resize(&mut self, _new_size: u64) -> Option<()> {
         None
     }
+
+    fn sync(&mut self) -> Option<()> {
+        self.flush_write_cache()
+    }
+}
+
+impl Drop for ATADeviceFile {
+    fn drop(&mut self) {
+        if self.flush_write_cache().is_none() {
+            use crate::UART;
+            use core::fmt::Write;
+            writeln!(UART.lock(), "Failed to flush ata write cache, some writes were lost!").unwrap();
+        }
+    }
 }
@@ -0,0 +1,180 @@
+use core::cell::RefCell;
+
+use alloc::{collections::VecDeque, rc::Rc, vec, vec::Vec};
+
+use crate::vfs::IFile;
+
+// Note: This file defines a write-back cache that sits in front of any IFile ( for ex. a partition or a whole disk )
+// and caches fixed size blocks of it in memory, evicting the least recently used block when full
+
+struct CachedBlock {
+    number: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+pub struct BlockCache {
+    backing_device: Rc<RefCell<dyn IFile>>,
+    block_size: usize,
+    max_blocks: usize,
+    // NOTE: Ordered from least recently used to most recently used
+    // RefCell because IFile::read only takes &self, but reading still needs to fill the cache
+    blocks: RefCell<VecDeque<CachedBlock>>,
+}
+
+impl BlockCache {
+    pub fn new(backing_device: Rc<RefCell<dyn IFile>>, block_size: usize, max_blocks: usize) -> Self {
+        assert!(block_size > 0 && max_blocks > 0, "Block cache must be able to hold at least one non-empty block!");
+        Self { backing_device, block_size, max_blocks, blocks: RefCell::new(VecDeque::with_capacity(max_blocks)) }
+    }
+
+    // Writes all dirty blocks to the backing device, they are kept in the cache
+    pub fn flush(&self) -> Option<()> {
+        for block in self.blocks.borrow_mut().iter_mut().filter(|block| block.dirty) {
+            self.write_back(block)?;
+        }
+        Some(())
+    }
+
+    fn write_back(&self, block: &mut CachedBlock) -> Option<()> {
+        let written = (*self.backing_device).borrow_mut().write(block.number * self.block_size as u64, &block.data)?;
+        if written < block.data.len() {
+            return None;
+        }
+        block.dirty = false;
+        Some(())
+    }
+
+    // NOTE: The last block of the backing device might be smaller than block_size
+    fn get_block_len(&self, number: u64) -> usize {
+        let size = (*self.backing_device).borrow().get_size();
+        core::cmp::min(self.block_size as u64, size.saturating_sub(number * self.block_size as u64)) as usize
+    }
+
+    // Moves the block to the back of the queue ( marking it as most recently used ), loading it first if needed
+    // If load_from_device is false and the block isn't cached then it is filled with zeros instead of being read
+    // Returns: The index of the block in the queue, which is always the last index
+    fn touch_block(&self, blocks: &mut VecDeque<CachedBlock>, number: u64, load_from_device: bool) -> Option<usize> {
+        if let Some(index) = blocks.iter().position(|block| block.number == number) {
+            let block = blocks.remove(index)?;
+            blocks.push_back(block);
+            return Some(blocks.len() - 1);
+        }
+
+        let block_len = self.get_block_len(number);
+        if block_len == 0 {
+            return None;
+        }
+        let data = if load_from_device {
+            (*self.backing_device).borrow().read(number * self.block_size as u64, block_len)?
+        } else {
+            vec![0; block_len]
+        };
+
+        // Evict the least recently used block
+        if blocks.len() >= self.max_blocks {
+            let mut evicted = blocks.pop_front()?;
+            if evicted.dirty {
+                if self.write_back(&mut evicted).is_none() {
+                    // Don't lose the data, just fail
+                    blocks.push_front(evicted);
+                    return None;
+                }
+            }
+        }
+
+        blocks.push_back(CachedBlock { number, data, dirty: false });
+        Some(blocks.len() - 1)
+    }
+}
+
+impl IFile for BlockCache {
+    fn read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        if offset + len as u64 > self.get_size() {
+            return None;
+        }
+
+        let mut res = Vec::with_capacity(len);
+        let mut blocks = self.blocks.borrow_mut();
+        while res.len() < len {
+            let cur_offset = offset + res.len() as u64;
+            let number = cur_offset / self.block_size as u64;
+            let offset_in_block = (cur_offset % self.block_size as u64) as usize;
+
+            let index = self.touch_block(&mut blocks, number, true)?;
+            let data = &blocks[index].data;
+            let bytes_from_block = core::cmp::min(len - res.len(), data.len() - offset_in_block);
+            res.extend_from_slice(&data[offset_in_block..offset_in_block + bytes_from_block]);
+        }
+        Some(res)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Option<usize> {
+        if offset + data.len() as u64 > self.get_size() {
+            return None;
+        }
+
+        let mut bytes_written = 0;
+        let mut blocks = self.blocks.borrow_mut();
+        while bytes_written < data.len() {
+            let cur_offset = offset + bytes_written as u64;
+            let number = cur_offset / self.block_size as u64;
+            let offset_in_block = (cur_offset % self.block_size as u64) as usize;
+            let bytes_to_block = core::cmp::min(data.len() - bytes_written, self.get_block_len(number) - offset_in_block);
+
+            // No need to read blocks that we know will be completly overriden
+            let overwrites_whole_block = offset_in_block == 0 && bytes_to_block == self.get_block_len(number);
+            let index = self.touch_block(&mut blocks, number, !overwrites_whole_block)?;
+            let block = &mut blocks[index];
+            block.data[offset_in_block..offset_in_block + bytes_to_block]
+                .copy_from_slice(&data[bytes_written..bytes_written + bytes_to_block]);
+            block.dirty = true;
+            bytes_written += bytes_to_block;
+        }
+        Some(bytes_written)
+    }
+
+    fn get_size(&self) -> u64 {
+        (*self.backing_device).borrow().get_size()
+    }
+
+    fn prefetch(&self, offset: u64, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = offset / self.block_size as u64;
+        let last = (offset + len as u64 - 1) / self.block_size as u64;
+        // NOTE: Prefetching more than fits would just evict the blocks we prefetched first
+        let last = core::cmp::min(last, first + self.max_blocks as u64 - 1);
+
+        let mut blocks = self.blocks.borrow_mut();
+        for number in first..=last {
+            // It's only a hint, so if it doesn't work the block will just be read later
+            if self.touch_block(&mut blocks, number, true).is_none() {
+                break;
+            }
+        }
+    }
+
+    fn sync(&mut self) -> Option<()> {
+        self.flush()?;
+        (*self.backing_device).borrow_mut().sync()
+    }
+
+    fn resize(&mut self, new_size: u64) -> Option<()> {
+        // Blocks may change size or stop existing, so start over
+        self.flush()?;
+        self.blocks.borrow_mut().clear();
+        (*self.backing_device).borrow_mut().resize(new_size)
+    }
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        if self.flush().is_none() {
+            use crate::UART;
+            use core::fmt::Write;
+            writeln!(UART.lock(), "Failed to flush block cache, some writes were lost!").unwrap();
+        }
+    }
+}
@@ -1,4 +1,10 @@
-use crate::framebuffer::{FrameBuffer, Pixel};
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+
+use crate::{
+    framebuffer::{FrameBuffer, Pixel},
+    primitives::Mutex,
+};
 
 pub trait CharDevice {
     fn get_rows(&self) -> usize;
@@ -6,6 +12,88 @@ pub trait CharDevice {
     fn write_char(&mut self, x: usize, y: usize, c: char, color: Pixel) -> Option<()>;
 }
 
+// Glyphs are drawn scaled up, so that text is still readable on high resolution screens
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    pub name: &'static str,
+    pub scale: usize,
+}
+
+impl Font {
+    pub fn cell_width(&self) -> usize {
+        8 * self.scale
+    }
+
+    pub fn cell_height(&self) -> usize {
+        16 * self.scale
+    }
+}
+
+// NOTE: Every font uses the 8x16 glyphs, an 8x8 font made by squashing them isn't readable
+pub const FONTS: &[Font] = &[Font { name: "8x16", scale: 1 }, Font { name: "16x32", scale: 2 }, Font { name: "24x48", scale: 3 }];
+
+// The font framebuffers draw characters with
+pub static FONT: Mutex<Font> = Mutex::from(FONTS[0]);
+
+pub fn find_font(name: &str) -> Option<Font> {
+    FONTS.iter().find(|font| font.name == name).copied()
+}
+
+// A glyph is 16 rows of 8 pixels, the most significant bit of a row is its leftmost pixel
+pub type Glyph = [u8; 16];
+
+// Shown for characters that have no glyph, a box with a ? in it
+const REPLACEMENT_GLYPH: Glyph = [0x00, 0x00, 0xFE, 0x82, 0xBA, 0xC6, 0x86, 0x8A, 0x92, 0x92, 0x82, 0x92, 0x82, 0xFE, 0x00, 0x00];
+
+// The font is code page 437, so the upper half of it has glyphs for these characters, in order
+// Source: https://en.wikipedia.org/wiki/Code_page_437#Character_set
+const CP437_UPPER_HALF: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+// Glyphs for characters the font doesn't have, each range starts at its char and has one glyph per character after that
+static EXTRA_GLYPHS: Mutex<Vec<(char, &'static [Glyph])>> = Mutex::from(Vec::new());
+
+// Makes glyphs[0] the glyph of first, glyphs[1] the glyph of the character after it and so on
+// NOTE: Ascii always uses the font, an extra glyph for an ascii character is never used
+// Returns: None if the range would overlap an already registered one, or go past the last char
+pub fn register_glyphs(first: char, glyphs: &'static [Glyph]) -> Option<()> {
+    let last = char::from_u32((first as u32).checked_add(u32::try_from(glyphs.len()).ok()?.checked_sub(1)?)?)?;
+    let mut extra_glyphs = EXTRA_GLYPHS.lock();
+    let overlaps = extra_glyphs.iter().any(|(other_first, other_glyphs)| {
+        let other_last = *other_first as u32 + other_glyphs.len() as u32 - 1;
+        first as u32 <= other_last && *other_first as u32 <= last as u32
+    });
+    if overlaps {
+        return None;
+    }
+    extra_glyphs.push((first, glyphs));
+    Some(())
+}
+
+// Returns: The glyph to draw c with, looked up in the font for ascii, then in the registered glyphs, then in the upper half of the font, or the replacement glyph if c isn't in any of them
+pub fn find_glyph(c: char) -> Glyph {
+    let font_glyph = |index: usize| -> Glyph { FONT_8X16[index * 16..index * 16 + 16].try_into().unwrap() };
+    if c.is_ascii() {
+        return font_glyph(c as usize);
+    }
+    let extra_glyph = EXTRA_GLYPHS.lock().iter().find_map(|(first, glyphs)| glyphs.get((c as u32).checked_sub(*first as u32)? as usize).copied());
+    if let Some(glyph) = extra_glyph {
+        return glyph;
+    }
+    if let Some(index) = CP437_UPPER_HALF.iter().position(|cp437_c| *cp437_c == c) {
+        return font_glyph(0x80 + index);
+    }
+    REPLACEMENT_GLYPH
+}
+
 const FONT_8X16: [u8; 4096] = [
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0x81,
     0xA5, 0x81, 0x81, 0xBD, 0x99, 0x81, 0x81, 0x7E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0xFF, 0xDB, 0xFF, 0xFF, 0xC3,
@@ -216,30 +304,26 @@ const FONT_8X16: [u8; 4096] = [
 
 impl CharDevice for &mut dyn FrameBuffer {
     fn write_char(&mut self, x: usize, y: usize, c: char, color: Pixel) -> Option<()> {
-        if !c.is_ascii() {
-            return None;
-        }
-        let c = c as u8;
-        let x = x * 8;
-        let y = y * 16;
-        for i in y..y + 16 {
-            let line = FONT_8X16[c as usize * 16 + (i - y)];
-            for j in x..x + 8 {
-                if line & (1 << (7 - (j - x))) != 0 {
-                    self.set_pixel(j, i, color);
-                } else {
-                    self.set_pixel(j, i, Pixel { r: 0, g: 0, b: 0 });
-                }
+        let glyph = find_glyph(c);
+        let font = *FONT.lock();
+        let x = x * font.cell_width();
+        let y = y * font.cell_height();
+        for (row, line) in glyph.iter().enumerate() {
+            for col in 0..8 {
+                let pixel = if line & (1 << (7 - col)) != 0 { color } else { Pixel { r: 0, g: 0, b: 0 } };
+                let pixel_x = x + col * font.scale;
+                let pixel_y = y + row * font.scale;
+                self.fill(pixel_x, pixel_y, pixel_x + font.scale, pixel_y + font.scale, pixel);
             }
         }
         Some(())
     }
 
     fn get_rows(&self) -> usize {
-        self.get_height() / 16
+        self.get_height() / FONT.lock().cell_height()
     }
 
     fn get_cols(&self) -> usize {
-        self.get_width() / 8
+        self.get_width() / FONT.lock().cell_width()
     }
 }
@@ -0,0 +1,82 @@
+use core::arch::x86_64::_rdtsc;
+
+use crate::{
+    primitives::{LazyInitialised, Mutex},
+    virtmem::KernPointer,
+};
+
+// Note: This file defines a millisecond clock based on the cpu's time stamp counter, which is calibrated using channel 2 of the pit
+// NOTE: Assumes the tsc ticks at a constant rate, which is true for any cpu made in the last 15 or so years
+// Source: https://wiki.osdev.org/TSC, https://wiki.osdev.org/Programmable_Interval_Timer
+
+pub static CLOCK: Mutex<LazyInitialised<TSCClock>> = Mutex::from(LazyInitialised::uninit());
+
+const PIT_FREQUENCY_HZ: u64 = 1_193_182;
+const CALIBRATION_TIME_MS: u64 = 10;
+// The pit should be done after CALIBRATION_TIME_MS, so if it takes this many tries something is very wrong
+const CALIBRATION_MAX_TRIES: usize = 100_000_000;
+
+const PORT_B_CHANNEL_2_GATE: u8 = 1 << 0;
+const PORT_B_SPEAKER_ENABLE: u8 = 1 << 1;
+const PORT_B_CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+#[derive(Debug)]
+pub struct TSCClock {
+    tsc_frequency_hz: u64,
+    start_tsc: u64,
+}
+
+impl TSCClock {
+    // Returns: None if the pit never finished counting down
+    pub unsafe fn calibrate() -> Option<Self> {
+        let mut port_b = KernPointer::<u8>::from_port(0x61);
+        let mut command = KernPointer::<u8>::from_port(0x43);
+        let mut channel_2_data = KernPointer::<u8>::from_port(0x42);
+
+        // Keep the speaker quiet and stop channel 2 while setting it up
+        let old_port_b = port_b.read();
+        port_b.write(old_port_b & !(PORT_B_SPEAKER_ENABLE | PORT_B_CHANNEL_2_GATE));
+
+        // Channel 2, lobyte/hibyte access, mode 0 ( interrupt on terminal count ), binary
+        command.write(0b1011_0000);
+        let count = (PIT_FREQUENCY_HZ * CALIBRATION_TIME_MS / 1000) as u16;
+        channel_2_data.write(count as u8);
+        channel_2_data.write((count >> 8) as u8);
+
+        // Raising the gate starts the count down, once it reaches 0 the output of channel 2 goes high
+        port_b.write((old_port_b & !PORT_B_SPEAKER_ENABLE) | PORT_B_CHANNEL_2_GATE);
+        let start_tsc = _rdtsc();
+        let mut tries = 0;
+        while port_b.read() & PORT_B_CHANNEL_2_OUTPUT == 0 {
+            tries += 1;
+            if tries > CALIBRATION_MAX_TRIES {
+                port_b.write(old_port_b);
+                return None;
+            }
+        }
+        let end_tsc = _rdtsc();
+        port_b.write(old_port_b);
+
+        let tsc_frequency_hz = (end_tsc - start_tsc) * 1000 / CALIBRATION_TIME_MS;
+        if tsc_frequency_hz == 0 {
+            return None;
+        }
+        Some(Self { tsc_frequency_hz, start_tsc: end_tsc })
+    }
+
+    // Returns: The number of milliseconds since the clock was calibrated
+    pub fn now_ms(&self) -> u64 {
+        let ticks = unsafe { _rdtsc() } - self.start_tsc;
+        // NOTE: u128 so that it doesn't overflow even after the machine has been on for years
+        (u128::from(ticks) * 1000 / u128::from(self.tsc_frequency_hz)) as u64
+    }
+}
+
+// Returns: The number of milliseconds since boot ( roughly ), or None if the clock isn't available
+pub fn now_ms() -> Option<u64> {
+    let clock = CLOCK.lock();
+    if !clock.is_initialised() {
+        return None;
+    }
+    Some(clock.now_ms())
+}
@@ -1,4 +1,4 @@
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use packed_struct::prelude::*;
 
 #[derive(PrimitiveEnum_u8, Clone, Copy, PartialEq, Debug)]
@@ -235,8 +235,15 @@ pub mod elf_program_header {
         Dynamic = 2,
         Interp = 3,
         Note = 4,
+        GnuStack = 0x6474e551, // Its flags say if the stack should be executable, it has no data
     }
 
+    // Segment permission flags
+    // Source: https://refspecs.linuxfoundation.org/elf/gabi4+/ch5.pheader.html
+    pub const PF_X: u32 = 1;
+    pub const PF_W: u32 = 2;
+    pub const PF_R: u32 = 4;
+
     #[derive(PackedStruct)]
     #[packed_struct(endian = "msb")]
     pub struct ProgramHeader32BitBig {
@@ -298,6 +305,20 @@ pub mod elf_program_header {
         pub segment_virtual_size: u64,
     }
 
+    impl UniversalProgramHeader {
+        pub fn is_readable(&self) -> bool {
+            self.flags & PF_R != 0
+        }
+
+        pub fn is_writable(&self) -> bool {
+            self.flags & PF_W != 0
+        }
+
+        pub fn is_executable(&self) -> bool {
+            self.flags & PF_X != 0
+        }
+    }
+
     impl From<ProgramHeader32BitBig> for UniversalProgramHeader {
         fn from(header: ProgramHeader32BitBig) -> Self {
             Self {
@@ -351,12 +372,167 @@ pub mod elf_program_header {
     }
 }
 
+pub mod elf_dynamic {
+    use super::*;
+
+    // Dynamic section tags, only the ones needed for relocation are listed
+    // Source: https://refspecs.linuxfoundation.org/elf/gabi4+/ch5.dynamic.html#dynamic_section
+    pub const DT_NULL: u64 = 0;
+    pub const DT_PLTRELSZ: u64 = 2;
+    pub const DT_RELA: u64 = 7;
+    pub const DT_RELASZ: u64 = 8;
+    pub const DT_RELAENT: u64 = 9;
+    pub const DT_REL: u64 = 17;
+    pub const DT_PLTREL: u64 = 20;
+    pub const DT_JMPREL: u64 = 23;
+
+    // Source: https://github.com/riscv-non-isa/riscv-elf-psabi-doc/blob/master/riscv-elf.adoc#relocations
+    pub const R_RISCV_NONE: u32 = 0;
+    pub const R_RISCV_RELATIVE: u32 = 3;
+
+    #[derive(PackedStruct)]
+    #[packed_struct(endian = "lsb")]
+    pub struct DynamicEntry64BitLittle {
+        pub tag: u64,
+        pub value: u64,
+    }
+
+    #[derive(PackedStruct)]
+    #[packed_struct(endian = "lsb")]
+    pub struct Rela64BitLittle {
+        offset: u64,
+        info: u64,
+        addend: u64,
+    }
+
+    #[derive(Debug)]
+    pub struct UniversalRela {
+        pub offset: u64,
+        pub relocation_type: u32,
+        pub symbol_index: u32,
+        pub addend: i64,
+    }
+
+    impl From<Rela64BitLittle> for UniversalRela {
+        fn from(rela: Rela64BitLittle) -> Self {
+            // The symbol index is in the upper 32 bits of info and the relocation type in the lower 32 bits
+            Self {
+                offset: rela.offset,
+                relocation_type: (rela.info & 0xFFFF_FFFF) as u32,
+                symbol_index: (rela.info >> 32) as u32,
+                addend: rela.addend as i64,
+            }
+        }
+    }
+}
+
+pub mod elf_section_header {
+    use super::*;
+
+    // Source: https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.sheader.html
+    pub const SHT_SYMTAB: u32 = 2;
+    pub const SHT_NOBITS: u32 = 8;
+
+    pub const SHF_WRITE: u64 = 0x1;
+    pub const SHF_ALLOC: u64 = 0x2;
+    pub const SHF_EXECINSTR: u64 = 0x4;
+
+    #[derive(PackedStruct)]
+    #[packed_struct(endian = "lsb")]
+    pub struct SectionHeader64BitLittle {
+        pub name_offset: u32,
+        pub section_type: u32,
+        pub flags: u64,
+        pub virtual_address: u64,
+        pub file_offset: u64,
+        pub size: u64,
+        pub link: u32, // For symbol tables it's the index of the section with the names of the symbols
+        pub info: u32,
+        pub alignment: u64,
+        pub entry_size: u64,
+    }
+}
+
+pub mod elf_symbol {
+    use super::*;
+
+    // Source: https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.symtab.html
+    pub const STB_LOCAL: u8 = 0;
+    pub const STB_WEAK: u8 = 2;
+
+    pub const STT_SECTION: u8 = 3;
+    pub const STT_FILE: u8 = 4;
+
+    pub const SHN_UNDEF: u16 = 0;
+    pub const SHN_ABS: u16 = 0xFFF1;
+
+    #[derive(PackedStruct)]
+    #[packed_struct(endian = "lsb")]
+    pub struct Symbol64BitLittle {
+        pub name_offset: u32,
+        pub info: u8,
+        pub other: u8,
+        pub section_index: u16,
+        pub value: u64,
+        pub size: u64,
+    }
+
+    #[derive(Debug)]
+    pub struct UniversalSymbol {
+        pub name: String,
+        pub value: u64,
+        pub size: u64,
+        pub binding: u8,
+        pub symbol_type: u8,
+        pub section_index: u16,
+        // What nm would print for the symbol, for ex. T for a global function, d for local data or U for undefined
+        pub kind: char,
+    }
+
+    impl UniversalSymbol {
+        // NOTE: The name, and what section the symbol is in ( which decides the kind ), aren't in the symbol itself, so they have to be passed in
+        pub fn from_raw(symbol: Symbol64BitLittle, name: String, section: Option<&elf_section_header::SectionHeader64BitLittle>) -> Self {
+            use elf_section_header::*;
+            let binding = symbol.info >> 4;
+            let kind = match (symbol.section_index, section) {
+                (SHN_UNDEF, _) => 'U',
+                (SHN_ABS, _) => 'A',
+                (_, Some(section)) if section.flags & SHF_EXECINSTR != 0 => 'T',
+                (_, Some(section)) if section.section_type == SHT_NOBITS => 'B',
+                (_, Some(section)) if section.flags & SHF_WRITE != 0 => 'D',
+                (_, Some(section)) if section.flags & SHF_ALLOC != 0 => 'R',
+                _ => 'N',
+            };
+            let kind = match binding {
+                STB_WEAK if kind != 'U' => 'W',
+                STB_WEAK => 'w',
+                STB_LOCAL => kind.to_ascii_lowercase(),
+                _ => kind,
+            };
+            Self {
+                name,
+                value: symbol.value,
+                size: symbol.size,
+                binding,
+                symbol_type: symbol.info & 0xF,
+                section_index: symbol.section_index,
+                kind,
+            }
+        }
+    }
+}
+
+use elf_dynamic::*;
 use elf_header::*;
 use elf_program_header::*;
+use elf_section_header::*;
+use elf_symbol::*;
 
 pub struct ElfFile {
     pub header: UniversalElfHeader,
     pub program_headers: Vec<UniversalProgramHeader>,
+    endianess: Endianess,
+    arch_width: ArchWidth,
 }
 
 impl ElfFile {
@@ -450,7 +626,144 @@ impl ElfFile {
             vec
         };
 
-        Some(ElfFile { header: universal_header, program_headers })
+        Some(ElfFile { header: universal_header, program_headers, endianess: id.endianess, arch_width: id.arch_width })
+    }
+
+    // Translates a virtual address ( as it is in the file, not relocated ) into an offset into the file, using the loadable segments
+    pub fn virtual_address_to_file_offset(&self, virtual_address: u64) -> Option<u64> {
+        self.program_headers
+            .iter()
+            .filter(|header| header.segment_type == EnumCatchAll::from(ProgramHeaderType::Load))
+            .find(|header| {
+                (header.segment_virtual_address..header.segment_virtual_address + header.segment_file_size)
+                    .contains(&virtual_address)
+            })
+            .map(|header| virtual_address - header.segment_virtual_address + header.segment_file_offset)
+    }
+
+    // Returns: The loadable segments that contain code
+    pub fn get_executable_segments(&self) -> impl Iterator<Item = &UniversalProgramHeader> {
+        self.program_headers
+            .iter()
+            .filter(|header| header.segment_type == EnumCatchAll::from(ProgramHeaderType::Load) && header.is_executable())
+    }
+
+    // NOTE: If there is no PT_GNU_STACK header then the stack is not executable
+    pub fn has_executable_stack(&self) -> bool {
+        self.program_headers
+            .iter()
+            .find(|header| header.segment_type == EnumCatchAll::from(elf_program_header::ProgramHeaderType::GnuStack))
+            .map(|header| header.is_executable())
+            .unwrap_or(false)
+    }
+
+    // Returns: All the relocations that the dynamic segment asks for, or an empty vector if there is no dynamic segment
+    // NOTE: Only 64-bit little endian files with RELA relocations are supported, returns None for anything else
+    pub fn get_dynamic_relocations(&self, bytes: &[u8]) -> Option<Vec<UniversalRela>> {
+        use core::convert::TryInto;
+        let Some(dynamic_header) =
+            self.program_headers.iter().find(|header| header.segment_type == EnumCatchAll::from(ProgramHeaderType::Dynamic))
+        else {
+            return Some(Vec::new());
+        };
+
+        if self.endianess != Endianess::LITTLE || self.arch_width != ArchWidth::Width64Bit {
+            return None;
+        }
+
+        let ondisk_rela_size = Rela64BitLittle::packed_bytes_size(None).ok()?;
+
+        // Read the dynamic section until DT_NULL
+        let (mut rela_addr, mut rela_size, mut rela_entry_size) = (None, 0, ondisk_rela_size as u64);
+        let (mut jmprel_addr, mut jmprel_size) = (None, 0);
+        let entry_size = DynamicEntry64BitLittle::packed_bytes_size(None).ok()?;
+        let mut curr_offset = dynamic_header.segment_file_offset as usize;
+        let dynamic_end = (dynamic_header.segment_file_offset + dynamic_header.segment_file_size) as usize;
+        while curr_offset + entry_size <= dynamic_end {
+            let entry =
+                DynamicEntry64BitLittle::unpack(bytes.get(curr_offset..curr_offset + entry_size)?.try_into().ok()?).ok()?;
+            match entry.tag {
+                DT_NULL => break,
+                DT_RELA => rela_addr = Some(entry.value),
+                DT_RELASZ => rela_size = entry.value,
+                DT_RELAENT => rela_entry_size = entry.value,
+                DT_JMPREL => jmprel_addr = Some(entry.value),
+                DT_PLTRELSZ => jmprel_size = entry.value,
+                DT_PLTREL if entry.value != DT_RELA => return None,
+                DT_REL => return None, // FIXME: Support REL relocations, RISC-V only uses RELA though
+                _ => (),
+            }
+            curr_offset += entry_size;
+        }
+
+        if rela_entry_size as usize != ondisk_rela_size {
+            return None;
+        }
+
+        let mut relocations = Vec::new();
+        for (table_addr, table_size) in [(rela_addr, rela_size), (jmprel_addr, jmprel_size)] {
+            let Some(table_addr) = table_addr else {
+                continue;
+            };
+            let table_offset = self.virtual_address_to_file_offset(table_addr)? as usize;
+            for entry_offset in (table_offset..table_offset + table_size as usize).step_by(ondisk_rela_size) {
+                let rela =
+                    Rela64BitLittle::unpack(bytes.get(entry_offset..entry_offset + ondisk_rela_size)?.try_into().ok()?).ok()?;
+                relocations.push(rela.into());
+            }
+        }
+        Some(relocations)
+    }
+
+    // Returns: The symbols in the symbol table ( .symtab ), or an empty vector if the file has none ( for ex. because it was stripped )
+    // NOTE: Like nm, symbols for sections and files are skipped, since they aren't really symbols
+    // NOTE: Only 64-bit little endian files are supported, returns None for anything else
+    pub fn get_symbols(&self, bytes: &[u8]) -> Option<Vec<UniversalSymbol>> {
+        use core::convert::TryInto;
+        if self.endianess != Endianess::LITTLE || self.arch_width != ArchWidth::Width64Bit {
+            return None;
+        }
+
+        // The section headers are only needed for this, so they are only parsed here instead of in from_bytes
+        let section_header_size = SectionHeader64BitLittle::packed_bytes_size(None).ok()?;
+        if self.header.section_header_table_len != 0 && usize::from(self.header.section_header_table_entry_size) != section_header_size {
+            return None;
+        }
+        let mut section_headers = Vec::new();
+        for index in 0..usize::from(self.header.section_header_table_len) {
+            let offset = self.header.section_header_table_offset as usize + index * section_header_size;
+            section_headers.push(
+                SectionHeader64BitLittle::unpack(bytes.get(offset..offset + section_header_size)?.try_into().ok()?).ok()?,
+            );
+        }
+
+        let Some(symbol_table) = section_headers.iter().find(|header| header.section_type == SHT_SYMTAB) else {
+            return Some(Vec::new());
+        };
+        let string_table = section_headers.get(symbol_table.link as usize)?;
+        let strings =
+            bytes.get(string_table.file_offset as usize..(string_table.file_offset + string_table.size) as usize)?;
+
+        let symbol_size = Symbol64BitLittle::packed_bytes_size(None).ok()?;
+        let symbols_start = symbol_table.file_offset as usize;
+        let symbols_end = symbols_start + symbol_table.size as usize;
+        let mut symbols = Vec::new();
+        // NOTE: The first symbol is always the null symbol
+        for offset in (symbols_start..symbols_end).step_by(symbol_size).skip(1) {
+            let symbol = Symbol64BitLittle::unpack(bytes.get(offset..offset + symbol_size)?.try_into().ok()?).ok()?;
+            let symbol_type = symbol.info & 0xF;
+            if symbol_type == STT_SECTION || symbol_type == STT_FILE {
+                continue;
+            }
+
+            // Names are null terminated
+            let name = strings.get(symbol.name_offset as usize..)?;
+            let name = &name[..name.iter().position(|c| *c == 0)?];
+            let name = String::from_utf8_lossy(name).into_owned();
+            let section = section_headers.get(usize::from(symbol.section_index));
+            symbols.push(UniversalSymbol::from_raw(symbol, name, section));
+        }
+        Some(symbols)
     }
 
     fn get_ondisk_identification_size() -> usize {
@@ -1,4 +1,5 @@
-use core::fmt::Debug;
+use alloc::{format, string::String};
+use core::fmt::{Debug, Display};
 use packed_struct::prelude::*;
 
 pub trait EmulatorMemory {
@@ -8,19 +9,20 @@ pub trait EmulatorMemory {
     // address in any endianness returns the stored value." (RISC-V Volume I, section 2.6)
 
     // NE - native endian, LE - little endian
-    fn read_u8_ne(&self, addr: u64) -> u8;
-    fn write_u8_ne(&mut self, addr: u64, val: u8);
+    // Returns: None if the access is out of range ( for ex. the address isn't mapped ), the cpu turns that into an access fault
+    fn read_u8_ne(&self, addr: u64) -> Option<u8>;
+    fn write_u8_ne(&mut self, addr: u64, val: u8) -> Option<()>;
 
-    fn read_u16_ne(&self, addr: u64) -> u16;
-    fn write_u16_ne(&mut self, addr: u64, val: u16);
+    fn read_u16_ne(&self, addr: u64) -> Option<u16>;
+    fn write_u16_ne(&mut self, addr: u64, val: u16) -> Option<()>;
 
-    fn read_u32_ne(&self, addr: u64) -> u32;
-    fn write_u32_ne(&mut self, addr: u64, val: u32);
+    fn read_u32_ne(&self, addr: u64) -> Option<u32>;
+    fn write_u32_ne(&mut self, addr: u64, val: u32) -> Option<()>;
 
-    fn read_u64_ne(&self, addr: u64) -> u64;
-    fn write_u64_ne(&mut self, addr: u64, val: u64);
+    fn read_u64_ne(&self, addr: u64) -> Option<u64>;
+    fn write_u64_ne(&mut self, addr: u64, val: u64) -> Option<()>;
 
-    fn read_u32_le(&self, addr: u64) -> u32; // For reading instructions
+    fn read_u32_le(&self, addr: u64) -> Option<u32>; // For reading instructions
                                              // Source: RISC-V Volume I 20191213, Section 1.5, in a footnote: "We have to fix the order in which instruction parcels are stored in memory, independent
                                              // of memory system endianness, to ensure that the length-encoding bits always appear first in
                                              // halfword address order"
@@ -31,6 +33,82 @@ pub trait EmulatorMemory {
                                              // However because it's little endian, then it is: 85 40 37 01 37 01
                                              // And it is no longer ambiguous
                                              // As 85 40 37 01 = 0x01374085, cannot be lui
+
+    // For reading instructions one 16-bit parcel at a time, so that a compressed instruction at the very end of memory can be read
+    fn read_u16_le(&self, addr: u64) -> Option<u16> {
+        Some(u16::from_le_bytes([self.read_u8_ne(addr)?, self.read_u8_ne(addr.checked_add(1)?)?]))
+    }
+
+    // Used to enforce memory protection, memory that doesn't have any permissions can just allow everything
+    fn is_readable(&self, _addr: u64, _len: usize) -> bool {
+        true
+    }
+
+    fn is_writable(&self, _addr: u64, _len: usize) -> bool {
+        true
+    }
+
+    fn is_executable(&self, _addr: u64) -> bool {
+        true
+    }
+}
+
+// Checks that mem behaves like the trait says it has to, meant for validating new memory implementations
+// NOTE: The 8 bytes at addr must be mapped and writable, they are overwritten
+// Returns: None if a check failed
+pub fn verify_emulator_memory<M: EmulatorMemory>(mem: &mut M, addr: u64) -> Option<()> {
+    // Byte-address invariance, every byte that's stored has to be read back unchanged
+    let bytes: [u8; 8] = [0x13, 0x05, 0xA0, 0x02, 0x85, 0x40, 0x37, 0x01];
+    for (i, byte) in bytes.iter().enumerate() {
+        mem.write_u8_ne(addr + i as u64, *byte)?;
+    }
+    for (i, byte) in bytes.iter().enumerate() {
+        if mem.read_u8_ne(addr + i as u64)? != *byte {
+            return None;
+        }
+    }
+
+    // Instructions are always little endian, no matter the endianness of the memory, and compressed ones make them only 2 byte aligned
+    for offset in [0, 2, 4] {
+        let expected = u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        if mem.read_u32_le(addr + offset as u64)? != expected {
+            return None;
+        }
+    }
+    for offset in [0, 2, 4, 6] {
+        if mem.read_u16_le(addr + offset as u64)? != u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) {
+            return None;
+        }
+    }
+
+    // Bigger accesses don't have to be little endian, but what is stored has to be what's read back at the same size
+    mem.write_u16_ne(addr, 0xBEEF)?;
+    if mem.read_u16_ne(addr)? != 0xBEEF {
+        return None;
+    }
+    mem.write_u32_ne(addr, 0xDEADBEEF)?;
+    if mem.read_u32_ne(addr)? != 0xDEADBEEF {
+        return None;
+    }
+    mem.write_u64_ne(addr, 0x0123_4567_89AB_CDEF)?;
+    if mem.read_u64_ne(addr)? != 0x0123_4567_89AB_CDEF {
+        return None;
+    }
+
+    // Though they do all have to use the same endianness
+    let mut stored_bytes = [0u8; 8];
+    for (i, byte) in stored_bytes.iter_mut().enumerate() {
+        *byte = mem.read_u8_ne(addr + i as u64)?;
+    }
+    let is_little_endian = stored_bytes == 0x0123_4567_89AB_CDEFu64.to_le_bytes();
+    if !is_little_endian && stored_bytes != 0x0123_4567_89AB_CDEFu64.to_be_bytes() {
+        return None;
+    }
+    let expected = if is_little_endian { 0x89AB_CDEF } else { 0x0123_4567 };
+    if mem.read_u32_ne(addr)? != expected {
+        return None;
+    }
+    Some(())
 }
 
 mod riscv_instruction {
@@ -713,7 +791,8 @@ mod riscv_instruction {
     impl RiscvCJTypeInstruction {
         pub fn parse_imm(&self) -> Option<u32> {
             match (self.funct3, self.opcode) {
-                (0b101, RiscvCompressedOpcode::C1) => {
+                // NOTE: 0b001 is C.JAL, which only exists in RV32
+                (0b101, RiscvCompressedOpcode::C1) | (0b001, RiscvCompressedOpcode::C1) => {
                     // The offset is sign-extended
                     // RISC-V Volume I (section 16.4)
                     let imm: u16 = (self.imm_bit11 as u16) << 15
@@ -778,6 +857,308 @@ where
     sign_extend::<T, u64>(val)
 }
 
+// Returns: The instruction in assembly syntax, for ex. "addi x1, x2, 5", or None if it isn't an instruction the emulator knows
+// NOTE: Registers are always shown as x0-x31 and branch/jump targets as offsets from the instruction, there are no pseudo-instructions
+pub fn disassemble(inst: u32) -> Option<String> {
+    let opcode = RiscvOpcode::from_primitive((inst & 0b111_1111) as u8)?;
+    let bytes = inst.to_be_bytes();
+    Some(match opcode.get_type() {
+        RiscvInstType::RType => {
+            let inst = RiscvRTypeInstruction::unpack(&bytes).ok()?;
+            let name = match (inst.opcode, inst.funct3, inst.funct7) {
+                (RiscvOpcode::OP, 0b000, 0b0000000) => "add",
+                (RiscvOpcode::OP, 0b000, 0b0100000) => "sub",
+                (RiscvOpcode::OP, 0b001, 0b0000000) => "sll",
+                (RiscvOpcode::OP, 0b010, 0b0000000) => "slt",
+                (RiscvOpcode::OP, 0b011, 0b0000000) => "sltu",
+                (RiscvOpcode::OP, 0b100, 0b0000000) => "xor",
+                (RiscvOpcode::OP, 0b101, 0b0000000) => "srl",
+                (RiscvOpcode::OP, 0b101, 0b0100000) => "sra",
+                (RiscvOpcode::OP, 0b110, 0b0000000) => "or",
+                (RiscvOpcode::OP, 0b111, 0b0000000) => "and",
+                (RiscvOpcode::OP, 0b000, 0b0000001) => "mul",
+                (RiscvOpcode::OP, 0b001, 0b0000001) => "mulh",
+                (RiscvOpcode::OP, 0b010, 0b0000001) => "mulhsu",
+                (RiscvOpcode::OP, 0b011, 0b0000001) => "mulhu",
+                (RiscvOpcode::OP, 0b100, 0b0000001) => "div",
+                (RiscvOpcode::OP, 0b101, 0b0000001) => "divu",
+                (RiscvOpcode::OP, 0b110, 0b0000001) => "rem",
+                (RiscvOpcode::OP, 0b111, 0b0000001) => "remu",
+                (RiscvOpcode::OP32, 0b000, 0b0000000) => "addw",
+                (RiscvOpcode::OP32, 0b000, 0b0100000) => "subw",
+                (RiscvOpcode::OP32, 0b001, 0b0000000) => "sllw",
+                (RiscvOpcode::OP32, 0b101, 0b0000000) => "srlw",
+                (RiscvOpcode::OP32, 0b101, 0b0100000) => "sraw",
+                (RiscvOpcode::OP32, 0b000, 0b0000001) => "mulw",
+                (RiscvOpcode::OP32, 0b100, 0b0000001) => "divw",
+                (RiscvOpcode::OP32, 0b101, 0b0000001) => "divuw",
+                (RiscvOpcode::OP32, 0b110, 0b0000001) => "remw",
+                (RiscvOpcode::OP32, 0b111, 0b0000001) => "remuw",
+                _ => return None,
+            };
+            format!("{} x{}, x{}, x{}", name, inst.rd, inst.rs1, inst.rs2)
+        }
+
+        RiscvInstType::IType => {
+            let inst = RiscvITypeInstruction::unpack(&bytes).ok()?;
+            let imm = inst.parse_imm() as i32;
+            match (inst.opcode, inst.funct3) {
+                (RiscvOpcode::LOAD, funct3) => {
+                    let name = match funct3 {
+                        0b000 => "lb",
+                        0b001 => "lh",
+                        0b010 => "lw",
+                        0b011 => "ld",
+                        0b100 => "lbu",
+                        0b101 => "lhu",
+                        0b110 => "lwu",
+                        _ => return None,
+                    };
+                    format!("{} x{}, {}(x{})", name, inst.rd, imm, inst.rs1)
+                }
+                (RiscvOpcode::JALR, 0b000) => format!("jalr x{}, {}(x{})", inst.rd, imm, inst.rs1),
+
+                // The shift amount is in the lower bits of the immediate, the rest tells apart logical and arithmetic shifts, like in execute_itype_inst
+                (RiscvOpcode::OPIMM, 0b001) => format!("slli x{}, x{}, {}", inst.rd, inst.rs1, imm & 0b11_1111),
+                (RiscvOpcode::OPIMM, 0b101) => {
+                    let name = if imm & !0b11_1111 != 0 { "srai" } else { "srli" };
+                    format!("{} x{}, x{}, {}", name, inst.rd, inst.rs1, imm & 0b11_1111)
+                }
+                (RiscvOpcode::OPIMM32, 0b001) => format!("slliw x{}, x{}, {}", inst.rd, inst.rs1, imm & 0b1_1111),
+                (RiscvOpcode::OPIMM32, 0b101) => {
+                    let name = if imm & !0b1_1111 != 0 { "sraiw" } else { "srliw" };
+                    format!("{} x{}, x{}, {}", name, inst.rd, inst.rs1, imm & 0b1_1111)
+                }
+
+                (RiscvOpcode::OPIMM, funct3) => {
+                    let name = match funct3 {
+                        0b000 => "addi",
+                        0b010 => "slti",
+                        0b011 => "sltiu",
+                        0b100 => "xori",
+                        0b110 => "ori",
+                        0b111 => "andi",
+                        _ => return None,
+                    };
+                    format!("{} x{}, x{}, {}", name, inst.rd, inst.rs1, imm)
+                }
+                (RiscvOpcode::OPIMM32, 0b000) => format!("addiw x{}, x{}, {}", inst.rd, inst.rs1, imm),
+
+                (RiscvOpcode::SYSTEM, 0b000) if imm == 0 => String::from("ecall"),
+                (RiscvOpcode::SYSTEM, 0b000) if imm == 1 => String::from("ebreak"),
+                (RiscvOpcode::MISCMEM, 0b000) => String::from("fence"),
+                _ => return None,
+            }
+        }
+
+        RiscvInstType::SType => {
+            let inst = RiscvSTypeInstruction::unpack(&bytes).ok()?;
+            let name = match inst.funct3 {
+                0b000 => "sb",
+                0b001 => "sh",
+                0b010 => "sw",
+                0b011 => "sd",
+                _ => return None,
+            };
+            format!("{} x{}, {}(x{})", name, inst.rs2, inst.parse_imm() as i32, inst.rs1)
+        }
+
+        RiscvInstType::BType => {
+            let inst = RiscvBTypeInstruction::unpack(&bytes).ok()?;
+            let name = match inst.funct3 {
+                0b000 => "beq",
+                0b001 => "bne",
+                0b100 => "blt",
+                0b101 => "bge",
+                0b110 => "bltu",
+                0b111 => "bgeu",
+                _ => return None,
+            };
+            format!("{} x{}, x{}, {}", name, inst.rs1, inst.rs2, inst.parse_imm() as i32)
+        }
+
+        RiscvInstType::UType => {
+            let inst = RiscvUTypeInstruction::unpack(&bytes).ok()?;
+            let name = if inst.opcode == RiscvOpcode::LUI { "lui" } else { "auipc" };
+            // NOTE: Like other disassemblers, this shows the upper 20 bits, not the value that ends up in the register
+            format!("{} x{}, 0x{:x}", name, inst.rd, inst.parse_imm() >> 12)
+        }
+
+        RiscvInstType::JType => {
+            let inst = RiscvJTypeInstruction::unpack(&bytes).ok()?;
+            format!("jal x{}, {}", inst.rd, inst.parse_imm() as i32)
+        }
+    })
+}
+
+// Returns: The compressed instruction in assembly syntax, for ex. "c.jr x1", or None if it isn't an instruction the emulator knows
+// NOTE: Only knows the compressed instructions that tick knows how to expand
+pub fn disassemble_compressed(inst: u16) -> Option<String> {
+    let bytes = inst.to_be_bytes();
+    Some(match get_compressed_instruction_type(inst)? {
+        RiscvCompressedInstType::CRType => {
+            let inst = RiscvCRTypeInstruction::unpack(&bytes).ok()?;
+            match (inst.funct4, inst.opcode, inst.rd_rs1, inst.rs2) {
+                (0b1000, RiscvCompressedOpcode::C2, rs1, 0) => format!("c.jr x{}", rs1),
+                (0b1000, RiscvCompressedOpcode::C2, rd, rs2) => format!("c.mv x{}, x{}", rd, rs2),
+                (0b1001, RiscvCompressedOpcode::C2, 0, 0) => String::from("c.ebreak"),
+                (0b1001, RiscvCompressedOpcode::C2, rs1, 0) => format!("c.jalr x{}", rs1),
+                (0b1001, RiscvCompressedOpcode::C2, rd_rs1, rs2) => format!("c.add x{}, x{}", rd_rs1, rs2),
+                _ => return None,
+            }
+        }
+
+        RiscvCompressedInstType::CIType => {
+            let inst = RiscvCITypeInstruction::unpack(&bytes).ok()?;
+            let imm = inst.parse_imm()?;
+            match (inst.funct3, inst.opcode) {
+                (0b000, RiscvCompressedOpcode::C2) => format!("c.slli x{}, {}", inst.rd_rs1, imm),
+                (0b010, RiscvCompressedOpcode::C2) => format!("c.lwsp x{}, {}(x2)", inst.rd_rs1, imm),
+                (0b011, RiscvCompressedOpcode::C2) => format!("c.ldsp x{}, {}(x2)", inst.rd_rs1, imm),
+                (0b010, RiscvCompressedOpcode::C1) => format!("c.li x{}, {}", inst.rd_rs1, imm as i32),
+                (0b011, RiscvCompressedOpcode::C1) if inst.rd_rs1 == 2 => format!("c.addi16sp x2, {}", imm as i32),
+                (0b011, RiscvCompressedOpcode::C1) => format!("c.lui x{}, 0x{:x}", inst.rd_rs1, (imm >> 12) & 0xF_FFFF),
+                (0b000, RiscvCompressedOpcode::C1) => format!("c.addi x{}, {}", inst.rd_rs1, imm as i32),
+                (0b001, RiscvCompressedOpcode::C1) => format!("c.addiw x{}, {}", inst.rd_rs1, imm as i32),
+                _ => return None,
+            }
+        }
+
+        RiscvCompressedInstType::CSSType => {
+            let inst = RiscvCSSTypeInstruction::unpack(&bytes).ok()?;
+            let name = if inst.funct3 == 0b110 { "c.swsp" } else { "c.sdsp" };
+            format!("{} x{}, {}(x2)", name, inst.rs2, inst.parse_imm()?)
+        }
+
+        RiscvCompressedInstType::CIWType => {
+            let inst = RiscvCIWTypeInstruction::unpack(&bytes).ok()?;
+            format!("c.addi4spn x{}, x2, {}", inst.parse_rd(), inst.parse_imm()?)
+        }
+
+        RiscvCompressedInstType::CLType => {
+            let inst = RiscvCLTypeInstruction::unpack(&bytes).ok()?;
+            let name = match inst.funct3 {
+                0b010 => "c.lw",
+                0b011 => "c.ld",
+                _ => return None,
+            };
+            format!("{} x{}, {}(x{})", name, inst.parse_rd(), inst.parse_imm()?, inst.parse_rs1())
+        }
+
+        RiscvCompressedInstType::CSType => {
+            let inst = RiscvCSTypeInstruction::unpack(&bytes).ok()?;
+            let name = match inst.funct3 {
+                0b110 => "c.sw",
+                0b111 => "c.sd",
+                _ => return None,
+            };
+            format!("{} x{}, {}(x{})", name, inst.parse_rs2(), inst.parse_imm()?, inst.parse_rs1())
+        }
+
+        RiscvCompressedInstType::CAType => {
+            let inst = RiscvCATypeInstruction::unpack(&bytes).ok()?;
+            let name = match (inst.funct6, inst.funct2) {
+                (0b100011, 0b00) => "c.sub",
+                (0b100011, 0b01) => "c.xor",
+                (0b100011, 0b10) => "c.or",
+                (0b100011, 0b11) => "c.and",
+                (0b100111, 0b00) => "c.subw",
+                (0b100111, 0b01) => "c.addw",
+                _ => return None,
+            };
+            format!("{} x{}, x{}", name, inst.parse_rd_rs1(), inst.parse_rs2())
+        }
+
+        RiscvCompressedInstType::CBType => {
+            let inst = RiscvCBTypeInstruction::unpack(&bytes).ok()?;
+            let imm = inst.parse_imm()?;
+            match (inst.funct3, inst.parse_funct2()) {
+                (0b110, _) => format!("c.beqz x{}, {}", inst.parse_rd_rs1(), imm as i32),
+                (0b111, _) => format!("c.bnez x{}, {}", inst.parse_rd_rs1(), imm as i32),
+                (0b100, 0b00) => format!("c.srli x{}, {}", inst.parse_rd_rs1(), imm),
+                (0b100, 0b01) => format!("c.srai x{}, {}", inst.parse_rd_rs1(), imm),
+                (0b100, 0b10) => format!("c.andi x{}, {}", inst.parse_rd_rs1(), imm as i32),
+                _ => return None,
+            }
+        }
+
+        RiscvCompressedInstType::CJType => {
+            let inst = RiscvCJTypeInstruction::unpack(&bytes).ok()?;
+            format!("c.j {}", inst.parse_imm()? as i32)
+        }
+    })
+}
+
+
+// Counts how many instructions of each type were executed, grouped by major opcode
+// NOTE: Compressed instructions are counted under the opcode of the instruction they expand to, as well as in compressed
+#[derive(Debug, Clone, Default)]
+pub struct InstructionProfile {
+    pub op: u64,
+    pub op32: u64,
+    pub opimm: u64,
+    pub opimm32: u64,
+    pub lui: u64,
+    pub auipc: u64,
+    pub jal: u64,
+    pub jalr: u64,
+    pub branch: u64,
+    pub load: u64,
+    pub store: u64,
+    pub miscmem: u64,
+    pub system: u64,
+    pub compressed: u64,
+}
+
+impl InstructionProfile {
+    fn record(&mut self, opcode: RiscvOpcode, is_compressed: bool) {
+        let counter = match opcode {
+            RiscvOpcode::OP => &mut self.op,
+            RiscvOpcode::OP32 => &mut self.op32,
+            RiscvOpcode::OPIMM => &mut self.opimm,
+            RiscvOpcode::OPIMM32 => &mut self.opimm32,
+            RiscvOpcode::LUI => &mut self.lui,
+            RiscvOpcode::AUIPC => &mut self.auipc,
+            RiscvOpcode::JAL => &mut self.jal,
+            RiscvOpcode::JALR => &mut self.jalr,
+            RiscvOpcode::BRANCH => &mut self.branch,
+            RiscvOpcode::LOAD => &mut self.load,
+            RiscvOpcode::STORE => &mut self.store,
+            RiscvOpcode::MISCMEM => &mut self.miscmem,
+            RiscvOpcode::SYSTEM => &mut self.system,
+        };
+        *counter += 1;
+
+        if is_compressed {
+            self.compressed += 1;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.op
+            + self.op32
+            + self.opimm
+            + self.opimm32
+            + self.lui
+            + self.auipc
+            + self.jal
+            + self.jalr
+            + self.branch
+            + self.load
+            + self.store
+            + self.miscmem
+            + self.system
+    }
+}
+
+impl Display for InstructionProfile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "OP: {}, OP-32: {}, OP-IMM: {}, OP-IMM-32: {}", self.op, self.op32, self.opimm, self.opimm32)?;
+        writeln!(f, "LUI: {}, AUIPC: {}, JAL: {}, JALR: {}, BRANCH: {}", self.lui, self.auipc, self.jal, self.jalr, self.branch)?;
+        writeln!(f, "LOAD: {}, STORE: {}, MISC-MEM: {}, SYSTEM: {}", self.load, self.store, self.miscmem, self.system)?;
+        write!(f, "Total: {} instructions, of which {} were compressed", self.total(), self.compressed)
+    }
+}
 
 // FIXME: Currently some illegal instructions don't halt the cpu, instead having the effect of a nop
 
@@ -790,6 +1171,63 @@ pub enum CpuAction {
     RAISE_EXCEPTION
 }
 
+// Exceptions that are caused by the program accessing memory it shouldn't ( or looping forever ), the program counter is left at the faulting instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum CpuException {
+    INSTRUCTION_ACCESS_FAULT { addr: u64 }, // Fetching an instruction from memory that isn't executable
+    LOAD_ACCESS_FAULT { addr: u64 },        // Loading from memory that isn't mapped or isn't readable
+    STORE_ACCESS_FAULT { addr: u64 },       // Storing to memory that isn't writable
+    LOAD_ADDRESS_MISALIGNED { addr: u64 },  // Loading from an address that isn't a multiple of the size, when misaligned accesses fault
+    STORE_ADDRESS_MISALIGNED { addr: u64 }, // Storing to an address that isn't a multiple of the size, when misaligned accesses fault
+    TIGHT_LOOP { addr: u64 },               // Jumping or branching to the same instruction, which would loop forever, when halting on tight loops
+}
+
+// Why run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum RunResult {
+    BUDGET_EXHAUSTED,      // All the steps were executed without stopping
+    HALTED,                // The program stopped running, for ex. it exited or is waiting for something, the state of the process says which
+    BREAKPOINT,            // An EBREAK was executed, the program counter is after it, so running again continues the program
+    TRAPPED(CpuException), // The program counter is left at the faulting instruction
+    TIGHT_LOOP,            // The program jumped to the jump itself, the program counter is left at it
+    ILLEGAL_INSTRUCTION,   // Ticking failed without an exception
+}
+
+// What the cpu does with loads and stores whose address isn't a multiple of their size
+// Source: RISC-V Volume I, section 2.6: "misaligned accesses ... may be handled in hardware or raise an address-misaligned exception"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum MisalignedAccessHandling {
+    EMULATE, // Split the access into byte sized ones, this also makes accesses that cross a region boundary work
+    FAULT,
+}
+
+// Which instructions that go back to themselves make the cpu halt with a TIGHT_LOOP exception, instead of running them forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum TightLoopDetection {
+    NONE,
+    JUMPS,              // JAL and JALR
+    JUMPS_AND_BRANCHES, // NOTE: Not the default, halting on a taken branch to itself is new behaviour programs may not expect, for ex. ones that wait there for a signal
+}
+
+// The width of the registers and of the address space
+// NOTE: In RV32 mode registers still have 64 bits of storage, but only the lower 32 are ever used, the rest are always 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xlen {
+    RV32,
+    RV64,
+}
+
+// Everything a program needs to continue from where it was, used to put it back after a signal handler runs
+#[derive(Debug, Clone)]
+pub struct CpuContext {
+    program_counter: u64,
+    registers: [u64; 31],
+}
+
 #[derive(Clone)]
 pub struct Riscv64Cpu<MemType>
 where
@@ -799,6 +1237,12 @@ where
     registers: [u64; 31],
     pub memory: MemType,
     syscall: fn(&mut Self, &mut ProcessData) -> CpuAction,
+    profile: Option<InstructionProfile>, // None when profiling is disabled
+    exception: Option<CpuException>,     // The memory exception that made the last tick fail, if any
+    misaligned_accesses: MisalignedAccessHandling,
+    xlen: Xlen,
+    tight_loop_detection: TightLoopDetection,
+    executed_breakpoint: bool, // Set when an EBREAK is executed, so that run can stop after it
 }
 
 impl<MemType> Debug for Riscv64Cpu<MemType>
@@ -809,6 +1253,12 @@ where
         f.debug_struct("Riscv64Cpu")
             .field("program_counter", &self.program_counter)
             .field("registers", &self.registers)
+            .field("profile", &self.profile)
+            .field("exception", &self.exception)
+            .field("misaligned_accesses", &self.misaligned_accesses)
+            .field("xlen", &self.xlen)
+            .field("tight_loop_detection", &self.tight_loop_detection)
+            .field("executed_breakpoint", &self.executed_breakpoint)
             .finish()
     }
 }
@@ -818,7 +1268,168 @@ where
     MemType: EmulatorMemory,
 {
     pub fn from(mem: MemType, start_address: u64, syscall: fn(&mut Self, &mut ProcessData) -> CpuAction) -> Riscv64Cpu<MemType> {
-        Riscv64Cpu { program_counter: start_address, registers: [0u64; 31], memory: mem, syscall }
+        Riscv64Cpu { program_counter: start_address, registers: [0u64; 31], memory: mem, syscall, profile: None, exception: None, misaligned_accesses: MisalignedAccessHandling::EMULATE, xlen: Xlen::RV64, tight_loop_detection: TightLoopDetection::JUMPS, executed_breakpoint: false }
+    }
+
+    // NOTE: Resets the counts if profiling was already enabled
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(InstructionProfile::default());
+    }
+
+    pub fn disable_profiling(&mut self) {
+        self.profile = None;
+    }
+
+    // Returns None if profiling is disabled
+    pub fn instruction_profile(&self) -> Option<&InstructionProfile> {
+        self.profile.as_ref()
+    }
+
+    // Returns: The memory exception that made the last tick fail, if there was one
+    // NOTE: A tick can also fail because of other reasons ( for ex. an illegal instruction ), in which case this returns None
+    pub fn take_exception(&mut self) -> Option<CpuException> {
+        self.exception.take()
+    }
+
+    // NOTE: Misaligned accesses are emulated by default, as some real programs rely on them working
+    pub fn set_misaligned_access_handling(&mut self, handling: MisalignedAccessHandling) {
+        self.misaligned_accesses = handling;
+    }
+
+    // NOTE: Jumps are detected by default, as a program that spins forever would never give the shell back, branches have to be opted into
+    pub fn set_tight_loop_detection(&mut self, detection: TightLoopDetection) {
+        self.tight_loop_detection = detection;
+    }
+
+    // Returns: true if jumping ( or branching, if is_branch ) to new_program_counter would execute the current instruction forever, and the cpu should halt instead
+    fn is_tight_loop(&self, new_program_counter: u64, is_branch: bool) -> bool {
+        let is_detected = match self.tight_loop_detection {
+            TightLoopDetection::NONE => false,
+            TightLoopDetection::JUMPS => !is_branch,
+            TightLoopDetection::JUMPS_AND_BRANCHES => true,
+        };
+        is_detected && new_program_counter & self.xlen_mask() == self.program_counter
+    }
+
+    // NOTE: Doesn't change the registers, so it should be set before the program starts running
+    // FIXME: Programs are always loaded in RV64 mode, as the syscall interface assumes 64-bit pointers
+    pub fn set_xlen(&mut self, xlen: Xlen) {
+        self.xlen = xlen;
+    }
+
+    // Returns: A mask of the bits that registers and addresses have
+    fn xlen_mask(&self) -> u64 {
+        match self.xlen {
+            Xlen::RV32 => u64::from(u32::MAX),
+            Xlen::RV64 => u64::MAX,
+        }
+    }
+
+    fn xlen_bits(&self) -> u32 {
+        match self.xlen {
+            Xlen::RV32 => 32,
+            Xlen::RV64 => 64,
+        }
+    }
+
+    // Shift amounts only use the lower log2(XLEN) bits of the register or immediate
+    fn shamt_mask(&self) -> u64 {
+        u64::from(self.xlen_bits() - 1)
+    }
+
+    // Returns: The register interpreted as a signed XLEN-bit number
+    fn read_reg_signed(&self, reg_n: u8) -> i64 {
+        match self.xlen {
+            Xlen::RV32 => i64::from(self.read_reg(reg_n) as u32 as i32),
+            Xlen::RV64 => self.read_reg(reg_n) as i64,
+        }
+    }
+
+    // Returns: true if the instruction only exists in RV64, a.k.a the W instructions and the 64-bit loads and stores
+    fn is_rv64_only_instruction(instruction: u32) -> bool {
+        let funct3 = (instruction >> 12) & 0b111;
+        match RiscvOpcode::from_primitive((instruction & 0b111_1111) as u8) {
+            Some(RiscvOpcode::OP32) | Some(RiscvOpcode::OPIMM32) => true,
+            Some(RiscvOpcode::LOAD) => funct3 == 0b011 /* LD */ || funct3 == 0b110, /* LWU */
+            Some(RiscvOpcode::STORE) => funct3 == 0b011, /* SD */
+            _ => false,
+        }
+    }
+
+    // Returns: The size bytes at addr zero-extended to 64 bits, or None if the cpu raised an exception
+    fn load(&mut self, addr: u64, size: usize) -> Option<u64> {
+        let addr = addr & self.xlen_mask();
+        if addr % size as u64 != 0 && self.misaligned_accesses == MisalignedAccessHandling::FAULT {
+            self.exception = Some(CpuException::LOAD_ADDRESS_MISALIGNED { addr });
+            return None;
+        }
+        if !self.memory.is_readable(addr, size) {
+            self.exception = Some(CpuException::LOAD_ACCESS_FAULT { addr });
+            return None;
+        }
+        if addr % size as u64 != 0 {
+            // NOTE: RISC-V is little endian, so the last byte is the most significant one
+            let mut val = 0u64;
+            for offset in (0..size as u64).rev() {
+                let byte_addr = addr.wrapping_add(offset) & self.xlen_mask();
+                let Some(byte) = self.memory.read_u8_ne(byte_addr) else {
+                    self.exception = Some(CpuException::LOAD_ACCESS_FAULT { addr: byte_addr });
+                    return None;
+                };
+                val = (val << 8) | u64::from(byte);
+            }
+            return Some(val);
+        }
+
+        let val = match size {
+            1 => self.memory.read_u8_ne(addr).map(u64::from),
+            2 => self.memory.read_u16_ne(addr).map(u64::from),
+            4 => self.memory.read_u32_ne(addr).map(u64::from),
+            8 => self.memory.read_u64_ne(addr),
+            _ => None,
+        };
+        if val.is_none() {
+            self.exception = Some(CpuException::LOAD_ACCESS_FAULT { addr });
+        }
+        val
+    }
+
+    // Stores the lowest size bytes of val at addr
+    // Returns: None if the cpu raised an exception
+    fn store(&mut self, addr: u64, size: usize, val: u64) -> Option<()> {
+        let addr = addr & self.xlen_mask();
+        if addr % size as u64 != 0 {
+            if self.misaligned_accesses == MisalignedAccessHandling::FAULT {
+                self.exception = Some(CpuException::STORE_ADDRESS_MISALIGNED { addr });
+                return None;
+            }
+            for (offset, byte) in val.to_le_bytes().iter().take(size).enumerate() {
+                let byte_addr = addr.wrapping_add(offset as u64) & self.xlen_mask();
+                if self.memory.write_u8_ne(byte_addr, *byte).is_none() {
+                    self.exception = Some(CpuException::STORE_ACCESS_FAULT { addr: byte_addr });
+                    return None;
+                }
+            }
+            return Some(());
+        }
+
+        let stored = match size {
+            1 => self.memory.write_u8_ne(addr, val as u8),
+            2 => self.memory.write_u16_ne(addr, val as u16),
+            4 => self.memory.write_u32_ne(addr, val as u32),
+            8 => self.memory.write_u64_ne(addr, val),
+            _ => None,
+        };
+        if stored.is_none() {
+            self.exception = Some(CpuException::STORE_ACCESS_FAULT { addr });
+        }
+        stored
+    }
+
+    // Remembers why the tick failed, so that it can be taken later
+    fn raise_exception(&mut self, exception: CpuException) -> CpuAction {
+        self.exception = Some(exception);
+        CpuAction::RAISE_EXCEPTION
     }
 
     pub fn reset_registers(&mut self, start_address: u64) {
@@ -826,9 +1437,24 @@ where
         self.registers = [0u64; 31];
     }
 
+    pub fn save_context(&self) -> CpuContext {
+        CpuContext { program_counter: self.program_counter, registers: self.registers }
+    }
+
+    pub fn restore_context(&mut self, context: CpuContext) {
+        self.program_counter = context.program_counter;
+        self.registers = context.registers;
+    }
+
+    // NOTE: Like a jump, the next instruction executed is the one at new_program_counter
+    pub fn set_program_counter(&mut self, new_program_counter: u64) {
+        self.program_counter = new_program_counter & self.xlen_mask();
+    }
+
+    // NOTE: In RV32 mode the value is truncated to 32 bits
     pub fn write_reg(&mut self, reg_n: u8, val: u64) {
         if reg_n != 0 {
-            self.registers[usize::from(reg_n - 1)] = val;
+            self.registers[usize::from(reg_n - 1)] = val & self.xlen_mask();
         }
     }
 
@@ -840,11 +1466,56 @@ where
         }
     }
 
+    // Ticks until the program stops or max_steps instructions were executed
+    // NOTE: Takes the exception, if there was one
+    pub fn run(&mut self, prog: &mut ProcessData, max_steps: usize) -> RunResult {
+        if !prog.state.is_runnable() {
+            return RunResult::HALTED;
+        }
+        // NOTE: A breakpoint executed by ticking outside of run shouldn't stop this run
+        self.executed_breakpoint = false;
+        for _ in 0..max_steps {
+            if self.tick(prog).is_none() {
+                return match self.take_exception() {
+                    Some(CpuException::TIGHT_LOOP { addr: _ }) => RunResult::TIGHT_LOOP,
+                    Some(exception) => RunResult::TRAPPED(exception),
+                    None => RunResult::ILLEGAL_INSTRUCTION,
+                };
+            }
+            if !prog.state.is_runnable() {
+                return RunResult::HALTED;
+            }
+            if core::mem::take(&mut self.executed_breakpoint) {
+                return RunResult::BREAKPOINT;
+            }
+        }
+        RunResult::BUDGET_EXHAUSTED
+    }
+
     // Run one clock cycle
     // Note: Returns None when ticking fails ( for example maybe instruction parsing failed, or maybe the cpu raised an exception )
     pub fn tick(&mut self, prog: &mut ProcessData) -> Option<()> {
-        let mut instruction = self.memory.read_u32_le(self.program_counter);
-        let is_compressed = (instruction & 0b11) != 0b11;
+        if !self.memory.is_executable(self.program_counter) {
+            self.exception = Some(CpuException::INSTRUCTION_ACCESS_FAULT { addr: self.program_counter });
+            return None;
+        }
+        let Some(lower_parcel) = self.memory.read_u16_le(self.program_counter) else {
+            self.exception = Some(CpuException::INSTRUCTION_ACCESS_FAULT { addr: self.program_counter });
+            return None;
+        };
+        let is_compressed = (lower_parcel & 0b11) != 0b11;
+        // NOTE: The upper half is only read if the instruction has one, so a compressed instruction can be the last thing in a region
+        let mut instruction = if is_compressed {
+            u32::from(lower_parcel)
+        } else {
+            let upper_parcel_addr = self.program_counter.wrapping_add(2) & self.xlen_mask();
+            let Some(upper_parcel) = self.memory.read_u16_le(upper_parcel_addr) else {
+                // The address of the part that faulted is reported, like mtval would have it
+                self.exception = Some(CpuException::INSTRUCTION_ACCESS_FAULT { addr: upper_parcel_addr });
+                return None;
+            };
+            u32::from(lower_parcel) | (u32::from(upper_parcel) << 16)
+        };
         let inst_size = if is_compressed { core::mem::size_of::<u16>() as u64 } else { core::mem::size_of::<u32>() as u64 };
         // use crate::UART;
         // use core::fmt::Write;
@@ -1030,6 +1701,17 @@ where
                             )
                         }
 
+                        (0b001, RiscvCompressedOpcode::C1) if self.xlen == Xlen::RV32 =>
+                        // C.JAL
+                        // C.JAL is an RV32C-only instruction that performs the same operation as C.J, but additionally writes the
+                        // address of the instruction following the jump (pc+2) to the link register, x1. (RISC-V Volume I, section 16.4)
+                        {
+                            let inst = RiscvCJTypeInstruction::unpack(&compressed_inst.to_be_bytes()).ok()?;
+                            instruction = u32::from_msb_bytes(
+                                &RiscvJTypeInstruction::from(RiscvOpcode::JAL, 1 /*x1*/, inst.parse_imm()?).pack().ok()?,
+                            )
+                        }
+
                         (0b001, RiscvCompressedOpcode::C1) =>
                         // C.ADDIW
                         {
@@ -1412,15 +2094,23 @@ where
         // Reference: Issue #92, https://github.com/hashmismatch/packed_struct.rs/issues/92
         // So therefore i am instead using big endian for parsing instructions
         let opcode: RiscvOpcode = RiscvOpcode::from_primitive((instruction & 0b111_1111) as u8)?;
+        if self.xlen == Xlen::RV32 && Self::is_rv64_only_instruction(instruction) {
+            return None;
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record(opcode, is_compressed);
+        }
         let mut action = CpuAction::NONE;
         match opcode.get_type() {
             RiscvInstType::RType => self.execute_rtype_inst(RiscvRTypeInstruction::unpack(&instruction.to_be_bytes()).ok()?),
             RiscvInstType::IType => {
                 action = self.execute_itype_inst(RiscvITypeInstruction::unpack(&instruction.to_be_bytes()).ok()?, inst_size, prog);
             }
-            RiscvInstType::SType => self.execute_stype_inst(RiscvSTypeInstruction::unpack(&instruction.to_be_bytes()).ok()?),
+            RiscvInstType::SType => {
+                action = self.execute_stype_inst(RiscvSTypeInstruction::unpack(&instruction.to_be_bytes()).ok()?)
+            }
             RiscvInstType::BType => {
-                self.execute_btype_inst(RiscvBTypeInstruction::unpack(&instruction.to_be_bytes()).ok()?, inst_size)
+                action = self.execute_btype_inst(RiscvBTypeInstruction::unpack(&instruction.to_be_bytes()).ok()?, inst_size)
             }
             RiscvInstType::UType => self.execute_utype_inst(RiscvUTypeInstruction::unpack(&instruction.to_be_bytes()).ok()?),
             RiscvInstType::JType => {
@@ -1428,14 +2118,15 @@ where
             }
         }
 
-        if action != CpuAction::REPEAT_INSTRUCTION {
-            self.program_counter += inst_size;
-        }
-
         if action == CpuAction::RAISE_EXCEPTION {
             return None;
         }
 
+        if action != CpuAction::REPEAT_INSTRUCTION {
+            self.program_counter = self.program_counter.wrapping_add(inst_size);
+        }
+        self.program_counter &= self.xlen_mask();
+
         Some(())
     }
 
@@ -1475,8 +2166,8 @@ where
             // SLT and SLTU perform signed and unsigned compares respectively, writing 1 to rd if rs1 < rs2, 0 otherwise (RISC-V Volume I, section 2.4)
             (RiscvOpcode::OP, 0b010, 0b0000000) => {
                 // SLT
-                let signed_rs1 = self.read_reg(inst.rs1) as i64;
-                let signed_rs2 = self.read_reg(inst.rs2) as i64;
+                let signed_rs1 = self.read_reg_signed(inst.rs1);
+                let signed_rs2 = self.read_reg_signed(inst.rs2);
                 self.write_reg(inst.rd, if signed_rs1 < signed_rs2 { 1 } else { 0 });
             }
 
@@ -1510,20 +2201,20 @@ where
             //considered for the shift amount. (RISC-V Volume I, section 5.2)
             (RiscvOpcode::OP, 0b001, 0b0000000) => {
                 // SLL
-                let shamt_mask = 0b11_1111;
+                let shamt_mask = self.shamt_mask();
                 self.write_reg(inst.rd, self.read_reg(inst.rs1) << (self.read_reg(inst.rs2) & shamt_mask));
             }
 
             (RiscvOpcode::OP, 0b101, 0b0000000) => {
                 // SRL
-                let shamt_mask = 0b11_1111;
+                let shamt_mask = self.shamt_mask();
                 self.write_reg(inst.rd, self.read_reg(inst.rs1) >> (self.read_reg(inst.rs2) & shamt_mask));
             }
 
             (RiscvOpcode::OP, 0b101, 0b0100000) => {
                 // SRA
-                let shamt_mask = 0b11_1111;
-                self.write_reg(inst.rd, ((self.read_reg(inst.rs1) as i64) >> (self.read_reg(inst.rs2) & shamt_mask)) as u64);
+                let shamt_mask = self.shamt_mask();
+                self.write_reg(inst.rd, (self.read_reg_signed(inst.rs1) >> (self.read_reg(inst.rs2) & shamt_mask)) as u64);
             }
 
             // SLLW, SRLW, and SRAW are RV64I-only instructions that are analogously defined but operate
@@ -1569,21 +2260,20 @@ where
             // and signed rs1×unsigned rs2 multiplication, respectively. (RSIC-V Volume I, section 7.1)
             (RiscvOpcode::OP, 0b001, 0b0000001) => {
                 // MULH
-                let res = sign_extend::<u64, u128>(self.read_reg(inst.rs1))
-                    .wrapping_mul(sign_extend::<u64, u128>(self.read_reg(inst.rs2)));
-                self.write_reg(inst.rd, (res >> 64) as u64);
+                let res = i128::from(self.read_reg_signed(inst.rs1)).wrapping_mul(i128::from(self.read_reg_signed(inst.rs2)));
+                self.write_reg(inst.rd, (res >> self.xlen_bits()) as u64);
             }
 
             (RiscvOpcode::OP, 0b011, 0b0000001) => {
                 // MULHU
                 let res = (self.read_reg(inst.rs1) as u128).wrapping_mul(self.read_reg(inst.rs2) as u128);
-                self.write_reg(inst.rd, (res >> 64) as u64);
+                self.write_reg(inst.rd, (res >> self.xlen_bits()) as u64);
             }
 
             (RiscvOpcode::OP, 0b010, 0b0000001) => {
                 // MULHSU
-                let res = sign_extend::<u64, u128>(self.read_reg(inst.rs1)).wrapping_mul(self.read_reg(inst.rs2) as u128);
-                self.write_reg(inst.rd, (res >> 64) as u64);
+                let res = (i128::from(self.read_reg_signed(inst.rs1)) as u128).wrapping_mul(self.read_reg(inst.rs2) as u128);
+                self.write_reg(inst.rd, (res >> self.xlen_bits()) as u64);
             }
 
             (RiscvOpcode::OP32, 0b000, 0b0000001) => {
@@ -1600,7 +2290,7 @@ where
             // rs2, rounding towards zero
             (RiscvOpcode::OP, 0b100, 0b0000001) => {
                 // DIV
-                self.write_reg(inst.rd, (self.read_reg(inst.rs1) as i64).wrapping_div(self.read_reg(inst.rs2) as i64) as u64);
+                self.write_reg(inst.rd, self.read_reg_signed(inst.rs1).wrapping_div(self.read_reg_signed(inst.rs2)) as u64);
             }
 
             (RiscvOpcode::OP, 0b101, 0b0000001) => {
@@ -1610,7 +2300,7 @@ where
 
             (RiscvOpcode::OP, 0b110, 0b0000001) => {
                 // REM
-                self.write_reg(inst.rd, (self.read_reg(inst.rs1) as i64).wrapping_rem(self.read_reg(inst.rs2) as i64) as u64);
+                self.write_reg(inst.rd, self.read_reg_signed(inst.rs1).wrapping_rem(self.read_reg_signed(inst.rs2)) as u64);
             }
 
             (RiscvOpcode::OP, 0b111, 0b0000001) => {
@@ -1661,6 +2351,7 @@ where
         }
     }
 
+    // Returns: RAISE_EXCEPTION if the load is from memory that isn't mapped, or is misaligned and misaligned accesses fault
     fn execute_itype_inst(&mut self, inst: RiscvITypeInstruction, inst_size: u64, proc_data: &mut ProcessData) -> CpuAction {
         match (inst.opcode, inst.funct3) {
             (RiscvOpcode::OPIMM, 0b000) => {
@@ -1682,7 +2373,7 @@ where
                 // SLTI
                 // SLTI (set less than immediate) places the value 1 in register rd if register rs1 is less than the sign-
                 // extended immediate when both are treated as signed numbers, else 0 is written to rd. (RISC-V Volume I, section 2.4)
-                let signed_rs1 = self.read_reg(inst.rs1) as i64;
+                let signed_rs1 = self.read_reg_signed(inst.rs1);
                 let signed_imm = sign_extend_to_u64(inst.parse_imm()) as i64;
                 self.write_reg(inst.rd, if signed_rs1 < signed_imm { 1 } else { 0 });
             }
@@ -1693,7 +2384,7 @@ where
                 // XLEN bits then treated as an unsigned number). Note, SLTIU rd, rs1, 1 sets rd to 1 if rs1 equals
                 // zero, otherwise sets rd to 0. (RISC-V Volume I, section 2.4)
                 let unsigned_rs1 = self.read_reg(inst.rs1);
-                let unsigned_imm = sign_extend_to_u64(inst.parse_imm());
+                let unsigned_imm = sign_extend_to_u64(inst.parse_imm()) & self.xlen_mask();
                 self.write_reg(inst.rd, if unsigned_rs1 < unsigned_imm { 1 } else { 0 });
             }
 
@@ -1719,7 +2410,7 @@ where
             (RiscvOpcode::OPIMM, 0b001) => {
                 // SLLI
                 // SLLI is a logical left shift (zeros are shifted into the lower bits)
-                let shamt_mask = 0b11_1111;
+                let shamt_mask = self.shamt_mask();
                 self.write_reg(inst.rd, self.read_reg(inst.rs1) << (sign_extend_to_u64(inst.parse_imm()) & shamt_mask));
             }
 
@@ -1727,12 +2418,13 @@ where
                 // SRLI/SRAI, depending on immediate
                 // SRLI is a logical right shift (zeros are shifted into the upper bits); and SRAI is an arithmetic right
                 // shift (the original sign bit is copied into the vacated upper bits).
-                let shamt_mask = 0b11_1111;
+                // NOTE: In RV32 an immediate with bit 5 set is reserved, so it's fine to treat it like SRAI
+                let shamt_mask = self.shamt_mask();
                 let imm = sign_extend_to_u64(inst.parse_imm());
                 if imm & (!shamt_mask) != 0 {
                     // SRAI
                     // *** Arithmetic right shift on signed integer types, logical right shift on unsigned integer types. The Rust Referance, section 8.2.4
-                    self.write_reg(inst.rd, ((self.read_reg(inst.rs1) as i64) >> (imm & shamt_mask)) as u64);
+                    self.write_reg(inst.rd, (self.read_reg_signed(inst.rs1) >> (imm & shamt_mask)) as u64);
                 } else {
                     // SRLI
                     self.write_reg(inst.rd, self.read_reg(inst.rs1) >> (imm & shamt_mask));
@@ -1773,11 +2465,9 @@ where
                 // (pc+4) is written to register rd. Register x0 can be used as the destination if the result is not
                 // required.
                 let new_program_counter = sign_extend_to_u64(inst.parse_imm()).wrapping_add(self.read_reg(inst.rs1)) & (!0b1);
-                if new_program_counter == self.program_counter {
-                    use crate::UART;
-                    use core::fmt::Write;
-                    writeln!(UART.lock(), "Detected infinite loop, raising exception!").unwrap();
-                    return CpuAction::RAISE_EXCEPTION;
+                // NOTE: If rd is rs1 then the jump changes where the next one goes, so it's not a loop
+                if (inst.rd == 0 || inst.rd != inst.rs1) && self.is_tight_loop(new_program_counter, false) {
+                    return self.raise_exception(CpuException::TIGHT_LOOP { addr: self.program_counter });
                 }
                 // NOTE: Order is important we cannot store to rd before calculating because rs1 might be rd
                 self.write_reg(inst.rd, self.program_counter + inst_size); // For C(compressed) instructions, because we exapnd them to full instructions
@@ -1799,43 +2489,64 @@ where
             (RiscvOpcode::LOAD, 0b000) => {
                 // LB
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.write_reg(inst.rd, sign_extend::<u8, u64>(self.memory.read_u8_ne(addr)));
+                let Some(val) = self.load(addr, core::mem::size_of::<u8>()) else {
+                    return CpuAction::RAISE_EXCEPTION;
+                };
+                self.write_reg(inst.rd, sign_extend::<u8, u64>(val as u8));
             }
 
             (RiscvOpcode::LOAD, 0b001) => {
                 // LH
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.write_reg(inst.rd, sign_extend::<u16, u64>(self.memory.read_u16_ne(addr)));
+                let Some(val) = self.load(addr, core::mem::size_of::<u16>()) else {
+                    return CpuAction::RAISE_EXCEPTION;
+                };
+                self.write_reg(inst.rd, sign_extend::<u16, u64>(val as u16));
             }
 
             (RiscvOpcode::LOAD, 0b010) => {
                 // LW
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.write_reg(inst.rd, sign_extend::<u32, u64>(self.memory.read_u32_ne(addr)));
+                let Some(val) = self.load(addr, core::mem::size_of::<u32>()) else {
+                    return CpuAction::RAISE_EXCEPTION;
+                };
+                self.write_reg(inst.rd, sign_extend::<u32, u64>(val as u32));
             }
 
             (RiscvOpcode::LOAD, 0b011) => {
                 // LD
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.write_reg(inst.rd, self.memory.read_u64_ne(addr));
+                let Some(val) = self.load(addr, core::mem::size_of::<u64>()) else {
+                    return CpuAction::RAISE_EXCEPTION;
+                };
+                self.write_reg(inst.rd, val);
             }
 
             (RiscvOpcode::LOAD, 0b110) => {
                 // LWU
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.write_reg(inst.rd, u64::from(self.memory.read_u32_ne(addr)));
+                let Some(val) = self.load(addr, core::mem::size_of::<u32>()) else {
+                    return CpuAction::RAISE_EXCEPTION;
+                };
+                self.write_reg(inst.rd, val);
             }
 
             (RiscvOpcode::LOAD, 0b101) => {
                 // LHU
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.write_reg(inst.rd, u64::from(self.memory.read_u16_ne(addr)));
+                let Some(val) = self.load(addr, core::mem::size_of::<u16>()) else {
+                    return CpuAction::RAISE_EXCEPTION;
+                };
+                self.write_reg(inst.rd, val);
             }
 
             (RiscvOpcode::LOAD, 0b100) => {
                 // LBU
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.write_reg(inst.rd, u64::from(self.memory.read_u8_ne(addr)));
+                let Some(val) = self.load(addr, core::mem::size_of::<u8>()) else {
+                    return CpuAction::RAISE_EXCEPTION;
+                };
+                self.write_reg(inst.rd, val);
             }
 
             (RiscvOpcode::SYSTEM, _) => {
@@ -1843,6 +2554,10 @@ where
                     // ECALL
                     return (self.syscall)(self, proc_data);
                 }
+                if inst.funct3 == 0b000 && inst.parse_imm() == 1 {
+                    // EBREAK ( C.EBREAK is expanded to it too ), it does nothing by itself, run stops after it
+                    self.executed_breakpoint = true;
+                }
             }
             _ => (),
         }
@@ -1850,8 +2565,21 @@ where
         CpuAction::NONE
     }
 
-    fn execute_stype_inst(&mut self, inst: RiscvSTypeInstruction) {
-        match (inst.opcode, inst.funct3) {
+    // Returns: RAISE_EXCEPTION if the store is to memory that isn't writable or isn't mapped, or is misaligned and misaligned accesses fault
+    fn execute_stype_inst(&mut self, inst: RiscvSTypeInstruction) -> CpuAction {
+        let store_size = match (inst.opcode, inst.funct3) {
+            (RiscvOpcode::STORE, 0b000) => core::mem::size_of::<u8>(),
+            (RiscvOpcode::STORE, 0b001) => core::mem::size_of::<u16>(),
+            (RiscvOpcode::STORE, 0b010) => core::mem::size_of::<u32>(),
+            (RiscvOpcode::STORE, 0b011) => core::mem::size_of::<u64>(),
+            _ => 0,
+        };
+        let store_addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm())) & self.xlen_mask();
+        if store_size != 0 && !self.memory.is_writable(store_addr, store_size) {
+            return self.raise_exception(CpuException::STORE_ACCESS_FAULT { addr: store_addr });
+        }
+
+        let stored = match (inst.opcode, inst.funct3) {
             // The effective address is obtained by adding register rs1
             // to the sign-extended 12-bit offset. Loads copy a value from memory to register rd. Stores copy the
             // value in register rs2 to memory.
@@ -1860,32 +2588,37 @@ where
             (RiscvOpcode::STORE, 0b000) => {
                 // SB
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.memory.write_u8_ne(addr, self.read_reg(inst.rs2) as u8)
+                self.store(addr, core::mem::size_of::<u8>(), self.read_reg(inst.rs2))
             }
 
             (RiscvOpcode::STORE, 0b001) => {
                 // SH
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.memory.write_u16_ne(addr, self.read_reg(inst.rs2) as u16)
+                self.store(addr, core::mem::size_of::<u16>(), self.read_reg(inst.rs2))
             }
 
             (RiscvOpcode::STORE, 0b010) => {
                 // SW
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.memory.write_u32_ne(addr, self.read_reg(inst.rs2) as u32)
+                self.store(addr, core::mem::size_of::<u32>(), self.read_reg(inst.rs2))
             }
 
             (RiscvOpcode::STORE, 0b011) => {
                 // SD
                 let addr = self.read_reg(inst.rs1).wrapping_add(sign_extend(inst.parse_imm()));
-                self.memory.write_u64_ne(addr, self.read_reg(inst.rs2))
+                self.store(addr, core::mem::size_of::<u64>(), self.read_reg(inst.rs2))
             }
-            _ => (),
+            _ => Some(()),
+        };
+        if stored.is_none() {
+            return CpuAction::RAISE_EXCEPTION;
         }
+        CpuAction::NONE
     }
 
-    fn execute_btype_inst(&mut self, inst: RiscvBTypeInstruction, inst_size: u64) {
-        match (inst.opcode, inst.funct3) {
+    // Returns: RAISE_EXCEPTION if the branch is taken and is a tight loop
+    fn execute_btype_inst(&mut self, inst: RiscvBTypeInstruction, inst_size: u64) -> CpuAction {
+        let is_taken = match (inst.opcode, inst.funct3) {
             // All branch instructions use the B-type instruction format. The 12-bit B-immediate encodes signed
             // offsets in multiples of 2 bytes. The offset is sign-extended and added to the address of the branch
             // instruction to give the target address. The conditional branch range is ±4 KiB.
@@ -1896,59 +2629,46 @@ where
             // than or equal to rs2, using signed and unsigned comparison respectively.
             (RiscvOpcode::BRANCH, 0b000) => {
                 // BEQ
-                if self.read_reg(inst.rs1) == self.read_reg(inst.rs2) {
-                    self.program_counter =
-                        self.program_counter.wrapping_add(sign_extend_to_u64(inst.parse_imm())).wrapping_sub(inst_size);
-                    // Subtract inst_size to counteract the pc increment in the tick function
-                }
+                self.read_reg(inst.rs1) == self.read_reg(inst.rs2)
             }
 
             (RiscvOpcode::BRANCH, 0b001) => {
                 // BNE
-                if self.read_reg(inst.rs1) != self.read_reg(inst.rs2) {
-                    self.program_counter =
-                        self.program_counter.wrapping_add(sign_extend_to_u64(inst.parse_imm())).wrapping_sub(inst_size);
-                    // Subtract inst_size to counteract the pc increment in the tick function
-                }
+                self.read_reg(inst.rs1) != self.read_reg(inst.rs2)
             }
 
             (RiscvOpcode::BRANCH, 0b100) => {
                 // BLT
-                if (self.read_reg(inst.rs1) as i64) < (self.read_reg(inst.rs2) as i64) {
-                    self.program_counter =
-                        self.program_counter.wrapping_add(sign_extend_to_u64(inst.parse_imm())).wrapping_sub(inst_size);
-                    // Subtract inst_size to counteract the pc increment in the tick function
-                }
+                self.read_reg_signed(inst.rs1) < self.read_reg_signed(inst.rs2)
             }
 
             (RiscvOpcode::BRANCH, 0b101) => {
                 // BGE
-                if (self.read_reg(inst.rs1) as i64) >= (self.read_reg(inst.rs2) as i64) {
-                    self.program_counter =
-                        self.program_counter.wrapping_add(sign_extend_to_u64(inst.parse_imm())).wrapping_sub(inst_size);
-                    // Subtract inst_size to counteract the pc increment in the tick function
-                }
+                self.read_reg_signed(inst.rs1) >= self.read_reg_signed(inst.rs2)
             }
 
             (RiscvOpcode::BRANCH, 0b110) => {
                 // BLTU
-                if self.read_reg(inst.rs1) < self.read_reg(inst.rs2) {
-                    self.program_counter =
-                        self.program_counter.wrapping_add(sign_extend_to_u64(inst.parse_imm())).wrapping_sub(inst_size);
-                    // Subtract inst_size to counteract the pc increment in the tick function
-                }
+                self.read_reg(inst.rs1) < self.read_reg(inst.rs2)
             }
 
             (RiscvOpcode::BRANCH, 0b111) => {
                 // BGEU
-                if self.read_reg(inst.rs1) >= self.read_reg(inst.rs2) {
-                    self.program_counter =
-                        self.program_counter.wrapping_add(sign_extend_to_u64(inst.parse_imm())).wrapping_sub(inst_size);
-                    // Subtract inst_size to counteract the pc increment in the tick function
-                }
+                self.read_reg(inst.rs1) >= self.read_reg(inst.rs2)
             }
-            _ => (),
+            _ => false,
+        };
+
+        if is_taken {
+            let new_program_counter = self.program_counter.wrapping_add(sign_extend_to_u64(inst.parse_imm()));
+            // NOTE: The branch compares registers that it doesn't change, so if it's taken once it's taken forever
+            if self.is_tight_loop(new_program_counter, true) {
+                return self.raise_exception(CpuException::TIGHT_LOOP { addr: self.program_counter });
+            }
+            self.program_counter = new_program_counter.wrapping_sub(inst_size);
+            // Subtract inst_size to counteract the pc increment in the tick function
         }
+        CpuAction::NONE
     }
 
     fn execute_utype_inst(&mut self, inst: RiscvUTypeInstruction) {
@@ -1972,11 +2692,8 @@ where
                 // jump instruction to form the jump target address. Jumps can therefore target a ±1 MiB range.
                 // JAL stores the address of the instruction following the jump (pc+4) into register rd (RISC-V Volume I, section 2.5)
                 let new_program_counter = sign_extend_to_u64(inst.parse_imm()).wrapping_add(self.program_counter);
-                if new_program_counter == self.program_counter {
-                    use crate::UART;
-                    use core::fmt::Write;
-                    writeln!(UART.lock(), "Detected infinite loop, raising excpetion cpu!").unwrap();
-                    return CpuAction::RAISE_EXCEPTION;
+                if self.is_tight_loop(new_program_counter, false) {
+                    return self.raise_exception(CpuException::TIGHT_LOOP { addr: self.program_counter });
                 }
 
                 // NOTE: Although order here is not important since calculating the new program counter does not involve reading a register, for consistency we still do it in the same order as JALR
@@ -1,9 +1,15 @@
-use core::{cell::RefCell, convert::TryInto, str::from_utf8};
+use core::{
+    any::Any,
+    cell::{Cell, RefCell, RefMut},
+    convert::TryInto,
+    str::from_utf8,
+};
 
 use alloc::{borrow::ToOwned, rc::Rc, vec, vec::Vec};
 use packed_struct::prelude::*;
 
 use crate::{
+    rtc,
     vfs::{self, IFile, IFolder},
     UART,
 };
@@ -38,7 +44,7 @@ pub struct Ext2SuperBlock {
     gid_for_reserved_blocks: u16,
 }
 
-#[derive(PackedStruct)]
+#[derive(PackedStruct, Clone)]
 #[packed_struct(endian = "lsb")] // ext2 is little endian (https://wiki.osdev.org/Ext2#Basic_Concepts)
 pub struct Ext2ExtendedSuperblock {
     first_non_reserved_inode_in_fs: u32,
@@ -61,6 +67,10 @@ pub struct Ext2ExtendedSuperblock {
 }
 
 impl Ext2ExtendedSuperblock {
+    fn has_optional_feature_directory_index(&self) -> bool {
+        self.optional_features & 0x0020 != 0
+    }
+
     fn has_unrecognised_required_features(&self) -> bool {
         self.required_features & 0x000F != self.required_features
     }
@@ -163,6 +173,14 @@ impl Default for Ext2RawInode {
     }
 }
 
+// Source: https://www.nongnu.org/ext2-doc/ext2.html#i-mode
+const MODE_PERMISSION_BITS: u16 = 0o7777;
+const MODE_OWNER_WRITE: u16 = 0o200;
+
+// The directory has a hash index ( htree ) of its entries
+// Source: https://www.kernel.org/doc/html/latest/filesystems/ext4/directory.html#hash-tree-directories
+const INODE_FLAG_INDEXED_DIRECTORY: u32 = 0x1000;
+
 #[derive(PackedStruct, Debug)]
 #[packed_struct(endian = "lsb")] // ext2 is little endian (https://wiki.osdev.org/Ext2#Basic_Concepts)
 pub struct Ext2DirectoryEntryHeader {
@@ -172,6 +190,34 @@ pub struct Ext2DirectoryEntryHeader {
     entry_type: u8,
 }
 
+// Values of entry_type, only used if the directory entry type field feature is enabled, otherwise entry_type is always 0
+// Source: https://www.nongnu.org/ext2-doc/ext2.html#ifdir-file-type
+mod directory_entry_type {
+    pub const UNKNOWN: u8 = 0;
+    pub const REGULAR_FILE: u8 = 1;
+    pub const DIRECTORY: u8 = 2;
+    pub const CHARACTER_DEVICE: u8 = 3;
+    pub const BLOCK_DEVICE: u8 = 4;
+    pub const FIFO: u8 = 5;
+    pub const SOCKET: u8 = 6;
+    pub const SYMBOLIC_LINK: u8 = 7;
+}
+
+// The longest name an entry can have when entry_type is used, since then the name length only has 8 bits
+const MAX_NAME_LENGTH: usize = 255;
+
+impl Ext2DirectoryEntryHeader {
+    // NOTE: Without the directory entry type field feature, entry_type is the high 8 bits of the name length
+    // Source: https://www.nongnu.org/ext2-doc/ext2.html#linked-directory-entry-structure
+    fn name_length(&self, has_entry_types: bool) -> usize {
+        if has_entry_types {
+            usize::from(self.name_length_low8)
+        } else {
+            usize::from(self.name_length_low8) | usize::from(self.entry_type) << 8
+        }
+    }
+}
+
 impl Default for Ext2DirectoryEntryHeader {
     fn default() -> Self {
         Self {
@@ -209,7 +255,7 @@ impl Ext2RawInode {
         Some((self.get_size() - 1) / fs.get_block_size() as usize)
     }
 
-    // Resturns pointer to data block #block_number
+    // Resturns pointer to data block #block_number, 0 if the block is a hole
     // This allows treating the hierarchical underlying structure as a flat structure
     fn read_data_block_pointer(&self, mut data_block_number: usize, fs: &Ext2FS) -> Option<u32> {
         // TODO: Test all posibilites of this function!!!
@@ -221,12 +267,22 @@ impl Ext2RawInode {
         let pointers_per_block = fs.get_block_size() as usize / core::mem::size_of::<u32>();
 
         let read_pointer_from_block = Self::read_value_from_u32_array_as_le_bytes;
+        // NOTE: A 0 pointer to an indirect block means every data block under it is a hole, so don't go reading block 0 as if it were full of pointers
+        macro_rules! read_indirect_block {
+            ($pointer: expr) => {{
+                let pointer = $pointer;
+                if pointer == 0 {
+                    return Some(0);
+                }
+                fs.read_block(pointer)?
+            }};
+        }
 
         // Singly indirect data
         data_block_number -= 12;
         if data_block_number < pointers_per_block {
             let singly_indirect_block_index = data_block_number; // Index of pointer to data block
-            let mut singly_indirect_block = fs.read_block(self.singly_indirect_block_pointer)?;
+            let mut singly_indirect_block = read_indirect_block!(self.singly_indirect_block_pointer);
 
             return read_pointer_from_block(&mut singly_indirect_block, singly_indirect_block_index);
         }
@@ -237,9 +293,9 @@ impl Ext2RawInode {
             let doubly_indirect_block_index = data_block_number / pointers_per_block; // Index of pointer to singly indirect block
             let singly_indirect_block_index = data_block_number % pointers_per_block; // Index of pointer to data block
 
-            let doubly_indirect_block = fs.read_block(self.doubly_indirect_block_pointer)?;
+            let doubly_indirect_block = read_indirect_block!(self.doubly_indirect_block_pointer);
             let mut singly_indirect_block =
-                fs.read_block(read_pointer_from_block(&doubly_indirect_block, doubly_indirect_block_index)?)?;
+                read_indirect_block!(read_pointer_from_block(&doubly_indirect_block, doubly_indirect_block_index)?);
             return read_pointer_from_block(&mut singly_indirect_block, singly_indirect_block_index);
         }
 
@@ -252,16 +308,24 @@ impl Ext2RawInode {
             let singly_indirect_block_index =
                 (data_block_number % (pointers_per_block * pointers_per_block)) % pointers_per_block; // Index of pointer to data block
 
-            let triply_indirect_block = fs.read_block(self.triply_indirect_block_pointer)?;
+            let triply_indirect_block = read_indirect_block!(self.triply_indirect_block_pointer);
             let doubly_indirect_block =
-                fs.read_block(read_pointer_from_block(&triply_indirect_block, triply_indirect_block_index)?)?;
+                read_indirect_block!(read_pointer_from_block(&triply_indirect_block, triply_indirect_block_index)?);
             let mut singly_indirect_block =
-                fs.read_block(read_pointer_from_block(&doubly_indirect_block, doubly_indirect_block_index)?)?;
+                read_indirect_block!(read_pointer_from_block(&doubly_indirect_block, doubly_indirect_block_index)?);
             return read_pointer_from_block(&mut singly_indirect_block, singly_indirect_block_index);
         }
         None
     }
 
+    // Returns: The physical block number each logical data block of the file maps to, 0 for holes
+    pub fn get_block_map(&self, fs: &Ext2FS) -> Option<Vec<u32>> {
+        let Some(last_data_block_number) = self.get_last_allocated_data_block_number(fs) else {
+            return Some(Vec::new());
+        };
+        (0..=last_data_block_number).map(|data_block_number| self.read_data_block_pointer(data_block_number, fs)).collect()
+    }
+
     // Writes pointer over the pointer pointing to #block_number in the hierarchichal data structure
     // NOTE: Will deallocate block to avoid data leaks
     fn write_data_block_pointer(&mut self, mut data_block_number: usize, pointer: u32, fs: &mut Ext2FS) -> Option<()> {
@@ -340,6 +404,48 @@ impl Ext2RawInode {
         None
     }
 
+    // Returns: Every block the inode uses, so the data blocks and the indirect blocks pointing to them, or None if the inode doesn't use blocks
+    // NOTE: Unlike everything else this follows every pointer, not just the ones that are within the size
+    fn get_all_used_blocks(&self, fs: &Ext2FS) -> Option<Vec<u32>> {
+        // Fast symbolic links store their target in the block pointers, and devices their device number
+        let is_symbolic_link = self.type_and_perm & 0xF000 == 0xA000;
+        let has_blocks = matches!(self.type_and_perm & 0xF000, 0x8000 | 0x4000) || (is_symbolic_link && self.disk_sectors_used != 0);
+        if !has_blocks {
+            return None;
+        }
+        Some(self.follow_all_block_pointers(fs))
+    }
+
+    // Returns: Every block the block pointers lead to, without checking if the inode actually uses its block pointers for blocks
+    fn follow_all_block_pointers(&self, fs: &Ext2FS) -> Vec<u32> {
+        fn add_blocks_under(pointer: u32, depth: usize, fs: &Ext2FS, blocks: &mut Vec<u32>) {
+            if pointer == 0 {
+                return;
+            }
+            blocks.push(pointer);
+            // Pointers past the end of the disk are reported by the checker, there is nothing to read there
+            if depth == 0 || pointer >= fs.sb.max_no_of_blocks {
+                return;
+            }
+            let Some(block) = fs.read_block(pointer) else {
+                return;
+            };
+            for i in 0..block.len() / core::mem::size_of::<u32>() {
+                let pointer = Ext2RawInode::read_value_from_u32_array_as_le_bytes(&block, i).unwrap();
+                add_blocks_under(pointer, depth - 1, fs, blocks);
+            }
+        }
+
+        let mut blocks = Vec::new();
+        for pointer in self.direct_block_pointers {
+            add_blocks_under(pointer, 0, fs, &mut blocks);
+        }
+        add_blocks_under(self.singly_indirect_block_pointer, 1, fs, &mut blocks);
+        add_blocks_under(self.doubly_indirect_block_pointer, 2, fs, &mut blocks);
+        add_blocks_under(self.triply_indirect_block_pointer, 3, fs, &mut blocks);
+        blocks
+    }
+
     pub fn read_data_block(&self, data_block_number: usize, fs: &Ext2FS) -> Option<Vec<u8>> {
         return fs.read_block(self.read_data_block_pointer(data_block_number, fs)?);
     }
@@ -349,208 +455,193 @@ impl Ext2RawInode {
     }
 
     pub fn dealloc_data_block(&mut self, data_block_number: usize, fs: &mut Ext2FS) -> Option<()> {
+        // Holes have nothing to deallocate, and the indirect blocks that would point to them might not even exist
+        if self.read_data_block_pointer(data_block_number, fs)? == 0 {
+            return Some(());
+        }
         // NOTE: Write will deallocate on it's own
         self.write_data_block_pointer(data_block_number, 0, fs)
     }
 
-    // NOTE: Will deallocate block if allocated and allocate a new one
-    pub fn alloc_data_block(&mut self, data_block_number: usize, fs: &mut Ext2FS) -> Option<()> {
-        // Get descriptor of last block in file and try to put new block there, if that fails, try descriptors next to it, until one succeds or all fails
-        // If there are no allocated blocks use descriptor 0 and ones next to it.
-        let get_appropriate_descriptor_index = || {
-            let Some(last_data_block_number) = self.get_last_allocated_data_block_number(fs) else {
-                return Some(0);
-            };
+    // Returns: The block that a new block for data block #data_block_number should be allocated as close as possible to,
+    // that being the block holding the data right before it, so that files stay contiguous on disk
+    // If there is no such block then the last allocated block is used instead, and if the file is empty there is no goal
+    fn get_allocation_goal(&self, data_block_number: usize, fs: &Ext2FS) -> Option<u32> {
+        if data_block_number > 0 {
+            if let Some(previous_block_pointer) =
+                self.read_data_block_pointer(data_block_number - 1, fs).filter(|pointer| *pointer != 0)
+            {
+                return Some(previous_block_pointer);
+            }
+        }
 
-            fs.get_descriptor_index_of_block_number(self.read_data_block_pointer(last_data_block_number, fs)?)
-        };
-        let descriptor_index = get_appropriate_descriptor_index()?; // Avoid borrowing fs twice
-        let new_block_pointer = fs.alloc_block_close_to(descriptor_index)?;
-        self.write_data_block_pointer(data_block_number, new_block_pointer, fs)?;
-        Some(())
+        let last_data_block_number = self.get_last_allocated_data_block_number(fs)?;
+        self.read_data_block_pointer(last_data_block_number, fs).filter(|pointer| *pointer != 0)
     }
 
-    pub fn shrink_data_structure_to_fit(&mut self, fs: &mut Ext2FS) {
-        let read_pointer_from_block = Self::read_value_from_u32_array_as_le_bytes;
-        let write_pointer_to_block = Self::write_value_to_u32_array_as_le_bytes;
+    // Returns: The pointer at index in the indirect block, if it is null a new block gets allocated close to goal and it's pointer is written there first
+    fn read_or_alloc_pointer_in_indirect_block(
+        indirect_block_pointer: u32,
+        index: usize,
+        goal: Option<u32>,
+        fs: &mut Ext2FS,
+    ) -> Option<u32> {
+        let mut indirect_block = fs.read_block(indirect_block_pointer)?;
+        let pointer = Self::read_value_from_u32_array_as_le_bytes(&indirect_block, index)?;
+        if pointer != 0 {
+            return Some(pointer);
+        }
 
-        if let Some(singly_indirect_block) = fs.read_block(self.singly_indirect_block_pointer) {
-            if singly_indirect_block.into_iter().all(|v| v == 0) {
-                // Empty block
-                fs.dealloc_block(self.singly_indirect_block_pointer);
-                self.singly_indirect_block_pointer = 0;
-            }
+        let new_pointer = fs.alloc_block_near(goal)?;
+        Self::write_value_to_u32_array_as_le_bytes(&mut indirect_block, index, new_pointer)?;
+        fs.write_block(indirect_block_pointer, &indirect_block)?;
+        Some(new_pointer)
+    }
+
+    // Makes sure that all the indirect blocks needed to store the pointer to data block #data_block_number exist
+    // NOTE: New indirect blocks are allocated close to goal, so that they end up right next to the data they point to
+    fn alloc_indirect_blocks_for(&mut self, mut data_block_number: usize, goal: Option<u32>, fs: &mut Ext2FS) -> Option<()> {
+        // Direct data
+        if data_block_number <= 11 {
+            return Some(());
         }
 
-        if let Some(mut doubly_indirect_block) = fs.read_block(self.doubly_indirect_block_pointer) {
-            for i in (0..fs.get_block_size() / (core::mem::size_of::<u32>() as u32)).rev() {
-                if let Some(singly_indirect_block_pointer) = read_pointer_from_block(&doubly_indirect_block, i as usize) {
-                    if let Some(singly_indirect_block) = fs.read_block(singly_indirect_block_pointer) {
-                        if singly_indirect_block.iter().all(|v| *v == 0) {
-                            // Empty block
-                            fs.dealloc_block(singly_indirect_block_pointer);
-                            write_pointer_to_block(&mut doubly_indirect_block, i as usize, 0);
-                        } else {
-                            // NOTE: This might not support holes in inodes, depending on how holes are implemented
-                            break; // If this block is not empty then none of the rest should be since they are sequential
-                        }
-                    }
-                }
-            }
+        let pointers_per_block = fs.get_block_size() as usize / core::mem::size_of::<u32>();
 
-            if doubly_indirect_block.iter().all(|v| *v == 0) {
-                // Empty block
-                fs.dealloc_block(self.doubly_indirect_block_pointer);
-                self.doubly_indirect_block_pointer = 0;
-            } else {
-                fs.write_block(self.doubly_indirect_block_pointer, &doubly_indirect_block);
+        // Singly indirect data
+        data_block_number -= 12;
+        if data_block_number < pointers_per_block {
+            if self.singly_indirect_block_pointer == 0 {
+                self.singly_indirect_block_pointer = fs.alloc_block_near(goal)?;
             }
+            return Some(());
         }
 
-        if let Some(mut triply_indirect_block) = fs.read_block(self.triply_indirect_block_pointer) {
-            'big_loop: for i in 0..fs.get_block_size() / (core::mem::size_of::<u32>() as u32) {
-                if let Some(doubly_indirect_block_pointer) = read_pointer_from_block(&triply_indirect_block, i as usize) {
-                    if let Some(mut doubly_indirect_block) = fs.read_block(doubly_indirect_block_pointer) {
-                        for j in (0..fs.get_block_size() / (core::mem::size_of::<u32>() as u32)).rev() {
-                            if let Some(singly_indirect_block_pointer) =
-                                read_pointer_from_block(&doubly_indirect_block, j as usize)
-                            {
-                                if let Some(singly_indirect_block) = fs.read_block(singly_indirect_block_pointer) {
-                                    if singly_indirect_block.iter().all(|v| *v == 0) {
-                                        // Empty block
-                                        fs.dealloc_block(singly_indirect_block_pointer);
-                                        write_pointer_to_block(&mut doubly_indirect_block, j as usize, 0);
-                                    } else {
-                                        // NOTE: This might not support holes in inodes, depending on how holes are implemented
-                                        break 'big_loop; // If this block is not empty then none of the rest should be
-                                    }
-                                }
-                            }
-                        }
-
-                        if doubly_indirect_block.iter().all(|v| *v == 0) {
-                            fs.dealloc_block(doubly_indirect_block_pointer);
-                            write_pointer_to_block(&mut triply_indirect_block, i as usize, 0);
-                        } else {
-                            fs.write_block(doubly_indirect_block_pointer, &doubly_indirect_block);
-                        }
-                    }
-                }
+        // Doubly indirect data
+        data_block_number -= pointers_per_block;
+        if data_block_number < pointers_per_block * pointers_per_block {
+            if self.doubly_indirect_block_pointer == 0 {
+                self.doubly_indirect_block_pointer = fs.alloc_block_near(goal)?;
             }
+            let doubly_indirect_block_index = data_block_number / pointers_per_block; // Index of pointer to singly indirect block
+            Self::read_or_alloc_pointer_in_indirect_block(
+                self.doubly_indirect_block_pointer,
+                doubly_indirect_block_index,
+                goal,
+                fs,
+            )?;
+            return Some(());
+        }
 
-            if triply_indirect_block.iter().all(|v| *v == 0) {
-                // Empty block
-                fs.dealloc_block(self.triply_indirect_block_pointer);
-                self.triply_indirect_block_pointer = 0;
-            } else {
-                fs.write_block(self.triply_indirect_block_pointer, &triply_indirect_block);
+        // Triply indirect data
+        data_block_number -= pointers_per_block * pointers_per_block;
+        if data_block_number < pointers_per_block * pointers_per_block * pointers_per_block {
+            if self.triply_indirect_block_pointer == 0 {
+                self.triply_indirect_block_pointer = fs.alloc_block_near(goal)?;
             }
+            let triply_indirect_block_index = data_block_number / (pointers_per_block * pointers_per_block); // Index of pointer to doubly indirect block
+            let doubly_indirect_block_index =
+                (data_block_number % (pointers_per_block * pointers_per_block)) / pointers_per_block; // Index of pointer to singly indirect data block
+            let doubly_indirect_block_pointer = Self::read_or_alloc_pointer_in_indirect_block(
+                self.triply_indirect_block_pointer,
+                triply_indirect_block_index,
+                goal,
+                fs,
+            )?;
+            Self::read_or_alloc_pointer_in_indirect_block(doubly_indirect_block_pointer, doubly_indirect_block_index, goal, fs)?;
+            return Some(());
         }
+        None
     }
 
-    pub fn grow_data_structure_by(&mut self, nblocks: usize, fs: &mut Ext2FS) -> Option<()> {
-        use core::convert::TryFrom;
-        use core::mem::size_of;
-        let mut new_block_size: isize = isize::try_from(self.get_size() / fs.get_block_size() as usize).ok()?
-            + if self.get_size() % fs.get_block_size() as usize != 0 { 1 } else { 0 }
-            + isize::try_from(nblocks).ok()?;
-        let write_pointer_to_block = Self::write_value_to_u32_array_as_le_bytes;
-
-        new_block_size -= 12;
-        if new_block_size <= 0 {
-            return Some(());
-        }
+    // NOTE: Will deallocate block if allocated and allocate a new one
+    // NOTE: Allocates any missing indirect blocks needed to point to the new block
+    pub fn alloc_data_block(&mut self, data_block_number: usize, fs: &mut Ext2FS) -> Option<()> {
+        // Try to put the new block right after the block before it in the file, so that reading the file sequentially reads the disk sequentially
+        let goal = self.get_allocation_goal(data_block_number, fs);
+        self.alloc_indirect_blocks_for(data_block_number, goal, fs)?;
+        let new_block_pointer = fs.alloc_block_near(goal)?;
+        self.write_data_block_pointer(data_block_number, new_block_pointer, fs)?;
+        Some(())
+    }
 
-        // IDK just allocate the indirect blocks next to the data blocks?
-        // TODO: Is this really a good approach to this?, idk, i haven't profiled or tested anything jsut guessing
-        let get_appropriate_descriptor_index = || {
-            let Some(last_data_block_number) = self.get_last_allocated_data_block_number(fs) else {
-                return Some(0);
+    // Deallocates the indirect blocks that don't point to anything anymore, has to be called after data blocks at the end are deallocated
+    // NOTE: Blocks are checked from the last one backwards, and the first one that still points to something stops the search,
+    // since only the end of the file was removed everything before it is still in use
+    pub fn shrink_data_structure_to_fit(&mut self, fs: &mut Ext2FS) {
+        // Returns: If the indirect block at pointer ( which has depth levels of indirect blocks under it, counting itself ) is empty,
+        // in which case it was deallocated and the pointer to it has to be cleared
+        fn dealloc_if_empty(pointer: u32, depth: usize, fs: &mut Ext2FS) -> bool {
+            let Some(mut block) = fs.read_block(pointer) else {
+                return false;
             };
-            fs.get_descriptor_index_of_block_number(self.read_data_block_pointer(last_data_block_number, fs)?)
-        };
-
-        let descriptor_index = get_appropriate_descriptor_index()?; // Avoid borrowing fs twice
-
-        if self.singly_indirect_block_pointer == 0 {
-            self.singly_indirect_block_pointer = fs.alloc_block_close_to(descriptor_index)?;
-        }
-        new_block_size -= fs.get_block_size() as isize / size_of::<u32>() as isize;
-
-        if new_block_size <= 0 {
-            return Some(());
-        }
+            if depth > 1 {
+                let mut changed = false;
+                for i in (0..block.len() / core::mem::size_of::<u32>()).rev() {
+                    let child_pointer = Ext2RawInode::read_value_from_u32_array_as_le_bytes(&block, i).unwrap();
+                    if child_pointer == 0 {
+                        continue;
+                    }
+                    if !dealloc_if_empty(child_pointer, depth - 1, fs) {
+                        break;
+                    }
+                    Ext2RawInode::write_value_to_u32_array_as_le_bytes(&mut block, i, 0).unwrap();
+                    changed = true;
+                }
+                // The block has to be written even if it's about to be deallocated, so that it never points to blocks that are free
+                if changed {
+                    fs.write_block(pointer, &block);
+                }
+            }
 
-        if self.doubly_indirect_block_pointer == 0 {
-            self.doubly_indirect_block_pointer = fs.alloc_block_close_to(descriptor_index)?;
+            if block.iter().all(|v| *v == 0) {
+                fs.dealloc_block(pointer);
+                return true;
+            }
+            false
         }
 
-        let mut doubly_indirect_block = fs.read_block(self.doubly_indirect_block_pointer)?;
-        let mut ind = 0;
-        while new_block_size > 0 && ind < fs.get_block_size() / size_of::<u32>() as u32 {
-            write_pointer_to_block(&mut doubly_indirect_block, ind as usize, fs.alloc_block_close_to(descriptor_index)?)?;
-            new_block_size -= fs.get_block_size() as isize / size_of::<u32>() as isize;
-            ind += 1;
+        if dealloc_if_empty(self.singly_indirect_block_pointer, 1, fs) {
+            self.singly_indirect_block_pointer = 0;
         }
-        fs.write_block(self.doubly_indirect_block_pointer, &doubly_indirect_block)?;
-        if new_block_size <= 0 {
-            return Some(());
+        if dealloc_if_empty(self.doubly_indirect_block_pointer, 2, fs) {
+            self.doubly_indirect_block_pointer = 0;
         }
-
-        if self.triply_indirect_block_pointer == 0 {
-            self.triply_indirect_block_pointer = fs.alloc_block_close_to(descriptor_index)?;
+        if dealloc_if_empty(self.triply_indirect_block_pointer, 3, fs) {
+            self.triply_indirect_block_pointer = 0;
         }
+    }
 
-        let mut triply_indirect_block = fs.read_block(self.triply_indirect_block_pointer)?;
-        let mut ind = 0;
-        while new_block_size > 0 && ind < fs.get_block_size() / size_of::<u32>() as u32 {
-            let doubly_indirect_block_pointer = fs.alloc_block_close_to(descriptor_index)?;
-            write_pointer_to_block(&mut triply_indirect_block, ind as usize, doubly_indirect_block_pointer)?;
-            {
-                let mut doubly_indirect_block = fs.read_block(self.doubly_indirect_block_pointer)?;
-                let mut j_ind = 0;
-                while new_block_size > 0 && j_ind < fs.get_block_size() / size_of::<u32>() as u32 {
-                    write_pointer_to_block(
-                        &mut doubly_indirect_block,
-                        j_ind as usize,
-                        fs.alloc_block_close_to(descriptor_index)?,
-                    )?;
-                    new_block_size -= fs.get_block_size() as isize / size_of::<u32>() as isize;
-                    j_ind += 1;
-                }
-                fs.write_block(self.doubly_indirect_block_pointer, &doubly_indirect_block)?;
-            }
+    // Allocates the indirect blocks needed to be able to store nblocks more data blocks
+    // NOTE: alloc_data_block already does this on it's own, one block at a time, which keeps indirect blocks closer to their data
+    pub fn grow_data_structure_by(&mut self, nblocks: usize, fs: &mut Ext2FS) -> Option<()> {
+        let first_new_data_block_number = self.get_size() / fs.get_block_size() as usize
+            + if self.get_size() % fs.get_block_size() as usize != 0 { 1 } else { 0 };
 
-            ind += 1;
-        }
-        fs.write_block(self.triply_indirect_block_pointer, &triply_indirect_block)?;
-        if new_block_size <= 0 {
-            return Some(());
+        for data_block_number in first_new_data_block_number..first_new_data_block_number + nblocks {
+            let goal = self.get_allocation_goal(data_block_number, fs);
+            self.alloc_indirect_blocks_for(data_block_number, goal, fs)?;
         }
-        None
+        Some(())
     }
 
     pub fn read_bytes(&self, offset: usize, len: usize, e2fs: &Ext2FS) -> Option<Vec<u8>> {
         if offset + len > self.get_size() {
             return None;
         }
-        
-        let starting_block_index = offset / (e2fs.get_block_size() as usize);
-        let starting_block_offset = offset % (e2fs.get_block_size() as usize);
-        let mut res: Vec<u8> = Vec::with_capacity(len);
-
-        let first_block = self.read_data_block(starting_block_index, e2fs)?;
-        for e in &first_block[starting_block_offset..] {
-            res.push(*e);
-        }
-
-        let extra_block = if len % e2fs.get_block_size() as usize != 0 { 1 } else { 0 };
-        for block_ind in 1..(len / e2fs.get_block_size() as usize + extra_block) {
-            res.append(&mut self.read_data_block(starting_block_index + block_ind, e2fs)?);
+        if len == 0 {
+            return Some(Vec::new());
         }
 
-        while res.len() > len {
-            res.pop();
+        let block_size = e2fs.get_block_size() as usize;
+        let mut res: Vec<u8> = Vec::with_capacity(len);
+        // NOTE: The range doesn't have to start at the start of a block, so it can touch one more block than len / block_size rounded up
+        for data_block_number in offset / block_size..=(offset + len - 1) / block_size {
+            let block = self.read_data_block(data_block_number, e2fs)?;
+            let block_start = data_block_number * block_size;
+            let start_in_block = offset.saturating_sub(block_start);
+            let end_in_block = core::cmp::min(offset + len - block_start, block_size);
+            res.extend_from_slice(&block[start_in_block..end_in_block]);
         }
         Some(res)
     }
@@ -559,38 +650,26 @@ impl Ext2RawInode {
         if offset + data.len() > self.get_size() {
             return None;
         }
-        let starting_block_addr = offset / (e2fs.get_block_size() as usize);
-        let offset_in_starting_block = offset % (e2fs.get_ondisk_inode_size() as usize);
-        let mut iter = data.iter();
-        let mut bytes_written = 0;
+        if data.is_empty() {
+            return Some(0);
+        }
 
-        let extra_block = if data.len() % e2fs.get_block_size() as usize != 0 { 1 } else { 0 };
-        let mut ind = offset_in_starting_block;
-        for block_ind in 0..(data.len() / e2fs.get_block_size() as usize + extra_block) {
-            // No need to read sectors that we know will be completly overriden
-            let mut v = if (block_ind == (data.len() / e2fs.get_block_size() as usize + extra_block - 1) && extra_block == 1)
-                || (block_ind == 0 && offset_in_starting_block != 0)
-            {
-                self.read_data_block(starting_block_addr + block_ind, e2fs)?
+        let block_size = e2fs.get_block_size() as usize;
+        let mut bytes_written = 0;
+        for data_block_number in offset / block_size..=(offset + data.len() - 1) / block_size {
+            let block_start = data_block_number * block_size;
+            let start_in_block = offset.saturating_sub(block_start);
+            let end_in_block = core::cmp::min(offset + data.len() - block_start, block_size);
+            // No need to read blocks that we know will be completly overriden
+            let mut block = if start_in_block != 0 || end_in_block != block_size {
+                self.read_data_block(data_block_number, e2fs)?
             } else {
-                vec![0u8; e2fs.get_block_size() as usize]
+                vec![0u8; block_size]
             };
 
-            loop {
-                if ind >= v.len() {
-                    break;
-                }
-
-                let Some(byte_to_write) = iter.next() else {
-                    break;
-                };
-                v[ind] = *byte_to_write;
-                ind += 1;
-                bytes_written += 1;
-            }
-            ind = 0;
-
-            self.write_data_block(starting_block_addr + block_ind, &v, e2fs)?;
+            block[start_in_block..end_in_block].copy_from_slice(&data[bytes_written..bytes_written + (end_in_block - start_in_block)]);
+            self.write_data_block(data_block_number, &block, e2fs)?;
+            bytes_written += end_in_block - start_in_block;
         }
 
         Some(bytes_written)
@@ -637,7 +716,7 @@ impl Ext2RawInode {
             self.dealloc_data_block(last_allocated_data_block_number - i, e2fs)?;
         }
         self.shrink_data_structure_to_fit(e2fs);
-        self.set_size(self.get_size() - nbytes);
+        self.set_size(self.get_size() - nbytes, e2fs);
         Some(())
     }
 
@@ -653,7 +732,7 @@ impl Ext2RawInode {
             let bytes_used_in_last_block = self.get_size() % e2fs.get_block_size() as usize;
             if bytes_used_in_last_block == 0 {
                 // Make sure the last block exists
-                self.grow_data_structure_by(1, e2fs)?; // In case the last block doesn't exist and it would overflow in a non-allocated part of the inode strucutre, a.k.a if the indirect blocks don't exist
+                // NOTE: alloc_data_block also allocates the indirect blocks if they don't exist
                 self.alloc_data_block(self.get_last_allocated_data_block_number(e2fs).map(|last_block_n| last_block_n + 1).unwrap_or(0) /* if no blocks allocated, then the last block is the first block, block 0, and it doesn't exist, so this is definetly needed */, e2fs)?;
             }
             let bytes_available_in_last_block = e2fs.get_block_size() as usize - bytes_used_in_last_block;
@@ -667,15 +746,13 @@ impl Ext2RawInode {
                     + if bytes_to_add % e2fs.get_block_size() as usize != 0 { 1 } else { 0 };
             }
         }
-        self.grow_data_structure_by(blocks_to_add, e2fs)?;
-
         let last_allocated_data_block_number = self.get_last_allocated_data_block_number(e2fs).unwrap_or(0)/* block 0 gets allocated above if it doesn't exist, so it's ok to skip it */;
         for i in 1..=blocks_to_add {
             // writeln!(UART.lock(),"Allocating block: {}", last_allocated_data_block_number+i).unwrap();
             self.alloc_data_block(last_allocated_data_block_number + i, e2fs)?;
         }
 
-        self.set_size(self.get_size() + nbytes);
+        self.set_size(self.get_size() + nbytes, e2fs);
         Some(())
     }
 
@@ -693,70 +770,327 @@ impl Ext2RawInode {
     pub fn as_vfs_node(self, fs: Rc<RefCell<Ext2FS>>, inode_addr: u32) -> Option<vfs::Node> {
         if self.type_and_perm & 0xF000 == 0x4000 {
             return Some(vfs::Node::Folder(
-                Rc::new(RefCell::new(Ext2Folder { inode: self, inode_addr, fs })) as Rc<RefCell<dyn IFolder>>
+                Rc::new(RefCell::new(Ext2Folder { inode: RefCell::new(Some(self)), inode_addr, fs })) as Rc<RefCell<dyn IFolder>>
             ));
         }
         if self.type_and_perm & 0xF000 == 0x8000 {
             return Some(vfs::Node::File(
-                Rc::new(RefCell::new(Ext2File { inode: self, inode_addr, fs })) as Rc<RefCell<dyn IFile>>
+                Rc::new(RefCell::new(Ext2File::new(Some(self), inode_addr, fs))) as Rc<RefCell<dyn IFile>>
+            ));
+        }
+        // Character devices, block devices and fifos
+        if matches!(self.type_and_perm & 0xF000, 0x2000 | 0x6000 | 0x1000) {
+            return Some(vfs::Node::File(
+                Rc::new(RefCell::new(Ext2SpecialFile { inode: RefCell::new(Some(self)), inode_addr, fs })) as Rc<RefCell<dyn IFile>>
             ));
         }
         None
     }
 
+    // Returns: A node for the inode without reading it, based only on the type from its directory entry
+    fn lazy_vfs_node(fs: Rc<RefCell<Ext2FS>>, inode_addr: u32, entry_type: u8) -> Option<vfs::Node> {
+        match entry_type {
+            directory_entry_type::DIRECTORY => Some(vfs::Node::Folder(
+                Rc::new(RefCell::new(Ext2Folder { inode: RefCell::new(None), inode_addr, fs })) as Rc<RefCell<dyn IFolder>>
+            )),
+            directory_entry_type::REGULAR_FILE => Some(vfs::Node::File(
+                Rc::new(RefCell::new(Ext2File::new(None, inode_addr, fs))) as Rc<RefCell<dyn IFile>>
+            )),
+            directory_entry_type::CHARACTER_DEVICE | directory_entry_type::BLOCK_DEVICE | directory_entry_type::FIFO => Some(vfs::Node::File(
+                Rc::new(RefCell::new(Ext2SpecialFile { inode: RefCell::new(None), inode_addr, fs })) as Rc<RefCell<dyn IFile>>
+            )),
+            _ => None,
+        }
+    }
+
     pub fn get_size(&self) -> usize {
         // FIXME: Handle larger files
         self.low32_size as usize
     }
 
-    fn set_size(&mut self, new_size: usize) {
+    // NOTE: There are no users yet, so only the owner's write bit is checked
+    fn is_writable(&self) -> bool {
+        self.type_and_perm & MODE_OWNER_WRITE != 0
+    }
+
+    // Changes the permission bits, keeping the type
+    fn set_permissions(&mut self, mode: u32) {
+        self.type_and_perm = (self.type_and_perm & !MODE_PERMISSION_BITS) | (mode as u16 & MODE_PERMISSION_BITS);
+        self.creation_unix_timestamp = rtc::now_unix() as u32; // The inode change time
+    }
+
+    pub fn get_metadata(&self, inode_addr: u32) -> vfs::NodeMetadata {
+        vfs::NodeMetadata {
+            inode_number: u64::from(inode_addr),
+            hard_links: u64::from(self.hard_links_to_inode),
+            mode: u32::from(self.type_and_perm),
+            user_id: u32::from(self.user_id),
+            group_id: u32::from(self.group_id),
+            last_access_unix_timestamp: u64::from(self.last_access_unix_timestamp),
+            last_modif_unix_timestamp: u64::from(self.last_modif_unix_timestamp),
+            // NOTE: Despite the name the field is actually the inode change time ( i_ctime ), ext2 doesn't store when an inode was created
+            last_change_unix_timestamp: u64::from(self.creation_unix_timestamp),
+        }
+    }
+
+    fn set_size(&mut self, new_size: usize, fs: &Ext2FS) {
         self.low32_size = new_size as u32;
-        // FIXME: Can the size of a disk sector in ext2 ever be anything else than 512 bytes?
-        self.disk_sectors_used = new_size as u32 / 512 + if new_size as u32 % 512 != 0 { 1 } else { 0 };
+        // NOTE: This counts every block the inode uses, indirect blocks included, in 512 byte units no matter what the block size is
+        // Source: https://www.nongnu.org/ext2-doc/ext2.html#i-blocks
+        // The blocks are counted instead of being worked out from the size, as holes don't use any
+        // NOTE: Called with the blocks already (de)allocated, and also for slow symbolic links that don't have any blocks yet, so get_all_used_blocks can't be used
+        let number_of_blocks = self.follow_all_block_pointers(fs).len();
+        self.disk_sectors_used = (number_of_blocks * (fs.get_block_size() as usize / 512)) as u32;
+    }
+}
+
+// How many data blocks to read ahead of a sequential reader
+const READAHEAD_BLOCKS: usize = 8;
+
+// Returns: The inode, reading it from the disk first if this is the first time it's needed
+fn load_inode_if_needed<'a>(inode: &'a RefCell<Option<Ext2RawInode>>, inode_addr: u32, fs: &RefCell<Ext2FS>) -> RefMut<'a, Ext2RawInode> {
+    let mut inode = inode.borrow_mut();
+    if inode.is_none() {
+        *inode = Some(fs.borrow().read_inode(inode_addr).expect("Inode in directory should be readable!"));
     }
+    RefMut::map(inode, |inode| inode.as_mut().unwrap())
 }
 
 pub struct Ext2File {
-    inode: Ext2RawInode,
+    // NOTE: None until it's first needed, so that listing a directory doesn't have to read the inode of every child
+    inode: RefCell<Option<Ext2RawInode>>,
     inode_addr: u32,
     fs: Rc<RefCell<Ext2FS>>,
+    // Where the next read has to start for the reads to count as sequential
+    next_sequential_offset: Cell<u64>,
+    // The data blocks before this one have already been prefetched
+    readahead_end: Cell<usize>,
+}
+
+impl Ext2File {
+    fn new(inode: Option<Ext2RawInode>, inode_addr: u32, fs: Rc<RefCell<Ext2FS>>) -> Self {
+        Self { inode: RefCell::new(inode), inode_addr, fs, next_sequential_offset: Cell::new(0), readahead_end: Cell::new(0) }
+    }
+
+    fn inode(&self) -> RefMut<'_, Ext2RawInode> {
+        load_inode_if_needed(&self.inode, self.inode_addr, &self.fs)
+    }
+
+    // Prefetches the data blocks starting with the first one the reader hasn't touched yet, offset being where the last read ended
+    // NOTE: Only done once the reader catches up with what was prefetched last time, otherwise reading a file one byte at a time
+    // would look up READAHEAD_BLOCKS block pointers on every read
+    fn readahead(&self, offset: usize) {
+        let inode = self.inode();
+        let fs = self.fs.borrow();
+        let block_size = fs.get_block_size() as usize;
+        // NOTE: If the read ended exactly at the end of a block, the next block hasn't been read yet, so it has to be prefetched too
+        let first = offset.div_ceil(block_size);
+        if first < self.readahead_end.get() {
+            return;
+        }
+
+        // Don't read past the end of the file
+        let number_of_blocks = inode.get_size().div_ceil(block_size);
+        let end = core::cmp::min(first + READAHEAD_BLOCKS, number_of_blocks);
+        for data_block_number in first..end {
+            match inode.read_data_block_pointer(data_block_number, &fs) {
+                Some(0) | None => {} // Holes have nothing to read
+                Some(block) => fs.prefetch_block(block),
+            }
+        }
+        self.readahead_end.set(end);
+    }
 }
 
 impl vfs::IFile for Ext2File {
+    fn get_metadata(&self) -> Option<vfs::NodeMetadata> {
+        Some(self.inode().get_metadata(self.inode_addr))
+    }
+
+    fn set_mode(&mut self, mode: u32) -> Option<()> {
+        let mut inode = self.inode();
+        inode.set_permissions(mode);
+        self.fs.borrow_mut().write_inode(self.inode_addr, &inode)
+    }
+
+    fn get_block_map(&self) -> Option<Vec<u32>> {
+        self.inode().get_block_map(&*self.fs.borrow())
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+
+    fn sync(&mut self) -> Option<()> {
+        // NOTE: The inode is written every time it changes, but writing it again is cheap and makes sure that it did reach the disk
+        if let Some(inode) = &*self.inode.borrow() {
+            self.fs.borrow_mut().write_inode(self.inode_addr, inode)?;
+        }
+        self.fs.borrow_mut().sync()
+    }
+
     fn read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
-        self.inode.read_bytes(offset as usize, len, &*self.fs.borrow())
+        let res = self.inode().read_bytes(offset as usize, len, &*self.fs.borrow())?;
+
+        if offset == self.next_sequential_offset.get() {
+            self.readahead(offset as usize + len);
+        } else {
+            // Random access, forget about what was prefetched so that if it turns sequential again we start over from there
+            self.readahead_end.set(0);
+        }
+        self.next_sequential_offset.set(offset + len as u64);
+
+        Some(res)
     }
 
     fn write(&mut self, offset: u64, data: &[u8]) -> Option<usize> {
-        self.inode.write_bytes(offset as usize, data, &mut *self.fs.borrow_mut())
+        let mut inode = self.inode();
+        if !inode.is_writable() {
+            return None;
+        }
+        let bytes_written = inode.write_bytes(offset as usize, data, &mut *self.fs.borrow_mut())?;
+        inode.last_modif_unix_timestamp = rtc::now_unix() as u32;
+        self.fs.borrow_mut().write_inode(self.inode_addr, &inode)?;
+        Some(bytes_written)
     }
 
     fn get_size(&self) -> u64 {
-        self.inode.get_size() as u64
+        self.inode().get_size() as u64
     }
 
     fn resize(&mut self, new_size: u64) -> Option<()> {
-        self.inode.resize(new_size as usize, &mut *self.fs.borrow_mut())?;
-        self.fs.borrow_mut().write_inode(self.inode_addr, &self.inode)?;
+        let mut inode = self.inode();
+        if !inode.is_writable() {
+            return None;
+        }
+        inode.resize(new_size as usize, &mut *self.fs.borrow_mut())?;
+        inode.last_modif_unix_timestamp = rtc::now_unix() as u32;
+        self.fs.borrow_mut().write_inode(self.inode_addr, &inode)?;
         Some(())
     }
 }
 
+// A character device, block device or fifo, the inode only says which device it is ( or that it's a fifo ), there is no data on the disk
+// FIXME: Hook these up to the actual devices ( and implement fifos ), for now they can only be listed, stat-ed, chmod-ed and deleted
+pub struct Ext2SpecialFile {
+    // NOTE: None until it's first needed, same as for Ext2File
+    inode: RefCell<Option<Ext2RawInode>>,
+    inode_addr: u32,
+    fs: Rc<RefCell<Ext2FS>>,
+}
+
+impl Ext2SpecialFile {
+    fn inode(&self) -> RefMut<'_, Ext2RawInode> {
+        load_inode_if_needed(&self.inode, self.inode_addr, &self.fs)
+    }
+}
+
+impl vfs::IFile for Ext2SpecialFile {
+    fn get_metadata(&self) -> Option<vfs::NodeMetadata> {
+        Some(self.inode().get_metadata(self.inode_addr))
+    }
+
+    fn set_mode(&mut self, mode: u32) -> Option<()> {
+        let mut inode = self.inode();
+        inode.set_permissions(mode);
+        self.fs.borrow_mut().write_inode(self.inode_addr, &inode)
+    }
+
+    fn get_file_type(&self) -> vfs::FileType {
+        match self.inode().type_and_perm & 0xF000 {
+            0x2000 => vfs::FileType::CharacterDevice,
+            0x6000 => vfs::FileType::BlockDevice,
+            _ => vfs::FileType::Fifo,
+        }
+    }
+
+    // NOTE: Fifos are streams too, so they are treated the same as character devices
+    fn is_character_device(&self) -> bool {
+        self.get_file_type() != vfs::FileType::BlockDevice
+    }
+
+    fn read(&self, _offset: u64, _len: usize) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn write(&mut self, _offset: u64, _data: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn get_size(&self) -> u64 {
+        0
+    }
+
+    fn resize(&mut self, _new_size: u64) -> Option<()> {
+        None
+    }
+}
+
 pub struct Ext2Folder {
-    inode: Ext2RawInode,
+    // NOTE: None until it's first needed, same as for Ext2File
+    inode: RefCell<Option<Ext2RawInode>>,
     inode_addr: u32,
     fs: Rc<RefCell<Ext2FS>>,
 }
 
 impl Ext2Folder {
+    fn inode(&self) -> RefMut<'_, Ext2RawInode> {
+        load_inode_if_needed(&self.inode, self.inode_addr, &self.fs)
+    }
+
+    // Marks the hash index of the directory ( if it has one ) as out of date, has to be done whenever entries are added or removed,
+    // since we don't update the index
+    // NOTE: This is what implementations that don't support hash indexes are supposed to do, the directory is then read linearly
+    // Source: https://www.kernel.org/doc/html/latest/filesystems/ext4/directory.html#hash-tree-directories
+    fn invalidate_hash_index(inode: &mut Ext2RawInode) {
+        inode.flags &= !INODE_FLAG_INDEXED_DIRECTORY;
+    }
+
+    fn read_raw_entries(&self) -> Option<Vec<u8>> {
+        let inode = self.inode();
+        inode.read_bytes(0, inode.get_size() as usize, &*self.fs.borrow())
+    }
+
+    // Returns: The contents of a new, empty, folder with the inode address new_folder_inode_addr, that is a child of this folder
+    fn new_folder_entries(&self, new_folder_inode_addr: u32) -> Option<Vec<u8>> {
+        let fs = self.fs.borrow();
+        let header_size = Ext2FS::get_ondisk_directory_entry_header_size();
+        let dot = Ext2DirectoryEntryHeader {
+            inode_addr: new_folder_inode_addr,
+            entry_size: (header_size + 4) as u16, // Entries must be 4-byte aligned
+            name_length_low8: 1,
+            entry_type: fs.get_directory_entry_type(&vfs::NodeType::Folder),
+        };
+        // The last entry takes up the rest of the block
+        let dot_dot = Ext2DirectoryEntryHeader {
+            inode_addr: self.inode_addr,
+            entry_size: fs.get_block_size() as u16 - dot.entry_size,
+            name_length_low8: 2,
+            entry_type: fs.get_directory_entry_type(&vfs::NodeType::Folder),
+        };
+
+        let mut raw_entries = vec![0u8; fs.get_block_size() as usize];
+        let dot_dot_start = usize::from(dot.entry_size);
+        raw_entries[..header_size].copy_from_slice(&dot.pack().ok()?);
+        raw_entries[header_size] = b'.';
+        raw_entries[dot_dot_start..dot_dot_start + header_size].copy_from_slice(&dot_dot.pack().ok()?);
+        raw_entries[dot_dot_start + header_size..dot_dot_start + header_size + 2].copy_from_slice(b"..");
+        Some(raw_entries)
+    }
+
+    // NOTE: Directories with a hash index ( htree ) are read the same way, the index is hidden from linear readers on purpose:
+    // the root of the index lives after a ".." entry that spans the rest of the first block, and the other index blocks look like a single
+    // unused entry ( inode 0 ) spanning the whole block, so all we see are the leaf blocks, which are normal blocks of entries
+    // FIXME: Use the hash index to find entries in O(log n) instead of going through all of them
     fn get_entries(&self) -> Vec<(usize, Ext2DirectoryEntryHeader, alloc::string::String)> {
-        let Some(raw_data) = self.inode.read_bytes(0, self.inode.get_size() as usize, &*self.fs.borrow()) else {
+        let Some(raw_data) = self.read_raw_entries() else {
             return Vec::new();
         };
+        let has_entry_types = self.fs.borrow().has_directory_entry_types();
         let mut cur_ind = 0;
 
         let mut res = Vec::new();
-        while cur_ind < raw_data.len() {
+        while cur_ind + Ext2FS::get_ondisk_directory_entry_header_size() <= raw_data.len() {
             let start_ind = cur_ind;
             let entry = Ext2DirectoryEntryHeader::unpack(
                 raw_data[cur_ind..cur_ind + Ext2FS::get_ondisk_directory_entry_header_size()]
@@ -764,6 +1098,10 @@ impl Ext2Folder {
                     .expect("Reading directory entry should always work!"),
             )
             .expect("Parsing directory entry should always work!");
+            if usize::from(entry.entry_size) < Ext2FS::get_ondisk_directory_entry_header_size() {
+                // A corrupt entry, we can't know where the next one is, so there is no way to keep going
+                break;
+            }
             cur_ind += Ext2FS::get_ondisk_directory_entry_header_size();
 
             if entry.inode_addr == 0 {
@@ -773,7 +1111,7 @@ impl Ext2Folder {
                 continue;
             }
 
-            let name: &str = from_utf8(&raw_data[cur_ind..cur_ind + entry.name_length_low8 as usize])
+            let name: &str = from_utf8(&raw_data[cur_ind..cur_ind + entry.name_length(has_entry_types)])
                 .expect("Ext2 inode name in directory entry should be valid utf-8!");
             cur_ind += entry.entry_size as usize - Ext2FS::get_ondisk_directory_entry_header_size();
             res.push((start_ind, entry, name.to_owned()))
@@ -781,51 +1119,93 @@ impl Ext2Folder {
         res
     }
 
-    fn write_entry_header_to_buffer(
-        &mut self,
-        raw_data: &mut [u8],
-        entry: &(usize, Ext2DirectoryEntryHeader, alloc::string::String),
-    ) -> Option<()> {
-        let mut indx = entry.0;
-        for byte in entry.1.pack().ok()? {
-            raw_data[indx] = byte;
-            indx += 1;
+    // Adds an entry for inode_addr named name, the new entry always becomes the last one
+    // NOTE: Doesn't change the number of hard links to anything, that's up to the caller
+    // NOTE: entry_type is ignored if the filesystem doesn't use the directory entry type field
+    fn add_entry(&mut self, name: &str, inode_addr: u32, entry_type: u8) -> Option<()> {
+        if name.len() > MAX_NAME_LENGTH {
+            return None;
         }
-        Some(())
-    }
+        let has_entry_types = self.fs.borrow().has_directory_entry_types();
+        let mut entries = self.get_entries();
+        let last_entry: &mut (usize, Ext2DirectoryEntryHeader, alloc::string::String) = entries.last_mut()?;
+        let mut raw_data = self.read_raw_entries()?;
 
-    fn write_entry_string_to_buffer(
-        &mut self,
-        raw_data: &mut [u8],
-        entry: &(usize, Ext2DirectoryEntryHeader, alloc::string::String),
-    ) -> Option<()> {
-        let mut indx = entry.0 + Ext2FS::get_ondisk_directory_entry_header_size();
-        for byte in entry.2.bytes() {
-            raw_data[indx] = byte;
-            indx += 1;
-        }
-        Some(())
-    }
-}
+        let mut new_entry_header = Ext2DirectoryEntryHeader {
+            inode_addr,
+            entry_size: name.len() as u16 + Ext2FS::get_ondisk_directory_entry_header_size() as u16,
+            name_length_low8: name.len() as u8,
+            entry_type: if has_entry_types { entry_type } else { (name.len() >> 8) as u8 },
+        };
 
-impl IFolder for Ext2Folder {
-    fn get_children(&self) -> Vec<(alloc::string::String, vfs::Node)> {
-        self.get_entries()
-            .into_iter()
-            .map(|(_, entry, name)| {
-                let child_inode =
-                    self.fs.borrow().read_inode(entry.inode_addr).expect("Inode in directory should be readable!");
-                (
-                    name,
-                    child_inode
-                        .as_vfs_node(self.fs.clone(), entry.inode_addr)
-                        .expect("Inodes should be parsable as vfs nodes!"),
-                )
-            })
-            .collect()
+        let new_entry_first_byte: usize = {
+            // Test to see if the entry could fit in the free space of the last entry in the list
+            // And if so shrink the last entry and put the new entry there, otherwise put the new entry after the last entry
+            // So the new entry will always become the new last entry
+
+            let mut actual_space_used_by_last_entry =
+                Ext2FS::get_ondisk_directory_entry_header_size() + last_entry.1.name_length(has_entry_types);
+            // Comply with the requirement that entries must be 4-byte aligned when calculating if there is enough free space and when updating the size of the last entry if there is enough free space
+            // https://www.nongnu.org/ext2-doc/ext2.html#directory
+            if actual_space_used_by_last_entry % 4 != 0 {
+                actual_space_used_by_last_entry += 4 - (actual_space_used_by_last_entry % 4);
+            }
+
+            let free_space_in_last_entry = last_entry.1.entry_size as usize - actual_space_used_by_last_entry;
+
+            if free_space_in_last_entry >= new_entry_header.entry_size as usize {
+                // Shrink the last entry
+                last_entry.1.entry_size = actual_space_used_by_last_entry as u16;
+
+                // Write the updated last entry header to buffer
+                self.write_entry_header_to_buffer(&mut raw_data, last_entry)?;
+            }
+
+            // This is fine since the last entry is either pointing to the end of the block, so the new entry will NOT
+            // span a block boundry and it will be 4-byte aligned
+            // Or we just shrunk it because the new entry would fit in the current block, and since we shrunk it to a multiple of 4,
+            // the new entry will be 4-byte aligned and NOT span a block boundry
+            last_entry.0 + usize::from(last_entry.1.entry_size)
+        };
+
+        // Grow new entry to the end of the current block
+        let location_of_new_entry_end_in_block =
+            (new_entry_first_byte + new_entry_header.entry_size as usize) % (self.fs.borrow().get_block_size() as usize);
+        // Note location_of_new_entry_end_in_block points one past the end of the entry, because new_entry_first_byte+new_entry.entry_size points one past the end of the entry
+        // This is correct, since if the last byte is byte 0 of the current block, then we only want to grow by 1023 bytes, but 1024-0 = 1024, but 1024-1 = 1023,
+        // so location_of_new_entry_end_in_block pointing one past the end is correct
+        let space_to_grow_by = self.fs.borrow().get_block_size() as usize - location_of_new_entry_end_in_block;
+        new_entry_header.entry_size += space_to_grow_by as u16;
+
+        // Then write the new entry to disk
+        //----------------------------------
+
+        raw_data.resize(new_entry_first_byte + usize::from(new_entry_header.entry_size), 0);
+        let new_entry_size = usize::from(new_entry_header.entry_size);
+
+        // Write new entry
+        let new_entry = (new_entry_first_byte, new_entry_header, name.to_owned());
+        self.write_entry_header_to_buffer(&mut raw_data, &new_entry)?;
+        self.write_entry_string_to_buffer(&mut raw_data, &new_entry)?;
+
+        // Resize inode(directory) to fit new entry
+        let mut inode = self.inode();
+        inode.resize(new_entry_first_byte + new_entry_size, &mut *self.fs.borrow_mut())?;
+
+        // Update inode(directory), to update its size
+        inode.last_modif_unix_timestamp = rtc::now_unix() as u32;
+        Self::invalidate_hash_index(&mut inode);
+        self.fs.borrow_mut().write_inode(self.inode_addr, &inode)?;
+
+        // Update directory entries
+        assert!(inode.get_size() == raw_data.len());
+        inode.write_bytes(0, &raw_data, &mut *self.fs.borrow_mut())?;
+        Some(())
     }
 
-    fn unlink_or_delete_empty_child(&mut self, child_name: &str) -> Option<()> {
+    // Removes the entry named child_name
+    // NOTE: Doesn't change the number of hard links to anything, that's up to the caller
+    fn remove_entry(&mut self, child_name: &str) -> Option<()> {
         let mut child = None;
         let mut last = None;
         for e in self.get_entries() {
@@ -836,24 +1216,144 @@ impl IFolder for Ext2Folder {
             last = Some(e);
         }
         let child = child?;
-        let mut last = last?;
+
+        let mut raw_data = self.read_raw_entries()?;
+
+        if let Some(mut last) = last.filter(|last| last.0 + usize::from(last.1.entry_size) == child.0) {
+            // Delete entry, by updating last entry to point past this entry
+            // FIXME: This "leaks" the entry currently, though it is possible to clean it up later
+            last.1.entry_size += child.1.entry_size;
+            self.write_entry_header_to_buffer(&mut raw_data, &last);
+        } else {
+            // The entry is the first one in its block ( or comes after an unused entry, which get_entries skips ), and entries can't span blocks,
+            // so mark the entry itself as unused instead
+            let mut child = child;
+            child.1.inode_addr = 0;
+            self.write_entry_header_to_buffer(&mut raw_data, &child);
+        }
+
+        // Update directory entries
+        // NOTE: No need to change(shrink) inode(directory) size, since we just "leak" the entry the size of the inode shouldn't change
+        let mut inode = self.inode();
+        if inode.flags & INODE_FLAG_INDEXED_DIRECTORY != 0 {
+            Self::invalidate_hash_index(&mut inode);
+            self.fs.borrow_mut().write_inode(self.inode_addr, &inode)?;
+        }
+        assert!(inode.get_size() == raw_data.len());
+        if inode.write_bytes(0, &raw_data, &mut *self.fs.borrow_mut())? != raw_data.len() {
+            return None;
+        }
+
+        Some(())
+    }
+
+    // Points the ".." entry at a new parent, for when the folder is moved to another folder
+    fn set_parent(&mut self, parent_inode_addr: u32) -> Option<()> {
+        let mut dot_dot = self.get_entries().into_iter().find(|(_, _, name)| name == "..")?;
+        dot_dot.1.inode_addr = parent_inode_addr;
+        let mut raw_data = self.read_raw_entries()?;
+        self.write_entry_header_to_buffer(&mut raw_data, &dot_dot)?;
+
+        let inode = self.inode();
+        if inode.write_bytes(0, &raw_data, &mut *self.fs.borrow_mut())? != raw_data.len() {
+            return None;
+        }
+        Some(())
+    }
+
+    fn write_entry_header_to_buffer(
+        &mut self,
+        raw_data: &mut [u8],
+        entry: &(usize, Ext2DirectoryEntryHeader, alloc::string::String),
+    ) -> Option<()> {
+        let mut indx = entry.0;
+        for byte in entry.1.pack().ok()? {
+            raw_data[indx] = byte;
+            indx += 1;
+        }
+        Some(())
+    }
+
+    fn write_entry_string_to_buffer(
+        &mut self,
+        raw_data: &mut [u8],
+        entry: &(usize, Ext2DirectoryEntryHeader, alloc::string::String),
+    ) -> Option<()> {
+        let mut indx = entry.0 + Ext2FS::get_ondisk_directory_entry_header_size();
+        for byte in entry.2.bytes() {
+            raw_data[indx] = byte;
+            indx += 1;
+        }
+        Some(())
+    }
+}
+
+impl IFolder for Ext2Folder {
+    fn get_children(&self) -> Vec<(alloc::string::String, vfs::Node)> {
+        let has_entry_types = self.fs.borrow().has_directory_entry_types();
+        self.get_entries()
+            .into_iter()
+            .filter_map(|(_, entry, name)| {
+                // If the entry says what the child is then there's no need to read the child's inode ( yet )
+                // NOTE: The type can still be unknown ( 0 ), or something we can't make a node for, then the inode decides
+                if has_entry_types {
+                    if let Some(node) = Ext2RawInode::lazy_vfs_node(self.fs.clone(), entry.inode_addr, entry.entry_type) {
+                        return Some((name, node));
+                    }
+                }
+
+                let child_inode =
+                    self.fs.borrow().read_inode(entry.inode_addr).expect("Inode in directory should be readable!");
+                // FIXME: Symbolic links and sockets have no vfs node yet, so they are left out
+                Some((name, child_inode.as_vfs_node(self.fs.clone(), entry.inode_addr)?))
+            })
+            .collect()
+    }
+
+    fn unlink_or_delete_empty_child(&mut self, child_name: &str) -> Option<()> {
+        let child = self.get_entries().into_iter().find(|e| e.2 == child_name)?;
 
         {
             // Update inode that is being unlinked/deleted
             let mut child_inode =
                 self.fs.borrow().read_inode(child.1.inode_addr).expect("Inode in directory entry should be valid!");
 
-            if child_inode.hard_links_to_inode >= 1 {
+            if child_inode.type_and_perm & 0xF000 == 0x4000 {
+                // Folders can only be deleted if they are empty, which means they can only have "." and ".."
+                let child_folder = Ext2Folder { inode: RefCell::new(None), inode_addr: child.1.inode_addr, fs: self.fs.clone() };
+                let child_entries = child_folder.get_entries();
+                if child_entries.iter().any(|(_, _, name)| name != "." && name != "..") {
+                    return None;
+                }
+
+                // The folder's "." goes away together with our entry
+                child_inode.hard_links_to_inode = child_inode.hard_links_to_inode.saturating_sub(2);
+                if child_inode.hard_links_to_inode == 0 {
+                    child_inode.resize(0, &mut *self.fs.borrow_mut())?;
+                    self.fs.borrow_mut().dealloc_inode(child.1.inode_addr)?;
+                    self.fs.borrow_mut().adjust_directories_in_group(child.1.inode_addr, false)?;
+                    child_inode.deletion_unix_timestamp = rtc::now_unix() as u32;
+                }
+
+                // And so does the link to us from its ".."
+                if child_entries.iter().any(|(_, entry, name)| name == ".." && entry.inode_addr == self.inode_addr) {
+                    let mut inode = self.inode();
+                    inode.hard_links_to_inode = inode.hard_links_to_inode.saturating_sub(1);
+                    self.fs.borrow_mut().write_inode(self.inode_addr, &inode)?;
+                }
+            } else if child_inode.hard_links_to_inode >= 1 {
                 child_inode.hard_links_to_inode -= 1;
 
-                // If inode is no longer hard linked to fs then try to fully deallocate it
+                // If inode is no longer hard linked to fs then fully deallocate it, data included
+                // NOTE: As long as there are other links the data has to stay, since it can still be reached through them
                 if child_inode.hard_links_to_inode == 0 {
-                    if child_inode.get_size() != 0 {
+                    if child_inode.resize(0, &mut *self.fs.borrow_mut()).is_none() {
                         child_inode.hard_links_to_inode = 1;
                         return None;
                     }
 
                     self.fs.borrow_mut().dealloc_inode(child.1.inode_addr)?;
+                    child_inode.deletion_unix_timestamp = rtc::now_unix() as u32;
                 }
             }
 
@@ -861,24 +1361,7 @@ impl IFolder for Ext2Folder {
             self.fs.borrow_mut().write_inode(child.1.inode_addr, &child_inode)?;
         }
 
-        // Delete entry, by updating last entry to point past this entry
-        // FIXME: This "leaks" the entry currently, though it is possible to clean it up later
-
-        last.1.entry_size += child.1.entry_size;
-
-        let mut raw_data = self.inode.read_bytes(0, self.inode.get_size() as usize, &*self.fs.borrow())?;
-
-        // Write updated last entry to raw data
-        self.write_entry_header_to_buffer(&mut raw_data, &last);
-
-        // Update directory entries
-        // NOTE: No need to change(shrink) inode(directory) size, so no need to update inode(directory), since we just "leak" the entry the size of the inode shouldn't change
-        assert!(self.inode.get_size() == raw_data.len());
-        if self.inode.write_bytes(0, &raw_data, &mut *self.fs.borrow_mut())? != raw_data.len() {
-            return None;
-        }
-
-        Some(())
+        self.remove_entry(child_name)
     }
 
     fn create_empty_child(&mut self, name: &str, typ: vfs::NodeType) -> Option<vfs::Node> {
@@ -886,8 +1369,8 @@ impl IFolder for Ext2Folder {
         //---------------------------------
 
         let mut new_child = Ext2RawInode::default();
-        let mut entries = self.get_entries();
-        let last_entry: &mut (usize, Ext2DirectoryEntryHeader, alloc::string::String) = entries.last_mut()?;
+        let entries = self.get_entries();
+        let last_entry = entries.last()?;
 
         let get_appropriate_descriptor_index =
             || -> Option<u32> { Some(Ext2FS::get_descriptor_index_of_inode_addr(&self.fs.borrow(), last_entry.1.inode_addr)) };
@@ -902,95 +1385,187 @@ impl IFolder for Ext2Folder {
 
         new_child.hard_links_to_inode = 1;
 
+        let now = rtc::now_unix() as u32;
+        new_child.creation_unix_timestamp = now;
+        new_child.last_access_unix_timestamp = now;
+        new_child.last_modif_unix_timestamp = now;
+
+        let is_folder = matches!(typ, vfs::NodeType::Folder);
+        if is_folder {
+            // Every directory starts out with "." and ".." entries, and "." is another link to it
+            new_child.hard_links_to_inode = 2;
+            let raw_entries = self.new_folder_entries(new_child_inode_addr)?;
+            new_child.resize(raw_entries.len(), &mut *self.fs.borrow_mut())?;
+            new_child.write_bytes(0, &raw_entries, &mut *self.fs.borrow_mut())?;
+            self.fs.borrow_mut().adjust_directories_in_group(new_child_inode_addr, true)?;
+        }
+
         self.fs.borrow_mut().write_inode(new_child_inode_addr, &new_child)?;
 
-        // We don't need to mutate new_child anymore, and name is only ever used as bytes from here on
+        // We don't need to mutate new_child anymore
         let new_child = new_child;
 
         // Then create a new directory entry
         //-----------------------------------
 
-        let mut raw_data = self.inode.read_bytes(0, self.inode.get_size() as usize, &*self.fs.borrow())?;
+        let entry_type = self.fs.borrow().get_directory_entry_type(&typ);
+        self.add_entry(name, new_child_inode_addr, entry_type)?;
+        if is_folder {
+            let mut inode = self.inode();
+            inode.hard_links_to_inode += 1; // From the new folder's ".."
+            self.fs.borrow_mut().write_inode(self.inode_addr, &inode)?;
+        }
 
-        let mut new_entry_header = {
-            let mut entry_type = 0;
-            if let Some(esb) = &self.fs.borrow().extended_sb {
-                if esb.has_required_feature_directory_entry_type_field() {
-                    entry_type = match typ {
-                        vfs::NodeType::File => 1,
-                        vfs::NodeType::Folder => 2,
-                    };
-                }
-            }
+        Some(new_child.as_vfs_node(self.fs.clone(), new_child_inode_addr).expect("New child inode should be valid!"))
+    }
 
-            Ext2DirectoryEntryHeader {
-                inode_addr: new_child_inode_addr,
-                entry_size: name.len() as u16 + Ext2FS::get_ondisk_directory_entry_header_size() as u16,
-                name_length_low8: name.len() as u8,
-                entry_type,
+    // NOTE: Only the directory entries change, the inode and the data stay where they are
+    // NOTE: Entries can only be moved to folders on the same filesystem
+    fn can_rename_children_to(&mut self, new_parent: Option<&mut dyn IFolder>) -> bool {
+        let Some(new_parent) = new_parent else {
+            return true;
+        };
+        new_parent
+            .as_any_mut()
+            .and_then(|new_parent| new_parent.downcast_mut::<Ext2Folder>())
+            .is_some_and(|new_parent| Rc::ptr_eq(&self.fs, &new_parent.fs))
+    }
+
+    fn rename_child(&mut self, old_name: &str, new_parent: Option<&mut dyn IFolder>, new_name: &str) -> Option<()> {
+        let mut new_parent = match new_parent {
+            Some(new_parent) => {
+                let new_parent = new_parent.as_any_mut()?.downcast_mut::<Ext2Folder>()?;
+                if !Rc::ptr_eq(&self.fs, &new_parent.fs) {
+                    return None;
+                }
+                Some(new_parent)
             }
+            None => None,
         };
 
-        let new_entry_first_byte: usize = {
-            // Test to see if the entry could fit in the free space of the last entry in the list
-            // And if so shrink the last entry and put the new entry there, otherwise put the new entry after the last entry
-            // So the new entry will always become the new last entry
+        if old_name == "." || old_name == ".." {
+            return None;
+        }
+        let (_, entry, _) = self.get_entries().into_iter().find(|(_, _, name)| name == old_name)?;
+        let is_folder = self.fs.borrow().read_inode(entry.inode_addr)?.type_and_perm & 0xF000 == 0x4000;
 
-            let mut actual_space_used_by_last_entry =
-                Ext2FS::get_ondisk_directory_entry_header_size() + usize::from(last_entry.1.name_length_low8);
-            // Comply with the requirement that entries must be 4-byte aligned when calculating if there is enough free space and when updating the size of the last entry if there is enough free space
-            // https://www.nongnu.org/ext2-doc/ext2.html#directory
-            if actual_space_used_by_last_entry % 4 != 0 {
-                actual_space_used_by_last_entry += 4 - (actual_space_used_by_last_entry % 4);
-            }
+        let new_parent_entries = match &new_parent {
+            Some(new_parent) => new_parent.get_entries(),
+            None => self.get_entries(),
+        };
+        if new_parent_entries.iter().any(|(_, _, name)| name == new_name) {
+            return None;
+        }
 
-            let free_space_in_last_entry = last_entry.1.entry_size as usize - actual_space_used_by_last_entry;
+        // Add the new entry first, so that if something fails the child is never left without an entry
+        match &mut new_parent {
+            Some(new_parent) => new_parent.add_entry(new_name, entry.inode_addr, entry.entry_type)?,
+            None => self.add_entry(new_name, entry.inode_addr, entry.entry_type)?,
+        }
+        self.remove_entry(old_name)?;
 
-            if free_space_in_last_entry >= new_entry_header.entry_size as usize {
-                // Shrink the last entry
-                last_entry.1.entry_size = actual_space_used_by_last_entry as u16;
+        // A folder's ".." is a link to its parent, so it has to follow the folder
+        if let Some(new_parent) = new_parent.filter(|_| is_folder) {
+            let mut child_folder = Ext2Folder { inode: RefCell::new(None), inode_addr: entry.inode_addr, fs: self.fs.clone() };
+            child_folder.set_parent(new_parent.inode_addr)?;
 
-                // Write the updated last entry header to buffer
-                self.write_entry_header_to_buffer(&mut raw_data, last_entry)?;
-            }
+            let mut new_parent_inode = new_parent.inode();
+            new_parent_inode.hard_links_to_inode += 1;
+            self.fs.borrow_mut().write_inode(new_parent.inode_addr, &new_parent_inode)?;
 
-            // This is fine since the last entry is either pointing to the end of the block, so the new entry will NOT
-            // span a block boundry and it will be 4-byte aligned
-            // Or we just shrunk it because the new entry would fit in the current block, and since we shrunk it to a multiple of 4,
-            // the new entry will be 4-byte aligned and NOT span a block boundry
-            last_entry.0 + usize::from(last_entry.1.entry_size)
+            let mut inode = self.inode();
+            inode.hard_links_to_inode = inode.hard_links_to_inode.saturating_sub(1);
+            self.fs.borrow_mut().write_inode(self.inode_addr, &inode)?;
+        }
+        Some(())
+    }
+
+    // NOTE: Only files can be linked, links to folders would make loops possible
+    fn link_child(&mut self, existing: &vfs::Node, new_name: &str) -> Option<()> {
+        let vfs::Node::File(existing) = existing else {
+            return None;
         };
+        let mut existing = existing.borrow_mut();
+        // Links can only be made to files on the same filesystem
+        let existing = existing.as_any_mut()?.downcast_mut::<Ext2File>()?;
+        if !Rc::ptr_eq(&self.fs, &existing.fs) {
+            return None;
+        }
+        if self.get_entries().iter().any(|(_, _, name)| name == new_name) {
+            return None;
+        }
 
-        // Grow new entry to the end of the current block
-        let location_of_new_entry_end_in_block =
-            (new_entry_first_byte + new_entry_header.entry_size as usize) % (self.fs.borrow().get_block_size() as usize);
-        // Note location_of_new_entry_end_in_block points one past the end of the entry, because new_entry_first_byte+new_entry.entry_size points one past the end of the entry
-        // This is correct, since if the last byte is byte 0 of the current block, then we only want to grow by 1023 bytes, but 1024-0 = 1024, but 1024-1 = 1023,
-        // so location_of_new_entry_end_in_block pointing one past the end is correct
-        let space_to_grow_by = self.fs.borrow().get_block_size() as usize - location_of_new_entry_end_in_block;
-        new_entry_header.entry_size += space_to_grow_by as u16;
+        // Count the link first, an extra link only wastes the inode if adding the entry fails, one too few would free it while it's still in use
+        let mut existing_inode = existing.inode();
+        existing_inode.hard_links_to_inode = existing_inode.hard_links_to_inode.checked_add(1)?;
+        self.fs.borrow_mut().write_inode(existing.inode_addr, &existing_inode)?;
+        drop(existing_inode);
 
-        // Then write the new entry to disk
-        //----------------------------------
+        let entry_type = self.fs.borrow().get_directory_entry_type(&vfs::NodeType::File);
+        self.add_entry(new_name, existing.inode_addr, entry_type)
+    }
 
-        raw_data.resize(new_entry_first_byte + usize::from(new_entry_header.entry_size), 0);
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
 
-        // Resize inode(directory) to fit new entry
-        self.inode.resize(new_entry_first_byte + usize::from(new_entry_header.entry_size), &mut *self.fs.borrow_mut())?;
+    fn get_fs_stats(&self) -> Option<vfs::FileSystemStats> {
+        Some(self.fs.borrow().get_stats())
+    }
 
-        // Update inode(directory), to update its size
-        self.fs.borrow_mut().write_inode(self.inode_addr, &self.inode)?;
+    fn get_metadata(&self) -> Option<vfs::NodeMetadata> {
+        Some(self.inode().get_metadata(self.inode_addr))
+    }
 
-        // Write new entry
-        let new_entry = (new_entry_first_byte, new_entry_header, name.to_owned());
-        self.write_entry_header_to_buffer(&mut raw_data, &new_entry)?;
-        self.write_entry_string_to_buffer(&mut raw_data, &new_entry)?;
+    fn set_mode(&mut self, mode: u32) -> Option<()> {
+        let mut inode = self.inode();
+        inode.set_permissions(mode);
+        self.fs.borrow_mut().write_inode(self.inode_addr, &inode)
+    }
 
-        // Update directory entries
-        assert!(self.inode.get_size() == raw_data.len());
-        self.inode.write_bytes(0, &raw_data, &mut *self.fs.borrow_mut())?;
+    fn sync(&mut self) -> Option<()> {
+        if let Some(inode) = &*self.inode.borrow() {
+            self.fs.borrow_mut().write_inode(self.inode_addr, inode)?;
+        }
+        self.fs.borrow_mut().sync()
+    }
+}
 
-        Some(new_child.as_vfs_node(self.fs.clone(), new_child_inode_addr).expect("New child inode should be valid!"))
+// Something that Ext2FS::check found to be wrong with the filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum Ext2Inconsistency {
+    BLOCK_IN_USE_BUT_MARKED_FREE { block: u32 },
+    BLOCK_MARKED_IN_USE_BUT_UNUSED { block: u32 }, // Leaked, it will never be freed
+    BLOCK_USED_MORE_THAN_ONCE { block: u32 },
+    BLOCK_OUT_OF_RANGE { inode_addr: u32, block: u32 },
+    GROUP_METADATA_OUT_OF_RANGE { group: u32, block: u32 }, // A bitmap or the inode table of the group isn't on the disk
+    INODE_IN_USE_BUT_MARKED_FREE { inode_addr: u32 },
+    INODE_MARKED_IN_USE_BUT_UNUSED { inode_addr: u32 },
+    WRONG_FREE_BLOCKS_IN_GROUP { group: u32, recorded: u32, actual: u32 },
+    WRONG_FREE_INODES_IN_GROUP { group: u32, recorded: u32, actual: u32 },
+    WRONG_DIRECTORIES_IN_GROUP { group: u32, recorded: u32, actual: u32 },
+    WRONG_FREE_BLOCKS_IN_SUPERBLOCK { recorded: u32, actual: u32 },
+    WRONG_FREE_INODES_IN_SUPERBLOCK { recorded: u32, actual: u32 },
+}
+
+impl core::fmt::Display for Ext2Inconsistency {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use Ext2Inconsistency::*;
+        match *self {
+            BLOCK_IN_USE_BUT_MARKED_FREE { block } => write!(f, "Block {} is in use, but the bitmap says it's free", block),
+            BLOCK_MARKED_IN_USE_BUT_UNUSED { block } => write!(f, "Block {} is not used by anything, but the bitmap says it's in use", block),
+            BLOCK_USED_MORE_THAN_ONCE { block } => write!(f, "Block {} is used more than once", block),
+            BLOCK_OUT_OF_RANGE { inode_addr, block } => write!(f, "Inode {} points to block {}, which is not on the disk", inode_addr, block),
+            GROUP_METADATA_OUT_OF_RANGE { group, block } => write!(f, "Block group {} says its bitmaps or inode table are at block {}, which is not on the disk", group, block),
+            INODE_IN_USE_BUT_MARKED_FREE { inode_addr } => write!(f, "Inode {} is in use, but the bitmap says it's free", inode_addr),
+            INODE_MARKED_IN_USE_BUT_UNUSED { inode_addr } => write!(f, "Inode {} is not in use, but the bitmap says it is", inode_addr),
+            WRONG_FREE_BLOCKS_IN_GROUP { group, recorded, actual } => write!(f, "Block group {} says it has {} free blocks, but it has {}", group, recorded, actual),
+            WRONG_FREE_INODES_IN_GROUP { group, recorded, actual } => write!(f, "Block group {} says it has {} free inodes, but it has {}", group, recorded, actual),
+            WRONG_DIRECTORIES_IN_GROUP { group, recorded, actual } => write!(f, "Block group {} says it has {} directories, but it has {}", group, recorded, actual),
+            WRONG_FREE_BLOCKS_IN_SUPERBLOCK { recorded, actual } => write!(f, "The superblock says there are {} free blocks, but there are {}", recorded, actual),
+            WRONG_FREE_INODES_IN_SUPERBLOCK { recorded, actual } => write!(f, "The superblock says there are {} free inodes, but there are {}", recorded, actual),
+        }
     }
 }
 
@@ -1048,6 +1623,10 @@ impl Ext2FS {
                 read_only = true;
             }
 
+            if esb.has_optional_feature_directory_index() {
+                writeln!(UART.lock(), "NOTE: Ext2FS has hash indexed directories, they will be read without using the index").unwrap();
+            }
+
             if esb.has_write_required_feature_directory_contents_binary_tree() {
                 writeln!(UART.lock(), "WARNING: Ext2FS uses a binary tree to store directory contents, which is not supported, mounting as read-only!").unwrap();
                 read_only = true;
@@ -1057,8 +1636,7 @@ impl Ext2FS {
             // Actual level of support
             // 64-bit file sizes: full support, but not really tested
             // directory entry type field: full support, but not really tested
-            // sparse superblocks and group descriptor tables: lol no support, but i don't think it actually matters unless the filesystem gets corrupted so honestly it's more of an optional feature anyways
-            // nonetheless i should probs FIXME add support for sparse superblocks and group descriptor tables, to comply with the spec
+            // sparse superblocks and group descriptor tables: full support, the backups are written to the right groups by flush_backup_super_blocks
 
             extended_sb = Some(esb);
         }
@@ -1121,6 +1699,41 @@ impl Ext2FS {
         self.read(self.get_block_size() * number, self.get_block_size() as usize)
     }
 
+    pub fn has_directory_entry_types(&self) -> bool {
+        self.extended_sb.as_ref().map(|esb| esb.has_required_feature_directory_entry_type_field()).unwrap_or(false)
+    }
+
+    // Returns: What the entry_type of a directory entry pointing to a node of type typ should be
+    pub fn get_directory_entry_type(&self, typ: &vfs::NodeType) -> u8 {
+        if !self.has_directory_entry_types() {
+            return 0;
+        }
+        match typ {
+            vfs::NodeType::File => directory_entry_type::REGULAR_FILE,
+            vfs::NodeType::Folder => directory_entry_type::DIRECTORY,
+        }
+    }
+
+    // Keeps track of how many directories the block group of the inode has, created is false when a directory is deleted
+    pub fn adjust_directories_in_group(&mut self, inode_addr: u32, created: bool) -> Option<()> {
+        let block_group_descriptor_index = self.get_descriptor_index_of_inode_addr(inode_addr);
+        let mut descriptor = self.read_block_group_descriptor(block_group_descriptor_index)?;
+        if created {
+            descriptor.directories_in_group += 1;
+        } else {
+            descriptor.directories_in_group = descriptor.directories_in_group.checked_sub(1)?;
+        }
+        self.write_block_group_descriptor(block_group_descriptor_index, &descriptor)
+    }
+
+    // Hints to the backing device that the block will be read soon
+    pub fn prefetch_block(&self, number: u32) {
+        if number == 0 {
+            return;
+        }
+        (*self.backing_device).borrow().prefetch(self.get_block_size() as u64 * number as u64, self.get_block_size() as usize);
+    }
+
     pub fn write_block(&mut self, number: u32, data: &[u8]) -> Option<()> {
         assert!(
             data.len() == self.get_block_size() as usize,
@@ -1175,6 +1788,11 @@ impl Ext2FS {
     }
 
     pub fn alloc_block(&mut self, block_group_descriptor_index: u32) -> Option<u32> {
+        self.alloc_block_starting_at(block_group_descriptor_index, 0)
+    }
+
+    // Allocates the first free block in the block group that comes at or after start_subindex
+    pub fn alloc_block_starting_at(&mut self, block_group_descriptor_index: u32, start_subindex: u32) -> Option<u32> {
         if self.sb.unallocated_blocks == 0 {
             return None;
         }
@@ -1186,20 +1804,16 @@ impl Ext2FS {
 
         let mut bitmap = self.read_block(descriptor.block_addr_for_block_usage_bitmap)?;
 
-        let found_loc_and_byte = bitmap.iter().cloned().enumerate().find(|(_, val)| *val != 0xff)?;
-        let mut found_bit = 0;
-        while (found_loc_and_byte.1 >> found_bit) & 1 != 0
-        /* 0 == free */
-        {
-            found_bit += 1;
-        }
-        let free_block_in_blockgroup = found_loc_and_byte.0 * 8 + found_bit;
+        // Search for a free block starting at start_subindex, wrapping around to the start of the group
+        let blocks_in_bitmap = core::cmp::min(self.sb.blocks_per_block_group as usize, bitmap.len() * 8);
+        let start_subindex = start_subindex as usize % blocks_in_bitmap;
+        let is_free = |block_in_blockgroup: usize| (bitmap[block_in_blockgroup / 8] >> (block_in_blockgroup % 8)) & 1 == 0;
+        let free_block_in_blockgroup =
+            (start_subindex..blocks_in_bitmap).chain(0..start_subindex).find(|block| is_free(*block))?;
 
-        let block_pointer_to_allocate = free_block_in_blockgroup as u32
-            + block_group_descriptor_index * self.sb.blocks_per_block_group
-            + self.get_number_of_special_blocks() as u32;
+        let block_pointer_to_allocate = self.get_first_block_of_group(block_group_descriptor_index) + free_block_in_blockgroup as u32;
 
-        bitmap[found_loc_and_byte.0] |= 1 << found_bit; // Mark as allocated
+        bitmap[free_block_in_blockgroup / 8] |= 1 << (free_block_in_blockgroup % 8); // Mark as allocated
 
         // Update bitmap
         self.write_block(descriptor.block_addr_for_block_usage_bitmap, &bitmap)?;
@@ -1218,19 +1832,45 @@ impl Ext2FS {
         return Some(block_pointer_to_allocate);
     }
 
-    pub fn alloc_block_close_to(&mut self, mut block_group_descriptor_index: u32) -> Option<u32> {
-        let new_block_pointer;
-        loop {
-            if let Some(ptr) = self.alloc_block(block_group_descriptor_index) {
-                new_block_pointer = ptr;
-                break;
+    // Tries the block group first, then the ones around it, closest ones first
+    pub fn alloc_block_close_to(&mut self, block_group_descriptor_index: u32) -> Option<u32> {
+        let number_of_block_groups = self.get_number_of_block_groups();
+        for distance in 0..number_of_block_groups {
+            if let Some(after) = block_group_descriptor_index.checked_add(distance).filter(|i| *i < number_of_block_groups) {
+                if let Some(ptr) = self.alloc_block(after) {
+                    return Some(ptr);
+                }
             }
-            block_group_descriptor_index += 1;
-            if block_group_descriptor_index > self.get_number_of_block_groups() {
-                return None;
+
+            if distance == 0 {
+                continue;
+            }
+
+            if let Some(before) = block_group_descriptor_index.checked_sub(distance) {
+                if let Some(ptr) = self.alloc_block(before) {
+                    return Some(ptr);
+                }
             }
         }
-        Some(new_block_pointer)
+        None
+    }
+
+    // Allocates the first free block after goal in goal's block group, or if there is none, in the closest block group with free blocks
+    // NOTE: If there is no goal then allocation starts at block group 0
+    pub fn alloc_block_near(&mut self, goal: Option<u32>) -> Option<u32> {
+        let Some(goal) = goal else {
+            return self.alloc_block_close_to(0);
+        };
+        let (Some(block_group_descriptor_index), Some(subindex)) =
+            (self.get_descriptor_index_of_block_number(goal), self.get_descriptor_subindex_of_block_number(goal))
+        else {
+            return self.alloc_block_close_to(0);
+        };
+
+        if let Some(ptr) = self.alloc_block_starting_at(block_group_descriptor_index, subindex) {
+            return Some(ptr);
+        }
+        self.alloc_block_close_to(block_group_descriptor_index)
     }
 
     pub fn read_inode(&self, inode_addr: u32) -> Option<Ext2RawInode> {
@@ -1373,7 +2013,7 @@ impl Ext2FS {
         // The Superblock is always located at byte 1024 from the beginning of the volume and is exactly 1024 bytes in length.
         // Source: https://wiki.osdev.org/Ext2#Locating_the_Superblock
 
-        let table_addr = ((1024 + 1024) / self.get_block_size()) * self.get_block_size(); // Find the block that's at 2048 bytes ( a.k.a immediatly after the superblock which is 1024 bytes in length and located AT byte 1024, so the first byte of the superblock is byte number 1024 and the last is 2048 )
+        let table_addr = self.get_block_group_descriptor_table_addr(0);
         let raw_descriptor: Vec<u8> =
             self.read(table_addr + offset_of_descriptor_in_table, Ext2BlockGroupDescriptor::packed_bytes_size(None).ok()?)?;
         Ext2BlockGroupDescriptor::unpack(raw_descriptor.as_slice().try_into().ok()?).ok()
@@ -1393,7 +2033,7 @@ impl Ext2FS {
         // The Superblock is always located at byte 1024 from the beginning of the volume and is exactly 1024 bytes in length.
         // Source: https://wiki.osdev.org/Ext2#Locating_the_Superblock
 
-        let table_addr = ((1024 + 1024) / self.get_block_size()) * self.get_block_size(); // Find the byte-address of the block that's 2048 bytes (a.k.a immediatly after the superblock which is 1024 bytes in length and located AT byte 1024)
+        let table_addr = self.get_block_group_descriptor_table_addr(0);
         self.write(table_addr + offset_of_descriptor_in_table, &descriptor.pack().ok()?)?;
         Some(())
     }
@@ -1410,18 +2050,31 @@ impl Ext2FS {
     }
 
     // Maps block numbers and inode addresses to block groups indecies and offsets(subindicies)
+    // NOTE: Block groups start at the block of the superblock, not at block 0, so for 1024 byte blocks bit 0 of the first bitmap is block 1
+    // Source: https://www.nongnu.org/ext2-doc/ext2.html#s-first-data-block
+    pub fn get_first_block_of_group(&self, block_group_descriptor_index: u32) -> u32 {
+        self.sb.superblock_block_number + block_group_descriptor_index * self.sb.blocks_per_block_group
+    }
+
+    // Returns: The byte-address of the block group descriptor table of the block group, which is in the block right after the superblock of the group
+    // NOTE: Only meaningful for groups that have a superblock, see block_group_has_super_block
+    // Source: https://www.nongnu.org/ext2-doc/ext2.html#block-group-descriptor-table
+    pub fn get_block_group_descriptor_table_addr(&self, block_group_descriptor_index: u32) -> u32 {
+        (self.get_first_block_of_group(block_group_descriptor_index) + 1) * self.get_block_size()
+    }
+
     pub fn get_descriptor_index_of_block_number(&self, block_number: u32) -> Option<u32> {
-        if block_number < self.get_number_of_special_blocks() as u32 {
+        if block_number < self.sb.superblock_block_number {
             return None;
         }
-        Some((block_number - self.get_number_of_special_blocks() as u32) / self.sb.blocks_per_block_group)
+        Some((block_number - self.sb.superblock_block_number) / self.sb.blocks_per_block_group)
     }
 
     pub fn get_descriptor_subindex_of_block_number(&self, block_number: u32) -> Option<u32> {
-        if block_number < self.get_number_of_special_blocks() as u32 {
+        if block_number < self.sb.superblock_block_number {
             return None;
         }
-        Some((block_number - self.get_number_of_special_blocks() as u32) % self.sb.blocks_per_block_group)
+        Some((block_number - self.sb.superblock_block_number) % self.sb.blocks_per_block_group)
     }
 
     pub fn get_descriptor_index_of_inode_addr(&self, inode_addr: u32) -> u32 {
@@ -1432,6 +2085,232 @@ impl Ext2FS {
         (inode_addr - 1) % self.sb.inodes_per_block_group
     }
 
+    // Returns: Wether the block group has a backup of the superblock at its start
+    // With sparse superblocks only groups 0, 1 and powers of 3, 5 and 7 have one, otherwise all of them do
+    // Source: https://www.nongnu.org/ext2-doc/ext2.html#s-feature-compat ( RO_COMPAT_SPARSE_SUPER )
+    fn block_group_has_super_block(&self, block_group_descriptor_index: u32) -> bool {
+        let is_sparse = self.extended_sb.as_ref().map(|esb| esb.has_write_required_feature_sparse()).unwrap_or(false);
+        if !is_sparse || block_group_descriptor_index <= 1 {
+            return true;
+        }
+
+        let is_power_of = |base: u32| {
+            let mut n = block_group_descriptor_index;
+            while n % base == 0 {
+                n /= base;
+            }
+            n == 1
+        };
+        is_power_of(3) || is_power_of(5) || is_power_of(7)
+    }
+
+    // Writes everything that's only in memory to the backing device, and makes sure the backing device doesn't keep it cached either
+    pub fn sync(&mut self) -> Option<()> {
+        if self.read_only {
+            return Some(()); // Nothing could have changed
+        }
+        self.flush_super_blocks()?;
+        self.flush_backup_super_blocks()?;
+        (*self.backing_device).borrow_mut().sync()
+    }
+
+    // Copies the primary superblock and block group descriptor table to the backups in the other block groups, so that the fs can still be recovered if the primary gets corrupted
+    // NOTE: This writes to a lot of block groups, so it is not done on every change like flush_super_blocks, only when the fs stops being used
+    pub fn flush_backup_super_blocks(&mut self) -> Option<()> {
+        if self.read_only {
+            return Some(()); // Nothing could have changed
+        }
+
+        let sb_data = self.sb.pack().ok()?;
+        let bgdt_data = self.read(
+            self.get_block_group_descriptor_table_addr(0),
+            self.get_number_of_block_groups() as usize * Self::get_ondisk_block_group_descriptor_size(),
+        )?;
+        for block_group_descriptor_index in 1..self.get_number_of_block_groups() {
+            if !self.block_group_has_super_block(block_group_descriptor_index) {
+                continue;
+            }
+
+            // The backup is at the start of the group, the same place the primary is in group 0
+            let backup_block_number = self.get_first_block_of_group(block_group_descriptor_index);
+            let backup_addr = backup_block_number * self.get_block_size();
+            self.write(backup_addr, &sb_data)?;
+
+            if let Some(esb) = &self.extended_sb {
+                // Each backup stores which group it is in
+                let mut backup_esb = esb.clone();
+                backup_esb.block_group_of_this_superblock = block_group_descriptor_index as u16;
+                self.write(backup_addr + Self::get_ondisk_super_block_size() as u32, &backup_esb.pack().ok()?)?;
+            }
+
+            // Every group with a backup superblock also has a backup of the block group descriptor table right after it
+            self.write(self.get_block_group_descriptor_table_addr(block_group_descriptor_index), &bgdt_data)?;
+        }
+        Some(())
+    }
+
+    // Walks every inode to find out which blocks and inodes are actually in use, and compares that to the bitmaps and the free counts
+    // NOTE: This only reports what is wrong, nothing is changed
+    // FIXME: Repair what can be repaired, like the free counts
+    // Returns: Everything that's wrong, or None if something couldn't be read
+    pub fn check(&self) -> Option<Vec<Ext2Inconsistency>> {
+        let mut problems = Vec::new();
+        let block_size = self.get_block_size();
+        let number_of_block_groups = self.get_number_of_block_groups();
+        let is_set = |bitmap: &[u8], bit: usize| bitmap[bit / 8] & (1 << (bit % 8)) != 0;
+        let is_on_disk = |block: u32| block >= self.sb.superblock_block_number && block < self.sb.max_no_of_blocks;
+
+        // First find the blocks that are used, starting with the ones that make up the filesystem itself
+        let mut block_used = vec![0u8; (self.sb.max_no_of_blocks as usize).div_ceil(8)];
+        let mut mark_block_used = |block: u32, problems: &mut Vec<Ext2Inconsistency>| {
+            if is_set(&block_used, block as usize) {
+                problems.push(Ext2Inconsistency::BLOCK_USED_MORE_THAN_ONCE { block });
+            }
+            block_used[block as usize / 8] |= 1 << (block % 8);
+        };
+
+        let inode_table_blocks = (self.sb.inodes_per_block_group * self.get_ondisk_inode_size() as u32).div_ceil(block_size);
+        let descriptor_table_blocks = (number_of_block_groups * Self::get_ondisk_block_group_descriptor_size() as u32).div_ceil(block_size);
+        let mut descriptors = Vec::new();
+        // NOTE: The block numbers in the descriptors can't be trusted either, groups whose bitmaps aren't on the disk are only reported
+        let mut groups_to_compare = vec![true; number_of_block_groups as usize];
+        let mut inode_tables_on_disk = true;
+        for group in 0..number_of_block_groups {
+            let descriptor = self.read_block_group_descriptor(group)?;
+            if self.block_group_has_super_block(group) {
+                // The superblock and right after it the block group descriptor table
+                let first_block = self.get_first_block_of_group(group);
+                (first_block..=first_block + descriptor_table_blocks).for_each(|block| mark_block_used(block, &mut problems));
+            }
+
+            for block in [descriptor.block_addr_for_block_usage_bitmap, descriptor.block_addr_for_inode_usage_bitmap] {
+                if is_on_disk(block) {
+                    mark_block_used(block, &mut problems);
+                } else {
+                    problems.push(Ext2Inconsistency::GROUP_METADATA_OUT_OF_RANGE { group, block });
+                    groups_to_compare[group as usize] = false;
+                }
+            }
+
+            let inode_table = descriptor.block_addr_for_inode_table;
+            // The whole table has to be on the disk, not just its start
+            match inode_table.checked_add(inode_table_blocks).filter(|end| is_on_disk(inode_table) && *end <= self.sb.max_no_of_blocks) {
+                Some(inode_table_end) => (inode_table..inode_table_end).for_each(|block| mark_block_used(block, &mut problems)),
+                None => {
+                    problems.push(Ext2Inconsistency::GROUP_METADATA_OUT_OF_RANGE { group, block: inode_table });
+                    inode_tables_on_disk = false;
+                }
+            }
+            descriptors.push(descriptor);
+        }
+        // Without all the inodes there's no way to know which blocks are in use
+        if !inode_tables_on_disk {
+            return Some(problems);
+        }
+
+        // Then the blocks used by inodes, and at the same time which inodes are in use
+        let first_non_reserved_inode = self.extended_sb.as_ref().map(|esb| esb.first_non_reserved_inode_in_fs).unwrap_or(11);
+        let mut inode_used = vec![false; self.sb.max_no_of_inodes as usize + 1]; // Inode indexing starts at 1
+        let mut directories_in_group = vec![0u32; number_of_block_groups as usize];
+        for inode_addr in 1..=self.sb.max_no_of_inodes {
+            let inode = self.read_inode(inode_addr)?;
+            // NOTE: The reserved inodes are always marked as in use, even if they aren't
+            if inode.hard_links_to_inode == 0 && inode_addr >= first_non_reserved_inode {
+                continue;
+            }
+            inode_used[inode_addr as usize] = true;
+            if inode.type_and_perm & 0xF000 == 0x4000 {
+                directories_in_group[self.get_descriptor_index_of_inode_addr(inode_addr) as usize] += 1;
+            }
+
+            for block in inode.get_all_used_blocks(self).unwrap_or_default() {
+                if !is_on_disk(block) {
+                    problems.push(Ext2Inconsistency::BLOCK_OUT_OF_RANGE { inode_addr, block });
+                    continue;
+                }
+                mark_block_used(block, &mut problems);
+            }
+        }
+
+        // Finally compare everything to the bitmaps and the counts
+        let mut total_free_blocks = 0;
+        let mut total_free_inodes = 0;
+        for (group, descriptor) in (0..number_of_block_groups).zip(descriptors) {
+            if !groups_to_compare[group as usize] {
+                continue;
+            }
+            let block_bitmap = self.read_block(descriptor.block_addr_for_block_usage_bitmap)?;
+            let first_block = self.get_first_block_of_group(group);
+            // The last group can be smaller than the others
+            let blocks_in_group = core::cmp::min(self.sb.blocks_per_block_group, self.sb.max_no_of_blocks - first_block);
+            let mut free_blocks = 0;
+            for bit in 0..blocks_in_group {
+                let block = first_block + bit;
+                match (is_set(&block_used, block as usize), is_set(&block_bitmap, bit as usize)) {
+                    (true, false) => problems.push(Ext2Inconsistency::BLOCK_IN_USE_BUT_MARKED_FREE { block }),
+                    (false, true) => problems.push(Ext2Inconsistency::BLOCK_MARKED_IN_USE_BUT_UNUSED { block }),
+                    _ => {}
+                }
+                if !is_set(&block_bitmap, bit as usize) {
+                    free_blocks += 1;
+                }
+            }
+
+            let inode_bitmap = self.read_block(descriptor.block_addr_for_inode_usage_bitmap)?;
+            let first_inode_addr = group * self.sb.inodes_per_block_group + 1;
+            let inodes_in_group = core::cmp::min(self.sb.inodes_per_block_group, self.sb.max_no_of_inodes - (first_inode_addr - 1));
+            let mut free_inodes = 0;
+            for bit in 0..inodes_in_group {
+                let inode_addr = first_inode_addr + bit;
+                match (inode_used[inode_addr as usize], is_set(&inode_bitmap, bit as usize)) {
+                    (true, false) => problems.push(Ext2Inconsistency::INODE_IN_USE_BUT_MARKED_FREE { inode_addr }),
+                    (false, true) => problems.push(Ext2Inconsistency::INODE_MARKED_IN_USE_BUT_UNUSED { inode_addr }),
+                    _ => {}
+                }
+                if !is_set(&inode_bitmap, bit as usize) {
+                    free_inodes += 1;
+                }
+            }
+
+            // NOTE: The counts are compared to the bitmaps, if the bitmaps are wrong that was already reported above
+            if u32::from(descriptor.unallocated_blocks_in_group) != free_blocks {
+                problems.push(Ext2Inconsistency::WRONG_FREE_BLOCKS_IN_GROUP {
+                    group,
+                    recorded: descriptor.unallocated_blocks_in_group.into(),
+                    actual: free_blocks,
+                });
+            }
+            if u32::from(descriptor.unallocated_inodes_in_group) != free_inodes {
+                problems.push(Ext2Inconsistency::WRONG_FREE_INODES_IN_GROUP {
+                    group,
+                    recorded: descriptor.unallocated_inodes_in_group.into(),
+                    actual: free_inodes,
+                });
+            }
+            if u32::from(descriptor.directories_in_group) != directories_in_group[group as usize] {
+                problems.push(Ext2Inconsistency::WRONG_DIRECTORIES_IN_GROUP {
+                    group,
+                    recorded: descriptor.directories_in_group.into(),
+                    actual: directories_in_group[group as usize],
+                });
+            }
+            total_free_blocks += free_blocks;
+            total_free_inodes += free_inodes;
+        }
+
+        // NOTE: The totals are missing the groups that couldn't be compared, so they would always be wrong
+        if groups_to_compare.contains(&false) {
+            return Some(problems);
+        }
+        if self.sb.unallocated_blocks != total_free_blocks {
+            problems.push(Ext2Inconsistency::WRONG_FREE_BLOCKS_IN_SUPERBLOCK { recorded: self.sb.unallocated_blocks, actual: total_free_blocks });
+        }
+        if self.sb.unallocated_inodes != total_free_inodes {
+            problems.push(Ext2Inconsistency::WRONG_FREE_INODES_IN_SUPERBLOCK { recorded: self.sb.unallocated_inodes, actual: total_free_inodes });
+        }
+        Some(problems)
+    }
+
     fn get_number_of_block_groups(&self) -> u32 {
         assert!(
             self.sb.max_no_of_blocks / self.sb.blocks_per_block_group
@@ -1443,23 +2322,15 @@ impl Ext2FS {
             + if self.sb.max_no_of_blocks % self.sb.blocks_per_block_group != 0 { 1 } else { 0 };
     }
 
-    fn get_number_of_special_blocks(&self) -> usize {
-        let initial_blocks = (1024/*reserved space*/+1024/*superblock*/) / self.get_block_size() as usize
-            + if (1024 + 1024) % self.get_block_size() as usize != 0 { 1 } else { 0 };
-
-        // Size of the block group table in blocks
-        let block_group_descriptor_table_blocks = Self::get_ondisk_block_group_descriptor_size()
-            * self.get_number_of_block_groups() as usize
-            / self.get_block_size() as usize
-            + if (Self::get_ondisk_block_group_descriptor_size() * self.get_number_of_block_groups() as usize)
-                % self.get_block_size() as usize
-                != 0
-            {
-                1
-            } else {
-                0
-            };
-        return initial_blocks + block_group_descriptor_table_blocks;
+    // Returns: The free space the superblock says the filesystem has
+    pub fn get_stats(&self) -> vfs::FileSystemStats {
+        vfs::FileSystemStats {
+            block_size: u64::from(self.get_block_size()),
+            total_blocks: u64::from(self.sb.max_no_of_blocks),
+            free_blocks: u64::from(self.sb.unallocated_blocks),
+            total_inodes: u64::from(self.sb.max_no_of_inodes),
+            free_inodes: u64::from(self.sb.unallocated_inodes),
+        }
     }
 
     pub fn get_block_size(&self) -> u32 {
@@ -1494,5 +2365,6 @@ impl Drop for Ext2FS {
     fn drop(&mut self) {
         // FIXME: Figure out why Ext2FS is leaked
         self.flush_super_blocks();
+        self.flush_backup_super_blocks();
     }
 }
@@ -0,0 +1,468 @@
+use core::{
+    cell::{Ref, RefCell},
+    convert::TryInto,
+};
+
+use alloc::{rc::Rc, string::String, vec::Vec};
+use packed_struct::prelude::*;
+
+use crate::{
+    vfs::{self, IFile, IFolder},
+    UART,
+};
+
+// Note: This file defines a read-only FAT32 filesystem, files and folders are chains of clusters, and which cluster comes after which
+// is stored in the file allocation table ( the FAT ), folders are arrays of 32 byte entries, with long names split across extra entries
+// Source: https://wiki.osdev.org/FAT, https://www.win.tue.nl/~aeb/linux/fs/fat/fat-1.html
+
+// The bios parameter block, followed by the FAT32 extended boot record, it's at the start of the volume
+#[derive(PackedStruct, Debug)]
+#[packed_struct(endian = "lsb")] // FAT is little endian (https://wiki.osdev.org/FAT#BPB_.28BIOS_Parameter_Block.29)
+pub struct Fat32BiosParameterBlock {
+    jump_instruction: [u8; 3],
+    oem_name: [u8; 8],
+    bytes_per_sector: u16,
+    sectors_per_cluster: u8,
+    reserved_sectors: u16,
+    number_of_fats: u8,
+    root_directory_entries: u16, // Always 0 on FAT32, as the root directory is a normal cluster chain
+    total_sectors_16: u16,
+    media_descriptor: u8,
+    sectors_per_fat_16: u16, // Always 0 on FAT32, sectors_per_fat_32 is used instead
+    sectors_per_track: u16,
+    number_of_heads: u16,
+    hidden_sectors: u32,
+    total_sectors_32: u32,
+    sectors_per_fat_32: u32,
+    flags: u16,
+    version: u16,
+    root_directory_cluster: u32,
+    fs_info_sector: u16,
+    backup_boot_sector: u16,
+    _reserved1: [u8; 12],
+    drive_number: u8,
+    _reserved2: u8,
+    signature: u8,
+    volume_id: u32,
+    volume_label: [u8; 11],
+    system_id: [u8; 8],
+}
+
+#[derive(PackedStruct, Debug)]
+#[packed_struct(endian = "lsb")] // FAT is little endian (https://wiki.osdev.org/FAT#Directories_on_FAT12.2F16.2F32)
+pub struct Fat32DirectoryEntry {
+    name: [u8; 11], // 8.3 name, padded with spaces, without the .
+    attributes: u8,
+    case_flags: u8, // Reserved for windows nt, which uses it to store if the name and extension are lowercase
+    creation_time_tenths: u8,
+    creation_time: u16,
+    creation_date: u16,
+    last_access_date: u16,
+    first_cluster_high: u16,
+    last_modif_time: u16,
+    last_modif_date: u16,
+    first_cluster_low: u16,
+    size: u32,
+}
+
+// A long file name entry, it holds 13 UCS-2 characters of the name of the normal entry that follows it
+// Source: https://wiki.osdev.org/FAT#Long_File_Names
+#[derive(PackedStruct, Debug)]
+#[packed_struct(endian = "lsb")]
+pub struct Fat32LongNameEntry {
+    order: u8, // The position of the entry in the name starting at 1, the last entry ( which comes first on disk ) has LAST_LONG_NAME_ENTRY set
+    name1: [u8; 10],
+    attributes: u8, // Always ATTRIBUTE_LONG_NAME
+    entry_type: u8,
+    checksum: u8, // Checksum of the 8.3 name of the normal entry, to check that it belongs to it
+    name2: [u8; 12],
+    first_cluster_low: u16, // Always 0
+    name3: [u8; 4],
+}
+
+const DIRECTORY_ENTRY_SIZE: usize = 32;
+const LONG_NAME_CHARS_PER_ENTRY: usize = 13;
+const LAST_LONG_NAME_ENTRY: u8 = 0x40;
+
+const ATTRIBUTE_VOLUME_ID: u8 = 0x08;
+const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+const ATTRIBUTE_LONG_NAME: u8 = 0x0F;
+
+const CASE_FLAG_LOWERCASE_NAME: u8 = 0x08;
+const CASE_FLAG_LOWERCASE_EXTENSION: u8 = 0x10;
+
+// The first byte of the name of an entry can be one of these instead of a character
+const END_OF_DIRECTORY: u8 = 0x00;
+const DELETED_ENTRY: u8 = 0xE5;
+const ESCAPED_E5: u8 = 0x05; // The name really starts with 0xE5
+
+// NOTE: Only the low 28 bits of a FAT entry are used
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FIRST_DATA_CLUSTER: u32 = 2;
+const BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8; // Anything >= this ends a chain
+
+impl Fat32DirectoryEntry {
+    fn first_cluster(&self) -> u32 {
+        u32::from(self.first_cluster_high) << 16 | u32::from(self.first_cluster_low)
+    }
+
+    fn is_directory(&self) -> bool {
+        self.attributes & ATTRIBUTE_DIRECTORY != 0
+    }
+
+    // Returns: The 8.3 name as it would be written, for ex. "README.TXT", or "readme.txt" if the case flags say it's lowercase
+    fn short_name(&self) -> String {
+        let mut name = self.name;
+        if name[0] == ESCAPED_E5 {
+            name[0] = DELETED_ENTRY;
+        }
+        let to_string = |part: &[u8], lowercase: bool| -> String {
+            // NOTE: Short names are in an oem code page, we just assume it's ascii
+            part.iter()
+                .take_while(|c| **c != b' ')
+                .map(|c| if lowercase { c.to_ascii_lowercase() as char } else { *c as char })
+                .collect()
+        };
+
+        let mut res = to_string(&name[0..8], self.case_flags & CASE_FLAG_LOWERCASE_NAME != 0);
+        let extension = to_string(&name[8..11], self.case_flags & CASE_FLAG_LOWERCASE_EXTENSION != 0);
+        if !extension.is_empty() {
+            res.push('.');
+            res.push_str(&extension);
+        }
+        res
+    }
+
+    // Source: https://en.wikipedia.org/wiki/Design_of_the_FAT_file_system#VFAT_long_file_names
+    fn short_name_checksum(&self) -> u8 {
+        self.name.iter().fold(0u8, |sum, c| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*c))
+    }
+}
+
+impl Fat32LongNameEntry {
+    fn chars(&self) -> impl Iterator<Item = u16> + '_ {
+        self.name1
+            .chunks(2)
+            .chain(self.name2.chunks(2))
+            .chain(self.name3.chunks(2))
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+    }
+}
+
+// Returns: The name made up of the long name entries, which are in the order they were on disk, or None if they aren't a complete name
+fn assemble_long_name(entries: &[Fat32LongNameEntry], checksum: u8) -> Option<String> {
+    // The entries are stored last part first, so the first one has to say it's the last and they have to count down to 1
+    let (first, _) = entries.split_first()?;
+    if first.order & LAST_LONG_NAME_ENTRY == 0 || usize::from(first.order & !LAST_LONG_NAME_ENTRY) != entries.len() {
+        return None;
+    }
+    for (i, entry) in entries.iter().rev().enumerate() {
+        if usize::from(entry.order & !LAST_LONG_NAME_ENTRY) != i + 1 || entry.checksum != checksum {
+            return None;
+        }
+    }
+
+    let mut chars = Vec::with_capacity(entries.len() * LONG_NAME_CHARS_PER_ENTRY);
+    for entry in entries.iter().rev() {
+        chars.extend(entry.chars());
+    }
+    // The name ends with a 0 if it doesn't fill the last entry, the rest is padded with 0xFFFF
+    let len = chars.iter().position(|c| *c == 0x0000 || *c == 0xFFFF).unwrap_or(chars.len());
+    core::char::decode_utf16(chars[..len].iter().cloned()).collect::<Result<String, _>>().ok()
+}
+
+pub struct Fat32FS {
+    backing_device: Rc<RefCell<dyn IFile>>,
+    pub bpb: Fat32BiosParameterBlock,
+}
+
+impl Fat32FS {
+    pub fn new(backing_dev: Rc<RefCell<dyn IFile>>) -> Option<Fat32FS> {
+        let boot_sector = (*backing_dev).borrow().read(0, 512)?;
+        if boot_sector[510..512] != [0x55, 0xAA] {
+            return None;
+        }
+        let bpb = Fat32BiosParameterBlock::unpack(
+            boot_sector[0..Fat32BiosParameterBlock::packed_bytes_size(None).ok()?].try_into().ok()?,
+        )
+        .ok()?;
+
+        if !matches!(bpb.bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !bpb.sectors_per_cluster.is_power_of_two()
+            || bpb.number_of_fats == 0
+            || bpb.reserved_sectors == 0
+        {
+            return None;
+        }
+
+        // FAT12 and FAT16 have a fixed size root directory and use the 16 bit fat size
+        if bpb.root_directory_entries != 0 || bpb.sectors_per_fat_16 != 0 || bpb.sectors_per_fat_32 == 0 {
+            use core::fmt::Write;
+            writeln!(UART.lock(), "ERROR: Fat32FS is not FAT32, FAT12 and FAT16 are not supported!").unwrap();
+            return None;
+        }
+        // NOTE: Strictly speaking whether a volume is FAT32 depends only on the number of clusters ( at least 65525 ),
+        // but small volumes formatted as FAT32 ( with mkfs.fat -F 32 ) are common and work just fine, so we go by the fields above
+        Some(Fat32FS { backing_device: backing_dev, bpb })
+    }
+
+    fn read(&self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        (*self.backing_device).borrow().read(addr, size)
+    }
+
+    pub fn get_cluster_size(&self) -> usize {
+        usize::from(self.bpb.bytes_per_sector) * usize::from(self.bpb.sectors_per_cluster)
+    }
+
+    fn get_total_sectors(&self) -> u64 {
+        if self.bpb.total_sectors_16 != 0 {
+            u64::from(self.bpb.total_sectors_16)
+        } else {
+            u64::from(self.bpb.total_sectors_32)
+        }
+    }
+
+    fn get_first_data_sector(&self) -> u64 {
+        u64::from(self.bpb.reserved_sectors) + u64::from(self.bpb.number_of_fats) * u64::from(self.bpb.sectors_per_fat_32)
+    }
+
+    // NOTE: Cluster numbers start at FIRST_DATA_CLUSTER, so the last one is this + 1
+    pub fn get_number_of_clusters(&self) -> u32 {
+        let data_sectors = self.get_total_sectors().saturating_sub(self.get_first_data_sector());
+        (data_sectors / u64::from(self.bpb.sectors_per_cluster)) as u32
+    }
+
+    // Returns: The number of free clusters, as recorded in the fs info sector, or None if it isn't known
+    // NOTE: The count is only a hint, it's never recomputed from the FAT since that would mean reading all of it
+    // Source: https://wiki.osdev.org/FAT#FSInfo_Structure_.28FAT32_only.29
+    pub fn get_free_cluster_count(&self) -> Option<u32> {
+        let fs_info = self.read(u64::from(self.bpb.fs_info_sector) * u64::from(self.bpb.bytes_per_sector), 492)?;
+        let read_u32 = |offset: usize| u32::from_le_bytes(fs_info[offset..offset + 4].try_into().unwrap());
+        if read_u32(0) != 0x4161_5252 || read_u32(484) != 0x6141_7272 {
+            return None;
+        }
+        let free_clusters = read_u32(488);
+        // 0xFFFFFFFF means the count is unknown
+        if free_clusters > self.get_number_of_clusters() {
+            return None;
+        }
+        Some(free_clusters)
+    }
+
+    pub fn get_root_directory_cluster(&self) -> u32 {
+        self.bpb.root_directory_cluster
+    }
+
+    // Returns: The entry of the first FAT for cluster, which is the next cluster in the chain
+    // NOTE: The other FATs are copies of the first one
+    fn read_fat_entry(&self, cluster: u32) -> Option<u32> {
+        let addr = u64::from(self.bpb.reserved_sectors) * u64::from(self.bpb.bytes_per_sector) + u64::from(cluster) * 4;
+        let entry = self.read(addr, 4)?;
+        Some(u32::from_le_bytes(entry.as_slice().try_into().ok()?) & FAT_ENTRY_MASK)
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_DATA_CLUSTER && cluster - FIRST_DATA_CLUSTER < self.get_number_of_clusters()
+    }
+
+    // Returns: All clusters of the chain that starts with first_cluster, in order, or None if the chain is broken
+    pub fn get_cluster_chain(&self, first_cluster: u32) -> Option<Vec<u32>> {
+        let mut res = Vec::new();
+        let mut cluster = first_cluster;
+        // An empty file has no clusters
+        if cluster == 0 {
+            return Some(res);
+        }
+        loop {
+            if !self.is_valid_cluster(cluster) {
+                return None;
+            }
+            // A chain can't be longer than the number of clusters, unless it loops back on itself
+            if res.len() >= self.get_number_of_clusters() as usize {
+                return None;
+            }
+            res.push(cluster);
+
+            cluster = self.read_fat_entry(cluster)?;
+            if cluster >= END_OF_CHAIN {
+                return Some(res);
+            }
+            if cluster == BAD_CLUSTER {
+                return None;
+            }
+        }
+    }
+
+    pub fn read_cluster(&self, cluster: u32) -> Option<Vec<u8>> {
+        if !self.is_valid_cluster(cluster) {
+            return None;
+        }
+        let sector = self.get_first_data_sector() + u64::from(cluster - FIRST_DATA_CLUSTER) * u64::from(self.bpb.sectors_per_cluster);
+        self.read(sector * u64::from(self.bpb.bytes_per_sector), self.get_cluster_size())
+    }
+
+    pub fn root_folder(fs: Rc<RefCell<Fat32FS>>) -> Rc<RefCell<dyn IFolder>> {
+        let first_cluster = fs.borrow().get_root_directory_cluster();
+        Rc::new(RefCell::new(Fat32Folder { first_cluster, fs }))
+    }
+}
+
+// Returns: The chain of first_cluster, reading it from the FAT first if this is the first time it's needed
+fn load_cluster_chain_if_needed<'a>(chain: &'a RefCell<Option<Vec<u32>>>, first_cluster: u32, fs: &RefCell<Fat32FS>) -> Option<Ref<'a, Vec<u32>>> {
+    if chain.borrow().is_none() {
+        *chain.borrow_mut() = Some(fs.borrow().get_cluster_chain(first_cluster)?);
+    }
+    Some(Ref::map(chain.borrow(), |chain| chain.as_ref().unwrap()))
+}
+
+pub struct Fat32File {
+    first_cluster: u32,
+    size: u32,
+    // NOTE: None until it's first needed, so that listing a directory doesn't have to walk the chain of every child
+    cluster_chain: RefCell<Option<Vec<u32>>>,
+    fs: Rc<RefCell<Fat32FS>>,
+}
+
+impl vfs::IFile for Fat32File {
+    fn read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        if offset.checked_add(len as u64)? > u64::from(self.size) {
+            return None;
+        }
+        let offset = offset as usize;
+        let fs = self.fs.borrow();
+        let cluster_size = fs.get_cluster_size();
+        let chain = load_cluster_chain_if_needed(&self.cluster_chain, self.first_cluster, &self.fs)?;
+
+        let mut res: Vec<u8> = Vec::with_capacity(len);
+        let mut cluster_index = offset / cluster_size;
+        let mut cluster_offset = offset % cluster_size;
+        while res.len() < len {
+            let cluster = fs.read_cluster(*chain.get(cluster_index)?)?;
+            let to_copy = core::cmp::min(cluster_size - cluster_offset, len - res.len());
+            res.extend_from_slice(&cluster[cluster_offset..cluster_offset + to_copy]);
+            cluster_index += 1;
+            cluster_offset = 0;
+        }
+        Some(res)
+    }
+
+    // FIXME: Support writing
+    fn write(&mut self, _offset: u64, _data: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn get_size(&self) -> u64 {
+        u64::from(self.size)
+    }
+
+    fn resize(&mut self, _new_size: u64) -> Option<()> {
+        None
+    }
+
+    fn get_block_map(&self) -> Option<Vec<u32>> {
+        Some(load_cluster_chain_if_needed(&self.cluster_chain, self.first_cluster, &self.fs)?.clone())
+    }
+}
+
+pub struct Fat32Folder {
+    first_cluster: u32,
+    fs: Rc<RefCell<Fat32FS>>,
+}
+
+impl Fat32Folder {
+    // Returns: The entries of the folder with their names, skipping deleted entries and volume labels
+    fn get_entries(&self) -> Vec<(Fat32DirectoryEntry, String)> {
+        let fs = self.fs.borrow();
+        let Some(chain) = fs.get_cluster_chain(self.first_cluster) else {
+            return Vec::new();
+        };
+
+        let mut res = Vec::new();
+        let mut long_name_entries = Vec::new();
+        for cluster in chain {
+            let Some(raw_data) = fs.read_cluster(cluster) else {
+                return res;
+            };
+            for raw_entry in raw_data.chunks_exact(DIRECTORY_ENTRY_SIZE) {
+                match raw_entry[0] {
+                    END_OF_DIRECTORY => return res,
+                    DELETED_ENTRY => {
+                        long_name_entries.clear();
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                if raw_entry[11] == ATTRIBUTE_LONG_NAME {
+                    let long_name_entry =
+                        Fat32LongNameEntry::unpack(raw_entry.try_into().expect("Directory entry should be 32 bytes!"))
+                            .expect("Parsing long name entry should always work!");
+                    // A new long name always starts with the last part, anything before that was an orphan
+                    if long_name_entry.order & LAST_LONG_NAME_ENTRY != 0 {
+                        long_name_entries.clear();
+                    }
+                    long_name_entries.push(long_name_entry);
+                    continue;
+                }
+
+                let entry = Fat32DirectoryEntry::unpack(raw_entry.try_into().expect("Directory entry should be 32 bytes!"))
+                    .expect("Parsing directory entry should always work!");
+                // NOTE: If the long name doesn't match the entry ( for ex. because it was renamed by something that only knows about 8.3 names )
+                // then the short name is the right one
+                let name = assemble_long_name(&long_name_entries, entry.short_name_checksum()).unwrap_or_else(|| entry.short_name());
+                long_name_entries.clear();
+                if entry.attributes & ATTRIBUTE_VOLUME_ID != 0 {
+                    continue;
+                }
+                res.push((entry, name));
+            }
+        }
+        res
+    }
+}
+
+impl IFolder for Fat32Folder {
+    // NOTE: "." and ".." are skipped, since the root folder doesn't have them
+    fn get_children(&self) -> Vec<(String, vfs::Node)> {
+        self.get_entries()
+            .into_iter()
+            .filter(|(_, name)| name != "." && name != "..")
+            .map(|(entry, name)| {
+                let node = if entry.is_directory() {
+                    vfs::Node::Folder(Rc::new(RefCell::new(Fat32Folder { first_cluster: entry.first_cluster(), fs: self.fs.clone() }))
+                        as Rc<RefCell<dyn IFolder>>)
+                } else {
+                    vfs::Node::File(Rc::new(RefCell::new(Fat32File {
+                        first_cluster: entry.first_cluster(),
+                        size: entry.size,
+                        cluster_chain: RefCell::new(None),
+                        fs: self.fs.clone(),
+                    })) as Rc<RefCell<dyn IFile>>)
+                };
+                (name, node)
+            })
+            .collect()
+    }
+
+    // FIXME: Support writing
+    fn create_empty_child(&mut self, _name: &str, _typ: vfs::NodeType) -> Option<vfs::Node> {
+        None
+    }
+
+    fn unlink_or_delete_empty_child(&mut self, _name: &str) -> Option<()> {
+        None
+    }
+
+    // NOTE: FAT has no inodes
+    fn get_fs_stats(&self) -> Option<vfs::FileSystemStats> {
+        let fs = self.fs.borrow();
+        Some(vfs::FileSystemStats {
+            block_size: fs.get_cluster_size() as u64,
+            total_blocks: u64::from(fs.get_number_of_clusters()),
+            free_blocks: u64::from(fs.get_free_cluster_count()?),
+            total_inodes: 0,
+            free_inodes: 0,
+        })
+    }
+}
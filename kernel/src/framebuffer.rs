@@ -2,10 +2,11 @@ use crate::{
     efi::{self, EfiGopMode},
     vga::{self, Color256, MixedRegisterState, Unblanked, Vga, VgaMode},
 };
+use alloc::{vec, vec::Vec};
 use core::fmt::Debug;
 use core::{ffi, ptr, slice};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Pixel {
     pub r: u8,
     pub g: u8,
@@ -32,6 +33,8 @@ pub trait FrameBuffer {
     fn get_width(&self) -> usize;
     fn get_height(&self) -> usize;
     fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) -> Option<(i16, i16, i16)>;
+    // Returns: None if the pixel is off screen, or if the framebuffer can't be read
+    fn get_pixel(&self, x: usize, y: usize) -> Option<Pixel>;
     fn fill(&mut self, x1: usize, y1: usize, x2: usize, y2: usize, pixel: Pixel) {
         for y in y1..y2 {
             for x in x1..x2 {
@@ -39,6 +42,48 @@ pub trait FrameBuffer {
             }
         }
     }
+
+    // Copies the w by h rectangle at (src_x, src_y) to (dst_x, dst_y), the rectangles can overlap, for ex. when scrolling
+    // NOTE: Only the part of the rectangles that is on screen is copied
+    fn blit(&mut self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize);
+
+    // Draws pixels, which is a w by h image stored row by row, at (x, y)
+    // NOTE: Only the part of the image that is on screen is drawn
+    // Returns: None if pixels has less than w*h pixels
+    fn draw_buffer(&mut self, x: usize, y: usize, w: usize, h: usize, pixels: &[Pixel]) -> Option<()> {
+        if pixels.len() < w.checked_mul(h)? {
+            return None;
+        }
+        let visible_w = core::cmp::min(w, self.get_width().saturating_sub(x));
+        let visible_h = core::cmp::min(h, self.get_height().saturating_sub(y));
+        for row in 0..visible_h {
+            for col in 0..visible_w {
+                self.set_pixel(x + col, y + row, pixels[row * w + col]);
+            }
+        }
+        Some(())
+    }
+
+    // Makes what was drawn so far visible
+    // NOTE: Most framebuffers draw straight to the screen, so by default this does nothing
+    fn present(&mut self) {}
+}
+
+// Returns: The width and height of the part of a w by h copy from (src_x, src_y) to (dst_x, dst_y) where both rectangles are on a width by height screen
+fn clip_blit(width: usize, height: usize, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) -> (usize, usize) {
+    let w = core::cmp::min(w, core::cmp::min(width.saturating_sub(src_x), width.saturating_sub(dst_x)));
+    let h = core::cmp::min(h, core::cmp::min(height.saturating_sub(src_y), height.saturating_sub(dst_y)));
+    (w, h)
+}
+
+// Returns: The index-th row ( or column ) to copy out of len, so that copying from src to dst never overwrites something that still has to be copied
+// NOTE: When moving towards higher addresses the copy has to start from the end, like memmove
+fn copy_order(src: usize, dst: usize, len: usize, index: usize) -> usize {
+    if dst > src {
+        len - 1 - index
+    } else {
+        index
+    }
 }
 
 impl<'a> FrameBuffer for EfiGopMode<'a> {
@@ -76,6 +121,38 @@ impl<'a> FrameBuffer for EfiGopMode<'a> {
             efi::EfiGraphicsPixelFormat::FormatMax => return None,
         };
     }
+
+    fn get_pixel(&self, x: usize, y: usize) -> Option<Pixel> {
+        if x >= self.get_width() || y >= self.get_height() {
+            return None;
+        }
+        let val = unsafe { ptr::read_volatile((self.framebuffer_base as *const u32).add(y * self.get_width() + x)) };
+        // NOTE: The bytes are in the order of the format, so the first one ends up in the lowest bits
+        match self.info.pix_format {
+            efi::EfiGraphicsPixelFormat::RgbR8bit => Some(Pixel::from_u32_bgr(u32::from_le(val))),
+            efi::EfiGraphicsPixelFormat::BgrR8bit => Some(Pixel::from_u32_rgb(u32::from_le(val))),
+            _ => None,
+        }
+    }
+
+    fn blit(&mut self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) {
+        let (w, h) = clip_blit(self.get_width(), self.get_height(), src_x, src_y, dst_x, dst_y, w, h);
+        if w == 0 {
+            return;
+        }
+        let fb_ptr = self.framebuffer_base as *mut u32;
+        for i in 0..h {
+            let row = copy_order(src_y, dst_y, h, i);
+            // NOTE: ptr::copy is a memmove, so rows that overlap with themselves are fine
+            unsafe {
+                ptr::copy(
+                    fb_ptr.add((src_y + row) * self.get_width() + src_x),
+                    fb_ptr.add((dst_y + row) * self.get_width() + dst_x),
+                    w,
+                );
+            }
+        }
+    }
 }
 impl<STATE: MixedRegisterState> FrameBuffer for Vga<Color256, STATE> {
     fn get_width(&self) -> usize {
@@ -107,13 +184,284 @@ impl<STATE: MixedRegisterState> FrameBuffer for Vga<Color256, STATE> {
         }
         Some((best_color.r as i16 - pixel.r as i16, best_color.g as i16 - pixel.r as i16, best_color.b as i16 - pixel.b as i16))
     }
+
+    // NOTE: The palette has 6 bits per channel, so they are scaled up the same way set_pixel scales them when comparing
+    fn get_pixel(&self, x: usize, y: usize) -> Option<Pixel> {
+        if x >= self.get_width() || y >= self.get_height() {
+            return None;
+        }
+        let (r, g, b) = vga::FANCY_PALETTE[unsafe { self.read(x, y) } as usize];
+        Some(Pixel { r: r * 4, g: g * 4, b: b * 4 })
+    }
+
+    // NOTE: Copies the palette indices, so unlike drawing there's no need to find the closest color again
+    fn blit(&mut self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) {
+        let (w, h) = clip_blit(self.get_width(), self.get_height(), src_x, src_y, dst_x, dst_y, w, h);
+        for i in 0..h {
+            let row = copy_order(src_y, dst_y, h, i);
+            for j in 0..w {
+                let col = copy_order(src_x, dst_x, w, j);
+                unsafe {
+                    let color = self.read(src_x + col, src_y + row);
+                    self.write(dst_x + col, dst_y + row, color);
+                }
+            }
+        }
+    }
+}
+
+// A framebuffer that draws into memory, and only copies what changed to the real framebuffer when present is called,
+// so that a half finished redraw is never visible
+// NOTE: The back buffer takes 3 bytes for every pixel, for ex. ~1.4 MiB at 800x600, or ~190 KiB for the 320x200 vga mode
+pub struct DoubleBufferedFrameBuffer<'a> {
+    front: &'a mut dyn FrameBuffer,
+    back: Vec<Pixel>,
+    // The rectangle ( x1, y1, x2, y2 ) that was drawn to since the last present, if anything was
+    dirty: Option<(usize, usize, usize, usize)>,
+}
+
+impl<'a> DoubleBufferedFrameBuffer<'a> {
+    // NOTE: The back buffer starts out black, whatever is already on the front buffer stays there until it's drawn over and presented
+    pub fn new(front: &'a mut dyn FrameBuffer) -> Self {
+        let back = vec![Pixel { r: 0, g: 0, b: 0 }; front.get_width() * front.get_height()];
+        Self { front, back, dirty: None }
+    }
+
+    fn mark_dirty(&mut self, x1: usize, y1: usize, x2: usize, y2: usize) {
+        self.dirty = Some(match self.dirty {
+            None => (x1, y1, x2, y2),
+            Some((dx1, dy1, dx2, dy2)) => (dx1.min(x1), dy1.min(y1), dx2.max(x2), dy2.max(y2)),
+        });
+    }
+}
+
+impl<'a> FrameBuffer for DoubleBufferedFrameBuffer<'a> {
+    fn get_width(&self) -> usize {
+        self.front.get_width()
+    }
+
+    fn get_height(&self) -> usize {
+        self.front.get_height()
+    }
+
+    // NOTE: The pixel is only converted to what the real framebuffer supports when presenting, so there is no error to return yet
+    fn set_pixel(&mut self, x: usize, y: usize, pixel: Pixel) -> Option<(i16, i16, i16)> {
+        if x >= self.get_width() || y >= self.get_height() {
+            return None;
+        }
+        let width = self.get_width();
+        self.back[y * width + x] = pixel;
+        self.mark_dirty(x, y, x + 1, y + 1);
+        Some((0, 0, 0))
+    }
+
+    fn get_pixel(&self, x: usize, y: usize) -> Option<Pixel> {
+        if x >= self.get_width() || y >= self.get_height() {
+            return None;
+        }
+        Some(self.back[y * self.get_width() + x])
+    }
+
+    fn blit(&mut self, src_x: usize, src_y: usize, dst_x: usize, dst_y: usize, w: usize, h: usize) {
+        let (w, h) = clip_blit(self.get_width(), self.get_height(), src_x, src_y, dst_x, dst_y, w, h);
+        if w == 0 || h == 0 {
+            return;
+        }
+        let width = self.get_width();
+        for i in 0..h {
+            let row = copy_order(src_y, dst_y, h, i);
+            let src = (src_y + row) * width + src_x;
+            // NOTE: copy_within is a memmove, so rows that overlap with themselves are fine
+            self.back.copy_within(src..src + w, (dst_y + row) * width + dst_x);
+        }
+        self.mark_dirty(dst_x, dst_y, dst_x + w, dst_y + h);
+    }
+
+    // Copies the part of the back buffer that changed since the last present to the real framebuffer, one row at a time
+    fn present(&mut self) {
+        let Some((x1, y1, x2, y2)) = self.dirty.take() else { return };
+        let width = self.get_width();
+        for y in y1..y2 {
+            self.front.draw_buffer(x1, y, x2 - x1, 1, &self.back[y * width + x1..y * width + x2]);
+        }
+        self.front.present();
+    }
+}
+
+// A framebuffer that isn't shown anywhere, for when there is no screen but something still needs a framebuffer, for ex. the terminal of a serial console
+pub struct HeadlessFrameBuffer {
+    width: usize,
+    height: usize,
+}
+
+impl HeadlessFrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height }
+    }
+}
+
+impl FrameBuffer for HeadlessFrameBuffer {
+    fn get_width(&self) -> usize {
+        self.width
+    }
+
+    fn get_height(&self) -> usize {
+        self.height
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, _pixel: Pixel) -> Option<(i16, i16, i16)> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((0, 0, 0))
+    }
+
+    // NOTE: Nothing is ever drawn, so there is nothing to read
+    fn get_pixel(&self, _x: usize, _y: usize) -> Option<Pixel> {
+        None
+    }
+
+    fn blit(&mut self, _src_x: usize, _src_y: usize, _dst_x: usize, _dst_y: usize, _w: usize, _h: usize) {}
+}
+
+// A small image drawn on top of whatever is on a framebuffer, that remembers the pixels it covers so it can be moved or erased
+// without destroying them, for ex. a mouse cursor
+// NOTE: Anything drawn under the sprite while it's drawn is lost when it's erased, so the sprite should be erased before drawing under it
+// NOTE: Only the part of the sprite that is on screen is drawn, the position is its top left corner, so it can only go off the right and bottom edges
+pub struct Sprite {
+    width: usize,
+    height: usize,
+    // Stored row by row, None is transparent
+    pixels: Vec<Option<Pixel>>,
+    // What was under the sprite, if it is drawn
+    covered: Option<CoveredArea>,
+}
+
+// The on screen part of what's under a drawn sprite, and where the sprite is drawn
+struct CoveredArea {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    pixels: Vec<Pixel>,
+}
+
+impl Sprite {
+    // Returns: None if pixels has less than width*height pixels
+    pub fn new(width: usize, height: usize, pixels: Vec<Option<Pixel>>) -> Option<Self> {
+        if pixels.len() < width.checked_mul(height)? {
+            return None;
+        }
+        Some(Self { width, height, pixels, covered: None })
+    }
+
+    // Makes a sprite out of lines of text, where a space is transparent, a . is background and anything else is foreground
+    // Returns: None if the lines aren't all the same length
+    pub fn from_ascii_art(lines: &[&str], foreground: Pixel, background: Pixel) -> Option<Self> {
+        let width = lines.first().map(|line| line.len()).unwrap_or(0);
+        if lines.iter().any(|line| line.len() != width) {
+            return None;
+        }
+        let pixels = lines
+            .iter()
+            .flat_map(|line| line.bytes())
+            .map(|c| match c {
+                b' ' => None,
+                b'.' => Some(background),
+                _ => Some(foreground),
+            })
+            .collect();
+        Self::new(width, lines.len(), pixels)
+    }
+
+    // A white arrow with a black outline, pointing at its top left corner
+    pub fn arrow_cursor() -> Self {
+        const ARROW: [&str; 16] = [
+            ".          ",
+            "..         ",
+            ".#.        ",
+            ".##.       ",
+            ".###.      ",
+            ".####.     ",
+            ".#####.    ",
+            ".######.   ",
+            ".#######.  ",
+            ".########. ",
+            ".#####.....",
+            ".##.##.    ",
+            ".#. .##.   ",
+            "..  .##.   ",
+            "     .##.  ",
+            "     ....  ",
+        ];
+        Self::from_ascii_art(&ARROW, Pixel { r: 0xFF, g: 0xFF, b: 0xFF }, Pixel { r: 0, g: 0, b: 0 }).unwrap()
+    }
+
+    // Returns: Where the sprite is drawn, if it is drawn
+    pub fn get_position(&self) -> Option<(usize, usize)> {
+        self.covered.as_ref().map(|covered| (covered.x, covered.y))
+    }
+
+    // Draws the sprite at (x, y), if it was already drawn somewhere it's moved from there
+    // NOTE: Doesn't present, so that the caller can present once it's done drawing everything else
+    // Returns: None if the framebuffer can't be read, in which case nothing is drawn, since it couldn't be erased later
+    pub fn draw(&mut self, fb: &mut dyn FrameBuffer, x: usize, y: usize) -> Option<()> {
+        // The old position is restored first, so if the two overlap then what's saved is what's really under the sprite, not the sprite itself
+        self.erase(fb);
+
+        let visible_w = core::cmp::min(self.width, fb.get_width().saturating_sub(x));
+        let visible_h = core::cmp::min(self.height, fb.get_height().saturating_sub(y));
+        let mut covered = Vec::with_capacity(visible_w * visible_h);
+        for row in 0..visible_h {
+            for col in 0..visible_w {
+                covered.push(fb.get_pixel(x + col, y + row)?);
+            }
+        }
+        for row in 0..visible_h {
+            for col in 0..visible_w {
+                if let Some(pixel) = self.pixels[row * self.width + col] {
+                    fb.set_pixel(x + col, y + row, pixel);
+                }
+            }
+        }
+        self.covered = Some(CoveredArea { x, y, w: visible_w, h: visible_h, pixels: covered });
+        Some(())
+    }
+
+    // Puts back what was under the sprite, if it is drawn
+    // NOTE: Doesn't present, same as draw
+    pub fn erase(&mut self, fb: &mut dyn FrameBuffer) {
+        let Some(covered) = self.covered.take() else { return };
+        fb.draw_buffer(covered.x, covered.y, covered.w, covered.h, &covered.pixels);
+    }
 }
 
+// Returns: The first of the candidate resolutions ( width, height ) that is_supported accepts, so the candidates should be ordered from most to least preferred
+pub fn pick_resolution(candidate_resolutions: &[(u32, u32)], mut is_supported: impl FnMut(u32, u32) -> bool) -> Option<(u32, u32)> {
+    candidate_resolutions.iter().copied().find(|(w, h)| is_supported(*w, *h))
+}
+
+// Returns: The number of a mode of the gop with the resolution w by h, and a pixel format we can draw with
+fn find_efi_mode(gop: &efi::EfiGop, w: u32, h: u32) -> Option<u32> {
+    (0..gop.mode.max_mode).find(|mode_number| {
+        let mut info: *const efi::EfiGopModeInfo = ptr::null();
+        let mut size_of_info: usize = 0;
+        if ((gop.query_mode)(gop, *mode_number, &mut size_of_info, &mut info) as isize) < 0 || info == ptr::null() {
+            return false;
+        }
+        let info = unsafe { &*info };
+        info.horz_res == w
+            && info.vert_res == h
+            && matches!(info.pix_format, efi::EfiGraphicsPixelFormat::RgbR8bit | efi::EfiGraphicsPixelFormat::BgrR8bit)
+    })
+}
+
+// Returns: The framebuffer and the resolution it ended up with
+// NOTE: If none of the candidates are available the mode the firmware was already using is kept
 pub fn try_setup_efi_framebuffer(
     efi_table: *mut efi::EfiSystemTable,
-    _desired_res_w: u32,
-    _desired_res_h: u32,
-) -> Option<&'static mut impl FrameBuffer> {
+    candidate_resolutions: &[(u32, u32)],
+) -> Option<(&'static mut impl FrameBuffer, (u32, u32))> {
     if efi_table == ptr::null_mut() {
         return None;
     }
@@ -132,7 +480,6 @@ pub fn try_setup_efi_framebuffer(
 
     let mut info: *const efi::EfiGopModeInfo = ptr::null();
     let mut size_of_info: usize = 0;
-    let mut _num_modes: usize = 0;
 
     let res = (gop.query_mode)(
         gop,
@@ -146,33 +493,31 @@ pub fn try_setup_efi_framebuffer(
         (gop.set_mode)(&mut gop, 0);
     } else if (res as isize) < 0 {
         return None;
-    } else {
-        _num_modes = gop.mode.max_mode as usize;
-    }
-
-    // FIXME: Seems to have problems on qemu ia32 uefi, and x64 real hardware
-    // For now just keeping the default mode seems to fix the issue
-    /*
-    let mut best_mode_ind = 0;
-    let mut best_mode_err = i64::MAX;
-       for i in 0..num_modes as u32{
-           (gop.query_mode)(&gop, i as u32, &mut size_of_info, &mut info);
-           let info = unsafe{&*info};
-           let err = ((info.horz_res*info.vert_res) as i64 - (desired_res_h*desired_res_w) as i64).abs();
-           if err < best_mode_err {
-               best_mode_err = err;
-               best_mode_ind = i;
-           }
-       }
-    (gop.set_mode)(&mut gop, best_mode_ind);*/
-    Some(gop.mode)
+    }
+
+    // NOTE: Switching modes used to have problems on qemu ia32 uefi and x64 real hardware, so the mode is only switched
+    // if the best candidate isn't the current mode, and if switching fails we stay in the current mode
+    let best_mode = pick_resolution(candidate_resolutions, |w, h| find_efi_mode(gop, w, h).is_some())
+        .and_then(|(w, h)| find_efi_mode(gop, w, h));
+    if let Some(best_mode) = best_mode {
+        if best_mode != gop.mode.mode && ((gop.set_mode)(&mut gop, best_mode) as isize) < 0 {
+            use core::fmt::Write;
+            writeln!(crate::UART.lock(), "WARNING: Failed to switch to efi graphics mode {}, keeping the current one!", best_mode).unwrap();
+        }
+    }
+    let resolution = (gop.mode.info.horz_res, gop.mode.info.vert_res);
+    Some((gop.mode, resolution))
 }
 
+// NOTE: Plain vga only gets us mode 13h ( 320x200 with 256 colors ), anything bigger needs vbe or a real graphics driver
+const VGA_RESOLUTIONS: [(u32, u32); 1] = [(320, 200)];
+
+// Returns: The framebuffer and the resolution it ended up with, or None if none of the candidates are possible with vga
 pub fn try_setup_vga_framebuffer<MODE: VgaMode + 'static>(
     vga: Vga<MODE, Unblanked>,
-    _desired_res_w: u32,
-    _desired_res_h: u32,
-) -> Option<Vga<Color256, Unblanked>> {
+    candidate_resolutions: &[(u32, u32)],
+) -> Option<(Vga<Color256, Unblanked>, (u32, u32))> {
+    let resolution = pick_resolution(candidate_resolutions, |w, h| VGA_RESOLUTIONS.contains(&(w, h)))?;
     let vga = unsafe { vga.blank_screen() };
-    Some(unsafe { vga.set_mode::<Color256>().unblank_screen() })
+    Some((unsafe { vga.set_mode::<Color256>().unblank_screen() }, resolution))
 }
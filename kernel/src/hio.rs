@@ -1,4 +1,4 @@
-use crate::ps2_8042::KeyboardModifiers;
+use crate::{primitives::Mutex, ps2_8042::KeyboardModifiers};
 // Human input/output
 #[repr(u8)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -13,6 +13,30 @@ pub struct KeyboardPacket {
     pub packet_type: KeyboardPacketType,
 }
 
+// How much the mouse moved since the last packet and which buttons are held down
+// NOTE: Positive dy is down, like on the screen, even though mice report up as positive
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MousePacket {
+    pub dx: i16,
+    pub dy: i16,
+    pub left_button: bool,
+    pub right_button: bool,
+    pub middle_button: bool,
+}
+
+// The size of MousePacket::to_bytes
+pub const MOUSE_PACKET_LEN: usize = 5;
+
+impl MousePacket {
+    // Returns: dx and dy as little endian i16s, followed by a byte with the left button in bit 0, the right one in bit 1 and the middle one in bit 2
+    pub fn to_bytes(&self) -> [u8; MOUSE_PACKET_LEN] {
+        let dx = self.dx.to_le_bytes();
+        let dy = self.dy.to_le_bytes();
+        let buttons = u8::from(self.left_button) | u8::from(self.right_button) << 1 | u8::from(self.middle_button) << 2;
+        [dx[0], dx[1], dy[0], dy[1], buttons]
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum KeyboardKey {
     Unmapped{row: usize, column: usize}, // These keys are user-configurable, for the meaning of row and column refer to ANSI keyboard layout
@@ -177,6 +201,8 @@ impl KeyboardKey {
 
             0x54 => return None, // Invalid
             0x55 => return None, // Invalid
+            // NOTE: ISO keyboards have an extra key to the left of Z that ANSI keyboards don't, so it gets the column after the last one
+            0x56 => KeyboardKey::Unmapped { row: 3, column: 10 },
 
             0x57 => KeyboardKey::F11,
             0x58 => KeyboardKey::F12,
@@ -229,6 +255,30 @@ pub fn is_scancode_in_set1_pressed(code: u32) -> bool {
     return code & 0x80 == 0;
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct KeyboardLayout {
+    pub name: &'static str,
+    // Tries to map the key to a char based on the modifiers active
+    pub parse_key: fn(KeyboardKey, KeyboardModifiers) -> Result<char, KeyboardKey>,
+}
+
+pub const KEYBOARD_LAYOUTS: &[KeyboardLayout] = &[
+    KeyboardLayout { name: "us", parse_key: standard_usa_qwerty::parse_key },
+    KeyboardLayout { name: "uk", parse_key: standard_uk_qwerty::parse_key },
+];
+
+pub static KEYBOARD_LAYOUT: Mutex<KeyboardLayout> = Mutex::from(KEYBOARD_LAYOUTS[0]);
+
+pub fn find_keyboard_layout(name: &str) -> Option<KeyboardLayout> {
+    KEYBOARD_LAYOUTS.iter().find(|layout| layout.name == name).copied()
+}
+
+// Tries to map the key to a char using the currently selected layout
+pub fn parse_key(key: KeyboardKey, modifiers: KeyboardModifiers) -> Result<char, KeyboardKey> {
+    let layout = *KEYBOARD_LAYOUT.lock();
+    (layout.parse_key)(key, modifiers)
+}
+
 pub mod standard_usa_qwerty {
     use super::*;
 
@@ -386,4 +436,29 @@ pub mod standard_usa_qwerty {
         };
     }
 
-}
\ No newline at end of file
+}
+
+pub mod standard_uk_qwerty {
+    use super::*;
+
+    // NOTE: Only the keys that are different from the usa layout are here
+    // Source: https://en.wikipedia.org/wiki/QWERTY#United_Kingdom
+    pub fn parse_key(key: KeyboardKey, modifiers: KeyboardModifiers) -> Result<char, KeyboardKey> {
+        return match key {
+            KeyboardKey::Unmapped{row: 0, column: 0} if modifiers.any_shift() => Ok('¬'),
+            KeyboardKey::Unmapped{row: 0, column: 2} if modifiers.any_shift() => Ok('"'),
+            KeyboardKey::Unmapped{row: 0, column: 3} if modifiers.any_shift() => Ok('£'),
+
+            KeyboardKey::Unmapped{row: 2, column: 10} if modifiers.any_shift() => Ok('@'),
+
+            // On iso keyboards this key is next to enter instead of above it
+            KeyboardKey::Unmapped{row: 1, column: 12} if !modifiers.any_shift() => Ok('#'),
+            KeyboardKey::Unmapped{row: 1, column: 12} if modifiers.any_shift() => Ok('~'),
+
+            KeyboardKey::Unmapped{row: 3, column: 10} if !modifiers.any_shift() => Ok('\\'),
+            KeyboardKey::Unmapped{row: 3, column: 10} if modifiers.any_shift() => Ok('|'),
+
+            _ => standard_usa_qwerty::parse_key(key, modifiers)
+        };
+    }
+}
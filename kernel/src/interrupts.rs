@@ -0,0 +1,450 @@
+use core::arch::asm;
+
+use crate::{primitives::Mutex, virtmem::KernPointer};
+
+// Note: This file defines how interrupts get to their handlers, that is the idt, the 8259 pics and the gdt and tss the idt needs,
+// and the handlers for cpu exceptions
+// Source: https://wiki.osdev.org/Interrupt_Descriptor_Table, https://wiki.osdev.org/8259_PIC, https://wiki.osdev.org/Task_State_Segment, https://wiki.osdev.org/Exceptions
+
+// What the cpu pushes before calling a handler
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub instruction_pointer: u64,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64,
+}
+
+pub type InterruptHandler = extern "x86-interrupt" fn(InterruptStackFrame);
+// Some exceptions also push an error code
+pub type InterruptHandlerWithErrorCode = extern "x86-interrupt" fn(InterruptStackFrame, u64);
+
+const IDT_ENTRIES: usize = 256;
+// Present, ring 0, 64 bit interrupt gate ( so interrupts are disabled while the handler runs )
+const IDT_INTERRUPT_GATE: u8 = 0x8E;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct IDTEntry {
+    offset_low: u16,
+    selector: u16,
+    // Which interrupt stack table entry to switch to, 0 means don't switch stacks
+    ist: u8,
+    type_attributes: u8,
+    offset_mid: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+impl IDTEntry {
+    const fn missing() -> Self {
+        IDTEntry { offset_low: 0, selector: 0, ist: 0, type_attributes: 0, offset_mid: 0, offset_high: 0, reserved: 0 }
+    }
+
+    fn new(handler: u64) -> Self {
+        IDTEntry {
+            offset_low: handler as u16,
+            selector: GDT_KERNEL_CODE_SELECTOR,
+            ist: INTERRUPT_STACK_IST_INDEX,
+            type_attributes: IDT_INTERRUPT_GATE,
+            offset_mid: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+// What lidt and lgdt take
+#[repr(C, packed)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64,
+}
+
+static IDT: Mutex<[IDTEntry; IDT_ENTRIES]> = Mutex::from([IDTEntry::missing(); IDT_ENTRIES]);
+
+// NOTE: The kernel is compiled with the red zone ( the 128 bytes below the stack pointer that functions can use without moving it ),
+// and the cpu pushes the interrupt stack frame right below the stack pointer, so handlers have to run on their own stack or they would overwrite it.
+// The only way to get the cpu to switch stacks when an interrupt comes in without a change of privilege level is the interrupt stack table,
+// which lives in the tss, which has to be in the gdt, so we bring our own gdt.
+const GDT_KERNEL_CODE_SELECTOR: u16 = 1 << 3;
+const GDT_KERNEL_DATA_SELECTOR: u16 = 2 << 3;
+const GDT_TSS_SELECTOR: u16 = 3 << 3;
+// Source: https://wiki.osdev.org/GDT_Tutorial#Flat_.2F_Long_Mode_Setup
+const GDT_KERNEL_CODE: u64 = 0x00AF_9A00_0000_FFFF;
+const GDT_KERNEL_DATA: u64 = 0x00CF_9200_0000_FFFF;
+// Present, 64 bit tss ( available )
+const GDT_TSS_TYPE: u64 = 0x89;
+
+// Interrupt stack table entries are numbered from 1
+const INTERRUPT_STACK_IST_INDEX: u8 = 1;
+const INTERRUPT_STACK_SIZE: usize = 64 * 1024;
+
+#[repr(C, align(16))]
+struct InterruptStack([u8; INTERRUPT_STACK_SIZE]);
+
+static mut INTERRUPT_STACK: InterruptStack = InterruptStack([0; INTERRUPT_STACK_SIZE]);
+
+#[derive(Debug)]
+#[repr(C, packed)]
+struct TaskStateSegment {
+    reserved0: u32,
+    privilege_stack_table: [u64; 3],
+    reserved1: u64,
+    interrupt_stack_table: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    io_map_base: u16,
+}
+
+static TSS: Mutex<TaskStateSegment> = Mutex::from(TaskStateSegment {
+    reserved0: 0,
+    privilege_stack_table: [0; 3],
+    reserved1: 0,
+    interrupt_stack_table: [0; 7],
+    reserved2: 0,
+    reserved3: 0,
+    // No io permission bitmap, since it's past the end of the tss
+    io_map_base: core::mem::size_of::<TaskStateSegment>() as u16,
+});
+
+// Null, kernel code, kernel data, and the tss which takes up two entries
+static GDT: Mutex<[u64; 5]> = Mutex::from([0; 5]);
+
+// Returns: The two gdt entries that describe a tss at base
+fn tss_descriptor(base: u64, limit: u32) -> [u64; 2] {
+    let low = u64::from(limit & 0xFFFF)
+        | (base & 0xFF_FFFF) << 16
+        | GDT_TSS_TYPE << 40
+        | u64::from((limit >> 16) & 0xF) << 48
+        | ((base >> 24) & 0xFF) << 56;
+    [low, base >> 32]
+}
+
+// SAFETY: Has to only be called once, loading the tss marks it as busy, so loading it again faults
+unsafe fn load_gdt_and_tss() {
+    let mut tss = TSS.lock();
+    // The stack grows down, so it starts at the end
+    tss.interrupt_stack_table[usize::from(INTERRUPT_STACK_IST_INDEX - 1)] =
+        core::ptr::addr_of!(INTERRUPT_STACK) as u64 + INTERRUPT_STACK_SIZE as u64;
+
+    let mut gdt = GDT.lock();
+    gdt[1] = GDT_KERNEL_CODE;
+    gdt[2] = GDT_KERNEL_DATA;
+    let tss_entries = tss_descriptor(&*tss as *const TaskStateSegment as u64, core::mem::size_of::<TaskStateSegment>() as u32 - 1);
+    gdt[3] = tss_entries[0];
+    gdt[4] = tss_entries[1];
+
+    let pointer = DescriptorTablePointer { limit: (core::mem::size_of::<[u64; 5]>() - 1) as u16, base: gdt.as_ptr() as u64 };
+    // NOTE: cs can't be moved into, so a far return is used to reload it
+    asm!(
+        "lgdt [{pointer}]",
+        "push {code}",
+        "lea {tmp}, [rip + 2f]",
+        "push {tmp}",
+        "retfq",
+        "2:",
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "mov ss, {data:x}",
+        "ltr {tss:x}",
+        pointer = in(reg) &pointer,
+        code = in(reg) u64::from(GDT_KERNEL_CODE_SELECTOR),
+        data = in(reg) GDT_KERNEL_DATA_SELECTOR,
+        tss = in(reg) GDT_TSS_SELECTOR,
+        tmp = lateout(reg) _,
+    );
+}
+
+const PIC_MASTER_COMMAND: u16 = 0x20;
+const PIC_MASTER_DATA: u16 = 0x21;
+const PIC_SLAVE_COMMAND: u16 = 0xA0;
+const PIC_SLAVE_DATA: u16 = 0xA1;
+const PIC_ICW1_INIT: u8 = 0x10;
+const PIC_ICW1_ICW4: u8 = 0x01;
+const PIC_ICW4_8086: u8 = 0x01;
+const PIC_READ_ISR: u8 = 0x0B;
+const PIC_END_OF_INTERRUPT: u8 = 0x20;
+// The slave is connected to irq 2 of the master
+const PIC_CASCADE_IRQ: u8 = 2;
+const PIC_SPURIOUS_IRQ: u8 = 7;
+// The pics start out sending irqs 0-7 as interrupts 8-15, which are also cpu exceptions, so they are moved after the exceptions
+pub const PIC_MASTER_OFFSET: u8 = 0x20;
+pub const PIC_SLAVE_OFFSET: u8 = 0x28;
+
+// Gives the pic time to take the last byte, by writing to an unused port
+unsafe fn io_wait() {
+    KernPointer::<u8>::from_port(0x80).write(0);
+}
+
+// Moves the irqs to PIC_MASTER_OFFSET and PIC_SLAVE_OFFSET, with all of them masked
+unsafe fn remap_pics() {
+    let mut master_command = KernPointer::<u8>::from_port(PIC_MASTER_COMMAND);
+    let mut master_data = KernPointer::<u8>::from_port(PIC_MASTER_DATA);
+    let mut slave_command = KernPointer::<u8>::from_port(PIC_SLAVE_COMMAND);
+    let mut slave_data = KernPointer::<u8>::from_port(PIC_SLAVE_DATA);
+
+    master_command.write(PIC_ICW1_INIT | PIC_ICW1_ICW4);
+    io_wait();
+    slave_command.write(PIC_ICW1_INIT | PIC_ICW1_ICW4);
+    io_wait();
+    master_data.write(PIC_MASTER_OFFSET);
+    io_wait();
+    slave_data.write(PIC_SLAVE_OFFSET);
+    io_wait();
+    // Tell the master which irq the slave is on ( as a bit ), and the slave its number
+    master_data.write(1 << PIC_CASCADE_IRQ);
+    io_wait();
+    slave_data.write(PIC_CASCADE_IRQ);
+    io_wait();
+    master_data.write(PIC_ICW4_8086);
+    io_wait();
+    slave_data.write(PIC_ICW4_8086);
+    io_wait();
+
+    master_data.write(0xFF);
+    slave_data.write(0xFF);
+}
+
+pub unsafe fn unmask_irq(irq: u8) {
+    let (mut data, bit) = if irq < 8 {
+        (KernPointer::<u8>::from_port(PIC_MASTER_DATA), irq)
+    } else {
+        unmask_irq(PIC_CASCADE_IRQ);
+        (KernPointer::<u8>::from_port(PIC_SLAVE_DATA), irq - 8)
+    };
+    let mask = data.read() & !(1 << bit);
+    data.write(mask);
+}
+
+// Has to be called at the end of every irq handler, otherwise the pic won't send that irq ( or any lower priority one ) again
+pub unsafe fn end_of_interrupt(irq: u8) {
+    if irq >= 8 {
+        KernPointer::<u8>::from_port(PIC_SLAVE_COMMAND).write(PIC_END_OF_INTERRUPT);
+    }
+    KernPointer::<u8>::from_port(PIC_MASTER_COMMAND).write(PIC_END_OF_INTERRUPT);
+}
+
+// Returns: Whether the pic is actually handling irq 7 of command_port's pic, if it isn't then the irq was spurious
+unsafe fn is_in_service(command_port: u16) -> bool {
+    let mut command = KernPointer::<u8>::from_port(command_port);
+    command.write(PIC_READ_ISR);
+    command.read() & (1 << PIC_SPURIOUS_IRQ) != 0
+}
+
+// NOTE: A pic can raise its lowest priority irq even if it's masked when the irq that it was going to raise went away,
+// the pic doesn't expect an end of interrupt for those, but the master does if it came from the slave
+// Source: https://wiki.osdev.org/8259_PIC#Spurious_IRQs
+extern "x86-interrupt" fn master_spurious_handler(_frame: InterruptStackFrame) {
+    unsafe {
+        if is_in_service(PIC_MASTER_COMMAND) {
+            end_of_interrupt(PIC_SPURIOUS_IRQ);
+        }
+    }
+}
+
+extern "x86-interrupt" fn slave_spurious_handler(_frame: InterruptStackFrame) {
+    unsafe {
+        if is_in_service(PIC_SLAVE_COMMAND) {
+            end_of_interrupt(8 + PIC_SPURIOUS_IRQ);
+        } else {
+            end_of_interrupt(PIC_CASCADE_IRQ);
+        }
+    }
+}
+
+pub fn set_handler(vector: u8, handler: InterruptHandler) {
+    set_handler_address(vector, handler as usize as u64);
+}
+
+pub fn set_handler_with_error_code(vector: u8, handler: InterruptHandlerWithErrorCode) {
+    set_handler_address(vector, handler as usize as u64);
+}
+
+fn set_handler_address(vector: u8, handler: u64) {
+    // NOTE: The entry can't be half written when its interrupt comes in
+    without_interrupts(|| IDT.lock()[usize::from(vector)] = IDTEntry::new(handler));
+}
+
+const EXCEPTION_DIVIDE_ERROR: u8 = 0;
+const EXCEPTION_BREAKPOINT: u8 = 3;
+const EXCEPTION_DOUBLE_FAULT: u8 = 8;
+const EXCEPTION_PAGE_FAULT: u8 = 14;
+
+pub fn exception_name(vector: u8) -> &'static str {
+    match vector {
+        0 => "Divide error",
+        1 => "Debug",
+        2 => "Non-maskable interrupt",
+        3 => "Breakpoint",
+        4 => "Overflow",
+        5 => "Bound range exceeded",
+        6 => "Invalid opcode",
+        7 => "Device not available",
+        8 => "Double fault",
+        10 => "Invalid TSS",
+        11 => "Segment not present",
+        12 => "Stack-segment fault",
+        13 => "General protection fault",
+        14 => "Page fault",
+        16 => "x87 floating-point exception",
+        17 => "Alignment check",
+        18 => "Machine check",
+        19 => "SIMD floating-point exception",
+        20 => "Virtualization exception",
+        21 => "Control protection exception",
+        _ => "Reserved",
+    }
+}
+
+// NOTE: The panic handler is what prints the exception, since it already knows how to get it to the uart and the terminal
+fn exception(vector: u8, error_code: Option<u64>, frame: InterruptStackFrame) -> ! {
+    let error_code = error_code.unwrap_or(0);
+    if vector == EXCEPTION_PAGE_FAULT {
+        // cr2 has the address that was being accessed
+        let address: u64;
+        unsafe { asm!("mov {}, cr2", out(reg) address, options(nomem, nostack)) };
+        panic!(
+            "CPU exception {} ({}) at rip {:#x}, error code: {:#x}, while accessing: {:#x}",
+            vector,
+            exception_name(vector),
+            frame.instruction_pointer,
+            error_code,
+            address
+        );
+    }
+    panic!("CPU exception {} ({}) at rip {:#x}, error code: {:#x}", vector, exception_name(vector), frame.instruction_pointer, error_code);
+}
+
+macro_rules! exception_handler {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame) {
+            exception($vector, None, frame)
+        }
+    };
+    ($name:ident, $vector:expr, error_code) => {
+        extern "x86-interrupt" fn $name(frame: InterruptStackFrame, error_code: u64) {
+            exception($vector, Some(error_code), frame)
+        }
+    };
+}
+
+exception_handler!(divide_error_handler, 0);
+exception_handler!(debug_handler, 1);
+exception_handler!(nmi_handler, 2);
+exception_handler!(overflow_handler, 4);
+exception_handler!(bound_range_handler, 5);
+exception_handler!(invalid_opcode_handler, 6);
+exception_handler!(device_not_available_handler, 7);
+exception_handler!(double_fault_handler, 8, error_code);
+exception_handler!(invalid_tss_handler, 10, error_code);
+exception_handler!(segment_not_present_handler, 11, error_code);
+exception_handler!(stack_segment_handler, 12, error_code);
+exception_handler!(general_protection_handler, 13, error_code);
+exception_handler!(page_fault_handler, 14, error_code);
+exception_handler!(x87_floating_point_handler, 16);
+exception_handler!(alignment_check_handler, 17, error_code);
+exception_handler!(machine_check_handler, 18);
+exception_handler!(simd_floating_point_handler, 19);
+exception_handler!(virtualization_handler, 20);
+exception_handler!(control_protection_handler, 21, error_code);
+
+// Breakpoints are meant to be continued from, so this one only reports it
+extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+    use crate::UART;
+    use core::fmt::Write;
+    if !UART.is_locked() {
+        writeln!(UART.lock(), "CPU exception {} ({}) at rip {:#x}", EXCEPTION_BREAKPOINT, exception_name(EXCEPTION_BREAKPOINT), frame.instruction_pointer)
+            .unwrap();
+    }
+}
+
+fn set_exception_handlers() {
+    set_handler(EXCEPTION_DIVIDE_ERROR, divide_error_handler);
+    set_handler(1, debug_handler);
+    set_handler(2, nmi_handler);
+    set_handler(EXCEPTION_BREAKPOINT, breakpoint_handler);
+    set_handler(4, overflow_handler);
+    set_handler(5, bound_range_handler);
+    set_handler(6, invalid_opcode_handler);
+    set_handler(7, device_not_available_handler);
+    set_handler_with_error_code(EXCEPTION_DOUBLE_FAULT, double_fault_handler);
+    set_handler_with_error_code(10, invalid_tss_handler);
+    set_handler_with_error_code(11, segment_not_present_handler);
+    set_handler_with_error_code(12, stack_segment_handler);
+    set_handler_with_error_code(13, general_protection_handler);
+    set_handler_with_error_code(EXCEPTION_PAGE_FAULT, page_fault_handler);
+    set_handler(16, x87_floating_point_handler);
+    set_handler_with_error_code(17, alignment_check_handler);
+    set_handler(18, machine_check_handler);
+    set_handler(19, simd_floating_point_handler);
+    set_handler(20, virtualization_handler);
+    set_handler_with_error_code(21, control_protection_handler);
+}
+
+// Deliberately causes an exception, so that the handlers can be checked
+// NOTE: Only the breakpoint comes back
+pub fn trigger_exception(vector: u8) -> Option<()> {
+    match vector {
+        EXCEPTION_DIVIDE_ERROR => unsafe {
+            asm!("div {}", in(reg) 0u64, inout("rax") 1u64 => _, inout("rdx") 0u64 => _, options(nomem, nostack));
+        },
+        EXCEPTION_BREAKPOINT => unsafe { asm!("int3", options(nomem, nostack)) },
+        // ud2 is the instruction that's guaranteed to be invalid
+        6 => unsafe { asm!("ud2", options(nomem, nostack)) },
+        _ => return None,
+    }
+    Some(())
+}
+
+pub fn set_irq_handler(irq: u8, handler: InterruptHandler) {
+    set_handler(if irq < 8 { PIC_MASTER_OFFSET + irq } else { PIC_SLAVE_OFFSET + irq - 8 }, handler);
+}
+
+// Sets up everything that's needed for interrupts, but leaves them disabled and every irq masked
+// SAFETY: Has to only be called once
+pub unsafe fn init() {
+    load_gdt_and_tss();
+    remap_pics();
+    set_exception_handlers();
+    set_irq_handler(PIC_SPURIOUS_IRQ, master_spurious_handler);
+    set_irq_handler(8 + PIC_SPURIOUS_IRQ, slave_spurious_handler);
+
+    let idt = IDT.lock();
+    let pointer = DescriptorTablePointer { limit: (core::mem::size_of::<[IDTEntry; IDT_ENTRIES]>() - 1) as u16, base: idt.as_ptr() as u64 };
+    asm!("lidt [{}]", in(reg) &pointer, options(nostack));
+}
+
+pub unsafe fn enable() {
+    asm!("sti", options(nostack));
+}
+
+pub unsafe fn disable() {
+    asm!("cli", options(nostack));
+}
+
+pub fn are_enabled() -> bool {
+    let flags: u64;
+    unsafe { asm!("pushfq", "pop {}", out(reg) flags) };
+    // Bit 9 of rflags is the interrupt flag
+    flags & (1 << 9) != 0
+}
+
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let were_enabled = are_enabled();
+    unsafe { disable() };
+    let res = f();
+    if were_enabled {
+        unsafe { enable() };
+    }
+    res
+}
+
+// Enables interrupts and waits for the next one
+// NOTE: sti only takes effect after the instruction after it, so if interrupts were disabled while checking whether to wait,
+// an interrupt can't sneak in between the check and the hlt, which would make us wait for the interrupt after it
+pub unsafe fn enable_and_halt() {
+    asm!("sti", "hlt", options(nostack));
+}
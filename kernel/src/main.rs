@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 #![feature(abi_efiapi)]
+#![feature(abi_x86_interrupt)]
 #![feature(default_alloc_error_handler)]
 #![feature(lang_items)]
 #![feature(allocator_api)]
@@ -22,10 +23,11 @@ use alloc::vec::Vec;
 use allocator::PROGRAM_ALLOCATOR;
 use ata::{ATABus, ATADevice, ATADeviceFile};
 use char_device::CharDevice;
-use hio::{KeyboardKey, standard_usa_qwerty};
+use hio::KeyboardKey;
 use primitives::{LazyInitialised, Mutex};
 use process::Process;
 use ps2_8042::KEYBOARD_INPUT;
+use rlibc::sys::SignalType;
 use terminal::{Terminal, TERMINAL};
 use vfs::{IFile, IFolder, Node, RootFSNode};
 use vga::{Color256, Unblanked};
@@ -75,22 +77,33 @@ fn panic(p: &::core::panic::PanicInfo) -> ! {
 
 mod allocator;
 mod ata;
+mod block_cache;
 mod char_device;
+mod clock;
 mod devfs;
 mod efi;
 mod elf;
 mod emulator;
 mod ext2;
+mod fat32;
 mod framebuffer;
 mod hio;
+mod interrupts;
 mod multiboot;
 mod partitions;
+mod pci;
+mod power;
 mod primitives;
 mod process;
+mod procfs;
+mod pty;
+mod ramdisk;
 mod scheduler;
 mod ps2_8042;
+mod rtc;
 mod syscall;
 mod terminal;
+mod tmpfs;
 mod uart_16550;
 mod vfs;
 mod vga;
@@ -98,6 +111,26 @@ mod virtmem;
 
 pub static UART: Mutex<LazyInitialised<UARTDevice>> = Mutex::from(LazyInitialised::uninit());
 
+const RAMDISK_SIZE_IN_BYTES: usize = 1024 * 1024;
+// The resolutions the framebuffer is set up with, from most to least preferred, the first one the hardware supports is used
+// NOTE: With plain vga only 320x200 is possible, so it has to stay in the list as the last resort
+const FRAMEBUFFER_RESOLUTIONS: [(u32, u32); 4] = [(1024, 768), (800, 600), (640, 480), (320, 200)];
+// Draw the terminal into a back buffer first, so that redraws don't flicker, at the cost of some heap, see DoubleBufferedFrameBuffer
+const DOUBLE_BUFFER_FRAMEBUFFER: bool = false;
+// The kernel heap gets 1/KERNEL_HEAP_SHARE of the memory for the heaps, programs get the rest
+const KERNEL_HEAP_SHARE: u64 = 4;
+const HEAP_ALIGNMENT: u64 = 4096;
+const HEAP_MAX_ADDRESS: u64 = 4 * 1024 * 1024 * 1024;
+// How big each heap can get by growing into the rest of the free memory
+const HEAP_MAX_LEN: usize = 1024 * 1024 * 1024;
+// How many written sectors each ata disk holds on to before writing them back, see ATADeviceFile
+const ATA_WRITE_CACHE_SECTORS: usize = 256;
+
+extern "C" {
+    // Defined by the linker script, right after the end of the kernel
+    static __kernel_end: u8;
+}
+
 #[allow(unused)]
 fn kprint_dump<T>(ptr: *const T, bytes: usize, uart: &mut UARTDevice) {
     let arr =
@@ -107,6 +140,252 @@ fn kprint_dump<T>(ptr: *const T, bytes: usize, uart: &mut UARTDevice) {
     }
 }
 
+const BUILTIN_COMMANDS: &str = "puts whoareyou rmrootfsdir mkrootfsdir rm mv ln touch mount.ext2 mount.fat32 mount.tmpfs umount sync fsck.ext2 df free meminfo stat chmod set export hexdump blockmap ls find cd \
+                                seq profile disas nm emumemcheck dmacheck lspci fault date uptime time ps kill jobs nice layout setfont stty clear reboot shutdown exit help";
+
+// A program that was started in the background using COMMAND &
+struct Job {
+    id: usize, // What the user knows the job by, like in bash it's one more than the newest job's
+    pid: usize,
+    command: String,
+}
+
+// Tells the user how the background jobs that ended since the last call went, and removes them from jobs, like bash does before showing the prompt
+// Returns: How the other processes without a parent that ended went
+fn report_finished_jobs(jobs: &mut Vec<Job>) -> Vec<process::WaitAction> {
+    let mut other_actions = Vec::new();
+    for finished in scheduler::take_finished_tasks() {
+        let Some(index) = jobs.iter().position(|job| job.pid == finished.pid) else {
+            other_actions.push(finished.action);
+            continue;
+        };
+        let job = jobs.remove(index);
+        let mut status = String::new();
+        match finished.action {
+            process::WaitAction::EXITED { exit_code: 0 } => write!(status, "Done").unwrap(),
+            // NOTE: Programs exit with a c int
+            process::WaitAction::EXITED { exit_code } => write!(status, "Exit {}", exit_code as i32).unwrap(),
+            process::WaitAction::TERMINATED_BY_SIGNAL { signal } => write!(status, "Killed by {}", signal.signal_type.name()).unwrap(),
+        }
+        writeln!(TERMINAL.lock(), "[{}] {:<18} {}", job.id, status, job.command).unwrap();
+    }
+    other_actions
+}
+
+// Writes everything that is cached back to the disks, the filesystems first, since syncing them writes to the disks
+// Returns: false if anything couldn't be written, everything else is still written
+fn sync_everything(mounted_filesystems: &[(Rc<RefCell<RootFSNode>>, Rc<RefCell<ext2::Ext2FS>>)], ata_devices: &[Rc<RefCell<ATADeviceFile>>]) -> bool {
+    let mut synced = true;
+    for (mntpoint_node, _) in mounted_filesystems {
+        synced &= (**mntpoint_node).borrow_mut().sync().is_some();
+    }
+    for ata_device in ata_devices {
+        synced &= (**ata_device).borrow_mut().sync().is_some();
+    }
+    synced
+}
+
+// Returns: What could be typed to finish the last word of line, a builtin if it's the first word ( since other commands have to be paths ), or a path otherwise
+// NOTE: Finished folder names end with a / so that completing can go on inside of them, anything else that's finished ends with a space
+fn get_completions(line: &str, cur_dir: &vfs::Path) -> Vec<String> {
+    let word_start = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+    let word = &line[word_start..];
+    if word_start == 0 && !word.starts_with('/') && !word.starts_with('.') {
+        return BUILTIN_COMMANDS
+            .split_whitespace()
+            .filter(|command| command.starts_with(word))
+            .map(|command| [&command[word.len()..], " "].concat())
+            .collect();
+    }
+
+    // Only the part after the last / is completed, the part before it says in what folder
+    let (folder, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..i + 1], &word[i + 1..]),
+        None => ("", word),
+    };
+    let Ok(folder) = vfs::resolve(cur_dir, folder) else {
+        return Vec::new();
+    };
+    let Some(Node::Folder(folder)) = folder.get_node() else {
+        return Vec::new();
+    };
+
+    let children = (*folder).borrow().get_children();
+    children
+        .into_iter()
+        // . and .. are only suggested if the user already started typing them
+        .filter(|(name, _)| name.starts_with(prefix) && (prefix.starts_with('.') || (name != "." && name != "..")))
+        .map(|(name, node)| [&name[prefix.len()..], if let Node::Folder(_) = node { "/" } else { " " }].concat())
+        .collect()
+}
+
+// Completes the last word of the line being typed in the terminal, if it can only be completed one way it's typed out,
+// otherwise the part that all of the ways have in common is typed, and if there is no such part they are listed
+fn complete_line(cur_dir: &vfs::Path) {
+    let line = TERMINAL.lock().line_buffer.iter().collect::<String>();
+    let completions = get_completions(&line, cur_dir);
+    let Some(first) = completions.first() else {
+        return;
+    };
+
+    let mut common_len = completions.iter().fold(first.len(), |len, completion| {
+        first.bytes().zip(completion.bytes()).take(len).take_while(|(a, b)| a == b).count()
+    });
+    // Don't split up a character
+    while !first.is_char_boundary(common_len) {
+        common_len -= 1;
+    }
+    let mut common = first[..common_len].to_owned();
+    // Don't finish a word that is only finished in some of the completions
+    if completions.len() > 1 && common.ends_with(|c| c == ' ' || c == '/') {
+        common.pop();
+    }
+
+    if !common.is_empty() {
+        common.chars().for_each(|c| TERMINAL.lock().recive_char(c));
+    } else if completions.len() > 1 {
+        let word = &line[line.rfind(' ').map(|i| i + 1).unwrap_or(0)..];
+        let word = &word[word.rfind('/').map(|i| i + 1).unwrap_or(0)..];
+        writeln!(TERMINAL.lock()).unwrap();
+        for completion in &completions {
+            write!(TERMINAL.lock(), "{}{} ", word, completion.trim_end()).unwrap();
+        }
+        // The line is still being typed, so it has to be shown again after the list
+        writeln!(TERMINAL.lock()).unwrap();
+        write!(TERMINAL.lock(), "{} # {}", cur_dir, line).unwrap();
+    }
+}
+
+fn is_variable_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Returns: line with every $NAME replaced by the value of the variable NAME in env
+// NOTE: Variables that aren't set expand to nothing, and a $ that isn't followed by a name is left as is, like in posix shells
+fn expand_variables(line: &str, env: &BTreeMap<String, String>) -> String {
+    let mut expanded_line = String::new();
+    let mut rest = line;
+    while let Some(i) = rest.find('$') {
+        expanded_line.push_str(&rest[..i]);
+        let after_dollar = &rest[i + 1..];
+        let name_len = after_dollar.find(|c| !is_variable_name_char(c)).unwrap_or(after_dollar.len());
+        if name_len == 0 {
+            expanded_line.push('$');
+        } else if let Some(value) = env.get(&after_dollar[..name_len]) {
+            expanded_line.push_str(value);
+        }
+        rest = &after_dollar[name_len..];
+    }
+    expanded_line.push_str(rest);
+    expanded_line
+}
+
+// Returns: If name matches pattern, where * matches any number of characters and ? matches exactly one
+// NOTE: When a * is followed by something that doesn't match, the * takes one more character and matching goes on from there,
+// only the last * ever has to be retried like that, so this doesn't blow up with lots of *s
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+    let (mut p, mut n) = (0, 0);
+    // Where to go on from if matching after the last * fails
+    let mut last_star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = last_star {
+            last_star = Some((star_p, star_n + 1));
+            p = star_p + 1;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    // Only *s can match nothing
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Returns: The names that match pattern, sorted
+// NOTE: Names starting with a . are only matched if the pattern starts with a . too, like in posix shells
+fn glob_filter<'a>(names: impl Iterator<Item = &'a str>, pattern: &str) -> Vec<&'a str> {
+    let mut matches = names
+        .filter(|name| (pattern.starts_with('.') || !name.starts_with('.')) && glob_matches(pattern, name))
+        .collect::<Vec<&str>>();
+    matches.sort_unstable();
+    matches
+}
+
+// Returns: line with every argument that has a * or a ? in it replaced by the paths it matches
+// NOTE: Only the part after the last / can have wildcards, and if nothing matches the argument is left as is, like in posix shells
+fn expand_globs(line: &str, cur_dir: &vfs::Path) -> String {
+    let mut words = line.split_inclusive(' ');
+    let mut expanded_line = String::from(words.next().unwrap_or(""));
+    for word in words {
+        // NOTE: The last word still has the newline after it
+        let arg = word.trim_end();
+        let (folder, pattern) = match arg.rfind('/') {
+            Some(i) => (&arg[..i + 1], &arg[i + 1..]),
+            None => ("", arg),
+        };
+        if !pattern.contains(|c| c == '*' || c == '?') || folder.contains(|c| c == '*' || c == '?') {
+            expanded_line.push_str(word);
+            continue;
+        }
+
+        let children = match vfs::resolve(cur_dir, folder).ok().and_then(|folder_path| folder_path.get_node()) {
+            Some(Node::Folder(folder_node)) => (*folder_node).borrow().get_children(),
+            _ => Vec::new(),
+        };
+        let matches = glob_filter(children.iter().map(|(name, _)| name.as_str()), pattern);
+        if matches.is_empty() {
+            expanded_line.push_str(word);
+            continue;
+        }
+        let expanded_word = matches.iter().map(|name| [folder, name].concat()).collect::<Vec<String>>().join(" ");
+        expanded_line.push_str(&expanded_word);
+        expanded_line.push_str(&word[arg.len()..]);
+    }
+    expanded_line
+}
+
+// How many folders deep find goes, so that it can't go on forever if mountpoints ever form a loop
+const FIND_MAX_DEPTH: usize = 64;
+
+// Returns: The paths of everything in start ( and its subfolders ) whose name contains pattern, in the order ls -R would list them
+fn find_by_name(start: &vfs::Path, pattern: &str) -> Vec<vfs::Path> {
+    let mut found = Vec::new();
+    // NOTE: Folders are visited using a stack instead of recursion, same as in ls -R
+    let mut to_visit = alloc::vec![(start.clone(), 0)];
+    while let Some((dir, depth)) = to_visit.pop() {
+        let Some(Node::Folder(folder)) = dir.get_node() else {
+            continue;
+        };
+
+        let mut subfolders = Vec::new();
+        for (name, node) in (*folder).borrow().get_children() {
+            if name == "." || name == ".." {
+                continue;
+            }
+            let mut path = dir.clone();
+            path.append_str(&name);
+            if name.contains(pattern) {
+                found.push(path.clone());
+            }
+            if let Node::Folder(_) = node {
+                if depth + 1 < FIND_MAX_DEPTH {
+                    subfolders.push((path, depth + 1));
+                }
+            }
+        }
+        // Reversed so that subfolders are visited in the order they were found
+        to_visit.extend(subfolders.into_iter().rev());
+    }
+    found
+}
+
 pub const unsafe fn from_utf8_unchecked(v: &[u8]) -> &str {
     // SAFETY: the caller must guarantee that the bytes `v` are valid UTF-8.
     // Also relies on `&str` and `&[u8]` having the same layout.
@@ -125,6 +404,11 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
     writeln!(UART.lock(), "Hello, world!").unwrap();
 
     let mut efi_system_table_ptr = 0usize;
+    // The bootloader gives us a copy of the acpi rsdp, which is needed to power off the machine
+    let mut acpi_rsdp_ptr: Option<usize> = None;
+    // Passing console=ttyS0 on the kernel command line makes the shell usable over the serial port, like on linux
+    let mut serial_console = false;
+    let mut memory_map: Option<&[u32]> = None;
     let mut i = 0;
     loop {
         let id = multiboot_data[i];
@@ -143,14 +427,58 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                 // FIXME: assumes little endian
             }
         }
+
+        // 0xE is the acpi 1.0 rsdp and 0xF the acpi 2.0 one, prefer the newer one if there are both
+        if id == 0xF || (id == 0xE && acpi_rsdp_ptr.is_none()) {
+            acpi_rsdp_ptr = Some(multiboot_data[i + 2..].as_ptr() as usize);
+        }
+
+        if id == 0x1 {
+            // The command line is a null terminated string right after the tag's header
+            let size = multiboot_data[i + 1] as usize - 2 * core::mem::size_of::<u32>();
+            let cmdline = unsafe { core::slice::from_raw_parts(multiboot_data[i + 2..].as_ptr() as *const u8, size) };
+            serial_console |= cmdline.split(|c| *c == b' ' || *c == 0).any(|arg| arg == b"console=ttyS0");
+        }
+
+        if id == 0x6 {
+            memory_map = Some(&multiboot_data[i..i + multiboot_data[i + 1] as usize / core::mem::size_of::<u32>()]);
+        }
         i += len as usize;
     }
 
-    // FIXME: Don't hardcode the starting location of the heap
-    // Stack size: 2mb, executable size (as of 17 sep 2022): ~6mb, so starting the heap at 8mb should be a safe bet.
-    allocator::ALLOCATOR.lock().init((8 * 1024 * 1024) as *mut u8, 8 * 1024 * 1024);
-    allocator::PROGRAM_ALLOCATOR.0.lock().init((16 * 1024 * 1024) as *mut u8, 240 * 1024 * 1024);
-
+    // The heaps go in the biggest piece of ram after the kernel, as long as it doesn't overlap the multiboot information ( which we still need )
+    // NOTE: Only the first 4gb are identity mapped, so memory above that can't be used
+    let kernel_end = unsafe { &__kernel_end as *const u8 as u64 };
+    let multiboot_info = multiboot::MemoryRegion { start: u64::from(r2), end: u64::from(r2) + u64::from(unsafe { *(r2 as usize as *const u32) }) };
+    let free_memory_regions = |memory_map| {
+        multiboot::free_memory_regions(
+            multiboot::available_memory_regions(memory_map),
+            core::slice::from_ref(&multiboot_info),
+            kernel_end,
+            HEAP_MAX_ADDRESS,
+            HEAP_ALIGNMENT,
+        )
+    };
+    let heap_region = memory_map.and_then(|memory_map| free_memory_regions(memory_map).max_by_key(|region| region.len()));
+    if let (Some(heap_region), Some(memory_map)) = (heap_region, memory_map) {
+        let kernel_heap_len = heap_region.len() / KERNEL_HEAP_SHARE;
+        let program_heap_start = heap_region.start + kernel_heap_len + (HEAP_ALIGNMENT - kernel_heap_len % HEAP_ALIGNMENT) % HEAP_ALIGNMENT;
+        writeln!(UART.lock(), "Heap: {:#x}-{:#x}", heap_region.start, heap_region.end).unwrap();
+        allocator::ALLOCATOR.lock().init(heap_region.start as *mut u8, kernel_heap_len as usize);
+        allocator::PROGRAM_ALLOCATOR.0.lock().init(program_heap_start as *mut u8, heap_region.end.saturating_sub(program_heap_start) as usize);
+
+        // The rest of the free memory is kept for when either heap runs out
+        for region in free_memory_regions(memory_map).filter(|region| *region != heap_region) {
+            let _ = allocator::add_spare_heap_region(region.start as *mut u8, region.len() as usize);
+        }
+        allocator::ALLOCATOR.lock().set_max_len(HEAP_MAX_LEN);
+        allocator::PROGRAM_ALLOCATOR.0.lock().set_max_len(HEAP_MAX_LEN);
+    } else {
+        // Stack size: 2mb, executable size (as of 17 sep 2022): ~6mb, so starting the heap at 8mb should be a safe bet.
+        writeln!(UART.lock(), "No usable memory map from the bootloader, guessing where the heap can go!").unwrap();
+        allocator::ALLOCATOR.lock().init((8 * 1024 * 1024) as *mut u8, 8 * 1024 * 1024);
+        allocator::PROGRAM_ALLOCATOR.0.lock().init((16 * 1024 * 1024) as *mut u8, 240 * 1024 * 1024);
+    }
 
     vfs::VFS_ROOT.lock().set(Rc::new(RefCell::new(RootFSNode::new_root())));
 
@@ -158,36 +486,59 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
     let dfs = Rc::new(RefCell::new(devfs::DevFS::new()));
     (*dev_folder).borrow_mut().mountpoint = Some(dfs.clone() as Rc<RefCell<dyn IFolder>>);
 
+    let proc_folder = vfs::RootFSNode::new_folder(vfs::VFS_ROOT.lock().clone(), "proc");
+    (*proc_folder).borrow_mut().mountpoint = Some(Rc::new(RefCell::new(procfs::ProcFS)) as Rc<RefCell<dyn IFolder>>);
+
     let vga;
     let mut fb: Option<&mut dyn framebuffer::FrameBuffer>;
     let o;
     let mut uo;
-    fb = framebuffer::try_setup_efi_framebuffer(efi_system_table_ptr as *mut efi::EfiSystemTable, 800, 600)
-        .map(|x| x as &mut dyn framebuffer::FrameBuffer);
+    fb = framebuffer::try_setup_efi_framebuffer(efi_system_table_ptr as *mut efi::EfiSystemTable, &FRAMEBUFFER_RESOLUTIONS)
+        .map(|(x, _)| x as &mut dyn framebuffer::FrameBuffer);
     if fb.is_none() {
         vga = unsafe { Vga::x86_default() };
-        o = framebuffer::try_setup_vga_framebuffer(vga, 800, 600);
+        o = framebuffer::try_setup_vga_framebuffer(vga, &FRAMEBUFFER_RESOLUTIONS);
         if o.is_some() {
-            uo = o.unwrap();
+            uo = o.unwrap().0;
             fb = Some(unsafe { &mut *((&mut uo) as *mut Vga<Color256, Unblanked>) as &mut dyn FrameBuffer });
         }
     }
-    let fb = fb.unwrap();
+    let mut fb = if let Some(fb) = fb {
+        writeln!(UART.lock(), "Framebuffer resolution: {}x{}", fb.get_width(), fb.get_height()).unwrap();
+        fb
+    } else {
+        writeln!(UART.lock(), "No framebuffer, using the serial port as the console!").unwrap();
+        serial_console = true;
+        // NOTE: Big enough for 80x25 characters, which is what most serial terminals expect
+        alloc::boxed::Box::leak(alloc::boxed::Box::new(framebuffer::HeadlessFrameBuffer::new(80 * 8, 25 * 16)))
+    };
+    if DOUBLE_BUFFER_FRAMEBUFFER && !serial_console {
+        fb = alloc::boxed::Box::leak(alloc::boxed::Box::new(framebuffer::DoubleBufferedFrameBuffer::new(fb)));
+    }
 
     fb.fill(0, 0, fb.get_width(), fb.get_height(), Pixel { r: 0, g: 0, b: 0 });
+    fb.present();
     TERMINAL.lock().set(Terminal::new(fb, Pixel { r: 0x0, g: 0xa8, b: 0x54 }));
+    TERMINAL.lock().serial_console = serial_console;
 
     writeln!(UART.lock(), "If you see this then that means the framebuffer subsystem didn't instantly crash the kernel :)")
         .unwrap();
     writeln!(TERMINAL.lock(), "Hello, world!").unwrap();
 
-    if let Some(primary_ata_bus) = unsafe { ATABus::primary_x86() } {
+    // Kept so that their write caches can be flushed before the machine goes away
+    let mut ata_devices: Vec<Rc<RefCell<ATADeviceFile>>> = Vec::new();
+    let bus_master_base = unsafe { ata::find_bus_master_base() };
+    if let Some(mut primary_ata_bus) = unsafe { ATABus::primary_x86() } {
+        if bus_master_base.and_then(|base| unsafe { primary_ata_bus.enable_dma(base) }).is_some() {
+            writeln!(TERMINAL.lock(), "Using dma for the primary ata bus").unwrap();
+        }
         let ata_ref = Rc::new(RefCell::new(primary_ata_bus));
         // NOTE: master device is not necessarilly the device from which the os was booted
 
         if unsafe { (*ata_ref).borrow_mut().identify(ATADevice::MASTER).is_some() } {
-            let master_dev = Rc::new(RefCell::new(ATADeviceFile { bus: ata_ref.clone(), bus_device: ATADevice::MASTER }));
+            let master_dev = Rc::new(RefCell::new(ATADeviceFile::new(ata_ref.clone(), ATADevice::MASTER, ATA_WRITE_CACHE_SECTORS)));
             (*dfs).borrow_mut().add_device_file(master_dev.clone() as Rc<RefCell<dyn IFile>>, "hda".to_owned());
+            ata_devices.push(master_dev.clone());
             for part_number in 0..4 {
                 if let Some(part_dev) = partitions::MBRPartitionFile::from(
                     master_dev.clone() as Rc<RefCell<dyn IFile>>,
@@ -210,8 +561,9 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
         }
 
         if unsafe { (*ata_ref).borrow_mut().identify(ATADevice::SLAVE).is_some() } {
-            let slave_dev = Rc::new(RefCell::new(ATADeviceFile { bus: ata_ref.clone(), bus_device: ATADevice::SLAVE }));
+            let slave_dev = Rc::new(RefCell::new(ATADeviceFile::new(ata_ref.clone(), ATADevice::SLAVE, ATA_WRITE_CACHE_SECTORS)));
             (*dfs).borrow_mut().add_device_file(slave_dev.clone() as Rc<RefCell<dyn IFile>>, "hdb".to_owned());
+            ata_devices.push(slave_dev.clone());
             for part_number in 0..4 {
                 if let Some(part_dev) = partitions::MBRPartitionFile::from(
                     slave_dev.clone() as Rc<RefCell<dyn IFile>>,
@@ -234,13 +586,17 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
         }
     }
 
-    if let Some(secondary_ata_bus) = unsafe { ATABus::secondary_x86() } {
+    if let Some(mut secondary_ata_bus) = unsafe { ATABus::secondary_x86() } {
+        if bus_master_base.and_then(|base| unsafe { secondary_ata_bus.enable_dma(base) }).is_some() {
+            writeln!(TERMINAL.lock(), "Using dma for the secondary ata bus").unwrap();
+        }
         let ata_ref = Rc::new(RefCell::new(secondary_ata_bus));
         // NOTE: master device is not necessarily the device from which the os was booted
 
         if unsafe { (*ata_ref).borrow_mut().identify(ATADevice::MASTER).is_some() } {
-            let master_dev = Rc::new(RefCell::new(ATADeviceFile { bus: ata_ref.clone(), bus_device: ATADevice::MASTER }));
+            let master_dev = Rc::new(RefCell::new(ATADeviceFile::new(ata_ref.clone(), ATADevice::MASTER, ATA_WRITE_CACHE_SECTORS)));
             (*dfs).borrow_mut().add_device_file(master_dev.clone() as Rc<RefCell<dyn IFile>>, "hdc".to_owned());
+            ata_devices.push(master_dev.clone());
             for part_number in 0..4 {
                 if let Some(part_dev) = partitions::MBRPartitionFile::from(
                     master_dev.clone() as Rc<RefCell<dyn IFile>>,
@@ -263,8 +619,9 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
         }
 
         if unsafe { (*ata_ref).borrow_mut().identify(ATADevice::SLAVE).is_some() } {
-            let slave_dev = Rc::new(RefCell::new(ATADeviceFile { bus: ata_ref.clone(), bus_device: ATADevice::SLAVE }));
+            let slave_dev = Rc::new(RefCell::new(ATADeviceFile::new(ata_ref.clone(), ATADevice::SLAVE, ATA_WRITE_CACHE_SECTORS)));
             (*dfs).borrow_mut().add_device_file(slave_dev.clone() as Rc<RefCell<dyn IFile>>, "hdd".to_owned());
+            ata_devices.push(slave_dev.clone());
             for part_number in 0..4 {
                 if let Some(part_dev) = partitions::MBRPartitionFile::from(
                     slave_dev.clone() as Rc<RefCell<dyn IFile>>,
@@ -287,45 +644,150 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
         }
     }
 
-    scheduler::init();
+    // A scratch disk that doesn't need any real hardware
+    (*dfs).borrow_mut().add_device_file(
+        Rc::new(RefCell::new(ramdisk::RamDisk::new(RAMDISK_SIZE_IN_BYTES))) as Rc<RefCell<dyn IFile>>,
+        "ram0".to_owned(),
+    );
+
+    // The kernel shell acts as the master end of pts0 while it's running programs
+    let (kernel_pty_master, kernel_pty_slave) = pty::PseudoTerminal::new_pair();
+    (*dfs).borrow_mut().add_device_file(kernel_pty_slave.clone() as Rc<RefCell<dyn IFile>>, "pts0".to_owned());
 
+    scheduler::init();
 
     KEYBOARD_INPUT.lock().set(unsafe { ps2_8042::PS2Device::x86_default() });
+    let has_mouse = if let Some(mouse) = unsafe { ps2_8042::PS2Mouse::init() } {
+        ps2_8042::MOUSE_INPUT.lock().set(mouse);
+        (*dfs).borrow_mut().add_device_file(Rc::new(RefCell::new(ps2_8042::PS2MouseFile)) as Rc<RefCell<dyn IFile>>, "mouse".to_owned());
+        true
+    } else {
+        writeln!(UART.lock(), "No ps/2 mouse found!").unwrap();
+        false
+    };
+    unsafe { interrupts::init() };
+    if unsafe { ps2_8042::enable_interrupts(has_mouse) }.is_none() {
+        writeln!(UART.lock(), "Couldn't enable the ps/2 interrupts, the keyboard will be polled!").unwrap();
+    }
+    unsafe { interrupts::enable() };
+    rtc::RTC.lock().set(unsafe { rtc::CMOSDevice::x86_default() });
+    if let Some(clock) = unsafe { clock::TSCClock::calibrate() } {
+        clock::CLOCK.lock().set(clock);
+    } else {
+        writeln!(UART.lock(), "Couldn't calibrate the tsc using the pit, there will be no clock!").unwrap();
+    }
 
     let mut cur_dir = vfs::Path::try_from("/").unwrap();
+    let mut profile_programs = false;
+    // The environment programs are started with, changed using set
+    let mut shell_env: BTreeMap<String, String> = BTreeMap::new();
+    shell_env.insert(String::from("HOME"), String::from("/"));
+    shell_env.insert(String::from("PATH"), String::from("/"));
+    // Mounted ext2 filesystems, so that they can be checked
+    let mut mounted_filesystems: Vec<(Rc<RefCell<RootFSNode>>, Rc<RefCell<ext2::Ext2FS>>)> = Vec::new();
+    let mut jobs: Vec<Job> = Vec::new();
     write!(TERMINAL.lock(), "{} # ", cur_dir).unwrap();
 
     'big_loop: loop {
-        let packet = unsafe { KEYBOARD_INPUT.lock().read_packet() };
+        // NOTE: Input can come from both the keyboard and the serial port ( if it's a serial console ), the serial port doesn't interrupt us,
+        // so the keyboard can only be waited on if there is no serial console
+        let packet = unsafe { KEYBOARD_INPUT.lock().try_read_packet() };
+        let c = if let Some(packet) = packet {
+            if packet.packet_type == KeyboardPacketType::KeyReleased && packet.key == KeyboardKey::Escape {
+                break;
+            }
 
-        if packet.packet_type == KeyboardPacketType::KeyReleased && packet.key == KeyboardKey::Escape {
-            break;
-        }
+            if packet.packet_type == KeyboardPacketType::KeyReleased {
+                continue;
+            }
 
-        if packet.packet_type == KeyboardPacketType::KeyReleased {
-            continue;
-        }
+            if packet.key == KeyboardKey::UpArrow {
+                TERMINAL.lock().visual_cursor_up();
+            } else if packet.key == KeyboardKey::DownArrow {
+                TERMINAL.lock().visual_cursor_down();
+            } else if packet.key == KeyboardKey::RightArrow {
+                TERMINAL.lock().visual_cursor_right();
+            } else if packet.key == KeyboardKey::LeftArrow {
+                TERMINAL.lock().visual_cursor_left();
+            }
 
-        if packet.key == KeyboardKey::UpArrow {
-            TERMINAL.lock().visual_cursor_up();
-        } else if packet.key == KeyboardKey::DownArrow {
-            TERMINAL.lock().visual_cursor_down();
-        } else if packet.key == KeyboardKey::RightArrow {
-            TERMINAL.lock().visual_cursor_right();
-        } else if packet.key == KeyboardKey::LeftArrow {
-            TERMINAL.lock().visual_cursor_left();
-        }
+            if packet.key == KeyboardKey::Tab {
+                complete_line(&cur_dir);
+                continue;
+            }
 
-        TERMINAL.lock().recive_key(packet.key, packet.modifiers);
+            TERMINAL.lock().recive_key(packet.key, packet.modifiers);
 
-        let Ok(c) = standard_usa_qwerty::parse_key(packet.key, packet.modifiers) else { continue; };
+            let Ok(c) = hio::parse_key(packet.key, packet.modifiers) else { continue; };
+            c
+        } else {
+            let serial_char = TERMINAL.lock().try_read_serial_char();
+            let Some(c) = serial_char else {
+                // Background jobs keep running while the prompt waits for input, the keyboard is only waited on once there is nothing left to run
+                if scheduler::tick() {
+                    pty::forward_output_to_kernel_terminal(&mut (*kernel_pty_master).borrow_mut());
+                    continue;
+                }
+                let serial_console = TERMINAL.lock().serial_console;
+                if !serial_console {
+                    unsafe { KEYBOARD_INPUT.lock().wait_for_input() };
+                }
+                continue;
+            };
+            if c == '\t' {
+                complete_line(&cur_dir);
+                continue;
+            }
+            TERMINAL.lock().recive_char(c);
+            c
+        };
 
         if c == '\n' {
+            // Jobs that ended while the prompt was waiting are reported first, since their pids can be given to the programs started now
+            let mut finished_actions = report_finished_jobs(&mut jobs);
+
             let splat = TERMINAL.lock().line_buffer.iter().collect::<String>();
             TERMINAL.lock().line_buffer.clear();
 
+            let splat = expand_variables(&splat, &shell_env);
+            let splat = expand_globs(&splat, &cur_dir);
+
+            // COMMAND &, runs a program in the background, the prompt comes back right away and the program is tracked in the job table
+            // NOTE: Builtins run inside the shell, so they always run in the foreground
+            let (splat, run_in_background) = match splat.trim_end().strip_suffix('&') {
+                // NOTE: The last word is expected to still have the newline after it
+                Some(line) => (String::from(line.trim_end()) + "\n", true),
+                None => (splat, false),
+            };
+            let command_line = splat.trim().to_owned();
             let mut splat = splat.split_inclusive(' ');
-            if let Some(cmnd) = splat.next() {
+            let mut program_pid = None;
+            let mut cmnd = splat.next();
+
+            // time COMMAND, reports how long COMMAND took, including waiting for the programs it started
+            let mut time_start_ms = None;
+            if cmnd.map(|cmnd| cmnd.trim()) == Some("time") {
+                if let Some(now) = clock::now_ms() {
+                    time_start_ms = Some(now);
+                } else {
+                    writeln!(TERMINAL.lock(), "There is no clock to time with!").unwrap();
+                }
+                cmnd = splat.next();
+            }
+
+            // nice PRIORITY COMMAND, runs a program with a priority other than the default one
+            let mut program_priority = process::DEFAULT_PRIORITY;
+            if cmnd.map(|cmnd| cmnd.trim()) == Some("nice") {
+                if let Some(priority) = splat.next().and_then(|priority| priority.trim().parse::<usize>().ok()) {
+                    program_priority = priority;
+                    cmnd = splat.next();
+                } else {
+                    writeln!(TERMINAL.lock(), "Usage: nice PRIORITY COMMAND").unwrap();
+                    cmnd = None;
+                }
+            }
+
+            if let Some(cmnd) = cmnd {
                 // Handle shell built-ins
                 if cmnd.starts_with("puts") {
                     let mut puts_output: String = String::new();
@@ -344,16 +806,19 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                     }
 
                     if let Some(redir_str) = redirect {
-                        let path = if redir_str.starts_with('/') {
-                            vfs::Path::try_from(redir_str).ok()
-                        } else {
-                            let mut actual_dir = cur_dir.clone();
-                            actual_dir.append_str(redir_str.as_str());
-                            Some(actual_dir)
+                        // >> appends to the file instead of replacing what's in it
+                        let (append, redir_str) = match redir_str.strip_prefix('>') {
+                            Some(redir_str) => (true, redir_str),
+                            None => (false, redir_str.as_str()),
                         };
+                        let path = vfs::resolve(&cur_dir, redir_str).ok();
                         if let Some(node) = path.map(|path| path.get_node()) {
                             if let Some(Node::File(file)) = node {
-                                if (*file).borrow_mut().resize(puts_output.len() as u64).is_some() {
+                                if append {
+                                    if (*file).borrow_mut().append(puts_output.as_bytes()).is_none() {
+                                        writeln!(TERMINAL.lock(), "Couldn't append to file!").unwrap();
+                                    }
+                                } else if (*file).borrow_mut().resize(puts_output.len() as u64).is_some() {
                                     if (*file).borrow_mut().write(0, puts_output.as_bytes()).is_none() {
                                         writeln!(TERMINAL.lock(), "Couldn't write to file!").unwrap();
                                     }
@@ -369,16 +834,72 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                     };
 
                     writeln!(TERMINAL.lock()).unwrap();
+                } else if cmnd.starts_with("setfont") {
+                    // Usage: setfont [NAME], without a name it shows the current and available fonts
+                    if let Some(name) = splat.next().map(|name| name.trim()).filter(|name| !name.is_empty()) {
+                        if let Some(font) = char_device::find_font(name) {
+                            let mut terminal = TERMINAL.lock();
+                            if terminal.set_font(font).is_some() {
+                                let (cols, rows) = (terminal.fb.get_cols(), terminal.fb.get_rows());
+                                writeln!(terminal, "Terminal is now {}x{} characters!", cols, rows).unwrap();
+                            } else {
+                                writeln!(terminal, "The screen is too small for that font!").unwrap();
+                            }
+                        } else {
+                            writeln!(TERMINAL.lock(), "Unknown font: \"{}\"!", name).unwrap();
+                        }
+                    } else {
+                        write!(TERMINAL.lock(), "Current font: {}, available fonts:", char_device::FONT.lock().name).unwrap();
+                        for font in char_device::FONTS {
+                            write!(TERMINAL.lock(), " {}", font.name).unwrap();
+                        }
+                        writeln!(TERMINAL.lock()).unwrap();
+                    }
+                } else if cmnd.starts_with("set") || cmnd.starts_with("export") {
+                    let args = splat.map(|arg| arg.trim()).filter(|arg| !arg.is_empty()).collect::<Vec<&str>>();
+                    if args.is_empty() {
+                        let mut terminal = TERMINAL.lock();
+                        for (name, value) in &shell_env {
+                            writeln!(terminal, "{}={}", name, value).unwrap();
+                        }
+                    }
+                    for arg in args {
+                        let Some((name, value)) = arg.split_once('=') else {
+                            writeln!(TERMINAL.lock(), "Usage: set NAME=VALUE ...").unwrap();
+                            continue;
+                        };
+                        if name.is_empty() || !name.chars().all(is_variable_name_char) {
+                            writeln!(TERMINAL.lock(), "Bad variable name: \"{}\"!", name).unwrap();
+                            continue;
+                        }
+                        shell_env.insert(name.to_owned(), value.to_owned());
+                    }
                 } else if cmnd.starts_with("whoareyou") {
                     writeln!(TERMINAL.lock(), "Ron").unwrap();
                 } else if cmnd.starts_with("help") {
-                    writeln!(
-                        TERMINAL.lock(),
-                        "puts whoareyou rmrootfsdir mkrootfsdir rm touch mount.ext2 umount free hexdump ls cd clear exit help"
-                    )
-                    .unwrap();
+                    writeln!(TERMINAL.lock(), "{}", BUILTIN_COMMANDS).unwrap();
                 } else if cmnd.starts_with("clear") {
                     TERMINAL.lock().clear();
+                } else if cmnd.starts_with("df") {
+                    for (path, folder) in RootFSNode::get_mounted_folders(vfs::VFS_ROOT.lock().clone()) {
+                        let Some(stats) = (*folder).borrow().get_fs_stats() else {
+                            writeln!(TERMINAL.lock(), "{}: no statistics available", path).unwrap();
+                            continue;
+                        };
+                        writeln!(
+                            TERMINAL.lock(),
+                            "{}: {} blocks of {} bytes, {} used, {} free, {} inodes, {} used, {} free",
+                            path,
+                            stats.total_blocks,
+                            stats.block_size,
+                            stats.total_blocks - stats.free_blocks,
+                            stats.free_blocks,
+                            stats.total_inodes,
+                            stats.total_inodes - stats.free_inodes,
+                            stats.free_inodes
+                        )
+                        .unwrap();
+                    }
                 } else if cmnd.starts_with("free") {
                     let kernel_heap_used = ALLOCATOR.lock().get_heap_used();
                     let program_heap_used = PROGRAM_ALLOCATOR.0.lock().get_heap_used();
@@ -394,14 +915,22 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                     .unwrap();
 
                     writeln!(TERMINAL.lock(), "Breakdown: {}% used of kernel heap, and {}% of program heap!", (kernel_heap_used as f32/kernel_heap_max as f32) * 100.0, (program_heap_used as f32/program_heap_max as f32)*100.0).unwrap();
+                } else if cmnd.starts_with("meminfo") {
+                    let kernel_heap_stats = ALLOCATOR.lock().get_stats();
+                    let program_heap_stats = PROGRAM_ALLOCATOR.0.lock().get_stats();
+                    let mut terminal = TERMINAL.lock();
+                    writeln!(terminal, "{:<8} {:>8} {:>12} {:>12} {:>8}", "HEAP", "LIVE", "FREE", "LARGEST", "STASHED").unwrap();
+                    for (name, stats) in [("kernel", kernel_heap_stats), ("program", program_heap_stats)] {
+                        writeln!(
+                            terminal,
+                            "{:<8} {:>8} {:>12} {:>12} {:>8}",
+                            name, stats.live_allocations, stats.total_free, stats.largest_free_block, stats.stashed_deallocations
+                        )
+                        .unwrap();
+                    }
                 } else if cmnd.starts_with("mount.ext2") {
                     if let (Some(file), Some(mntpoint)) = (splat.next(), splat.next()) {
-                        let mut file_node = vfs::Path::try_from(file.trim());
-                        if !file.starts_with("/") {
-                            let mut actual_node = cur_dir.clone();
-                            actual_node.append_str(file);
-                            file_node = Ok(actual_node);
-                        }
+                        let file_node = vfs::resolve(&cur_dir, file.trim());
 
                         let Ok(file_node) = file_node else {
                             writeln!(TERMINAL.lock(), "Malformed source path: \"{}\"!", file).unwrap();
@@ -418,7 +947,9 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                             continue;
                         };
 
-                        let Some(e2fs) = ext2::Ext2FS::new(file_node, false) else {
+                        // NOTE: 1024 bytes is the smallest block size ext2 allows, so cached blocks never straddle ext2 blocks
+                        let cache = Rc::new(RefCell::new(block_cache::BlockCache::new(file_node, 1024, 256)));
+                        let Some(e2fs) = ext2::Ext2FS::new(cache as Rc<RefCell<dyn IFile>>, false) else {
                             writeln!(TERMINAL.lock(), "Source file does not contain a valid ext2 fs!").unwrap();
                             continue;
                         };
@@ -431,12 +962,7 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                             .as_vfs_node(e2fs.clone(), 2)
                             .expect("Root inode should be parsable in vfs!")
                             .expect_folder();
-                        let mut mntpoint_node = vfs::Path::try_from(mntpoint.trim());
-                        if !mntpoint.starts_with("/") {
-                            let mut actual_node = cur_dir.clone();
-                            actual_node.append_str(mntpoint);
-                            mntpoint_node = Ok(actual_node);
-                        }
+                        let mntpoint_node = vfs::resolve(&cur_dir, mntpoint.trim());
 
                         let Ok(mntpoint_node) = mntpoint_node else {
                             writeln!(TERMINAL.lock(), "Malformed mountpoint path!").unwrap();
@@ -448,17 +974,74 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                             continue;
                         };
                         (*mntpoint_node).borrow_mut().mountpoint = Some(root_inode);
+                        mounted_filesystems.push((mntpoint_node, e2fs));
+                    } else {
+                        writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
+                    }
+                } else if cmnd.starts_with("mount.fat32") {
+                    if let (Some(file), Some(mntpoint)) = (splat.next(), splat.next()) {
+                        let file_node = vfs::resolve(&cur_dir, file.trim());
+
+                        let Ok(file_node) = file_node else {
+                            writeln!(TERMINAL.lock(), "Malformed source path: \"{}\"!", file).unwrap();
+                            continue;
+                        };
+
+                        let Some(file_node) = file_node.get_node() else {
+                            writeln!(TERMINAL.lock(), "Source path: \"{}\" does not exist!", file).unwrap();
+                            continue;
+                        };
+
+                        let vfs::Node::File(file_node) = file_node else {
+                            writeln!(TERMINAL.lock(), "Source path: \"{}\" is not a file!", file).unwrap();
+                            continue;
+                        };
+
+                        // NOTE: 512 bytes is the smallest sector size FAT allows, so cached blocks never straddle sectors
+                        let cache = Rc::new(RefCell::new(block_cache::BlockCache::new(file_node, 512, 256)));
+                        let Some(fat32fs) = fat32::Fat32FS::new(cache as Rc<RefCell<dyn IFile>>) else {
+                            writeln!(TERMINAL.lock(), "Source file does not contain a valid fat32 fs!").unwrap();
+                            continue;
+                        };
+                        let root_folder = fat32::Fat32FS::root_folder(Rc::new(RefCell::new(fat32fs)));
+
+                        let mntpoint_node = vfs::resolve(&cur_dir, mntpoint.trim());
+
+                        let Ok(mntpoint_node) = mntpoint_node else {
+                            writeln!(TERMINAL.lock(), "Malformed mountpoint path!").unwrap();
+                            continue;
+                        };
+
+                        let Some(mntpoint_node) = mntpoint_node.get_rootfs_node() else {
+                            writeln!(TERMINAL.lock(), "Mountpoint should exist in vfs!").unwrap();
+                            continue;
+                        };
+                        // NOTE: The fs is read-only, so unlike ext2 there is nothing to write back when it's unmounted
+                        (*mntpoint_node).borrow_mut().mountpoint = Some(root_folder);
+                    } else {
+                        writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
+                    }
+                } else if cmnd.starts_with("mount.tmpfs") {
+                    if let Some(mntpoint) = splat.next() {
+                        let mntpoint_node = vfs::resolve(&cur_dir, mntpoint.trim());
+
+                        let Ok(mntpoint_node) = mntpoint_node else {
+                            writeln!(TERMINAL.lock(), "Malformed mountpoint path!").unwrap();
+                            continue;
+                        };
+
+                        let Some(mntpoint_node) = mntpoint_node.get_rootfs_node() else {
+                            writeln!(TERMINAL.lock(), "Mountpoint should exist in vfs!").unwrap();
+                            continue;
+                        };
+                        // NOTE: The mountpoint is the only thing that points to the root folder, so umount frees everything in it, except for files that are still open
+                        (*mntpoint_node).borrow_mut().mountpoint = Some(Rc::new(RefCell::new(tmpfs::TmpFSFolder::new())) as Rc<RefCell<dyn IFolder>>);
                     } else {
                         writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
                     }
                 } else if cmnd.starts_with("umount") {
                     if let Some(mntpoint) = splat.next() {
-                        let mut mntpoint_node = vfs::Path::try_from(mntpoint.trim());
-                        if !mntpoint.starts_with("/") {
-                            let mut actual_node = cur_dir.clone();
-                            actual_node.append_str(mntpoint);
-                            mntpoint_node = Ok(actual_node);
-                        }
+                        let mntpoint_node = vfs::resolve(&cur_dir, mntpoint.trim());
 
                         let Ok(mntpoint_node) = mntpoint_node else {
                             writeln!(TERMINAL.lock(), "Malformed mountpoint path!").unwrap();
@@ -470,31 +1053,221 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                             continue;
                         };
 
+                        // Everything has to reach the disk before the filesystem is detached, otherwise nothing would write it later
+                        if (*mntpoint_node).borrow_mut().sync().is_none() {
+                            writeln!(TERMINAL.lock(), "Failed to write cached data back to disk!").unwrap();
+                        }
                         (*mntpoint_node).borrow_mut().mountpoint = None;
+                        mounted_filesystems.retain(|(node, _)| !Rc::ptr_eq(node, &mntpoint_node));
+                    } else {
+                        writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
+                    }
+                } else if cmnd.starts_with("fsck.ext2") {
+                    if let Some(mntpoint) = splat.next() {
+                        let mntpoint = mntpoint.trim();
+                        let mntpoint_node = vfs::resolve(&cur_dir, mntpoint).ok().and_then(|path| path.get_rootfs_node());
+
+                        let e2fs = mntpoint_node.and_then(|mntpoint_node| {
+                            mounted_filesystems.iter().find(|(node, _)| Rc::ptr_eq(node, &mntpoint_node)).map(|(_, e2fs)| e2fs.clone())
+                        });
+                        if let Some(e2fs) = e2fs {
+                            match (*e2fs).borrow().check() {
+                                Some(problems) if problems.is_empty() => writeln!(TERMINAL.lock(), "No problems found!").unwrap(),
+                                Some(problems) => {
+                                    for problem in &problems {
+                                        writeln!(TERMINAL.lock(), "{}!", problem).unwrap();
+                                    }
+                                    writeln!(TERMINAL.lock(), "Found {} problems!", problems.len()).unwrap();
+                                }
+                                None => writeln!(TERMINAL.lock(), "Failed to read the filesystem!").unwrap(),
+                            }
+                        } else {
+                            writeln!(TERMINAL.lock(), "No ext2 filesystem is mounted there!").unwrap();
+                        }
                     } else {
                         writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
                     }
+                } else if cmnd.starts_with("sync") {
+                    if !sync_everything(&mounted_filesystems, &ata_devices) {
+                        writeln!(TERMINAL.lock(), "Failed to write cached data back to disk!").unwrap();
+                    }
+                } else if cmnd.starts_with("chmod") {
+                    let args = splat.map(|arg| arg.trim()).filter(|arg| !arg.is_empty()).collect::<Vec<&str>>();
+                    let [mode, path] = args.as_slice() else {
+                        writeln!(TERMINAL.lock(), "Usage: chmod OCTAL_MODE PATH").unwrap();
+                        continue;
+                    };
+                    let Some(mode) = u32::from_str_radix(mode, 8).ok().filter(|mode| *mode <= 0o7777) else {
+                        writeln!(TERMINAL.lock(), "Invalid mode: \"{}\"!", mode).unwrap();
+                        continue;
+                    };
+                    let Some(node) = vfs::resolve(&cur_dir, path).ok().and_then(|path| path.get_node()) else {
+                        writeln!(TERMINAL.lock(), "No such file or folder: \"{}\"!", path).unwrap();
+                        continue;
+                    };
+
+                    let res = match node {
+                        Node::File(file) => (*file).borrow_mut().set_mode(mode),
+                        Node::Folder(folder) => (*folder).borrow_mut().set_mode(mode),
+                    };
+                    if res.is_none() {
+                        writeln!(TERMINAL.lock(), "Failed to change the mode of \"{}\"!", path).unwrap();
+                    }
+                } else if cmnd.starts_with("stat") {
+                    let Some(path) = splat.next().map(|path| path.trim()).filter(|path| !path.is_empty()) else {
+                        writeln!(TERMINAL.lock(), "Usage: stat PATH").unwrap();
+                        continue;
+                    };
+                    let Some(node) = vfs::resolve(&cur_dir, path).ok().and_then(|path| path.get_node()) else {
+                        writeln!(TERMINAL.lock(), "No such file or folder: \"{}\"!", path).unwrap();
+                        continue;
+                    };
+
+                    let (node_type, size, metadata) = match &node {
+                        Node::File(file) => {
+                            let file = (**file).borrow();
+                            let node_type = match file.get_file_type() {
+                                vfs::FileType::Regular => "file",
+                                vfs::FileType::CharacterDevice => "character device",
+                                vfs::FileType::BlockDevice => "block device",
+                                vfs::FileType::Fifo => "fifo",
+                            };
+                            (node_type, Some(file.get_size()), file.get_metadata())
+                        }
+                        Node::Folder(folder) => ("folder", None, (**folder).borrow().get_metadata()),
+                    };
+
+                    let mut terminal = TERMINAL.lock();
+                    writeln!(terminal, "  Path: {}", path).unwrap();
+                    write!(terminal, "  Type: {}", node_type).unwrap();
+                    if let Some(size) = size {
+                        write!(terminal, ", size: {} bytes", size).unwrap();
+                    }
+                    writeln!(terminal).unwrap();
+                    if let Some(metadata) = metadata {
+                        writeln!(terminal, " Inode: {}, links: {}", metadata.inode_number, metadata.hard_links).unwrap();
+                        writeln!(terminal, "  Mode: {:04o}, uid: {}, gid: {}", metadata.mode & 0o7777, metadata.user_id, metadata.group_id).unwrap();
+                        writeln!(terminal, "Access: {}", rtc::RTCTime::from_unix_timestamp(metadata.last_access_unix_timestamp)).unwrap();
+                        writeln!(terminal, "Modify: {}", rtc::RTCTime::from_unix_timestamp(metadata.last_modif_unix_timestamp)).unwrap();
+                        writeln!(terminal, "Change: {}", rtc::RTCTime::from_unix_timestamp(metadata.last_change_unix_timestamp)).unwrap();
+                    } else {
+                        writeln!(terminal, "The filesystem keeps no other metadata").unwrap();
+                    }
+                } else if cmnd.starts_with("lspci") {
+                    for function in unsafe { pci::enumerate() } {
+                        writeln!(
+                            TERMINAL.lock(),
+                            "{} {:04x}:{:04x} {} ({:02x}:{:02x}, prog if {:02x})",
+                            function.addr,
+                            function.vendor_id,
+                            function.device_id,
+                            pci::class_name(function.class),
+                            function.class,
+                            function.subclass,
+                            function.prog_if
+                        )
+                        .unwrap();
+                        for (index, bar) in function.bars {
+                            writeln!(TERMINAL.lock(), "    BAR{}: {}", index, bar).unwrap();
+                        }
+                    }
                 } else if cmnd.starts_with("ls") {
-                    for subnode in (*cur_dir.get_node().expect("Shell path should be valid at all times!").expect_folder())
-                        .borrow()
-                        .get_children()
-                    {
-                        write!(TERMINAL.lock(), "{} ", subnode.0).unwrap();
-                        if let Node::File(f) = subnode.1 {
-                            write!(TERMINAL.lock(), "(size: {} kb) ", (*f).borrow().get_size() as f32 / 1024.0).unwrap();
+                    // -l lists one entry per line with its type and size, -R also lists everything in subfolders
+                    let mut long_format = false;
+                    let mut recursive = false;
+                    let mut dir = cur_dir.clone();
+                    let mut bad_args = false;
+                    for arg in splat.by_ref().map(|arg| arg.trim()).filter(|arg| !arg.is_empty()) {
+                        if let Some(flags) = arg.strip_prefix('-') {
+                            for flag in flags.chars() {
+                                match flag {
+                                    'l' => long_format = true,
+                                    'R' => recursive = true,
+                                    _ => {
+                                        writeln!(TERMINAL.lock(), "Unknown flag: -{}!", flag).unwrap();
+                                        bad_args = true;
+                                    }
+                                }
+                            }
+                        } else if let Ok(path) = vfs::resolve(&cur_dir, arg) {
+                            dir = path;
+                        } else {
+                            writeln!(TERMINAL.lock(), "Invalid path!").unwrap();
+                            bad_args = true;
                         }
                     }
-                    writeln!(TERMINAL.lock()).unwrap();
+                    if bad_args {
+                        continue;
+                    }
+
+                    // NOTE: Folders are visited using a stack instead of recursion, so deep trees can't overflow the kernel stack
+                    let mut to_list = alloc::vec![dir];
+                    while let Some(dir) = to_list.pop() {
+                        let Some(Node::Folder(folder)) = dir.get_node() else {
+                            writeln!(TERMINAL.lock(), "{} is not a folder!", dir).unwrap();
+                            continue;
+                        };
+                        if recursive {
+                            writeln!(TERMINAL.lock(), "{}:", dir).unwrap();
+                        }
+
+                        let mut subfolders = Vec::new();
+                        for (name, node) in (*folder).borrow().get_children() {
+                            match &node {
+                                Node::File(f) if long_format => {
+                                    let typ = match (*f).borrow().get_file_type() {
+                                        vfs::FileType::Regular => '-',
+                                        vfs::FileType::CharacterDevice => 'c',
+                                        vfs::FileType::BlockDevice => 'b',
+                                        vfs::FileType::Fifo => 'p',
+                                    };
+                                    writeln!(TERMINAL.lock(), "{} {:>10} {}", typ, (*f).borrow().get_size(), name).unwrap();
+                                }
+                                Node::Folder(_) if long_format => writeln!(TERMINAL.lock(), "d {:>10} {}", "-", name).unwrap(),
+                                Node::File(f) => write!(TERMINAL.lock(), "{} (size: {} kb) ", name, (*f).borrow().get_size() as f32 / 1024.0).unwrap(),
+                                Node::Folder(_) => write!(TERMINAL.lock(), "{} ", name).unwrap(),
+                            }
+
+                            // . and .. would make the listing go on forever
+                            if let Node::Folder(_) = node {
+                                if recursive && name != "." && name != ".." {
+                                    let mut subfolder = dir.clone();
+                                    subfolder.append_str(&name);
+                                    subfolders.push(subfolder);
+                                }
+                            }
+                        }
+                        if !long_format {
+                            writeln!(TERMINAL.lock()).unwrap();
+                        }
+                        // Reversed so that subfolders are listed in the order they were found
+                        to_list.extend(subfolders.into_iter().rev());
+                    }
+                } else if cmnd.starts_with("find") {
+                    // NOTE: Globs are expanded before builtins run, so the name is matched as a substring instead
+                    let args = splat.map(|arg| arg.trim()).filter(|arg| !arg.is_empty()).collect::<Vec<&str>>();
+                    let [start, name] = args.as_slice() else {
+                        writeln!(TERMINAL.lock(), "Usage: find START_PATH NAME").unwrap();
+                        continue;
+                    };
+                    let Ok(start) = vfs::resolve(&cur_dir, start) else {
+                        writeln!(TERMINAL.lock(), "Invalid path!").unwrap();
+                        continue;
+                    };
+                    for path in find_by_name(&start, name) {
+                        writeln!(TERMINAL.lock(), "{}", path).unwrap();
+                    }
                 } else if cmnd.starts_with("hexdump") {
+                    // Usage: hexdump <offset> <file> [length], the length defaults to one row
+                    // Every row is the offset of its first byte in hex, two spaces, up to 16 bytes in hex each followed by a space ( with an extra space after the 8th ), padding for the missing bytes of a short row, and then the bytes as ascii between |s, for ex.:
+                    // 00000010  48 65 6C 6C 6F 2C 20 77  6F 72 6C 64 21 0A        |Hello, world!.|
+                    // NOTE: Bytes that aren't printable ascii are shown as a . in the ascii column
+                    const HEXDUMP_ROW_LEN: usize = 16;
+                    const HEXDUMP_CHUNK_LEN: usize = 32 * HEXDUMP_ROW_LEN;
                     if let (Some(offset_str), Some(file_str)) = (splat.next(), splat.next()) {
-                        if let Ok(offset) = offset_str.trim().parse::<usize>() {
-                            let arg_path = if file_str.starts_with('/') {
-                                vfs::Path::try_from(file_str)
-                            } else {
-                                let mut actual_dir = cur_dir.clone();
-                                actual_dir.append_str(file_str);
-                                Ok(actual_dir)
-                            };
+                        let len = splat.next().map(|len_str| len_str.trim().parse::<usize>());
+                        if let (Ok(offset), Ok(len)) = (offset_str.trim().parse::<usize>(), len.unwrap_or(Ok(HEXDUMP_ROW_LEN))) {
+                            let arg_path = vfs::resolve(&cur_dir, file_str);
 
                             let node = arg_path.map(|path| path.get_node());
                             let Ok(node)= node else {
@@ -507,35 +1280,80 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                             };
 
                             if let Node::File(file) = node {
-                                if let Some(data) =
-                                    (*file).borrow().read(offset as u64, min(16, (*file).borrow().get_size() as usize))
-                                {
-                                    for e in data.iter() {
-                                        write!(TERMINAL.lock(), "0x{:02X} ", e).unwrap();
+                                let end = min(offset.saturating_add(len), (*file).borrow().get_size() as usize);
+                                // Read a few rows at a time, so that dumping a big range doesn't need a buffer as big as the range
+                                let mut chunk_start = offset;
+                                while chunk_start < end {
+                                    let chunk_len = min(HEXDUMP_CHUNK_LEN, end - chunk_start);
+                                    let Some(data) = (*file).borrow().read(chunk_start as u64, chunk_len) else {
+                                        writeln!(TERMINAL.lock(), "Couldn't read file!").unwrap();
+                                        break;
+                                    };
+
+                                    let mut terminal = TERMINAL.lock();
+                                    for (row_index, row) in data.chunks(HEXDUMP_ROW_LEN).enumerate() {
+                                        write!(terminal, "{:08X} ", chunk_start + row_index * HEXDUMP_ROW_LEN).unwrap();
+                                        for i in 0..HEXDUMP_ROW_LEN {
+                                            if i % 8 == 0 {
+                                                write!(terminal, " ").unwrap();
+                                            }
+                                            if let Some(byte) = row.get(i) {
+                                                write!(terminal, "{:02X} ", byte).unwrap();
+                                            } else {
+                                                write!(terminal, "   ").unwrap();
+                                            }
+                                        }
+                                        write!(terminal, " |").unwrap();
+                                        for byte in row {
+                                            let c = if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' };
+                                            write!(terminal, "{}", c).unwrap();
+                                        }
+                                        writeln!(terminal, "|").unwrap();
+                                    }
+                                    chunk_start += data.len();
+                                    // A short read means the file ended early
+                                    if data.len() < chunk_len {
+                                        break;
                                     }
-                                } else {
-                                    write!(TERMINAL.lock(), "Couldn't read file!").unwrap();
                                 }
                             } else {
-                                write!(TERMINAL.lock(), "Path should be a file!").unwrap();
+                                writeln!(TERMINAL.lock(), "Path should be a file!").unwrap();
                             }
                         } else {
-                            write!(TERMINAL.lock(), "Bad offset!").unwrap();
+                            writeln!(TERMINAL.lock(), "Bad offset or length!").unwrap();
                         }
                     } else {
-                        write!(TERMINAL.lock(), "Not enough arguments!").unwrap();
+                        writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
+                    }
+                } else if cmnd.starts_with("blockmap") {
+                    if let Some(file_str) = splat.next() {
+                        let arg_path = vfs::resolve(&cur_dir, file_str);
+
+                        match arg_path.map(|path| path.get_node()) {
+                            Err(_) => writeln!(TERMINAL.lock(), "Invalid path!").unwrap(),
+                            Ok(None) => writeln!(TERMINAL.lock(), "Path doesn't exist!").unwrap(),
+                            Ok(Some(Node::Folder(_))) => writeln!(TERMINAL.lock(), "Path should be a file!").unwrap(),
+                            Ok(Some(Node::File(file))) => {
+                                if let Some(block_map) = (*file).borrow().get_block_map() {
+                                    let mut terminal = TERMINAL.lock();
+                                    for (data_block_number, pointer) in block_map.iter().enumerate() {
+                                        if *pointer == 0 {
+                                            writeln!(terminal, "{}: hole", data_block_number).unwrap();
+                                        } else {
+                                            writeln!(terminal, "{}: {}", data_block_number, pointer).unwrap();
+                                        }
+                                    }
+                                } else {
+                                    writeln!(TERMINAL.lock(), "File has no block map!").unwrap();
+                                }
+                            }
+                        }
+                    } else {
+                        writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
                     }
-
-                    writeln!(TERMINAL.lock()).unwrap();
                 } else if cmnd.starts_with("touch") {
                     while let Some(name) = splat.next() {
-                        let arg_path = if name.starts_with('/') {
-                            vfs::Path::try_from(name)
-                        } else {
-                            let mut actual_dir = cur_dir.clone();
-                            actual_dir.append_str(name);
-                            Ok(actual_dir)
-                        };
+                        let arg_path = vfs::resolve(&cur_dir, name);
                         let Ok(mut arg_path) = arg_path else {
                             writeln!(TERMINAL.lock(), "Bad path!").unwrap();
                             continue;
@@ -560,20 +1378,10 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                 } else if cmnd.starts_with("cd") {
                     if let Some(name) = splat.next() {
                         let name = name.trim();
-                        let old_dir = cur_dir.clone();
-                        if name.starts_with("/") {
-                            if let Ok(new_dir) = name.try_into() {
-                                cur_dir = new_dir;
-                            } else {
-                                writeln!(TERMINAL.lock(), "Invalid cd path!").unwrap();
-                            };
-                        } else {
-                            cur_dir.append_str(name);
-                        }
-
-                        if cur_dir.get_node().is_none() {
-                            writeln!(TERMINAL.lock(), "Invalid cd path: {}!", cur_dir).unwrap();
-                            cur_dir = old_dir;
+                        match vfs::resolve(&cur_dir, name) {
+                            Ok(new_dir) if new_dir.get_node().is_some() => cur_dir = new_dir,
+                            Ok(new_dir) => writeln!(TERMINAL.lock(), "Invalid cd path: {}!", new_dir).unwrap(),
+                            Err(_) => writeln!(TERMINAL.lock(), "Invalid cd path!").unwrap(),
                         }
                     }
                 } else if cmnd.starts_with("mkrootfsdir") {
@@ -604,13 +1412,7 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                     }
                 } else if cmnd.starts_with("rm") {
                     while let Some(name) = splat.next() {
-                        let arg_path = if name.starts_with('/') {
-                            vfs::Path::try_from(name)
-                        } else {
-                            let mut actual_dir = cur_dir.clone();
-                            actual_dir.append_str(name);
-                            Ok(actual_dir)
-                        };
+                        let arg_path = vfs::resolve(&cur_dir, name);
                         let Ok(mut arg_path) = arg_path else {
                             writeln!(TERMINAL.lock(), "Bad path!").unwrap();
                             continue;
@@ -628,33 +1430,336 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                                 writeln!(TERMINAL.lock(), "File doesn't exist in folder!").unwrap();
                                 continue;
                             };
-                            let Node::File(child) = child else {
+                            let Node::File(_) = child else {
                                 writeln!(TERMINAL.lock(), "Not a file!").unwrap();
                                 continue;
                             };
 
-                            writeln!(TERMINAL.lock(), "Removing the data from \"{}\"!", name).unwrap();
-                            if child.borrow_mut().resize(0).is_none() {
-                                writeln!(TERMINAL.lock(), "Failed to remove the data!").unwrap();
+                            // NOTE: The data isn't removed here, the file might have other links that still need it, the filesystem removes it with the last link
+                            writeln!(TERMINAL.lock(), "Deleting/unlinking file!").unwrap();
+                            if folder.borrow_mut().unlink_or_delete_empty_child(&name).is_none() {
+                                writeln!(TERMINAL.lock(), "Failed to delete/unlink file!").unwrap();
+                            }
+                        }
+                    }
+                } else if cmnd.starts_with("mv") {
+                    if let (Some(from), Some(to)) = (splat.next(), splat.next()) {
+                        let [from, to] = [from.trim(), to.trim()].map(|name| vfs::resolve(&cur_dir, name));
+                        if let (Ok(from), Ok(to)) = (from, to) {
+                            if vfs::rename(&from, &to).is_none() {
+                                writeln!(TERMINAL.lock(), "Failed to move \"{}\" to \"{}\"!", from, to).unwrap();
+                            }
+                        } else {
+                            writeln!(TERMINAL.lock(), "Bad path!").unwrap();
+                        }
+                    } else {
+                        writeln!(TERMINAL.lock(), "Usage: mv SOURCE DESTINATION").unwrap();
+                    }
+                } else if cmnd.starts_with("ln") {
+                    if let (Some(existing), Some(new)) = (splat.next(), splat.next()) {
+                        let [existing, new] = [existing.trim(), new.trim()].map(|name| vfs::resolve(&cur_dir, name));
+                        if let (Ok(existing), Ok(new)) = (existing, new) {
+                            if vfs::link(&existing, &new).is_none() {
+                                writeln!(TERMINAL.lock(), "Failed to link \"{}\" to \"{}\"!", new, existing).unwrap();
+                            }
+                        } else {
+                            writeln!(TERMINAL.lock(), "Bad path!").unwrap();
+                        }
+                    } else {
+                        writeln!(TERMINAL.lock(), "Usage: ln EXISTING NEW").unwrap();
+                    }
+                } else if cmnd.starts_with("ps") {
+                    writeln!(TERMINAL.lock(), "{:<6} {:<6} {:<4} {:<18} NAME", "PID", "PPID", "PRI", "STATE").unwrap();
+                    for task in scheduler::list_tasks() {
+                        write!(TERMINAL.lock(), "{:<6} ", task.pid).unwrap();
+                        match task.parent_pid {
+                            Some(parent_pid) => write!(TERMINAL.lock(), "{:<6} ", parent_pid).unwrap(),
+                            None => write!(TERMINAL.lock(), "{:<6} ", "-").unwrap(),
+                        }
+                        write!(TERMINAL.lock(), "{:<4} ", task.priority).unwrap();
+                        let mut state = String::from(task.state.get_short_name());
+                        if let Some(signal_type) = task.state.get_terminating_signal() {
+                            write!(state, " by {}", signal_type.name()).unwrap();
+                        }
+                        writeln!(TERMINAL.lock(), "{:<18} {}", state, task.name).unwrap();
+                    }
+                } else if cmnd.starts_with("jobs") {
+                    // NOTE: Jobs that ended are left out, since they are reported right after this
+                    let tasks = scheduler::list_tasks().collect::<Vec<scheduler::ProcessInfo>>();
+                    for job in jobs.iter() {
+                        let Some(task) = tasks.iter().find(|task| task.pid == job.pid && !task.state.is_terminated()) else {
+                            continue;
+                        };
+                        writeln!(TERMINAL.lock(), "[{}] {:<6} {:<18} {}", job.id, job.pid, task.state.get_short_name(), job.command).unwrap();
+                    }
+                } else if cmnd.starts_with("kill") {
+                    let args = splat.map(|arg| arg.trim()).filter(|arg| !arg.is_empty()).collect::<Vec<&str>>();
+                    if args.first() == Some(&"-l") {
+                        let mut terminal = TERMINAL.lock();
+                        for signal_type in SignalType::ALL {
+                            writeln!(terminal, "{:>2}) {}", u8::from(signal_type), signal_type.name()).unwrap();
+                        }
+                    } else {
+                        // kill [-SIGNAL] PID, where SIGNAL is a number or a name, SIGTERM by default
+                        let (signal_type, pid) = match args.as_slice() {
+                            [signal_str, pid_str] if signal_str.starts_with('-') => {
+                                let signal_str = &signal_str[1..];
+                                let signal_type = signal_str
+                                    .parse::<u8>()
+                                    .ok()
+                                    .and_then(SignalType::from_number)
+                                    .or_else(|| SignalType::from_name(&signal_str.to_uppercase()));
+                                (signal_type, pid_str.parse::<usize>().ok())
+                            }
+                            [pid_str] => (Some(SignalType::SIGTERM), pid_str.parse::<usize>().ok()),
+                            _ => (None, None),
+                        };
+
+                        match (signal_type, pid) {
+                            (None, _) => writeln!(TERMINAL.lock(), "Unknown signal, use kill -l to list them!").unwrap(),
+                            (_, None) => writeln!(TERMINAL.lock(), "Usage: kill -l | kill [-SIGNAL] PID").unwrap(),
+                            (Some(signal_type), Some(pid)) => {
+                                // NOTE: A SIGKILL can't be caught, so the process is terminated right away instead of waiting for its next tick
+                                let res = if matches!(signal_type, SignalType::SIGKILL) {
+                                    scheduler::terminate_task(pid)
+                                } else if pid != 0 {
+                                    scheduler::kill_task(pid, process::ProcessSignal { signal_type })
+                                } else {
+                                    None
+                                };
+                                if res.is_none() {
+                                    writeln!(TERMINAL.lock(), "No process with pid {}!", pid).unwrap();
+                                }
+                            }
+                        }
+                    }
+                } else if cmnd.starts_with("uptime") {
+                    if let Some(now) = clock::now_ms() {
+                        let seconds = now / 1000;
+                        writeln!(TERMINAL.lock(), "up {}:{:02}:{:02}.{:03}", seconds / 3600, seconds / 60 % 60, seconds % 60, now % 1000).unwrap();
+                    } else {
+                        writeln!(TERMINAL.lock(), "There is no clock!").unwrap();
+                    }
+                } else if cmnd.starts_with("date") {
+                    let now = rtc::now_unix();
+                    writeln!(TERMINAL.lock(), "{}", rtc::RTCTime::from_unix_timestamp(now)).unwrap();
+                } else if cmnd.starts_with("layout") {
+                    // Usage: layout [NAME], without a name it shows the current and available layouts
+                    if let Some(name) = splat.next().map(|name| name.trim()).filter(|name| !name.is_empty()) {
+                        if let Some(layout) = hio::find_keyboard_layout(name) {
+                            *hio::KEYBOARD_LAYOUT.lock() = layout;
+                        } else {
+                            writeln!(TERMINAL.lock(), "Unknown keyboard layout: \"{}\"!", name).unwrap();
+                        }
+                    } else {
+                        write!(TERMINAL.lock(), "Current layout: {}, available layouts:", hio::KEYBOARD_LAYOUT.lock().name).unwrap();
+                        for layout in hio::KEYBOARD_LAYOUTS {
+                            write!(TERMINAL.lock(), " {}", layout.name).unwrap();
+                        }
+                        writeln!(TERMINAL.lock()).unwrap();
+                    }
+                } else if cmnd.starts_with("stty") {
+                    // Usage: stty [[-]echo] [[-]icanon] [[-]isig] [raw] [sane], without arguments it shows the current settings
+                    // NOTE: Applies to both the terminal that programs use as stdin and pts0
+                    let args = splat.map(|arg| arg.trim()).filter(|arg| !arg.is_empty()).collect::<Vec<&str>>();
+                    let mut settings = TERMINAL.lock().line_discipline;
+                    if args.is_empty() {
+                        let flag = |enabled: bool| if enabled { "" } else { "-" };
+                        writeln!(
+                            TERMINAL.lock(),
+                            "{}echo {}icanon {}isig",
+                            flag(settings.echo),
+                            flag(settings.canonical),
+                            flag(settings.signals)
+                        )
+                        .unwrap();
+                    } else if let Some(arg) = args.iter().find(|arg| settings.apply_stty_argument(arg).is_none()) {
+                        writeln!(TERMINAL.lock(), "Unknown setting: \"{}\"!", arg).unwrap();
+                    } else {
+                        TERMINAL.lock().line_discipline = settings;
+                        (*(*kernel_pty_master).borrow().get_pty()).borrow_mut().settings = settings;
+                    }
+                } else if cmnd.starts_with("seq") {
+                    // Usage: seq [FIRST] [STEP] LAST
+                    let args = splat.map(|arg| arg.trim()).filter(|arg| !arg.is_empty()).collect::<Vec<&str>>();
+                    let Ok(nums) = args.iter().map(|arg| arg.parse::<i64>()).collect::<Result<Vec<i64>, _>>() else {
+                        writeln!(TERMINAL.lock(), "Arguments should be numbers!").unwrap();
+                        continue;
+                    };
+
+                    let (first, step, last) = match nums.as_slice() {
+                        [last] => (1, 1, *last),
+                        [first, last] => (*first, if first <= last { 1 } else { -1 }, *last),
+                        [first, step, last] => (*first, *step, *last),
+                        _ => {
+                            writeln!(TERMINAL.lock(), "Usage: seq [FIRST] [STEP] LAST").unwrap();
+                            continue;
+                        }
+                    };
+
+                    if step == 0 {
+                        writeln!(TERMINAL.lock(), "Step can't be zero!").unwrap();
+                        continue;
+                    }
+
+                    if (step > 0 && first > last) || (step < 0 && first < last) {
+                        writeln!(TERMINAL.lock(), "Step goes the wrong way, LAST would never be reached!").unwrap();
+                        continue;
+                    }
+
+                    // NOTE: LAST is inclusive, but only printed if it's actually hit by the step
+                    let mut n = first;
+                    while (step > 0 && n <= last) || (step < 0 && n >= last) {
+                        writeln!(TERMINAL.lock(), "{}", n).unwrap();
+                        let Some(next) = n.checked_add(step) else { break; };
+                        n = next;
+                    }
+                } else if cmnd.starts_with("profile") {
+                    // Toggles counting the types of instructions executed by programs started from the shell
+                    profile_programs = !profile_programs;
+                    writeln!(
+                        TERMINAL.lock(),
+                        "Program profiling {}!",
+                        if profile_programs { "enabled" } else { "disabled" }
+                    )
+                    .unwrap();
+                } else if cmnd.starts_with("emumemcheck") {
+                    // Checks that the memory programs run in behaves like the emulator expects, using a scratch region
+                    use virtmem::VirtualMemory;
+                    const SCRATCH_ADDRESS: u64 = 0x1000;
+                    let mut virtual_memory = virtmem::LittleEndianVirtualMemory::<&'static allocator::ProgramBasicAlloc>::new();
+                    let mut scratch = Vec::new_in(&PROGRAM_ALLOCATOR);
+                    scratch.resize(8, 0u8);
+                    let result = virtual_memory
+                        .add_region(SCRATCH_ADDRESS, scratch)
+                        .and_then(|_| emulator::verify_emulator_memory(&mut virtual_memory, SCRATCH_ADDRESS));
+                    writeln!(TERMINAL.lock(), "Emulator memory {}!", if result.is_some() { "passed all checks" } else { "failed a check" }).unwrap();
+                } else if cmnd.starts_with("dmacheck") {
+                    // Reads the first sectors of every disk using both dma and pio, to check that dma gives the right data
+                    let sector_count = splat
+                        .next()
+                        .and_then(|count| count.trim().parse::<usize>().ok())
+                        .unwrap_or(ata::MAX_SECTORS_PER_COMMAND)
+                        .clamp(1, ata::MAX_SECTORS_PER_COMMAND);
+                    for ata_device in &ata_devices {
+                        let ata_device = (**ata_device).borrow();
+                        let mut bus = (*ata_device.bus).borrow_mut();
+                        let name = (bus.get_bus_name(), ata_device.bus_device.into_str());
+                        let sector_count = core::cmp::min(sector_count as u64, unsafe { bus.get_sector_count(ata_device.bus_device) }.unwrap_or(0)) as usize;
+                        if !bus.is_dma_enabled() {
+                            writeln!(TERMINAL.lock(), "{} {}: dma isn't enabled", name.0, name.1).unwrap();
+                            continue;
+                        }
+                        match unsafe { bus.verify_dma(ata_device.bus_device, 0, sector_count) } {
+                            Some(true) => writeln!(TERMINAL.lock(), "{} {}: dma and pio read the same {} sectors", name.0, name.1, sector_count).unwrap(),
+                            Some(false) => writeln!(TERMINAL.lock(), "{} {}: dma and pio read different data!", name.0, name.1).unwrap(),
+                            None => writeln!(TERMINAL.lock(), "{} {}: reading failed!", name.0, name.1).unwrap(),
+                        }
+                    }
+                } else if cmnd.starts_with("fault") {
+                    // Causes a cpu exception on purpose, to check that it's reported instead of resetting the machine
+                    let vector = match splat.next().map(|kind| kind.trim()) {
+                        Some("divide") => Some(0),
+                        Some("breakpoint") => Some(3),
+                        Some("opcode") => Some(6),
+                        _ => None,
+                    };
+                    if vector.and_then(interrupts::trigger_exception).is_none() {
+                        writeln!(TERMINAL.lock(), "Usage: fault divide|breakpoint|opcode").unwrap();
+                    } else {
+                        writeln!(TERMINAL.lock(), "Came back from the exception").unwrap();
+                    }
+                } else if cmnd.starts_with("disas") {
+                    if let Some(file_str) = splat.next() {
+                        let file_str = file_str.trim();
+                        let node = vfs::resolve(&cur_dir, file_str).ok().and_then(|path| path.get_node());
+
+                        if let Some(Node::File(file)) = node {
+                            let contents = (*file).borrow().read(0, (*file).borrow().get_size() as usize);
+                            if let Some(elf) = contents.as_ref().and_then(|contents| elf::ElfFile::from_bytes(contents)) {
+                                let contents = contents.as_ref().unwrap();
+                                for segment in elf.get_executable_segments() {
+                                    let start = segment.segment_file_offset as usize;
+                                    let end = min(start + segment.segment_file_size as usize, contents.len());
+                                    let mut offset = start;
+                                    // NOTE: Instructions are little endian and the lowest 2 bits of the first parcel tell if it's compressed
+                                    while offset + core::mem::size_of::<u16>() <= end {
+                                        let addr = segment.segment_virtual_address + (offset - start) as u64;
+                                        let parcel = u16::from_le_bytes([contents[offset], contents[offset + 1]]);
+                                        let (text, size) = if parcel & 0b11 != 0b11 {
+                                            (emulator::disassemble_compressed(parcel), core::mem::size_of::<u16>())
+                                        } else if offset + core::mem::size_of::<u32>() <= end {
+                                            let inst = u32::from_le_bytes(contents[offset..offset + 4].try_into().unwrap());
+                                            (emulator::disassemble(inst), core::mem::size_of::<u32>())
+                                        } else {
+                                            break;
+                                        };
+                                        writeln!(TERMINAL.lock(), "0x{:x}: {}", addr, text.as_deref().unwrap_or("unknown")).unwrap();
+                                        offset += size;
+                                    }
+                                }
                             } else {
-                                writeln!(TERMINAL.lock(), "Deleting/unlinking file!").unwrap();
-                                if folder.borrow_mut().unlink_or_delete_empty_child(&name).is_none() {
-                                    writeln!(TERMINAL.lock(), "Failed to delete/unlink file!").unwrap();
+                                writeln!(TERMINAL.lock(), "File is not an elf file!").unwrap();
+                            }
+                        } else {
+                            writeln!(TERMINAL.lock(), "Path should be a file!").unwrap();
+                        }
+                    } else {
+                        writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
+                    }
+                } else if cmnd.starts_with("nm") {
+                    if let Some(file_str) = splat.next() {
+                        let file_str = file_str.trim();
+                        let node = vfs::resolve(&cur_dir, file_str).ok().and_then(|path| path.get_node());
+
+                        if let Some(Node::File(file)) = node {
+                            let contents = (*file).borrow().read(0, (*file).borrow().get_size() as usize);
+                            if let Some(elf) = contents.as_ref().and_then(|contents| elf::ElfFile::from_bytes(contents)) {
+                                match elf.get_symbols(contents.as_ref().unwrap()) {
+                                    Some(symbols) if symbols.is_empty() => writeln!(TERMINAL.lock(), "No symbols!").unwrap(),
+                                    Some(mut symbols) => {
+                                        // Sorted by address like nm -n, since that's the useful order for looking up an address
+                                        symbols.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+                                        let mut terminal = TERMINAL.lock();
+                                        for symbol in symbols {
+                                            // Undefined symbols have no address
+                                            if symbol.kind == 'U' || symbol.kind == 'w' {
+                                                writeln!(terminal, "{:16} {} {}", "", symbol.kind, symbol.name).unwrap();
+                                            } else {
+                                                writeln!(terminal, "{:016x} {} {}", symbol.value, symbol.kind, symbol.name).unwrap();
+                                            }
+                                        }
+                                    }
+                                    None => writeln!(TERMINAL.lock(), "Couldn't read the symbol table!").unwrap(),
                                 }
+                            } else {
+                                writeln!(TERMINAL.lock(), "File is not an elf file!").unwrap();
                             }
+                        } else {
+                            writeln!(TERMINAL.lock(), "Path should be a file!").unwrap();
                         }
+                    } else {
+                        writeln!(TERMINAL.lock(), "Not enough arguments!").unwrap();
                     }
                 } else if cmnd.starts_with("elp") {
                     writeln!(TERMINAL.lock(), "NOPERS, no elp!").unwrap();
+                } else if cmnd.starts_with("reboot") || cmnd.starts_with("shutdown") {
+                    // Everything has to reach the disk before the machine goes away
+                    if !sync_everything(&mounted_filesystems, &ata_devices) {
+                        writeln!(TERMINAL.lock(), "Failed to write cached data back to disk!").unwrap();
+                    }
+
+                    if cmnd.starts_with("reboot") {
+                        power::reboot();
+                    }
+                    power::shutdown(acpi_rsdp_ptr);
+                    writeln!(TERMINAL.lock(), "Couldn't power off, use exit instead!").unwrap();
                 } else if cmnd.starts_with("exit") {
                     break 'big_loop;
                 } else if !cmnd.trim().is_empty() {
-                    let executable_path = if cmnd.starts_with('/') {
-                        vfs::Path::try_from(cmnd)
-                    } else if cmnd.starts_with('.') {
-                        let mut actual_dir = cur_dir.clone();
-                        actual_dir.append_str(cmnd);
-                        Ok(actual_dir)
+                    // NOTE: Anything that isn't a builtin has to be a path, and relative ones have to start with a . ( for ex. ./program )
+                    let executable_path = if cmnd.starts_with('/') || cmnd.starts_with('.') {
+                        vfs::resolve(&cur_dir, cmnd)
                     } else {
                         Err(())
                     };
@@ -688,31 +1793,77 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
                                 .unwrap();
                         }
 
-                        let mut program_env = BTreeMap::new();
-                        program_env.insert("HOME", "/");
-                        program_env.insert("PATH", "/");
+                        let program_env =
+                            shell_env.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<BTreeMap<&str, &str>>();
 
                         let mut args = Vec::new();
                         args.push(cmnd);
                         args.extend(splat);
-                        let program =
+                        let mut program =
                             if let Some(p) = Process::from_elf(&contents, &args, cur_dir.clone(), &program_env) {
                                 p
                             } else {
                                 writeln!(TERMINAL.lock(), "Failed to load elf file into program!").unwrap();
                                 continue;
                             };
-                        scheduler::new_task(program);
-
-                        writeln!(TERMINAL.lock(), "Program loaded!").unwrap();
+                        if profile_programs {
+                            program.emu.enable_profiling();
+                        }
+                        if run_in_background {
+                            // NOTE: Background jobs don't get the keyboard, otherwise they would take the keys meant for the prompt
+                            program.data.fd_mappings[0] = None;
+                        }
+                        let pid = scheduler::new_task(program);
+                        scheduler::set_priority(pid, program_priority);
+                        if run_in_background {
+                            let id = jobs.last().map_or(1, |job| job.id + 1);
+                            writeln!(TERMINAL.lock(), "[{}] {}", id, pid).unwrap();
+                            jobs.push(Job { id, pid, command: command_line.clone() });
+                        } else {
+                            (*(*kernel_pty_master).borrow().get_pty()).borrow_mut().foreground_pid = Some(pid);
+                            program_pid = Some(pid);
+                        }
                     } else {
                         writeln!(TERMINAL.lock(), "Executable path is not a file!").unwrap();
                     }
                 }
             }
 
-            // Wait until all processes finish executing
-            while scheduler::tick() {}
+            let forward_pty = || {
+                pty::forward_output_to_kernel_terminal(&mut (*kernel_pty_master).borrow_mut());
+                // NOTE: Only take keys when a program actually has pts0 open ( the shell and devfs always hold a reference ),
+                // otherwise keys that were meant for stdin would get eaten
+                if Rc::strong_count(&kernel_pty_slave) > 2 {
+                    pty::forward_kernel_keyboard_input(&mut (*kernel_pty_master).borrow_mut());
+                } else if let Some(packet) = unsafe { KEYBOARD_INPUT.lock().try_read_packet() } {
+                    // The program reads stdin through the kernel terminal instead, so Ctrl+C still has to reach it even if it never reads
+                    pty::pass_key_to_kernel_terminal(packet, program_pid);
+                }
+            };
+
+            // Wait for the program that was just started in the foreground, Ctrl+C sends it a SIGINT
+            // NOTE: Everything else, like background jobs or processes the program left behind, keeps running while the prompt waits for input
+            let program_action = program_pid.and_then(|pid| scheduler::wait(pid, forward_pty));
+            pty::forward_output_to_kernel_terminal(&mut (*kernel_pty_master).borrow_mut());
+            (*(*kernel_pty_master).borrow().get_pty()).borrow_mut().foreground_pid = None;
+
+            finished_actions.extend(report_finished_jobs(&mut jobs));
+            for action in program_action.into_iter().chain(finished_actions) {
+                match action {
+                    process::WaitAction::EXITED { exit_code } => {
+                        // NOTE: Programs exit with a c int
+                        writeln!(TERMINAL.lock(), "Program exited with code: {}", exit_code as i32).unwrap()
+                    }
+                    process::WaitAction::TERMINATED_BY_SIGNAL { signal } => {
+                        writeln!(TERMINAL.lock(), "Program was killed by {}", signal.signal_type.name()).unwrap()
+                    }
+                }
+            }
+
+            if let (Some(start), Some(end)) = (time_start_ms, clock::now_ms()) {
+                let elapsed = end - start;
+                writeln!(TERMINAL.lock(), "real {}.{:03}s", elapsed / 1000, elapsed % 1000).unwrap();
+            }
 
             write!(TERMINAL.lock(), "{} # ", cur_dir).unwrap();
             continue;
@@ -720,6 +1871,10 @@ pub extern "C" fn main(r1: u32, r2: u32) -> ! {
 
     }
 
+    if !sync_everything(&mounted_filesystems, &ata_devices) {
+        writeln!(UART.lock(), "Failed to write cached data back to disk!").unwrap();
+    }
+
     writeln!(UART.lock(), "Kernel heap usage: {} bytes", allocator::ALLOCATOR.lock().get_heap_used()).unwrap();
     writeln!(UART.lock(), "Program heap usage: {} bytes", allocator::PROGRAM_ALLOCATOR.0.lock().get_heap_used()).unwrap();
 
@@ -31,3 +31,74 @@ pub fn init(r1: usize, r2: usize) -> &'static [u32] {
         )
     }
 }
+
+// Source: https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Memory-map
+const MULTIBOOT2_MEMORY_MAP_ENTRY_LEN_IN_U32S: usize = 6;
+const MULTIBOOT2_MEMORY_AVAILABLE: u32 = 1;
+
+// A range of physical memory, end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl MemoryRegion {
+    pub fn len(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+// Returns: The regions of ram that the memory map tag ( header included ) says are free to use
+pub fn available_memory_regions(tag: &[u32]) -> impl Iterator<Item = MemoryRegion> + '_ {
+    let entry_len = tag.get(2).map_or(0, |entry_size| *entry_size as usize / core::mem::size_of::<u32>());
+    // NOTE: Entries can be bigger than we expect ( newer versions can add fields ), but not smaller
+    let entries = if entry_len >= MULTIBOOT2_MEMORY_MAP_ENTRY_LEN_IN_U32S { tag.get(4..).unwrap_or(&[]) } else { &[] };
+    entries.chunks_exact(entry_len.max(1)).filter(|entry| entry[4] == MULTIBOOT2_MEMORY_AVAILABLE).map(|entry| {
+        let start = u64::from(entry[0]) | u64::from(entry[1]) << 32;
+        let len = u64::from(entry[2]) | u64::from(entry[3]) << 32;
+        MemoryRegion { start, end: start.saturating_add(len) }
+    })
+}
+
+// Splits the available memory into the pieces that are between min_addr and max_addr and don't overlap anything in reserved
+// NOTE: This runs before there is a heap, so the pieces can't be collected anywhere, instead every region is walked from gap to gap as they are needed
+// Returns: The pieces, with their starts aligned to alignment
+pub fn free_memory_regions<'a>(
+    available: impl Iterator<Item = MemoryRegion> + 'a,
+    reserved: &'a [MemoryRegion],
+    min_addr: u64,
+    max_addr: u64,
+    alignment: u64,
+) -> impl Iterator<Item = MemoryRegion> + 'a {
+    available.flat_map(move |region| {
+        let end = core::cmp::min(region.end, max_addr);
+        let mut cursor = Some(core::cmp::max(region.start, min_addr));
+        core::iter::from_fn(move || {
+            while let Some(start) = cursor.filter(|start| *start < end) {
+                // The reserved range closest to the cursor that overlaps what's left of the region
+                let next_reserved = reserved
+                    .iter()
+                    .filter(|reserved| reserved.end > start && reserved.start < end && reserved.len() != 0)
+                    .min_by_key(|reserved| reserved.start);
+                cursor = next_reserved.map(|reserved| reserved.end);
+
+                let piece = MemoryRegion {
+                    start: align_up(start, alignment),
+                    end: next_reserved.map_or(end, |reserved| core::cmp::max(reserved.start, start)),
+                };
+                if piece.len() != 0 {
+                    return Some(piece);
+                }
+            }
+            None
+        })
+    })
+}
+
+fn align_up(addr: u64, alignment: u64) -> u64 {
+    match addr % alignment {
+        0 => addr,
+        rem => addr.saturating_add(alignment - rem),
+    }
+}
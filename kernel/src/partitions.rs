@@ -79,6 +79,14 @@ impl IFile for MBRPartitionFile {
         self.partition_size
     }
 
+    fn prefetch(&self, offset: u64, len: usize) {
+        self.device.borrow().prefetch(self.partition_offset + offset, len)
+    }
+
+    fn sync(&mut self) -> Option<()> {
+        (*self.device).borrow_mut().sync()
+    }
+
     fn resize(&mut self, _new_size: u64) -> Option<()> {
         None
     }
@@ -0,0 +1,218 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use crate::virtmem::KernPointer;
+
+// Note: This file defines how the pci configuration space is accessed, using configuration mechanism #1, and how the devices on the pci buses are found
+// Source: https://wiki.osdev.org/PCI#Configuration_Space_Access_Mechanism_.231, https://wiki.osdev.org/PCI#Enumerating_PCI_Buses
+
+const CONFIG_ADDRESS_PORT: u16 = 0xCF8;
+const CONFIG_DATA_PORT: u16 = 0xCFC;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+pub const MAX_BUSES: u16 = 256;
+pub const MAX_DEVICES_PER_BUS: u8 = 32;
+pub const MAX_FUNCTIONS_PER_DEVICE: u8 = 8;
+
+// Offsets of the registers that all header types share, registers are 32 bits wide so the offsets are of the whole register
+pub const CONFIG_VENDOR_DEVICE_ID: u8 = 0x00;
+pub const CONFIG_COMMAND_STATUS: u8 = 0x04;
+pub const CONFIG_CLASS: u8 = 0x08;
+pub const CONFIG_HEADER_TYPE: u8 = 0x0C;
+pub const CONFIG_BAR0: u8 = 0x10;
+
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+// A vendor id of 0xFFFF means that there is nothing there
+const NO_VENDOR: u16 = 0xFFFF;
+const HEADER_TYPE_MULTI_FUNCTION: u8 = 1 << 7;
+// Only general devices and pci to pci bridges have bars, 6 and 2 of them
+const HEADER_TYPE_GENERAL: u8 = 0x00;
+const HEADER_TYPE_PCI_TO_PCI_BRIDGE: u8 = 0x01;
+const MAX_BARS: usize = 6;
+
+const BAR_IO_SPACE: u32 = 1 << 0;
+const BAR_MEMORY_TYPE_64: u32 = 0x2 << 1;
+const BAR_MEMORY_TYPE_MASK: u32 = 0x3 << 1;
+const BAR_MEMORY_PREFETCHABLE: u32 = 1 << 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PCIAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Display for PCIAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+// Source: https://wiki.osdev.org/PCI#Base_Address_Registers
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bar {
+    Io { port: u32 },
+    Memory32 { address: u32, prefetchable: bool },
+    Memory64 { address: u64, prefetchable: bool },
+}
+
+impl Display for Bar {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Bar::Io { port } => write!(f, "io {:#x}", port),
+            Bar::Memory32 { address, prefetchable } => {
+                write!(f, "mem32 {:#x}{}", address, if *prefetchable { " prefetchable" } else { "" })
+            }
+            Bar::Memory64 { address, prefetchable } => {
+                write!(f, "mem64 {:#x}{}", address, if *prefetchable { " prefetchable" } else { "" })
+            }
+        }
+    }
+}
+
+// Returns: The bars that are in use, along with their index, a 64 bit bar takes up two slots, the second one isn't returned on its own
+// NOTE: This doesn't find out how big the bars are, since that needs writing to them
+pub fn decode_bars(raw: &[u32]) -> Vec<(usize, Bar)> {
+    let mut bars = Vec::new();
+    let mut i = 0;
+    while i < raw.len() {
+        let bar = raw[i];
+        let index = i;
+        i += 1;
+        if bar & BAR_IO_SPACE != 0 {
+            if bar & !0x3 != 0 {
+                bars.push((index, Bar::Io { port: bar & !0x3 }));
+            }
+            continue;
+        }
+
+        let prefetchable = bar & BAR_MEMORY_PREFETCHABLE != 0;
+        if bar & BAR_MEMORY_TYPE_MASK == BAR_MEMORY_TYPE_64 {
+            // The next bar has the high 32 bits of the address
+            let Some(high) = raw.get(i) else { break };
+            i += 1;
+            let address = u64::from(*high) << 32 | u64::from(bar & !0xF);
+            if address != 0 {
+                bars.push((index, Bar::Memory64 { address, prefetchable }));
+            }
+        } else if bar & !0xF != 0 {
+            bars.push((index, Bar::Memory32 { address: bar & !0xF, prefetchable }));
+        }
+    }
+    bars
+}
+
+pub struct PCIFunction {
+    pub addr: PCIAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub bars: Vec<(usize, Bar)>,
+}
+
+// Source: https://wiki.osdev.org/PCI#Class_Codes
+pub fn class_name(class: u8) -> &'static str {
+    match class {
+        0x00 => "Unclassified",
+        0x01 => "Mass storage controller",
+        0x02 => "Network controller",
+        0x03 => "Display controller",
+        0x04 => "Multimedia controller",
+        0x05 => "Memory controller",
+        0x06 => "Bridge",
+        0x07 => "Communication controller",
+        0x08 => "Base system peripheral",
+        0x09 => "Input device controller",
+        0x0A => "Docking station",
+        0x0B => "Processor",
+        0x0C => "Serial bus controller",
+        0x0D => "Wireless controller",
+        0x0E => "Intelligent controller",
+        0x0F => "Satellite communication controller",
+        0x10 => "Encryption controller",
+        0x11 => "Signal processing controller",
+        0x12 => "Processing accelerator",
+        _ => "Unknown",
+    }
+}
+
+// Returns: What has to be written to the config address port to access the 32 bit register at offset of addr
+// NOTE: The low 2 bits of the offset are ignored, since registers are only accessed whole
+pub fn config_address(addr: PCIAddress, offset: u8) -> u32 {
+    CONFIG_ENABLE
+        | u32::from(addr.bus) << 16
+        | u32::from(addr.device & (MAX_DEVICES_PER_BUS - 1)) << 11
+        | u32::from(addr.function & (MAX_FUNCTIONS_PER_DEVICE - 1)) << 8
+        | u32::from(offset & 0xFC)
+}
+
+pub unsafe fn read_config(addr: PCIAddress, offset: u8) -> u32 {
+    KernPointer::<u32>::from_port(CONFIG_ADDRESS_PORT).write(config_address(addr, offset));
+    KernPointer::<u32>::from_port(CONFIG_DATA_PORT).read()
+}
+
+pub unsafe fn write_config(addr: PCIAddress, offset: u8, val: u32) {
+    KernPointer::<u32>::from_port(CONFIG_ADDRESS_PORT).write(config_address(addr, offset));
+    KernPointer::<u32>::from_port(CONFIG_DATA_PORT).write(val);
+}
+
+// Returns: The class, subclass and programming interface of the function
+pub unsafe fn read_class(addr: PCIAddress) -> (u8, u8, u8) {
+    let [_revision, prog_if, subclass, class] = read_config(addr, CONFIG_CLASS).to_le_bytes();
+    (class, subclass, prog_if)
+}
+
+pub unsafe fn exists(addr: PCIAddress) -> bool {
+    read_config(addr, CONFIG_VENDOR_DEVICE_ID) as u16 != NO_VENDOR
+}
+
+// Sets bits in the command register, leaving the status register alone
+// NOTE: Status bits are cleared by writing 1s, so the status half is written as 0
+pub unsafe fn enable_command_bits(addr: PCIAddress, bits: u16) {
+    let command = read_config(addr, CONFIG_COMMAND_STATUS) as u16;
+    write_config(addr, CONFIG_COMMAND_STATUS, u32::from(command | bits));
+}
+
+unsafe fn read_function(addr: PCIAddress) -> PCIFunction {
+    let ids = read_config(addr, CONFIG_VENDOR_DEVICE_ID);
+    let (class, subclass, prog_if) = read_class(addr);
+    let bar_count = match read_config(addr, CONFIG_HEADER_TYPE).to_le_bytes()[2] & !HEADER_TYPE_MULTI_FUNCTION {
+        HEADER_TYPE_GENERAL => MAX_BARS,
+        HEADER_TYPE_PCI_TO_PCI_BRIDGE => 2,
+        _ => 0,
+    };
+    let raw_bars: Vec<u32> = (0..bar_count).map(|i| read_config(addr, CONFIG_BAR0 + 4 * i as u8)).collect();
+    PCIFunction { addr, vendor_id: ids as u16, device_id: (ids >> 16) as u16, class, subclass, prog_if, bars: decode_bars(&raw_bars) }
+}
+
+// Returns: Every function on every bus
+// NOTE: This checks every possible bus instead of following the bridges, which is slower but doesn't miss anything
+pub unsafe fn enumerate() -> Vec<PCIFunction> {
+    let mut functions = Vec::new();
+    for bus in 0..MAX_BUSES {
+        for device in 0..MAX_DEVICES_PER_BUS {
+            let first = PCIAddress { bus: bus as u8, device, function: 0 };
+            if !exists(first) {
+                continue;
+            }
+            let is_multi_function = read_config(first, CONFIG_HEADER_TYPE).to_le_bytes()[2] & HEADER_TYPE_MULTI_FUNCTION != 0;
+            let function_count = if is_multi_function { MAX_FUNCTIONS_PER_DEVICE } else { 1 };
+            for function in 0..function_count {
+                let addr = PCIAddress { bus: bus as u8, device, function };
+                if exists(addr) {
+                    functions.push(read_function(addr));
+                }
+            }
+        }
+    }
+    functions
+}
+
+// Returns: The first function with this class and subclass
+pub unsafe fn find_by_class(class: u8, subclass: u8) -> Option<PCIAddress> {
+    enumerate().into_iter().find(|function| function.class == class && function.subclass == subclass).map(|function| function.addr)
+}
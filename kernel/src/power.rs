@@ -0,0 +1,195 @@
+use core::{
+    arch::{asm, x86_64::__cpuid},
+    convert::TryInto,
+};
+
+use crate::virtmem::KernPointer;
+
+// Note: This file defines how the machine is rebooted and powered off
+// Source: https://wiki.osdev.org/Reboot, https://wiki.osdev.org/Shutdown, https://wiki.osdev.org/ACPI
+
+const PS2_STATUS_INPUT_BUF_FULL: u8 = 1 << 1;
+const PS2_COMMAND_PULSE_RESET_LINE: u8 = 0xFE;
+// The ps/2 controller should be ready way before this, if it isn't then there probably is no controller
+const PS2_MAX_TRIES: usize = 1_000_000;
+
+const ACPI_SDT_HEADER_LEN: usize = 36;
+const ACPI_PM1_CNT_SCI_EN: u16 = 1 << 0;
+const ACPI_PM1_CNT_SLP_EN: u16 = 1 << 13;
+const ACPI_ENABLE_MAX_TRIES: usize = 1_000_000;
+// AML opcodes, needed to find the \_S5 package in the DSDT
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+
+// Resets the machine, the methods are tried in this order:
+// 1. Pulsing the cpu's reset line using the ps/2 controller
+// 2. Triple faulting, by loading an empty idt and causing an interrupt
+pub fn reboot() -> ! {
+    unsafe {
+        let mut status_and_command = KernPointer::<u8>::from_port(0x64);
+        let mut tries = 0;
+        while status_and_command.read() & PS2_STATUS_INPUT_BUF_FULL != 0 && tries < PS2_MAX_TRIES {
+            tries += 1;
+            core::hint::spin_loop();
+        }
+        status_and_command.write(PS2_COMMAND_PULSE_RESET_LINE);
+
+        // Give the controller some time to actually reset the cpu
+        for _ in 0..PS2_MAX_TRIES {
+            core::hint::spin_loop();
+        }
+
+        // With no idt the interrupt causes a double fault, which causes a triple fault, which resets the cpu
+        let empty_idt: [u8; 10] = [0; 10];
+        asm!("lidt [{}]", "int3", in(reg) empty_idt.as_ptr(), options(nostack));
+    }
+    // NOTE: Interrupts are left as they are, with the empty idt any interrupt that wakes us up is another chance to triple fault
+    loop {
+        unsafe { asm!("hlt", options(nomem, nostack)) };
+    }
+}
+
+// Powers off the machine, the methods are tried in this order:
+// 1. ACPI, entering the S5 sleep state, if the bootloader gave us the rsdp
+// 2. Only when running under an emulator ( according to cpuid ), the emulator specific ports of qemu, bochs and virtualbox
+// NOTE: The emulator ports are only tried under an emulator because on real hardware they can belong to some other device
+// Returns: Only if none of the methods worked
+pub fn shutdown(rsdp_ptr: Option<usize>) {
+    if let Some(rsdp_ptr) = rsdp_ptr {
+        unsafe { acpi_enter_s5(rsdp_ptr) };
+    }
+
+    if is_running_under_hypervisor() {
+        unsafe {
+            // Newer qemu
+            KernPointer::<u16>::from_port(0x604).write(0x2000);
+            // Bochs and older qemu
+            KernPointer::<u16>::from_port(0xB004).write(0x2000);
+            // Virtualbox
+            KernPointer::<u16>::from_port(0x4004).write(0x3400);
+        }
+    }
+}
+
+// Source: https://en.wikipedia.org/wiki/CPUID#EAX=1:_Processor_Info_and_Feature_Bits ( bit 31 of ecx is reserved for hypervisors )
+fn is_running_under_hypervisor() -> bool {
+    let info = __cpuid(1);
+    info.ecx & (1 << 31) != 0
+}
+
+// SAFTEY: addr has to point to readable memory
+unsafe fn read_bytes<'a>(addr: usize, len: usize) -> &'a [u8] {
+    core::slice::from_raw_parts(addr as *const u8, len)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn is_checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+// Returns: The whole table ( including the header ) at addr, if it has the right signature and checksum
+unsafe fn read_sdt<'a>(addr: usize, signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let header = read_bytes(addr, ACPI_SDT_HEADER_LEN);
+    if &header[0..4] != signature {
+        return None;
+    }
+    let table = read_bytes(addr, read_u32(header, 4)? as usize);
+    if table.len() < ACPI_SDT_HEADER_LEN || !is_checksum_valid(table) {
+        return None;
+    }
+    Some(table)
+}
+
+// Returns: The fadt, found using the xsdt if there is one, otherwise using the rsdt
+unsafe fn find_fadt<'a>(rsdp_ptr: usize) -> Option<&'a [u8]> {
+    // The first 20 bytes are the acpi 1.0 rsdp, acpi 2.0 added the xsdt after that
+    let rsdp = read_bytes(rsdp_ptr, 20);
+    if &rsdp[0..8] != b"RSD PTR " || !is_checksum_valid(rsdp) {
+        return None;
+    }
+    let revision = rsdp[15];
+
+    let (sdt, entry_size) = if revision >= 2 && read_u64(read_bytes(rsdp_ptr, 36), 24)? != 0 {
+        (read_sdt(read_u64(read_bytes(rsdp_ptr, 36), 24)? as usize, b"XSDT")?, 8)
+    } else {
+        (read_sdt(read_u32(rsdp, 16)? as usize, b"RSDT")?, 4)
+    };
+
+    sdt[ACPI_SDT_HEADER_LEN..].chunks_exact(entry_size).find_map(|entry| {
+        let addr = if entry_size == 8 { read_u64(entry, 0)? as usize } else { read_u32(entry, 0)? as usize };
+        read_sdt(addr, b"FACP")
+    })
+}
+
+// Reads an integer in a package, which is a byte prefixed with BytePrefix, or the Zero and One opcodes
+// Returns: The value and how many bytes it took
+fn read_aml_small_integer(bytes: &[u8]) -> Option<(u8, usize)> {
+    match *bytes.first()? {
+        AML_BYTE_PREFIX => Some((*bytes.get(1)?, 2)),
+        AML_ZERO_OP => Some((0, 1)),
+        AML_ONE_OP => Some((1, 1)),
+        _ => None,
+    }
+}
+
+// Returns: The SLP_TYPa and SLP_TYPb values of the \_S5 package
+// NOTE: This doesn't parse the aml, it just searches for the name, which works for pretty much every dsdt
+fn find_s5_sleep_types(dsdt: &[u8]) -> Option<(u8, u8)> {
+    let name_pos = dsdt.windows(4).position(|window| window == b"_S5_")?;
+    // The name is either defined as Name(_S5_, ...) or Name(\_S5_, ...)
+    let is_name = name_pos >= 1 && dsdt[name_pos - 1] == AML_NAME_OP
+        || name_pos >= 2 && dsdt[name_pos - 2] == AML_NAME_OP && dsdt[name_pos - 1] == b'\\';
+    let package = dsdt.get(name_pos + 4..)?;
+    if !is_name || *package.first()? != AML_PACKAGE_OP {
+        return None;
+    }
+
+    // The top 2 bits of the first byte of the package length say how many bytes follow it
+    let package_length_len = 1 + (*package.get(1)? >> 6) as usize;
+    // Skip the opcode, the package length and the number of elements
+    let elements = package.get(1 + package_length_len + 1..)?;
+    let (sleep_type_a, len) = read_aml_small_integer(elements)?;
+    let (sleep_type_b, _) = read_aml_small_integer(elements.get(len..)?)?;
+    Some((sleep_type_a, sleep_type_b))
+}
+
+// Returns: Only if entering S5 failed
+unsafe fn acpi_enter_s5(rsdp_ptr: usize) -> Option<()> {
+    let fadt = find_fadt(rsdp_ptr)?;
+    let dsdt = read_sdt(read_u32(fadt, 40)? as usize, b"DSDT")?;
+    let smi_command_port = read_u32(fadt, 48)? as u16;
+    let acpi_enable = *fadt.get(52)?;
+    let pm1a_control_port = read_u32(fadt, 64)? as u16;
+    let pm1b_control_port = read_u32(fadt, 68)? as u16;
+    let (sleep_type_a, sleep_type_b) = find_s5_sleep_types(dsdt)?;
+    if pm1a_control_port == 0 {
+        return None;
+    }
+
+    let mut pm1a_control = KernPointer::<u16>::from_port(pm1a_control_port);
+    // If acpi isn't enabled yet, then the firmware has to be asked to switch to it
+    if pm1a_control.read() & ACPI_PM1_CNT_SCI_EN == 0 && smi_command_port != 0 && acpi_enable != 0 {
+        KernPointer::<u8>::from_port(smi_command_port).write(acpi_enable);
+        let mut tries = 0;
+        while pm1a_control.read() & ACPI_PM1_CNT_SCI_EN == 0 && tries < ACPI_ENABLE_MAX_TRIES {
+            tries += 1;
+            core::hint::spin_loop();
+        }
+    }
+
+    pm1a_control.write(u16::from(sleep_type_a) << 10 | ACPI_PM1_CNT_SLP_EN);
+    if pm1b_control_port != 0 {
+        KernPointer::<u16>::from_port(pm1b_control_port).write(u16::from(sleep_type_b) << 10 | ACPI_PM1_CNT_SLP_EN);
+    }
+    None
+}
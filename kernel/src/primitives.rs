@@ -1,6 +1,10 @@
 use core::fmt::{Debug, Error, Formatter};
 use core::ops::{Deref, DerefMut};
-use core::{cell::UnsafeCell, sync::atomic::AtomicBool};
+use core::panic::Location;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
 
 pub struct LazyInitialised<T> {
     inner: Option<T>,
@@ -54,6 +58,10 @@ pub struct MutexGuard<'lock_lifetime, T> {
 pub struct Mutex<T> {
     lock: AtomicBool,
     inner: UnsafeCell<T>,
+    // Where the mutex was last locked, used to say who is holding it when it gets locked twice
+    // NOTE: Only tracked in debug builds, so that release builds don't pay for it
+    #[cfg(debug_assertions)]
+    holder: UnsafeCell<Option<&'static Location<'static>>>,
 }
 
 unsafe impl<T> Sync for Mutex<T> {}
@@ -76,14 +84,37 @@ where
     }
 
     pub const fn from(val: T) -> Self {
-        Self { inner: UnsafeCell::new(val), lock: AtomicBool::new(false) }
+        Self {
+            inner: UnsafeCell::new(val),
+            lock: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            holder: UnsafeCell::new(None),
+        }
     }
 
+    #[track_caller]
     pub fn with(&self, f: fn(MutexGuard<T>)) {
         f(self.lock());
     }
 
+    #[track_caller]
     pub fn lock(&self) -> MutexGuard<T> {
+        // NOTE: The kernel runs on one cpu and interrupt handlers never lock anything, so if the mutex is already locked it's being held further up
+        // our own call stack and will never be unlocked, so instead of spinning forever say who is holding it
+        #[cfg(debug_assertions)]
+        if self.is_locked() {
+            // NOTE: Don't print the inner value, it's in the middle of being used
+            match unsafe { *self.holder.get() } {
+                Some(holder) => panic!(
+                    "Double-lock of Mutex<{}> at {}, it's already held at {}!",
+                    core::any::type_name::<T>(),
+                    Location::caller(),
+                    holder
+                ),
+                None => panic!("Double-lock of Mutex<{}> at {}!", core::any::type_name::<T>(), Location::caller()),
+            }
+        }
+
         let mut deadlock_warning_iter_count = 1_000_000; // FIXME: Arbitrary number
         while self
             .lock
@@ -99,6 +130,11 @@ where
             }
         }
 
+        #[cfg(debug_assertions)]
+        unsafe {
+            *self.holder.get() = Some(Location::caller())
+        };
+
         MutexGuard { lock_ref: &self.lock, inner_ref: unsafe { &mut *self.inner.get() } }
     }
 }
@@ -127,3 +163,46 @@ impl<'lock_lifetime, T> Drop for MutexGuard<'lock_lifetime, T> {
         }
     }
 }
+
+// A queue of bytes that can be pushed to from an interrupt handler while the code it interrupted pops from it, without locking
+// NOTE: Only one side may push and only one side may pop at a time, one slot is always left empty to tell a full queue from an empty one
+pub struct AtomicRingBuffer<const N: usize> {
+    data: [AtomicU8; N],
+    // The next slot to pop from
+    head: AtomicUsize,
+    // The next slot to push to
+    tail: AtomicUsize,
+}
+
+impl<const N: usize> AtomicRingBuffer<N> {
+    pub const fn new() -> Self {
+        // NOTE: Atomics aren't Copy, and array::from_fn can't be used in a const fn, so every element comes from an inline const
+        Self { data: [const { AtomicU8::new(0) }; N], head: AtomicUsize::new(0), tail: AtomicUsize::new(0) }
+    }
+
+    // Returns: None if the queue is full, then the byte is dropped
+    pub fn push(&self, byte: u8) -> Option<()> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        self.data[tail].store(byte, Ordering::Relaxed);
+        self.tail.store(next, Ordering::Release);
+        Some(())
+    }
+
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let byte = self.data[head].load(Ordering::Relaxed);
+        self.head.store((head + 1) % N, Ordering::Release);
+        Some(byte)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
@@ -10,10 +10,11 @@ use packed_struct::EnumCatchAll;
 use crate::allocator::{ProgramBasicAlloc, BasicAlloc};
 use crate::{
     allocator,
-    elf::{elf_header, elf_program_header, ElfFile},
-    emulator::Riscv64Cpu,
+    elf::{elf_dynamic, elf_header, elf_program_header, ElfFile},
+    emulator::{CpuContext, EmulatorMemory, Riscv64Cpu, RunResult},
     syscall, vfs,
-    virtmem::{LittleEndianVirtualMemory, VirtualMemory},
+    virtmem::{LittleEndianVirtualMemory, RegionPermissions, VirtualMemory},
+    UART,
 };
 
 
@@ -23,6 +24,30 @@ pub struct ProcessSignal {
     pub signal_type: SignalType
 }
 
+impl ProcessSignal {
+    // Returns: true if a program can choose what happens when it gets this signal
+    // NOTE: SIGKILL always has to terminate, and faults can't be handled, since returning from the handler would just run the instruction that faulted again
+    pub fn can_be_handled(&self) -> bool {
+        matches!(self.signal_type, SignalType::SIGINT | SignalType::SIGTERM)
+    }
+}
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalDisposition {
+    DEFAULT,
+    IGNORE,
+    // The handler gets called with the signal number, and returns into the restorer, which has to call sigreturn
+    HANDLER { handler: u64, restorer: u64 },
+}
+
+// What the program was doing when a signal handler was called, so that sigreturn can put it back
+#[derive(Debug, Clone)]
+pub struct SignalFrame {
+    pub context: CpuContext,
+    pub state: ProcessState,
+}
+
 
 #[derive(Debug)]
 pub struct ProcessPipe {
@@ -88,6 +113,45 @@ pub enum ProcessState {
     TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED{signal: ProcessSignal},
 }
 
+impl ProcessState {
+    // Returns: true if the process gets ticked in this state
+    pub fn is_runnable(&self) -> bool {
+        matches!(self, ProcessState::RUNNING | ProcessState::RUNNING_NEW_CHILD_JUST_FORKED | ProcessState::FINISHED_WAITING_FOR_CHILD_PROCESS(_))
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        matches!(
+            self,
+            ProcessState::TERMINATED_NORMALLY_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { exit_code: _ }
+                | ProcessState::TERMINATED_NORMALLY_WAITING_TO_BE_DEALLOCATED { exit_code: _ }
+                | ProcessState::TERMINATED_DUE_TO_SIGNAL_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { signal: _ }
+                | ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal: _ }
+        )
+    }
+
+    // Returns: A short human readable description of the state, for ex. for ps
+    pub fn get_short_name(&self) -> &'static str {
+        match self {
+            ProcessState::RUNNING | ProcessState::RUNNING_NEW_CHILD_JUST_FORKED | ProcessState::FINISHED_WAITING_FOR_CHILD_PROCESS(_) => "running",
+            ProcessState::WAITING_FOR_CHILD_PROCESS { cpid: _ } => "waiting for child",
+            ProcessState::WAITING_FOR_READ_PIPE { pipe_index: _ } => "waiting for pipe",
+            ProcessState::TERMINATED_NORMALLY_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { exit_code: _ }
+            | ProcessState::TERMINATED_DUE_TO_SIGNAL_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { signal: _ } => "zombie",
+            ProcessState::TERMINATED_NORMALLY_WAITING_TO_BE_DEALLOCATED { exit_code: _ }
+            | ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal: _ } => "terminated",
+        }
+    }
+
+    // Returns: The signal that terminated the process, None if it wasn't terminated by a signal ( or hasn't terminated )
+    pub fn get_terminating_signal(&self) -> Option<SignalType> {
+        match self {
+            ProcessState::TERMINATED_DUE_TO_SIGNAL_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { signal }
+            | ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal } => Some(signal.signal_type),
+            _ => None,
+        }
+    }
+}
+
 
 #[derive(Debug, Clone)]
 pub struct ProcessData {
@@ -101,18 +165,27 @@ pub struct ProcessData {
     pub cwd: vfs::Path,
     pub env: BTreeMap<String, u64>, // Maps environment variable names to a virtual pointer where the value of the variable is loaded as a c-string
     pub virtual_allocator: BasicAlloc, // Allows the process to manage virtual segments/mappings dynamically
+    pub mappings: BTreeMap<u64, usize>, // Maps the start of every region created by mmap to its length, so that munmap can't remove any other region
     pub state: ProcessState,
     pub pid: Option<usize>, // FIXME: Right now processes can be run without a set pid
-    pub parent_pid: Option<usize>
+    pub parent_pid: Option<usize>,
+    pub name: String, // The first argument the process was started with, changes on exec
+    pub priority: usize, // How many instructions the process gets to execute every time the scheduler ticks, inherited on fork
+    pub signal_dispositions: BTreeMap<u8, SignalDisposition>, // Maps signal numbers to what happens when they are received, signals that aren't in here get the default disposition
+    pub signal_frames: Vec<SignalFrame>, // One for every signal handler that hasn't returned yet, the last one is the innermost
 }
 
+pub const DEFAULT_PRIORITY: usize = 1;
+pub const MAX_PRIORITY: usize = 64;
+
 impl ProcessData {
     fn new(
         cwd: vfs::Path,
         env: BTreeMap<String, u64>,
-        virtual_allocator: BasicAlloc
+        virtual_allocator: BasicAlloc,
+        name: String
     ) -> Self {
-        ProcessData { open_nodes: Vec::new(), fd_mappings: vec![Some(FdMapping::Stdin), Some(FdMapping::Stdout), Some(FdMapping::Stderr)], cwd, env, virtual_allocator, state: ProcessState::RUNNING, pid: None, parent_pid: None}
+        ProcessData { open_nodes: Vec::new(), fd_mappings: vec![Some(FdMapping::Stdin), Some(FdMapping::Stdout), Some(FdMapping::Stderr)], cwd, env, virtual_allocator, mappings: BTreeMap::new(), state: ProcessState::RUNNING, pid: None, parent_pid: None, name, priority: DEFAULT_PRIORITY, signal_dispositions: BTreeMap::new(), signal_frames: Vec::new()}
     }
 }
 
@@ -212,8 +285,55 @@ impl Process {
         Some(map)
     }
 
+    // Returns: The address at which the elf should be loaded, all virtual addresses in the elf are relative to this
+    // NOTE: Position-independent executables are linked at 0, so they need to be moved away from the null page
+    pub fn get_elf_load_base(elf: &ElfFile) -> u64 {
+        const PIE_LOAD_BASE: u64 = 0x10000;
+        if elf.header.elf_type == elf_header::ElfType::SHARED {
+            PIE_LOAD_BASE
+        } else {
+            0
+        }
+    }
+
+    // Applies the dynamic relocations of an elf that was loaded at load_base
+    // NOTE: Fails on relocation types that are not supported, as running the program with wrong addresses would be worse
+    pub fn apply_elf_relocations(elf: &ElfFile, elf_bytes: &[u8], load_base: u64, virt_mem: &mut impl VirtualMemory<A = &'static ProgramBasicAlloc>) -> Option<()> {
+        use core::fmt::Write;
+        let Some(relocations) = elf.get_dynamic_relocations(elf_bytes) else {
+            writeln!(UART.lock(), "Can't load elf: dynamic segment is malformed or in an unsupported format!").unwrap();
+            return None;
+        };
+
+        for rela in relocations {
+            match rela.relocation_type {
+                elf_dynamic::R_RISCV_NONE => (),
+
+                elf_dynamic::R_RISCV_RELATIVE => {
+                    // B + A, where B is the base address the elf is loaded at and A is the addend
+                    // Source: https://github.com/riscv-non-isa/riscv-elf-psabi-doc/blob/master/riscv-elf.adoc#relocations
+                    let virt_addr = load_base.wrapping_add(rela.offset);
+                    virt_mem.write_u64_ne(virt_addr, load_base.wrapping_add(rela.addend as u64))?;
+                }
+
+                unsupported => {
+                    writeln!(UART.lock(), "Can't load elf: relocation type {} is not supported!", unsupported).unwrap();
+                    return None;
+                }
+            }
+        }
+        Some(())
+    }
+
+    // Returns: The permissions the stack of the program should have, as asked for by the PT_GNU_STACK header
+    pub fn get_stack_permissions(elf: &ElfFile) -> RegionPermissions {
+        RegionPermissions { readable: true, writable: true, executable: elf.has_executable_stack() }
+    }
+
     // Returns: lowest virtual address that is after all segments loaded, a.k.a the address at the end of the convex hull of the loaded elf
+    // NOTE: Position-independent executables get loaded at get_elf_load_base and have their relocations applied
     pub fn load_elf_into_virtual_memory(elf: &ElfFile, elf_bytes: &[u8], virt_mem: &mut impl VirtualMemory<A = &'static ProgramBasicAlloc>) -> Option<u64> {
+        let load_base = Self::get_elf_load_base(elf);
         let mut lower_virt_addr = 0; // Used to keep track of first virtual address that is free, so we can put the virtual allocator(heap) there
         
         // Map elf into virtual memory
@@ -226,27 +346,39 @@ impl Process {
             if header.segment_type == EnumCatchAll::from(elf_program_header::ProgramHeaderType::Load) {
                 // Read segment data into a vector allocated using the program allocator
                 let segment = {
-                    let segment_data = &elf_bytes[header.segment_file_offset as usize..(header.segment_file_offset + header.segment_file_size) as usize];
+                    // NOTE: A segment can't have more bytes in the file than in memory
+                    if header.segment_file_size > header.segment_virtual_size {
+                        return None;
+                    }
+                    let segment_data = elf_bytes.get(header.segment_file_offset as usize..header.segment_file_offset.checked_add(header.segment_file_size)? as usize)?;
                     
                     let mut segment = Vec::new_in(&allocator::PROGRAM_ALLOCATOR);
                     segment.clear();
                     segment.extend(segment_data);
-                    // Some segments have a bigger virtual size than physical, however, for simplicity, our system requires that virtual and physical segments be the same size 
-                    // So we resize it to it's virtual size.
-                    // For example: the .bss segment
+                    // Some segments have a bigger virtual size than physical, for example: the .bss segment
+                    // The bytes in [file size, virtual size) are not in the file, and have to be zero ( uninitialised globals rely on this )
+                    // NOTE: The program allocator doesn't zero memory, so they are explicitly filled in here
                     segment.resize(header.segment_virtual_size as usize, 0); 
                     segment
                 };
 
                 // Keep track of the end
-                if header.segment_virtual_address + segment.len() as u64 > lower_virt_addr {
-                    lower_virt_addr = header.segment_virtual_address + segment.len() as u64;
+                if load_base + header.segment_virtual_address + segment.len() as u64 > lower_virt_addr {
+                    lower_virt_addr = load_base + header.segment_virtual_address + segment.len() as u64;
                 }
                 
-                virt_mem.add_region(header.segment_virtual_address, segment)?;
+                // NOTE: Relocations are applied by the kernel, so they can still be written into read-only segments
+                let permissions = RegionPermissions {
+                    readable: header.is_readable(),
+                    writable: header.is_writable(),
+                    executable: header.is_executable(),
+                };
+                virt_mem.add_region_with_permissions(load_base + header.segment_virtual_address, segment, permissions)?;
             }
         }
 
+        Self::apply_elf_relocations(elf, elf_bytes, load_base, virt_mem)?;
+
         Some(lower_virt_addr)
     }
 
@@ -258,7 +390,8 @@ impl Process {
             return None;
         }
 
-        if elf.header.elf_type != elf_header::ElfType::EXECUTABLE {
+        // NOTE: SHARED is what position-independent executables use
+        if elf.header.elf_type != elf_header::ElfType::EXECUTABLE && elf.header.elf_type != elf_header::ElfType::SHARED {
             return None;
         }
 
@@ -272,9 +405,10 @@ impl Process {
         program_stack.resize(PROGRAM_STACK_SIZE as usize, 0u8);
 
         // Add 8kb of stack space at the end of the virtual address space
-        virt_mem.add_region(
+        virt_mem.add_region_with_permissions(
             u64::MAX - (PROGRAM_STACK_SIZE) + 1,     /* +1 because the address itself is included in the region */
             program_stack,
+            Self::get_stack_permissions(&elf),
         )?;
 
 
@@ -290,7 +424,7 @@ impl Process {
         let argv_virtual_ptr = Self::load_args_into_virtual_memory(args.iter().map(|arg|*arg), args.len(), &mut virt_mem, &mut virtual_allocator)?;
         let prog_env = Self::load_env_into_virtual_memory(env.iter().map(|(key, value)|(*key, *value)), &mut virt_mem, &mut virtual_allocator)?;
 
-        let mut emu = Riscv64Cpu::from(virt_mem, elf.header.program_entry, syscall::syscall_entry_point);
+        let mut emu = Riscv64Cpu::from(virt_mem, Self::get_elf_load_base(&elf) + elf.header.program_entry, syscall::syscall_entry_point);
         
         // Setup argc and argv
         emu.write_reg(10, args.len() as u64); // argc
@@ -301,7 +435,8 @@ impl Process {
             data: ProcessData::new(
                 cwd,
                 prog_env,
-                virtual_allocator
+                virtual_allocator,
+                args.first().map(|arg| arg.trim()).unwrap_or("").to_owned()
             ),
         })
     }
@@ -310,32 +445,54 @@ impl Process {
         self.emu.tick(&mut self.data)
     }
 
+    pub fn run(&mut self, max_steps: usize) -> RunResult {
+        self.emu.run(&mut self.data, max_steps)
+    }
+
+    // Makes the program call handler(signal number) before its next instruction, with restorer as the return address
+    // NOTE: A waiting program is still at the syscall that made it wait, so once the handler returns it just waits again
+    fn call_signal_handler(&mut self, signal: ProcessSignal, handler: u64, restorer: u64) {
+        self.data.signal_frames.push(SignalFrame { context: self.emu.save_context(), state: self.data.state.clone() });
+        self.data.state = ProcessState::RUNNING;
+        self.emu.set_program_counter(handler);
+        self.emu.write_reg(10 /* a0 */, u64::from(u8::from(signal.signal_type)));
+        self.emu.write_reg(1 /* ra */, restorer);
+    }
+
     pub fn recive_signal(&mut self, signal: ProcessSignal) {
+        // Check to make sure we are not already dead
+        if self.data.state.is_terminated() {
+            return;
+        }
+
+        // NOTE: SIGKILL and faults always get their default disposition
+        let disposition = if signal.can_be_handled() {
+            self.data.signal_dispositions.get(&u8::from(signal.signal_type)).copied().unwrap_or(SignalDisposition::DEFAULT)
+        } else {
+            SignalDisposition::DEFAULT
+        };
+
         let mut dispostion_terminate = || {
-            // Check to make sure we are not already dead
-            let is_dead = match self.data.state {
-                ProcessState::TERMINATED_NORMALLY_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { exit_code: _ } => true,
-                ProcessState::TERMINATED_NORMALLY_WAITING_TO_BE_DEALLOCATED { exit_code: _ } => true,
-                ProcessState::TERMINATED_DUE_TO_SIGNAL_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { signal: _ } => true,
-                ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal: _ } => true,
-                _ => false
-            };
-
-            if !is_dead {
-                if self.data.parent_pid.is_none() {
-                    self.data.state = ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal };
-                } else {
-                    self.data.state = ProcessState::TERMINATED_DUE_TO_SIGNAL_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { signal };
-                }
-            }   
+            if self.data.parent_pid.is_none() {
+                self.data.state = ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal };
+            } else {
+                self.data.state = ProcessState::TERMINATED_DUE_TO_SIGNAL_CHILD_WAITING_FOR_PARENT_ACKNOWLEDGEMENT { signal };
+            }
         };
 
         // FIXME: Implement core dumping
         let mut disposition_coredump = || dispostion_terminate();
 
-        match signal.signal_type {
-            SignalType::SIGKILL => dispostion_terminate(),
-            SignalType::SIGILL => disposition_coredump(),
+        match disposition {
+            SignalDisposition::IGNORE => (),
+            SignalDisposition::HANDLER { handler, restorer } => self.call_signal_handler(signal, handler, restorer),
+            SignalDisposition::DEFAULT => match signal.signal_type {
+                SignalType::SIGINT => dispostion_terminate(),
+                SignalType::SIGTERM => dispostion_terminate(),
+                SignalType::SIGKILL => dispostion_terminate(),
+                SignalType::SIGILL => disposition_coredump(),
+                SignalType::SIGSEGV => disposition_coredump(),
+            },
         }
     }
 }
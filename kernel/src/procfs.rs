@@ -0,0 +1,163 @@
+use core::{cell::RefCell, convert::TryFrom, fmt::Write};
+
+use alloc::{boxed::Box, format, rc::Rc, string::String, vec::Vec};
+
+use crate::{
+    allocator::{BasicAlloc, ALLOCATOR, PROGRAM_ALLOCATOR},
+    clock,
+    primitives::Mutex,
+    scheduler,
+    vfs::{IFile, IFolder, Node, NodeType},
+};
+
+// Note: This file defines procfs, a read-only filesystem whose files are generated when they are read, so they always show what the kernel is doing right now,
+// it has meminfo, uptime and a folder for every process, named after its pid, with a status file in it
+// Source: man proc
+
+// A file whose contents are made by generate
+// NOTE: Reading from the start makes the contents again, reads from anywhere else use what was made then,
+// so reading a file piece by piece doesn't mix up different versions of it
+pub struct GeneratedFile {
+    generate: Box<dyn Fn() -> String>,
+    contents: RefCell<Option<Vec<u8>>>,
+}
+
+impl GeneratedFile {
+    pub fn new(generate: Box<dyn Fn() -> String>) -> Self {
+        Self { generate, contents: RefCell::new(None) }
+    }
+
+    fn regenerate(&self) {
+        *self.contents.borrow_mut() = Some((self.generate)().into_bytes());
+    }
+}
+
+impl IFile for GeneratedFile {
+    // NOTE: The size can change between reads, so reads that go past the end return what there is instead of failing
+    // Returns: None if offset is past the end
+    fn read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        if offset == 0 || self.contents.borrow().is_none() {
+            self.regenerate();
+        }
+        let contents = self.contents.borrow();
+        let contents = contents.as_ref()?;
+        let start = usize::try_from(offset).ok()?;
+        let end = core::cmp::min(start.checked_add(len)?, contents.len());
+        Some(contents.get(start..end)?.to_vec())
+    }
+
+    fn write(&mut self, _offset: u64, _data: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn get_size(&self) -> u64 {
+        if self.contents.borrow().is_none() {
+            self.regenerate();
+        }
+        self.contents.borrow().as_ref().map_or(0, |contents| contents.len() as u64)
+    }
+
+    fn resize(&mut self, _new_size: u64) -> Option<()> {
+        None
+    }
+}
+
+fn generate_meminfo() -> String {
+    let mut res = String::new();
+    // NOTE: The stats are copied out first, since formatting allocates, which needs the allocator's lock
+    let get_heap_info = |heap: &Mutex<BasicAlloc>| {
+        let heap = heap.lock();
+        (heap.get_heap_used(), heap.get_heap_max(), heap.get_stats())
+    };
+    let kernel_heap = get_heap_info(&ALLOCATOR);
+    let program_heap = get_heap_info(&PROGRAM_ALLOCATOR.0);
+    for (name, (used, max, stats)) in [("Kernel", kernel_heap), ("Program", program_heap)] {
+        let _ = writeln!(res, "{}HeapUsed: {} bytes", name, used);
+        let _ = writeln!(res, "{}HeapMax: {} bytes", name, max);
+        let _ = writeln!(res, "{}LiveAllocations: {}", name, stats.live_allocations);
+        let _ = writeln!(res, "{}Free: {} bytes", name, stats.total_free);
+        let _ = writeln!(res, "{}LargestFreeBlock: {} bytes", name, stats.largest_free_block);
+        let _ = writeln!(res, "{}StashedDeallocations: {}", name, stats.stashed_deallocations);
+    }
+    res
+}
+
+// Like linux, the seconds since boot with 2 decimals, empty if there is no clock
+fn generate_uptime() -> String {
+    match clock::now_ms() {
+        Some(now) => format!("{}.{:02}\n", now / 1000, now % 1000 / 10),
+        None => String::new(),
+    }
+}
+
+// Returns: An empty string if the process doesn't exist anymore ( or if it can't be looked up right now )
+fn generate_process_status(pid: usize) -> String {
+    let mut res = String::new();
+    let Some(task) = scheduler::try_list_tasks().and_then(|mut tasks| tasks.find(|task| task.pid == pid)) else {
+        return res;
+    };
+    let _ = writeln!(res, "Name: {}", task.name);
+    let _ = writeln!(res, "Pid: {}", task.pid);
+    match task.parent_pid {
+        Some(parent_pid) => {
+            let _ = writeln!(res, "PPid: {}", parent_pid);
+        }
+        None => {
+            let _ = writeln!(res, "PPid: -");
+        }
+    }
+    let _ = write!(res, "State: {}", task.state.get_short_name());
+    if let Some(signal_type) = task.state.get_terminating_signal() {
+        let _ = write!(res, " by {}", signal_type.name());
+    }
+    let _ = writeln!(res);
+    let _ = writeln!(res, "Priority: {}", task.priority);
+    res
+}
+
+fn generated_file_node(generate: Box<dyn Fn() -> String>) -> Node {
+    Node::File(Rc::new(RefCell::new(GeneratedFile::new(generate))))
+}
+
+pub struct ProcFS;
+
+impl IFolder for ProcFS {
+    // NOTE: The folders of processes that have ended disappear right away, and files that are already open become empty
+    // FIXME: Processes can't see the folders of any process, since the scheduler is holding the list of processes while they run
+    fn get_children(&self) -> Vec<(String, Node)> {
+        let mut v = Vec::<(String, Node)>::new();
+        v.push((String::from("meminfo"), generated_file_node(Box::new(generate_meminfo))));
+        v.push((String::from("uptime"), generated_file_node(Box::new(generate_uptime))));
+        for task in scheduler::try_list_tasks().into_iter().flatten() {
+            v.push((format!("{}", task.pid), Node::Folder(Rc::new(RefCell::new(ProcessFolder { pid: task.pid })))));
+        }
+        v
+    }
+
+    fn create_empty_child(&mut self, _name: &str, _typ: NodeType) -> Option<Node> {
+        None
+    }
+
+    fn unlink_or_delete_empty_child(&mut self, _name: &str) -> Option<()> {
+        None
+    }
+}
+
+pub struct ProcessFolder {
+    pid: usize,
+}
+
+impl IFolder for ProcessFolder {
+    fn get_children(&self) -> Vec<(String, Node)> {
+        let pid = self.pid;
+        alloc::vec![(String::from("status"), generated_file_node(Box::new(move || generate_process_status(pid))))]
+    }
+
+    fn create_empty_child(&mut self, _name: &str, _typ: NodeType) -> Option<Node> {
+        None
+    }
+
+    fn unlink_or_delete_empty_child(&mut self, _name: &str) -> Option<()> {
+        None
+    }
+}
@@ -1,12 +1,38 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use crate::{
-    hio::{KeyboardPacket, KeyboardKey, KeyboardPacketType},
-    primitives::{LazyInitialised, Mutex},
+    clock,
+    hio::{KeyboardPacket, KeyboardKey, KeyboardPacketType, MousePacket, MOUSE_PACKET_LEN},
+    interrupts::{self, InterruptStackFrame},
+    primitives::{AtomicRingBuffer, LazyInitialised, Mutex},
     virtmem::KernPointer,
+    vfs::IFile,
     X86Default,
 };
 use packed_struct::prelude::*;
 
 pub static KEYBOARD_INPUT: Mutex<LazyInitialised<PS2Device>> = Mutex::from(LazyInitialised::uninit());
+pub static MOUSE_INPUT: Mutex<LazyInitialised<PS2Mouse>> = Mutex::from(LazyInitialised::uninit());
+
+// Bytes from the second port that the keyboard took out of the controller, waiting for the mouse to take them
+// NOTE: Both ports share the data port, but the keyboard is polled way more often, so it ends up taking most of the mouse's bytes too
+static SECOND_PORT_BYTES: Mutex<VecDeque<u8>> = Mutex::from(VecDeque::new());
+// If the mouse isn't being read, only the newest bytes are kept
+const SECOND_PORT_BYTES_MAX_LEN: usize = 64 * 3;
+
+// Bytes the interrupt handlers took from the controller, until they are moved into the scancode buffer or SECOND_PORT_BYTES
+// NOTE: The handlers can't lock anything, since they could have interrupted whoever is holding the lock, so these are lock free.
+// They are only popped from with interrupts disabled, so that bytes taken by polling can't get ahead of bytes that were already taken by the handlers
+static KEYBOARD_INTERRUPT_BYTES: AtomicRingBuffer<256> = AtomicRingBuffer::new();
+static MOUSE_INTERRUPT_BYTES: AtomicRingBuffer<256> = AtomicRingBuffer::new();
+static IS_INTERRUPT_DRIVEN: AtomicBool = AtomicBool::new(false);
+const KEYBOARD_IRQ: u8 = 1;
+const MOUSE_IRQ: u8 = 12;
+
+// How long a key has to be held before it starts repeating, and how often it repeats after that
+const KEY_REPEAT_DELAY_MS: u64 = 500;
+const KEY_REPEAT_INTERVAL_MS: u64 = 33;
 
 #[derive(PackedStruct, Clone, Copy, Debug)]
 #[packed_struct(bit_numbering = "lsb0", size_bytes = "1")]
@@ -67,20 +93,31 @@ struct StatusRegister {
     system_flag: bool,
     #[packed_field(bits = "3")]
     selector: bool, // false = data goes to ps/2 device, 1 = data goes to ps/2 controller command
+    #[packed_field(bits = "5")]
+    is_from_second_port: bool, // Only meaningful when is_output_buf_full is set
     #[packed_field(bits = "6")]
     timeout_error: bool,
     #[packed_field(bits = "7")]
     parity_error: bool,
 }
 
+#[derive(Debug)]
+struct HeldKey {
+    key: KeyboardKey,
+    next_repeat_ms: u64,
+}
+
 /// FIXME: We assume the PS/2 controller exists, is already initialized and no devices are plugged or unplugged ever, oh and also that all communication is 100% reliable
-/// Also assumes first ps/2 port is keyboard, and for now just disables the second one ( if it exists )
+/// Also assumes first ps/2 port is keyboard, and disables the second one ( if it exists ), PS2Mouse enables it again
 // What could go wrong ¯\_(ツ)_/¯
 #[derive(Debug)]
 pub struct PS2Device {
     data: KernPointer<u8>,
     status_and_command: KernPointer<u8>,
     active_modifiers: KeyboardModifiers,
+    held_key: Option<HeldKey>,
+    // Bytes that were taken from the controller but haven't been turned into packets yet
+    scancode_buffer: VecDeque<u8>,
 }
 
 impl X86Default for PS2Device {
@@ -88,7 +125,9 @@ impl X86Default for PS2Device {
         let mut ps2 = Self {
             data: KernPointer::<u8>::from_port(0x60),
             status_and_command: KernPointer::<u8>::from_port(0x64),
-            active_modifiers: KeyboardModifiers::none()
+            active_modifiers: KeyboardModifiers::none(),
+            held_key: None,
+            scancode_buffer: VecDeque::new(),
         };
 
         wait_for!(!StatusRegister::unpack_from_slice(&[ps2.status_and_command.read()]).unwrap().is_input_buf_full);
@@ -98,14 +137,53 @@ impl X86Default for PS2Device {
 }
 
 impl PS2Device {
-    unsafe fn try_read_byte(&mut self) -> Option<u8> {
-        if !(StatusRegister::unpack_from_slice(&[self.status_and_command.read()]).unwrap().is_output_buf_full) {
-            return None;
+    // Moves every byte the interrupt handler or the controller has into the scancode buffer, without blocking
+    // NOTE: The controller can only hold one byte at a time, so the keyboard can't send anything else until it's taken.
+    // Interrupts are disabled while talking to the controller, otherwise the handler could take the byte between reading the status and reading the data
+    pub unsafe fn poll(&mut self) {
+        interrupts::without_interrupts(|| {
+            while let Some(byte) = KEYBOARD_INTERRUPT_BYTES.pop() {
+                self.scancode_buffer.push_back(byte);
+            }
+            loop {
+                let status = StatusRegister::unpack_from_slice(&[self.status_and_command.read()]).unwrap();
+                if !status.is_output_buf_full {
+                    break;
+                }
+                let byte = self.data.read();
+                if status.is_from_second_port {
+                    let mut second_port_bytes = SECOND_PORT_BYTES.lock();
+                    take_mouse_interrupt_bytes(&mut second_port_bytes);
+                    push_second_port_byte(&mut second_port_bytes, byte);
+                } else {
+                    self.scancode_buffer.push_back(byte);
+                }
+            }
+        });
+    }
+
+    // Waits until there might be something to read, halting the cpu until the next interrupt if there is nothing yet
+    // NOTE: Doesn't halt if the keyboard isn't interrupt driven, since then nothing would wake us up,
+    // or if a key is held, since then its repeats have to be made on time
+    pub unsafe fn wait_for_input(&mut self) {
+        if !IS_INTERRUPT_DRIVEN.load(Ordering::Relaxed) || self.held_key.is_some() {
+            core::hint::spin_loop();
+            return;
+        }
+        interrupts::disable();
+        self.poll();
+        if self.scancode_buffer.is_empty() {
+            interrupts::enable_and_halt();
         } else {
-            return Some(self.data.read());
+            interrupts::enable();
         }
     }
 
+    unsafe fn try_read_byte(&mut self) -> Option<u8> {
+        self.poll();
+        self.scancode_buffer.pop_front()
+    }
+
     unsafe fn read_byte(&mut self) -> u8 {
         let mut res;
         wait_for!({
@@ -139,7 +217,7 @@ impl PS2Device {
     }
 
     // NOTE: This only supports scan code set 1
-    pub unsafe fn try_read_packet(&mut self) -> Option<KeyboardPacket> {
+    unsafe fn try_read_hardware_packet(&mut self) -> Option<KeyboardPacket> {
         let scancode = self.read_scancode()?;
         let key = KeyboardKey::from_scancode_in_set1(scancode)?;
         let packet_type = if crate::hio::is_scancode_in_set1_pressed(scancode) { KeyboardPacketType::KeyPressed } else { KeyboardPacketType::KeyReleased };
@@ -176,12 +254,309 @@ impl PS2Device {
         })
     }
 
+    // Reads a packet, generating repeated key presses for held keys
+    // NOTE: While a key is held the keyboard itself keeps sending its make code ( typematic repeat ), and sends a break code only once
+    // it is released, so a make code for the key we already know is held just means it's still held. Those are dropped and
+    // replaced with our own repeats, so that the delay and rate don't depend on how the keyboard happens to be configured.
+    // If there is no clock to time the repeats with then the keyboard's repeats are used as is
+    pub unsafe fn try_read_packet(&mut self) -> Option<KeyboardPacket> {
+        let Some(now) = clock::now_ms() else {
+            return self.try_read_hardware_packet();
+        };
+
+        if let Some(packet) = self.try_read_hardware_packet() {
+            let is_held_key = self.held_key.as_ref().map(|held| held.key == packet.key).unwrap_or(false);
+            match packet.packet_type {
+                KeyboardPacketType::KeyPressed if is_held_key => return None,
+                KeyboardPacketType::KeyPressed if Self::can_repeat(packet.key) => {
+                    self.held_key = Some(HeldKey { key: packet.key, next_repeat_ms: now + KEY_REPEAT_DELAY_MS })
+                }
+                KeyboardPacketType::KeyReleased if is_held_key => self.held_key = None,
+                _ => {}
+            }
+            return Some(packet);
+        }
+
+        let held = self.held_key.as_mut()?;
+        if now < held.next_repeat_ms {
+            return None;
+        }
+        held.next_repeat_ms = now + KEY_REPEAT_INTERVAL_MS;
+        Some(KeyboardPacket { key: held.key, modifiers: self.active_modifiers, packet_type: KeyboardPacketType::KeyPressed })
+    }
+
+    // Modifiers and lock keys are held down to be used together with other keys, not to type them repeatedly
+    fn can_repeat(key: KeyboardKey) -> bool {
+        !matches!(
+            key,
+            KeyboardKey::LeftShift
+                | KeyboardKey::RightShift
+                | KeyboardKey::LeftCtrl
+                | KeyboardKey::RightCtrl
+                | KeyboardKey::LeftAlt
+                | KeyboardKey::RightAlt
+                | KeyboardKey::LeftSuper
+                | KeyboardKey::RightSuper
+                | KeyboardKey::CapsLock
+                | KeyboardKey::NumLock
+                | KeyboardKey::ScrollLock
+        )
+    }
+
     pub unsafe fn read_packet(&mut self) -> KeyboardPacket {
-        let mut res;
-        wait_for!({
-            res = self.try_read_packet();
-            res.is_some()
+        loop {
+            if let Some(packet) = self.try_read_packet() {
+                return packet;
+            }
+            self.wait_for_input();
+        }
+    }
+}
+
+fn push_second_port_byte(second_port_bytes: &mut VecDeque<u8>, byte: u8) {
+    if second_port_bytes.len() >= SECOND_PORT_BYTES_MAX_LEN {
+        second_port_bytes.pop_front();
+    }
+    second_port_bytes.push_back(byte);
+}
+
+// NOTE: Has to be called with interrupts disabled
+fn take_mouse_interrupt_bytes(second_port_bytes: &mut VecDeque<u8>) {
+    while let Some(byte) = MOUSE_INTERRUPT_BYTES.pop() {
+        push_second_port_byte(second_port_bytes, byte);
+    }
+}
+
+// Takes the byte the controller has, both ports share the data port, so both interrupts end up here
+fn take_interrupt_byte() {
+    unsafe {
+        let status = StatusRegister::unpack_from_slice(&[KernPointer::<u8>::from_port(0x64).read()]).unwrap();
+        if !status.is_output_buf_full {
+            return;
+        }
+        let byte = KernPointer::<u8>::from_port(0x60).read();
+        // NOTE: If nobody is reading the bytes then the newest ones are dropped
+        let _ = if status.is_from_second_port { MOUSE_INTERRUPT_BYTES.push(byte) } else { KEYBOARD_INTERRUPT_BYTES.push(byte) };
+    }
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(_frame: InterruptStackFrame) {
+    take_interrupt_byte();
+    unsafe { interrupts::end_of_interrupt(KEYBOARD_IRQ) };
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_frame: InterruptStackFrame) {
+    take_interrupt_byte();
+    unsafe { interrupts::end_of_interrupt(MOUSE_IRQ) };
+}
+
+// Makes the controller raise an interrupt for every byte, so that bytes are taken as soon as they come instead of only when something polls
+// NOTE: The mouse's interrupt is only enabled if there is a mouse, interrupts::init has to have been called already
+// Returns: None if the controller didn't answer
+pub unsafe fn enable_interrupts(with_mouse: bool) -> Option<()> {
+    let data = KernPointer::<u8>::from_port(0x60);
+    let status_and_command = KernPointer::<u8>::from_port(0x64);
+    interrupts::set_irq_handler(KEYBOARD_IRQ, keyboard_interrupt_handler);
+    interrupts::set_irq_handler(MOUSE_IRQ, mouse_interrupt_handler);
+
+    interrupts::without_interrupts(|| {
+        write_controller_byte(status_and_command, status_and_command, PS2_COMMAND_READ_CONFIG)?;
+        let mut config = read_controller_byte(status_and_command, data)? | PS2_CONFIG_FIRST_PORT_INTERRUPT;
+        if with_mouse {
+            config |= PS2_CONFIG_SECOND_PORT_INTERRUPT;
+        }
+        write_controller_byte(status_and_command, status_and_command, PS2_COMMAND_WRITE_CONFIG)?;
+        write_controller_byte(status_and_command, data, config)
+    })?;
+
+    interrupts::unmask_irq(KEYBOARD_IRQ);
+    if with_mouse {
+        interrupts::unmask_irq(MOUSE_IRQ);
+    }
+    IS_INTERRUPT_DRIVEN.store(true, Ordering::Relaxed);
+    Some(())
+}
+
+// Returns: None if the controller never became ready to take the byte
+unsafe fn write_controller_byte(status_and_command: KernPointer<u8>, mut port: KernPointer<u8>, byte: u8) -> Option<()> {
+    for _ in 0..CONTROLLER_MAX_TRIES {
+        if !StatusRegister::unpack_from_slice(&[status_and_command.read()]).unwrap().is_input_buf_full {
+            port.write(byte);
+            return Some(());
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+// Returns: None if the controller had nothing to read for too long
+unsafe fn read_controller_byte(status_and_command: KernPointer<u8>, data: KernPointer<u8>) -> Option<u8> {
+    for _ in 0..CONTROLLER_MAX_TRIES {
+        if StatusRegister::unpack_from_slice(&[status_and_command.read()]).unwrap().is_output_buf_full {
+            return Some(data.read());
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+// Source: https://wiki.osdev.org/PS/2_Mouse, https://wiki.osdev.org/%228042%22_PS/2_Controller
+const PS2_COMMAND_READ_CONFIG: u8 = 0x20;
+const PS2_COMMAND_WRITE_CONFIG: u8 = 0x60;
+const PS2_COMMAND_ENABLE_SECOND_PORT: u8 = 0xA8;
+const PS2_COMMAND_WRITE_TO_SECOND_PORT: u8 = 0xD4;
+const PS2_CONFIG_FIRST_PORT_INTERRUPT: u8 = 1 << 0;
+const PS2_CONFIG_SECOND_PORT_INTERRUPT: u8 = 1 << 1;
+const PS2_CONFIG_SECOND_PORT_CLOCK_DISABLED: u8 = 1 << 5;
+const MOUSE_COMMAND_SET_DEFAULTS: u8 = 0xF6;
+const MOUSE_COMMAND_ENABLE_DATA_REPORTING: u8 = 0xF4;
+const MOUSE_ACK: u8 = 0xFA;
+// The controller and the mouse should answer way before this, if they don't then there probably is no mouse
+const CONTROLLER_MAX_TRIES: usize = 1_000_000;
+
+// The first byte of a packet
+const MOUSE_LEFT_BUTTON: u8 = 1 << 0;
+const MOUSE_RIGHT_BUTTON: u8 = 1 << 1;
+const MOUSE_MIDDLE_BUTTON: u8 = 1 << 2;
+const MOUSE_ALWAYS_SET: u8 = 1 << 3;
+const MOUSE_X_SIGN: u8 = 1 << 4;
+const MOUSE_Y_SIGN: u8 = 1 << 5;
+const MOUSE_X_OVERFLOW: u8 = 1 << 6;
+const MOUSE_Y_OVERFLOW: u8 = 1 << 7;
+
+// Turns the 3 bytes the mouse sends into a packet, the movement is 9 bits, the 8 in its own byte and the sign in the first byte
+// NOTE: If the movement didn't fit in 9 bits then the overflow bit is set, the movement is meaningless then, so it's as far as it can go in the direction of the sign
+// Returns: None if bytes[0] can't be the first byte of a packet
+pub fn parse_mouse_packet(bytes: [u8; 3]) -> Option<MousePacket> {
+    let flags = bytes[0];
+    if flags & MOUSE_ALWAYS_SET == 0 {
+        return None;
+    }
+    let movement = |value: u8, is_negative: bool, overflowed: bool| -> i16 {
+        match (is_negative, overflowed) {
+            (false, false) => i16::from(value),
+            (true, false) => i16::from(value) - 0x100,
+            (false, true) => 0xFF,
+            (true, true) => -0x100,
+        }
+    };
+    Some(MousePacket {
+        dx: movement(bytes[1], flags & MOUSE_X_SIGN != 0, flags & MOUSE_X_OVERFLOW != 0),
+        dy: -movement(bytes[2], flags & MOUSE_Y_SIGN != 0, flags & MOUSE_Y_OVERFLOW != 0),
+        left_button: flags & MOUSE_LEFT_BUTTON != 0,
+        right_button: flags & MOUSE_RIGHT_BUTTON != 0,
+        middle_button: flags & MOUSE_MIDDLE_BUTTON != 0,
+    })
+}
+
+/// FIXME: Same assumptions as PS2Device, and the mouse is polled, so bytes can be lost if it isn't polled often enough
+/// Assumes the second ps/2 port is a standard 3 byte packet mouse
+#[derive(Debug)]
+pub struct PS2Mouse {
+    data: KernPointer<u8>,
+    status_and_command: KernPointer<u8>,
+    // Bytes that were taken from the controller but haven't been turned into packets yet
+    packet_buffer: VecDeque<u8>,
+}
+
+impl PS2Mouse {
+    // Enables the second port and makes the mouse start sending packets
+    // Returns: None if there is no mouse
+    pub unsafe fn init() -> Option<Self> {
+        let mut mouse = Self {
+            data: KernPointer::<u8>::from_port(0x60),
+            status_and_command: KernPointer::<u8>::from_port(0x64),
+            packet_buffer: VecDeque::new(),
+        };
+
+        mouse.write_command(PS2_COMMAND_ENABLE_SECOND_PORT)?;
+        mouse.write_command(PS2_COMMAND_READ_CONFIG)?;
+        // NOTE: The interrupt stays off until enable_interrupts, until then the mouse is polled
+        let config = mouse.read_response()? & !PS2_CONFIG_SECOND_PORT_INTERRUPT & !PS2_CONFIG_SECOND_PORT_CLOCK_DISABLED;
+        mouse.write_command(PS2_COMMAND_WRITE_CONFIG)?;
+        mouse.write_data(config)?;
+
+        for command in [MOUSE_COMMAND_SET_DEFAULTS, MOUSE_COMMAND_ENABLE_DATA_REPORTING] {
+            mouse.write_command(PS2_COMMAND_WRITE_TO_SECOND_PORT)?;
+            mouse.write_data(command)?;
+            if mouse.read_response()? != MOUSE_ACK {
+                return None;
+            }
+        }
+        Some(mouse)
+    }
+
+    unsafe fn write_command(&mut self, command: u8) -> Option<()> {
+        write_controller_byte(self.status_and_command, self.status_and_command, command)
+    }
+
+    unsafe fn write_data(&mut self, data: u8) -> Option<()> {
+        write_controller_byte(self.status_and_command, self.data, data)
+    }
+
+    unsafe fn read_response(&mut self) -> Option<u8> {
+        read_controller_byte(self.status_and_command, self.data)
+    }
+
+    // Moves every byte of the mouse into the packet buffer, without blocking
+    // NOTE: Stops at a byte from the keyboard, since the keyboard has to be the one to take it
+    pub unsafe fn poll(&mut self) {
+        interrupts::without_interrupts(|| {
+            let mut second_port_bytes = SECOND_PORT_BYTES.lock();
+            take_mouse_interrupt_bytes(&mut second_port_bytes);
+            self.packet_buffer.extend(second_port_bytes.drain(..));
+            loop {
+                let status = StatusRegister::unpack_from_slice(&[self.status_and_command.read()]).unwrap();
+                if !status.is_output_buf_full || !status.is_from_second_port {
+                    break;
+                }
+                self.packet_buffer.push_back(self.data.read());
+            }
         });
-        return res.unwrap();
+    }
+
+    pub unsafe fn try_read_packet(&mut self) -> Option<MousePacket> {
+        self.poll();
+        while self.packet_buffer.len() >= 3 {
+            let bytes = [self.packet_buffer[0], self.packet_buffer[1], self.packet_buffer[2]];
+            if let Some(packet) = parse_mouse_packet(bytes) {
+                self.packet_buffer.drain(..3);
+                return Some(packet);
+            }
+            // Not the start of a packet, so a byte was lost somewhere, skip until the packets line up again
+            self.packet_buffer.pop_front();
+        }
+        None
+    }
+}
+
+// The mouse as a file, every read gives whole packets, as returned by MousePacket::to_bytes
+pub struct PS2MouseFile;
+
+impl IFile for PS2MouseFile {
+    fn read(&self, _offset: u64, len: usize) -> Option<Vec<u8>> {
+        let mut mouse = MOUSE_INPUT.lock();
+        let mut data = Vec::new();
+        while data.len() + MOUSE_PACKET_LEN <= len {
+            let Some(packet) = (unsafe { mouse.try_read_packet() }) else { break };
+            data.extend_from_slice(&packet.to_bytes());
+        }
+        Some(data)
+    }
+
+    fn write(&mut self, _offset: u64, _data: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn get_size(&self) -> u64 {
+        0
+    }
+
+    fn resize(&mut self, _new_size: u64) -> Option<()> {
+        None
+    }
+
+    fn is_character_device(&self) -> bool {
+        true
     }
 }
@@ -0,0 +1,260 @@
+use core::{cell::RefCell, fmt::Write};
+
+use alloc::{collections::VecDeque, rc::Rc, string::String, vec::Vec};
+use rlibc::sys::SignalType;
+
+use crate::{
+    hio::{self, KeyboardKey, KeyboardPacket, KeyboardPacketType},
+    process::ProcessSignal,
+    ps2_8042::KEYBOARD_INPUT,
+    scheduler,
+    terminal::TERMINAL,
+    vfs::IFile,
+};
+
+// Note: This file defines a pseudo terminal, the master end is used by whoever displays the terminal and provides the keyboard input,
+// the slave end is what a program uses as its terminal, in between sits a line discipline which does echoing, line editing and signals
+// Source: man pty, man termios
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineDisciplineSettings {
+    // Input is only made available to the slave one line at a time and can be edited before that
+    pub canonical: bool,
+    // Input is written back to the master as it's typed
+    pub echo: bool,
+    // Ctrl+C sends a SIGINT to the foreground process instead of being passed through
+    pub signals: bool,
+}
+
+impl LineDisciplineSettings {
+    pub fn cooked() -> Self {
+        Self { canonical: true, echo: true, signals: true }
+    }
+
+    pub fn raw() -> Self {
+        Self { canonical: false, echo: false, signals: false }
+    }
+
+    // Changes the setting named by an stty style argument, for ex. "-echo" turns off echo
+    // Returns: None if the argument isn't known
+    pub fn apply_stty_argument(&mut self, arg: &str) -> Option<()> {
+        let (name, value) = if let Some(name) = arg.strip_prefix('-') { (name, false) } else { (arg, true) };
+        match name {
+            "echo" => self.echo = value,
+            "icanon" => self.canonical = value,
+            "isig" => self.signals = value,
+            "raw" if value => *self = Self::raw(),
+            "sane" | "cooked" if value => *self = Self::cooked(),
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+#[derive(Debug)]
+pub struct PseudoTerminal {
+    pub settings: LineDisciplineSettings,
+    pub foreground_pid: Option<usize>,
+    line_being_edited: Vec<u8>,
+    input: VecDeque<u8>,  // master -> slave, after the line discipline
+    output: VecDeque<u8>, // slave -> master
+}
+
+impl PseudoTerminal {
+    pub fn new() -> Self {
+        Self {
+            settings: LineDisciplineSettings::cooked(),
+            foreground_pid: None,
+            line_being_edited: Vec::new(),
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+        }
+    }
+
+    // Returns: The master and slave end of a new pseudo terminal
+    pub fn new_pair() -> (Rc<RefCell<PtyMaster>>, Rc<RefCell<PtySlave>>) {
+        let pty = Rc::new(RefCell::new(Self::new()));
+        (Rc::new(RefCell::new(PtyMaster { pty: pty.clone() })), Rc::new(RefCell::new(PtySlave { pty })))
+    }
+
+    fn echo(&mut self, data: &[u8]) {
+        if self.settings.echo {
+            self.output.extend(data);
+        }
+    }
+
+    // Passes a byte typed on the master end through the line discipline
+    fn recive_input_byte(&mut self, byte: u8) {
+        if self.settings.signals && byte == CTRL_C {
+            self.line_being_edited.clear();
+            self.echo(b"^C\n");
+            if let Some(pid) = self.foreground_pid {
+                // NOTE: If the process already ended there is no one to notify, so ignoring the error is fine
+                let _ = scheduler::kill_task(pid, ProcessSignal { signal_type: SignalType::SIGINT });
+            }
+            return;
+        }
+
+        if !self.settings.canonical {
+            self.input.push_back(byte);
+            self.echo(&[byte]);
+            return;
+        }
+
+        match byte {
+            BACKSPACE | DELETE => {
+                if self.line_being_edited.pop().is_some() {
+                    self.echo(&[BACKSPACE]);
+                }
+            }
+            b'\r' | b'\n' => {
+                self.input.extend(self.line_being_edited.drain(..));
+                self.input.push_back(b'\n');
+                self.echo(b"\n");
+            }
+            _ => {
+                self.line_being_edited.push(byte);
+                self.echo(&[byte]);
+            }
+        }
+    }
+
+    fn read_input(&mut self, len: usize) -> Vec<u8> {
+        let mut res = Vec::new();
+        while res.len() < len {
+            let Some(byte) = self.input.pop_front() else { break };
+            res.push(byte);
+            // In canonical mode a read never returns more than one line
+            if self.settings.canonical && byte == b'\n' {
+                break;
+            }
+        }
+        res
+    }
+
+    fn read_output(&mut self, len: usize) -> Vec<u8> {
+        let len = core::cmp::min(len, self.output.len());
+        self.output.drain(..len).collect()
+    }
+}
+
+// NOTE: Terminals are streams, so offsets are ignored and reads return whatever is available, possibly nothing
+pub struct PtyMaster {
+    pty: Rc<RefCell<PseudoTerminal>>,
+}
+
+impl PtyMaster {
+    pub fn get_pty(&self) -> Rc<RefCell<PseudoTerminal>> {
+        self.pty.clone()
+    }
+}
+
+impl IFile for PtyMaster {
+    fn read(&self, _offset: u64, len: usize) -> Option<Vec<u8>> {
+        Some((*self.pty).borrow_mut().read_output(len))
+    }
+
+    fn write(&mut self, _offset: u64, data: &[u8]) -> Option<usize> {
+        let mut pty = (*self.pty).borrow_mut();
+        data.iter().for_each(|byte| pty.recive_input_byte(*byte));
+        Some(data.len())
+    }
+
+    fn get_size(&self) -> u64 {
+        (*self.pty).borrow().output.len() as u64
+    }
+
+    fn resize(&mut self, _new_size: u64) -> Option<()> {
+        None
+    }
+
+    fn is_character_device(&self) -> bool {
+        true
+    }
+}
+
+pub struct PtySlave {
+    pty: Rc<RefCell<PseudoTerminal>>,
+}
+
+impl IFile for PtySlave {
+    fn read(&self, _offset: u64, len: usize) -> Option<Vec<u8>> {
+        Some((*self.pty).borrow_mut().read_input(len))
+    }
+
+    fn write(&mut self, _offset: u64, data: &[u8]) -> Option<usize> {
+        (*self.pty).borrow_mut().output.extend(data);
+        Some(data.len())
+    }
+
+    fn get_size(&self) -> u64 {
+        (*self.pty).borrow().input.len() as u64
+    }
+
+    fn resize(&mut self, _new_size: u64) -> Option<()> {
+        None
+    }
+
+    fn is_character_device(&self) -> bool {
+        true
+    }
+}
+
+// Shows whatever was written to the slave end on the kernel's terminal
+pub fn forward_output_to_kernel_terminal(master: &mut PtyMaster) {
+    let output = master.read(0, usize::MAX).unwrap_or_default();
+    if output.is_empty() {
+        return;
+    }
+    let mut terminal = TERMINAL.lock();
+    // NOTE: Like with stdout, a utf-8 character split across two writes shows up as two replacement characters
+    String::from_utf8_lossy(&output).chars().for_each(|c| terminal.write_char(c));
+}
+
+// Sends a key pressed on the kernel's keyboard to the master end, without blocking if no key was pressed
+// Passes a key to the kernel terminal, except for Ctrl+C, which sends a SIGINT to foreground_pid instead, if the terminal has signals on
+// NOTE: The kernel terminal has no notion of a foreground process, so whoever passes it keys has to say who gets the signal
+pub fn pass_key_to_kernel_terminal(packet: KeyboardPacket, foreground_pid: Option<usize>) {
+    if packet.packet_type == KeyboardPacketType::KeyReleased {
+        return;
+    }
+    let mut terminal = TERMINAL.lock();
+    let is_ctrl_c = packet.modifiers.any_ctrl() && matches!(hio::parse_key(packet.key, packet.modifiers), Ok('c' | 'C'));
+    if terminal.line_discipline.signals && is_ctrl_c {
+        let _ = writeln!(terminal, "^C");
+        if let Some(pid) = foreground_pid {
+            // NOTE: If the process already ended there is no one to notify, so ignoring the error is fine
+            let _ = scheduler::kill_task(pid, ProcessSignal { signal_type: SignalType::SIGINT });
+        }
+        return;
+    }
+    terminal.recive_key(packet.key, packet.modifiers);
+}
+
+pub fn forward_kernel_keyboard_input(master: &mut PtyMaster) {
+    let Some(packet) = (unsafe { KEYBOARD_INPUT.lock().try_read_packet() }) else { return };
+    if packet.packet_type == KeyboardPacketType::KeyReleased {
+        return;
+    }
+    let byte = match packet.key {
+        KeyboardKey::Enter => b'\n',
+        KeyboardKey::Backspace => BACKSPACE,
+        _ => {
+            let Ok(c) = hio::parse_key(packet.key, packet.modifiers) else { return };
+            if !c.is_ascii() {
+                return;
+            }
+            if packet.modifiers.any_ctrl() && c.is_ascii_alphabetic() {
+                // Ctrl+A is 1, Ctrl+B is 2 and so on
+                c.to_ascii_lowercase() as u8 - b'a' + 1
+            } else {
+                c as u8
+            }
+        }
+    };
+    master.write(0, &[byte]);
+}
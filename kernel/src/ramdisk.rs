@@ -0,0 +1,45 @@
+use core::convert::TryFrom;
+
+use alloc::{vec, vec::Vec};
+
+use crate::vfs::IFile;
+
+// Note: This file defines a disk that lives entirely in memory, so it's gone once the computer is turned off,
+// it's useful as a scratch disk or for trying out filesystems without a real disk
+
+pub struct RamDisk {
+    data: Vec<u8>,
+}
+
+impl RamDisk {
+    pub fn new(size_in_bytes: usize) -> Self {
+        Self { data: vec![0; size_in_bytes] }
+    }
+}
+
+impl IFile for RamDisk {
+    // Returns: None if the range doesn't fit on the disk
+    fn read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(len)?;
+        Some(self.data.get(start..end)?.to_vec())
+    }
+
+    // NOTE: Like a real disk writing doesn't change its size, so writes that don't fit fail, use resize to make room first
+    fn write(&mut self, offset: u64, data: &[u8]) -> Option<usize> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(data.len())?;
+        self.data.get_mut(start..end)?.copy_from_slice(data);
+        Some(data.len())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    // NOTE: New space is zeroed
+    fn resize(&mut self, new_size: u64) -> Option<()> {
+        self.data.resize(usize::try_from(new_size).ok()?, 0);
+        Some(())
+    }
+}
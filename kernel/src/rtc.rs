@@ -0,0 +1,250 @@
+use core::{
+    convert::TryFrom,
+    fmt::{Debug, Display},
+};
+
+use crate::{
+    primitives::{LazyInitialised, Mutex},
+    virtmem::KernPointer,
+    X86Default,
+};
+
+// Note: This file defines a reader for the real time clock found in the CMOS of x86 pcs
+// Source: https://wiki.osdev.org/CMOS#The_Real-Time_Clock
+
+pub static RTC: Mutex<LazyInitialised<CMOSDevice>> = Mutex::from(LazyInitialised::uninit());
+
+mod cmos_register {
+    pub const SECONDS: u8 = 0x00;
+    pub const MINUTES: u8 = 0x02;
+    pub const HOURS: u8 = 0x04;
+    pub const DAY_OF_MONTH: u8 = 0x07;
+    pub const MONTH: u8 = 0x08;
+    pub const YEAR: u8 = 0x09;
+    pub const STATUS_A: u8 = 0x0A;
+    pub const STATUS_B: u8 = 0x0B;
+    // NOTE: Technically the ACPI FADT says where ( and if ) the century register exists, but we don't parse ACPI tables, and 0x32 is where it almost always is
+    pub const CENTURY: u8 = 0x32;
+}
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR_MODE: u8 = 1 << 1;
+const STATUS_B_BINARY_MODE: u8 = 1 << 2;
+const HOUR_PM_BIT: u8 = 1 << 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RTCTime {
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8, // Always in 24 hour format
+    pub day: u8,
+    pub month: u8,
+    pub year: u16, // Full year, for ex. 2022
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct RawRTCReading {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+fn bcd_to_binary(val: u8) -> u8 {
+    (val & 0x0F) + (val >> 4) * 10
+}
+
+impl RawRTCReading {
+    fn read(read_register: &mut impl FnMut(u8) -> u8) -> Self {
+        use cmos_register::*;
+        Self {
+            second: read_register(SECONDS),
+            minute: read_register(MINUTES),
+            hour: read_register(HOURS),
+            day: read_register(DAY_OF_MONTH),
+            month: read_register(MONTH),
+            year: read_register(YEAR),
+            century: read_register(CENTURY),
+        }
+    }
+
+    fn decode(self, status_b: u8) -> RTCTime {
+        let decode_value = |val: u8| if status_b & STATUS_B_BINARY_MODE != 0 { val } else { bcd_to_binary(val) };
+
+        // NOTE: In 12 hour mode the pm bit is set on the hour itself, even in bcd mode, so it has to be removed before decoding
+        let mut hour = decode_value(self.hour & !HOUR_PM_BIT);
+        if status_b & STATUS_B_24_HOUR_MODE == 0 {
+            // 12 am is midnight and 12 pm is noon
+            hour %= 12;
+            if self.hour & HOUR_PM_BIT != 0 {
+                hour += 12;
+            }
+        }
+
+        let year = u16::from(decode_value(self.year));
+        let century = u16::from(decode_value(self.century));
+        // If the century register doesn't exist it usually reads as garbage, so only trust it if it's reasonable,
+        // otherwise assume 2 digit years before 70 are after 2000 ( which is the same as what unix timestamps can represent )
+        let full_year = if (19..=21).contains(&century) {
+            century * 100 + year
+        } else if year < 70 {
+            2000 + year
+        } else {
+            1900 + year
+        };
+
+        RTCTime {
+            second: decode_value(self.second),
+            minute: decode_value(self.minute),
+            hour,
+            day: decode_value(self.day),
+            month: decode_value(self.month),
+            year: full_year,
+        }
+    }
+}
+
+// Reads the time using read_register to read a cmos register
+// NOTE: Takes a function so that the logic doesn't depend on actually having a cmos
+pub fn read_rtc_time(mut read_register: impl FnMut(u8) -> u8) -> RTCTime {
+    let wait_for_update_to_finish = |read_register: &mut dyn FnMut(u8) -> u8| {
+        while read_register(cmos_register::STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+            core::hint::spin_loop();
+        }
+    };
+
+    // The rtc might start updating right after we check the flag, so read until we get the same values twice in a row
+    wait_for_update_to_finish(&mut read_register);
+    let mut last_reading = RawRTCReading::read(&mut read_register);
+    loop {
+        wait_for_update_to_finish(&mut read_register);
+        let reading = RawRTCReading::read(&mut read_register);
+        if reading == last_reading {
+            break;
+        }
+        last_reading = reading;
+    }
+
+    last_reading.decode(read_register(cmos_register::STATUS_B))
+}
+
+// Returns: The number of days since 1970-01-01, negative for dates before it
+// Source: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400; // [0, 399]
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1; // [0, 365]
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year; // [0, 146096]
+    era * 146097 + day_of_era - 719468
+}
+
+// Returns: (year, month, day)
+// Source: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097; // [0, 146096]
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365; // [0, 399]
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100); // [0, 365]
+    let mp = (5 * day_of_year + 2) / 153; // [0, 11]
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8; // [1, 31]
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8; // [1, 12]
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl RTCTime {
+    // NOTE: The rtc is assumed to be in UTC
+    // Returns: None if the time is before the unix epoch
+    pub fn to_unix_timestamp(&self) -> Option<u64> {
+        let days = days_from_civil(i64::from(self.year), self.month, self.day);
+        let seconds =
+            days * 24 * 60 * 60 + i64::from(self.hour) * 60 * 60 + i64::from(self.minute) * 60 + i64::from(self.second);
+        u64::try_from(seconds).ok()
+    }
+
+    pub fn from_unix_timestamp(timestamp: u64) -> Self {
+        let seconds_in_day = timestamp % (24 * 60 * 60);
+        let (year, month, day) = civil_from_days((timestamp / (24 * 60 * 60)) as i64);
+        Self {
+            second: (seconds_in_day % 60) as u8,
+            minute: (seconds_in_day / 60 % 60) as u8,
+            hour: (seconds_in_day / (60 * 60)) as u8,
+            day,
+            month,
+            year: year as u16,
+        }
+    }
+}
+
+impl Display for RTCTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+pub struct CMOSDevice {
+    index: KernPointer<u8>,
+    data: KernPointer<u8>,
+    // NOTE: Used to make sure time never goes backwards, even if the rtc gets changed under us
+    last_unix_timestamp: u64,
+}
+
+impl CMOSDevice {
+    pub unsafe fn new(index: KernPointer<u8>, data: KernPointer<u8>) -> Self {
+        Self { index, data, last_unix_timestamp: 0 }
+    }
+
+    pub fn read_register(&mut self, register: u8) -> u8 {
+        unsafe {
+            // NOTE: Bit 7 of the index is the nmi disable bit, keep nmis enabled
+            self.index.write(register & 0x7F);
+            self.data.read()
+        }
+    }
+
+    pub fn read_time(&mut self) -> RTCTime {
+        read_rtc_time(|register| self.read_register(register))
+    }
+
+    // Returns: The current unix timestamp, guaranteed to never be smaller than a previously returned one
+    pub fn now_unix(&mut self) -> u64 {
+        let now = self.read_time().to_unix_timestamp().unwrap_or(0);
+        self.last_unix_timestamp = core::cmp::max(self.last_unix_timestamp, now);
+        self.last_unix_timestamp
+    }
+}
+
+impl Debug for CMOSDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CMOSDevice")
+            .field("index", &self.index)
+            .field("data", &self.data)
+            .field("last_unix_timestamp", &self.last_unix_timestamp)
+            .finish()
+    }
+}
+
+impl X86Default for CMOSDevice {
+    unsafe fn x86_default() -> Self {
+        Self::new(KernPointer::<u8>::from_port(0x70), KernPointer::<u8>::from_port(0x71))
+    }
+}
+
+// Returns: The current unix timestamp, or 0 if the rtc hasn't been initialised yet
+pub fn now_unix() -> u64 {
+    let mut rtc = RTC.lock();
+    if !rtc.is_initialised() {
+        return 0;
+    }
+    rtc.now_unix()
+}
@@ -1,5 +1,5 @@
-use crate::{Mutex, primitives::{LazyInitialised, MutexGuard}, process::{Process, ProcessState, WaitInformation, ProcessPipe, ProcessSignal, WaitAction}, UART};
-use alloc::{vec::Vec, collections::VecDeque};
+use crate::{Mutex, emulator::CpuException, primitives::{LazyInitialised, MutexGuard}, process::{Process, ProcessState, MAX_PRIORITY, WaitInformation, ProcessPipe, ProcessSignal, WaitAction}, UART, terminal::TERMINAL};
+use alloc::{vec::Vec, collections::VecDeque, string::String};
 use rlibc::sys::SignalType;
 
 static TASK_LIST: Mutex<LazyInitialised<Vec<Option<Process>>>> = Mutex::from(LazyInitialised::uninit());
@@ -10,6 +10,25 @@ static NEW_TASK_LIST: Mutex<LazyInitialised<Vec<Option<Process>>>> = Mutex::from
 
 pub static PIPES: Mutex<LazyInitialised<Vec<Option<ProcessPipe>>>> = Mutex::from(LazyInitialised::uninit());
 
+// How the processes that had no parent ended, so that whoever started them can report it
+static FINISHED_TASKS: Mutex<LazyInitialised<Vec<FinishedTask>>> = Mutex::from(LazyInitialised::uninit());
+
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: usize,
+    pub parent_pid: Option<usize>,
+    pub name: String,
+    pub state: ProcessState,
+    pub priority: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FinishedTask {
+    pub pid: usize,
+    pub name: String,
+    pub action: WaitAction,
+}
+
 // A global list of queues of signals for processes
 pub static SIGNAL_QUEUES: Mutex<LazyInitialised<Vec<Option<VecDeque<ProcessSignal>>>>> = Mutex::from(LazyInitialised::uninit());
 
@@ -21,6 +40,7 @@ pub fn init() {
     NUMBER_OF_TASKS.lock().set(0);
     PIPES.lock().set(Vec::new());
     SIGNAL_QUEUES.lock().set(Vec::new());
+    FINISHED_TASKS.lock().set(Vec::new());
 }
 
 // Returns: The new processes pid
@@ -41,7 +61,71 @@ fn move_new_tasks_into_list(list: &mut MutexGuard<LazyInitialised<Vec<Option<Pro
 }
 
 
+// Returns: A snapshot of all the processes that currently exist, including the ones that were just created
+pub fn list_tasks() -> impl Iterator<Item = ProcessInfo> {
+    let list = TASK_LIST.lock();
+    let new_list = NEW_TASK_LIST.lock();
+    let infos = list.iter().chain(new_list.iter()).filter_map(|process| process.as_ref()).map(|process| ProcessInfo {
+        pid: process.data.pid.unwrap_or(0),
+        parent_pid: process.data.parent_pid,
+        name: process.data.name.clone(),
+        state: process.data.state.clone(),
+        priority: process.data.priority,
+    }).collect::<Vec<ProcessInfo>>();
+    infos.into_iter()
+}
+
+// Same as list_tasks, but for code that can be reached from a syscall, where the scheduler is already holding the list because it's in the middle of a tick
+// Returns: None if the list is being used right now
+pub fn try_list_tasks() -> Option<impl Iterator<Item = ProcessInfo>> {
+    if TASK_LIST.is_locked() || NEW_TASK_LIST.is_locked() {
+        return None;
+    }
+    Some(list_tasks())
+}
+
+// Returns: How all the processes without a parent that finished since the last call ended, in the order they finished
+pub fn take_finished_tasks() -> Vec<FinishedTask> {
+    core::mem::take(&mut **FINISHED_TASKS.lock())
+}
+
+// Runs the scheduler until the process with the given pid finishes, calling on_tick after every tick
+// NOTE: Only works for processes without a parent, the ones with a parent are waited for by their parent using the wait syscall
+// NOTE: The other processes keep running while waiting, and the ones that finish are still returned by take_finished_tasks
+// Returns: How the process ended, None if there is no process with that pid
+pub fn wait(pid: usize, mut on_tick: impl FnMut()) -> Option<WaitAction> {
+    loop {
+        {
+            let mut finished_tasks = FINISHED_TASKS.lock();
+            if let Some(index) = finished_tasks.iter().position(|task| task.pid == pid) {
+                return Some(finished_tasks.remove(index).action);
+            }
+        }
+
+        // NOTE: A process is only removed from the list after it's recorded as finished, so if it's gone it never existed
+        let is_waitable = |process: &Option<Process>| process.as_ref().map(|process| process.data.pid == Some(pid) && process.data.parent_pid.is_none()).unwrap_or(false);
+        let exists = TASK_LIST.lock().iter().any(is_waitable) || NEW_TASK_LIST.lock().iter().any(is_waitable);
+        if !exists {
+            return None;
+        }
+
+        tick();
+        on_tick();
+    }
+}
+
+// Sets how many instructions the process gets to execute every tick, it's clamped to [1, MAX_PRIORITY]
+// Returns None if pid is invalid
+pub fn set_priority(pid: usize, priority: usize) -> Option<()> {
+    let mut list = TASK_LIST.lock();
+    move_new_tasks_into_list(&mut list);
+    let process = list.get_mut(pid.checked_sub(1)?)?.as_mut()?;
+    process.data.priority = priority.clamp(1, MAX_PRIORITY);
+    Some(())
+}
+
 // Queues a signal to be received by the program on the next tick
+// NOTE: The queue is emptied before the program gets to run again, so a SIGKILL takes effect before it can execute another instruction
 // Returns None if pid is invalid
 pub fn kill_task(pid: usize, signal: ProcessSignal) -> Option<()>{
     let mut signals = SIGNAL_QUEUES.lock();
@@ -52,6 +136,66 @@ pub fn kill_task(pid: usize, signal: ProcessSignal) -> Option<()>{
 }
 
 
+// Prints the instruction profile of a process to the terminal, if it was profiled
+fn report_instruction_profile(pid: usize, process: &Process) {
+    if let Some(profile) = process.emu.instruction_profile() {
+        use core::fmt::Write;
+        writeln!(TERMINAL.lock(), "Instruction profile of process with pid {}:\n{}", pid, profile).unwrap();
+    }
+}
+
+// Keeps track of how a process that is about to be deallocated ended, if it has no parent to tell
+fn record_finished_task(process: &Process) {
+    if process.data.parent_pid.is_some() {
+        return; // The parent already found out using wait
+    }
+    let action = match process.data.state {
+        ProcessState::TERMINATED_NORMALLY_WAITING_TO_BE_DEALLOCATED { exit_code } => WaitAction::EXITED { exit_code },
+        ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal } => WaitAction::TERMINATED_BY_SIGNAL { signal },
+        _ => return,
+    };
+    FINISHED_TASKS.lock().push(FinishedTask { pid: process.data.pid.unwrap_or(0), name: process.data.name.clone(), action });
+}
+
+// Removes the process at index i from the list, which frees all of its memory ( including the memory it got from the PROGRAM_ALLOCATOR )
+fn deallocate_task(list: &mut MutexGuard<LazyInitialised<Vec<Option<Process>>>>, i: usize) {
+    record_finished_task(list[i].as_ref().unwrap());
+    list[i] = None;
+
+    // Drain None's if it wouldn't affect the indices of elements that are Some
+    while list.last().map(|val|val.is_none()).unwrap_or(false) { list.pop(); }
+    list.shrink_to_fit();
+    // NOTE: The pids that were drained get given out again, so the next new task's pid has to match the index it will be moved to
+    **NUMBER_OF_TASKS.lock() = list.len() + NEW_TASK_LIST.lock().len();
+
+    // Remove our signal queue
+    let mut signals = SIGNAL_QUEUES.lock();
+    signals[i] = None;
+
+    // Drain None's if it wouldn't affect the indices of elements that are Some
+    while signals.last().map(|val|val.is_none()).unwrap_or(false) { signals.pop(); }
+    signals.shrink_to_fit();
+}
+
+// Terminates a process right away, as if it received a SIGKILL, instead of on its next tick
+// NOTE: If it has a parent it becomes a zombie until the parent waits for it, otherwise it's deallocated immediately
+// WARNING: Can't be called during a tick ( for eg. from a syscall ), use kill_task there instead
+// Returns None if pid is invalid
+pub fn terminate_task(pid: usize) -> Option<()> {
+    let mut list = TASK_LIST.lock();
+    move_new_tasks_into_list(&mut list);
+    let i = pid.checked_sub(1)?;
+    let process = list.get_mut(i)?.as_mut()?;
+    process.recive_signal(ProcessSignal { signal_type: SignalType::SIGKILL });
+
+    match process.data.state {
+        ProcessState::TERMINATED_NORMALLY_WAITING_TO_BE_DEALLOCATED { exit_code: _ }
+        | ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal: _ } => deallocate_task(&mut list, i),
+        _ => (),
+    }
+    Some(())
+}
+
 pub fn tick() -> bool {
     let mut list = TASK_LIST.lock();
     move_new_tasks_into_list(&mut list); // Since we have a lock might as well make sure we have all the tasks in one list
@@ -69,8 +213,19 @@ pub fn tick() -> bool {
 
         match list[i].as_ref().unwrap().data.state {
             ProcessState::RUNNING | crate::process::ProcessState::RUNNING_NEW_CHILD_JUST_FORKED  | ProcessState::FINISHED_WAITING_FOR_CHILD_PROCESS(_) => {
-                if list[i].as_mut().unwrap().tick().is_none() { // Program ended due to illegal instruction or another type of exception
-                    kill_task(i+1, ProcessSignal { signal_type: SignalType::SIGILL });
+                // Weighted round-robin, a process gets as many instructions as its priority, unless it stops running before that, for eg. because it started waiting
+                let process = list[i].as_mut().unwrap();
+                for _ in 0..process.data.priority {
+                    if process.tick().is_none() { // Program ended due to illegal instruction or another type of exception
+                        // Memory exceptions are reported as segfaults, tight loops and everything else are treated as an illegal instruction
+                        let signal_type = match process.emu.take_exception() {
+                            Some(CpuException::TIGHT_LOOP { addr: _ }) | None => SignalType::SIGILL,
+                            Some(_) => SignalType::SIGSEGV,
+                        };
+                        kill_task(i+1, ProcessSignal { signal_type });
+                        break;
+                    }
+                    if !matches!(process.data.state, ProcessState::RUNNING) { break; }
                 }
                     
                 move_new_tasks_into_list(&mut list); // In case the process called a syscall which created a new process like fork, move the new process into the list
@@ -186,37 +341,15 @@ pub fn tick() -> bool {
             ProcessState::TERMINATED_NORMALLY_WAITING_TO_BE_DEALLOCATED{exit_code} => {
                 use core::fmt::Write;
                 writeln!(UART.lock(), "State after process ended normally with code 0x{:x}: {:?}", exit_code, list[i].as_ref().unwrap()).unwrap();
-                list[i] = None;
-
-                // Drain None's if it wouldn't affect the indices of elements that are Some
-                while list.last().map(|val|val.is_none()).unwrap_or(false) { list.pop(); }
-                list.shrink_to_fit();
-
-                // Remove our signal queue
-                let mut signals = SIGNAL_QUEUES.lock();
-                signals[i] = None;
-
-                // Drain None's if it wouldn't affect the indices of elements that are Some
-                while signals.last().map(|val|val.is_none()).unwrap_or(false) { signals.pop(); }
-                signals.shrink_to_fit();
+                report_instruction_profile(i+1, list[i].as_ref().unwrap());
+                deallocate_task(&mut list, i);
             }
 
             ProcessState::TERMINATED_DUE_TO_SIGNAL_WAITING_TO_BE_DEALLOCATED { signal } => {
                 use core::fmt::Write;
                 writeln!(UART.lock(), "State after process ended due to signal {:?}: {:?}", signal, list[i].as_ref().unwrap()).unwrap();
-                list[i] = None;
-
-                // Drain None's if it wouldn't affect the indices of elements that are Some
-                while list.last().map(|val|val.is_none()).unwrap_or(false) { list.pop(); }
-                list.shrink_to_fit();
-
-                // Remove our signal queue
-                let mut signals = SIGNAL_QUEUES.lock();
-                signals[i] = None;
-
-                // Drain None's if it wouldn't affect the indices of elements that are Some
-                while signals.last().map(|val|val.is_none()).unwrap_or(false) { signals.pop(); }
-                signals.shrink_to_fit();
+                report_instruction_profile(i+1, list[i].as_ref().unwrap());
+                deallocate_task(&mut list, i);
             }
         }
         
@@ -1,19 +1,20 @@
 use core::{
+    cell::RefCell,
     convert::{TryFrom, TryInto},
     ptr::null_mut
 };
 
-use alloc::{vec::Vec, string::String, borrow::ToOwned, collections::{BTreeMap, VecDeque}};
+use alloc::{vec::Vec, string::String, borrow::ToOwned, collections::{BTreeMap, VecDeque}, rc::Rc};
 use rlibc::sys::O_RDONLY;
-use rlibc::sys::SyscallNumber;
+use rlibc::sys::{SignalType, SyscallNumber, SIG_DFL, SIG_ERR, SIG_IGN};
 
 use crate::{
-    hio::{KeyboardPacketType, standard_usa_qwerty},
-    process::{FdMapping, ProcessData, ProcessNode, Emulator, Process, ProcessState, ProcessPipe},
+    pty,
+    process::{FdMapping, ProcessData, ProcessNode, Emulator, Process, ProcessState, ProcessPipe, ProcessSignal, SignalDisposition},
     ps2_8042::KEYBOARD_INPUT,
     vfs::{self, Path},
     virtmem::{self, UserPointer, VirtualMemory},
-    UART, allocator::{ProgramBasicAlloc, self, BasicAlloc}, scheduler, emulator::CpuAction, elf::{ElfFile, elf_header}, terminal::TERMINAL,
+    UART, allocator::{self, BasicAlloc}, scheduler, emulator::CpuAction, elf::{ElfFile, elf_header}, terminal::TERMINAL,
 };
 
 /* TODO: Add errno to program
@@ -178,6 +179,38 @@ pub fn syscall_entry_point(emu: &mut Emulator, proc_data: &mut ProcessData) -> C
             return_value(res as i64 as u64, emu)
         }
 
+        SyscallNumber::Fstat => {
+            let res = fstat(emu, proc_data, argument_1() as usize, unsafe{virtmem::UserPointer::<[u8]>::from_mem(argument_2())});
+            return_value(res as i64 as u64, emu)
+        }
+
+        SyscallNumber::Mmap => {
+            let val = mmap(emu, proc_data, argument_2() as usize, argument_3() as usize, argument_4() as usize, argument_5() as i32, argument_6());
+            return_value(val, emu)
+        }
+
+        SyscallNumber::Munmap => {
+            let val = munmap(emu, proc_data, argument_1(), argument_2() as usize);
+            return_value(val as i64 as u64, emu)
+        }
+
+        SyscallNumber::AlignedAlloc => {
+            let val = aligned_alloc(emu, proc_data, argument_1() as usize, argument_2() as usize);
+            return_value(val, emu)
+        }
+
+        SyscallNumber::Sigaction => {
+            let val = sigaction(proc_data, argument_1() as usize, argument_2(), argument_3());
+            return_value(val, emu)
+        }
+
+        SyscallNumber::Sigreturn => {
+            if sigreturn(emu, proc_data).is_some() {
+                return CpuAction::REPEAT_INSTRUCTION; // The program continues from the instruction it was at when the signal came, not from after this syscall
+            }
+            return_value(-1i64 as u64, emu)
+        }
+
         SyscallNumber::MaxValue => (),
     }
 
@@ -207,10 +240,21 @@ fn write(emu: &mut Emulator, proc_data: &mut ProcessData, fd: usize, user_buf: U
             }
 
             if let vfs::Node::File(f) = node.vfs_node.clone() {
+                if (*f).borrow().is_character_device() {
+                    // Character devices have no size or position, so just hand them the data
+                    let Some(written) = (*f).borrow_mut().write(0, buf) else { return -1 };
+                    return written as i32;
+                }
+
                 if node.flags & rlibc::sys::O_APPEND != 0 {
                     // Before each write, the file offset is positioned at the end of the file
                     // Source: man open
                     node.cursor = (*f).borrow().get_size();
+                    let Some(inc) = (*f).borrow_mut().append(buf) else {
+                        return -1;
+                    };
+                    node.cursor += inc as u64;
+                    return inc as i32;
                 }
 
                 // Make sure file is big enough
@@ -247,9 +291,8 @@ fn write(emu: &mut Emulator, proc_data: &mut ProcessData, fd: usize, user_buf: U
 
         FdMapping::Stdout | crate::process::FdMapping::Stderr => {
             use core::fmt::Write;
-            let Ok(str_buf) = core::str::from_utf8(buf) else {
-                return -1;
-            };
+            // NOTE: Programs don't have to write whole utf-8 characters at a time, so don't fail the whole write because of it
+            let str_buf = String::from_utf8_lossy(buf);
 
             let res = write!(TERMINAL.lock(), "{}", str_buf);
             if res.is_err() {
@@ -276,6 +319,17 @@ fn read(emu: &mut Emulator, proc_data: &mut ProcessData, fd: usize, user_buf: Us
             }
 
             if let vfs::Node::File(f) = node.vfs_node.clone() {
+                if (*f).borrow().is_character_device() {
+                    let Some(data) = (*f).borrow().try_read(0, buf.len()) else {
+                        if node.flags & rlibc::sys::O_NONBLOCK != 0 {
+                            return Some(-1);
+                        }
+                        return None; // Nothing to read yet, so block by repeating the read until there is
+                    };
+                    buf[..data.len()].copy_from_slice(&data);
+                    return Some(data.len() as i32);
+                }
+
                 let mut index: u64 = 0;
                 while index < buf.len() as u64 {
                     // Make sure we don't pass file boundaries
@@ -332,19 +386,24 @@ fn read(emu: &mut Emulator, proc_data: &mut ProcessData, fd: usize, user_buf: Us
                 return Some(1);
             }
 
-            loop {
-                // We only allow applications to read one character from stdin at a time to stop the keyboard from being hogged by applications
-                // FIXME: Implement better drivers
-                let packet = unsafe { KEYBOARD_INPUT.lock().read_packet() };
-                if packet.packet_type == KeyboardPacketType::KeyReleased {
-                    continue;
+            // We only allow applications to read one character from stdin at a time to stop the keyboard from being hogged by applications
+            // NOTE: Only takes the keys that are already there, if that's not enough for a whole char the read is repeated later,
+            // so that waiting for input doesn't stop everything else from running
+            // FIXME: Implement better drivers
+            while let Some(packet) = unsafe { KEYBOARD_INPUT.lock().try_read_packet() } {
+                pty::pass_key_to_kernel_terminal(packet, proc_data.pid);
+                if let Some(c) = TERMINAL.lock().read_char() {
+                    buf[0] = c as u8;
+                    return Some(1);
                 }
-                TERMINAL.lock().recive_key(packet.key, packet.modifiers);
+            }
+            while TERMINAL.lock().try_recive_serial_input().is_some() {
                 if let Some(c) = TERMINAL.lock().read_char() {
                     buf[0] = c as u8;
                     return Some(1);
                 }
             }
+            None
         }
 
         FdMapping::Stdout | FdMapping::Stderr => return Some(-1), // Can't read stdout or stderr
@@ -353,13 +412,7 @@ fn read(emu: &mut Emulator, proc_data: &mut ProcessData, fd: usize, user_buf: Us
 
 fn open(emu: &mut Emulator, proc_data: &mut ProcessData, pathname: virtmem::UserPointer<[u8]>, flags: usize) -> isize {
     let Some(path) = virtmem::cstr_user_pointer_to_str(pathname, &emu.memory) else { return -1 };
-    let path = if let Ok(val) = vfs::Path::try_from(path) { val } else { 
-        // Maybe the pathname is relative to cwd
-        let mut current = proc_data.cwd.clone();
-        current.append_str(path);
-        current
-    };
-    let path = path.canonicalize();
+    let Ok(path) = vfs::resolve(&proc_data.cwd, path) else { return -1 };
     // Get directory containing node
     let node = 
     if let Some(node_to_search_for_name) = path.last(){
@@ -499,28 +552,27 @@ fn lseek(proc_data: &mut ProcessData, fd: usize, offset: i64, whence: usize) ->
     match node_mapping {
         FdMapping::Regular(node_index) => {
             let node = proc_data.open_nodes[node_index].as_mut().unwrap();
-            match whence {
-                rlibc::sys::SEEK_SET => {
-                    node.cursor = offset as u64;
-                    return node.cursor as i64;
-                }
-
-                rlibc::sys::SEEK_CUR => {
-                    node.cursor = ((node.cursor as i64) + offset) as u64;
-                    return node.cursor as i64;
-                }
-
+            let new_cursor = match whence {
+                rlibc::sys::SEEK_SET => offset,
+                rlibc::sys::SEEK_CUR => (node.cursor as i64) + offset,
                 rlibc::sys::SEEK_END => {
                     if let vfs::Node::File(f) = &node.vfs_node {
-                        node.cursor = ((f.borrow().get_size() as i64) + offset) as u64;
-                        return node.cursor as i64;
+                        (f.borrow().get_size() as i64) + offset
                     } else {
                         return -1;
                     }
                 }
 
                 _ => return -1,
+            };
+
+            // EINVAL: ... the resulting file offset would be negative
+            // Source: man lseek
+            if new_cursor < 0 {
+                return -1;
             }
+            node.cursor = new_cursor as u64;
+            return node.cursor as i64;
         }
 
         FdMapping::Stdin | FdMapping::Stdout | FdMapping::Stderr => return -1,
@@ -531,9 +583,58 @@ fn lseek(proc_data: &mut ProcessData, fd: usize, offset: i64, whence: usize) ->
     }
 }
 
+fn fstat(emu: &mut Emulator, proc_data: &mut ProcessData, fd: usize, statbuf: UserPointer<[u8]>) -> isize {
+    use rlibc::sys::{S_IFCHR, S_IFDIR, S_IFIFO, S_IFREG};
+    let Some(Some(node_mapping)) = proc_data.fd_mappings.get(fd).cloned() else { return -1 };
+
+    let (st_mode, st_size) = match node_mapping {
+        FdMapping::Regular(node_index) => {
+            let node = proc_data.open_nodes[node_index].as_ref().unwrap();
+            match &node.vfs_node {
+                vfs::Node::File(f) if f.borrow().is_character_device() => (S_IFCHR, 0),
+                vfs::Node::File(f) => (S_IFREG, f.borrow().get_size()),
+                vfs::Node::Folder(_) => (S_IFDIR, 0),
+            }
+        }
+
+        FdMapping::PipeReadEnd(pipe_index) | FdMapping::PipeWriteEnd(pipe_index) => {
+            let pipes = scheduler::PIPES.lock();
+            (S_IFIFO, pipes[pipe_index].as_ref().unwrap().buf.len() as u64)
+        }
+
+        FdMapping::Stdin | FdMapping::Stdout | FdMapping::Stderr => (S_IFCHR, 0),
+    };
+
+    let stat = rlibc::sys::stat { st_mode, st_reserved: 0, st_size: st_size as core::ffi::c_long };
+    let Some(buf) = statbuf.try_as_mut(&mut emu.memory, core::mem::size_of::<rlibc::sys::stat>()) else { return -1 };
+    // SAFETY: stat is repr(C), has no padding and the guest is little endian just like us
+    buf.copy_from_slice(unsafe { core::slice::from_raw_parts(&stat as *const _ as *const u8, core::mem::size_of::<rlibc::sys::stat>()) });
+    return 0;
+}
+
 fn malloc(emu: &mut Emulator, proc_data: &mut ProcessData, size: usize) -> u64 {
-    // We also allocate size_of::<usize>() bytes more than we are requested to, to store the size of the allocation
-    let Ok(allocation_info) = core::alloc::Layout::from_size_align(size + core::mem::size_of::<usize>(), 8) else {
+    aligned_alloc(emu, proc_data, core::mem::size_of::<usize>(), size)
+}
+
+// The size of the allocation is stored at the start of it, and the pointer that is returned comes after that, at an offset of alignment bytes,
+// so that it's aligned as long as the start is, for malloc that's just enough room for the size
+// NOTE: The size counts the header as well
+fn allocation_header_len(alignment: usize) -> usize {
+    core::cmp::max(alignment, core::mem::size_of::<usize>())
+}
+
+// Returns: NULL if alignment isn't a power of two
+// Source: man aligned_alloc
+fn aligned_alloc(emu: &mut Emulator, proc_data: &mut ProcessData, alignment: usize, size: usize) -> u64 {
+    if !alignment.is_power_of_two() {
+        return virtmem::USERSPACE_NULL_PTR;
+    }
+    let header_len = allocation_header_len(alignment);
+    // We also allocate header_len bytes more than we are requested to, to store the size of the allocation
+    let Some(total_size) = size.checked_add(header_len) else {
+        return virtmem::USERSPACE_NULL_PTR;
+    };
+    let Ok(allocation_info) = core::alloc::Layout::from_size_align(total_size, header_len) else {
         return virtmem::USERSPACE_NULL_PTR;
     };
 
@@ -554,7 +655,7 @@ fn malloc(emu: &mut Emulator, proc_data: &mut ProcessData, size: usize) -> u64 {
     // Create mapping
     emu.memory.add_region(virtual_allocation_ptr, physical_allocation);
 
-    return virtual_allocation_ptr+core::mem::size_of::<usize>() as u64;
+    return virtual_allocation_ptr+header_len as u64;
 }
 
 fn free(emu: &mut Emulator, proc_data: &mut ProcessData, virtual_ptr: u64) {
@@ -570,28 +671,167 @@ fn free(emu: &mut Emulator, proc_data: &mut ProcessData, virtual_ptr: u64) {
     };
 
     let alloc_region_index = mapped_alloc.1.region_index;
+    // NOTE: The header can be bigger than a usize if the allocation came from aligned_alloc, so the start of the allocation is found from the region
+    let allocation_ptr = virtual_ptr - mapped_alloc.1.offset_in_region as u64;
     emu.memory.remove_region(alloc_region_index);
 
-    proc_data.virtual_allocator.dealloc((virtual_ptr-core::mem::size_of::<usize>() as u64) as *mut u8, allocation_info);
+    proc_data.virtual_allocator.dealloc(allocation_ptr as *mut u8, allocation_info);
 }
 
+// Source: man realloc
 fn realloc(emu: &mut Emulator, proc_data: &mut ProcessData, virtual_ptr: u64, new_size: usize) -> u64 {
+    // If ptr is NULL, then the call is equivalent to malloc(size)
+    if virtual_ptr == virtmem::USERSPACE_NULL_PTR {
+        return malloc(emu, proc_data, new_size);
+    }
+    // If size is equal to zero, and ptr is not NULL, then the call is equivalent to free(ptr)
+    if new_size == 0 {
+        free(emu, proc_data, virtual_ptr);
+        return virtmem::USERSPACE_NULL_PTR;
+    }
+
     // Translate pointer from user space
     let Some(mapped_alloc) = emu.memory.try_map_mut(virtual_ptr) else {
         return virtmem::USERSPACE_NULL_PTR;
     };
+    // Only pointers that malloc or aligned_alloc returned can be reallocated, and those always point right after the header,
+    // which is a power of two at least a usize big, that the pointer is aligned to
+    let header_len = mapped_alloc.1.offset_in_region;
+    if header_len != allocation_header_len(header_len) || !header_len.is_power_of_two() || virtual_ptr % header_len as u64 != 0 {
+        return virtmem::USERSPACE_NULL_PTR;
+    }
 
-    let data: Vec<u8, &'static ProgramBasicAlloc> = mapped_alloc.0.backing_storage.clone();
-    free(emu, proc_data, virtual_ptr);
+    let size = usize::from_le_bytes(mapped_alloc.0.backing_storage[0..core::mem::size_of::<usize>()].try_into().unwrap());
+    let Ok(allocation_info) = core::alloc::Layout::from_size_align(size, 8) else {
+        return virtmem::USERSPACE_NULL_PTR;
+    };
+    let Some(new_allocation_size) = new_size.checked_add(header_len) else {
+        return virtmem::USERSPACE_NULL_PTR;
+    };
+
+    // If the virtual space after the allocation is free ( or if it shrinks ), the allocation can just change size where it is
+    let allocation_ptr = (virtual_ptr - header_len as u64) as *mut u8;
+    if proc_data.virtual_allocator.resize_in_place(allocation_ptr, allocation_info, new_allocation_size) {
+        mapped_alloc.0.backing_storage.resize(new_allocation_size, 0u8);
+        for (index, byte) in new_allocation_size.to_le_bytes().iter().enumerate() {
+            mapped_alloc.0.backing_storage[index] = *byte;
+        }
+        return virtual_ptr;
+    }
+
+    // Otherwise it has to be moved, but if that fails the original block is left untouched
+    // NOTE: The new block is only aligned like malloc's are, the alignment that aligned_alloc was asked for isn't kept
+    // Source: man realloc
+    let data = mapped_alloc.0.backing_storage[header_len..core::cmp::min(size, new_allocation_size)].to_vec();
     let new_virtual_ptr = malloc(emu, proc_data, new_size);
-    // Translate pointer from user space
     let Some(new_mapped_alloc) = emu.memory.try_map_mut(new_virtual_ptr) else {
         return virtmem::USERSPACE_NULL_PTR;
     };
-    new_mapped_alloc.0.backing_storage = data;
+    new_mapped_alloc.0.backing_storage[core::mem::size_of::<usize>()..][..data.len()].copy_from_slice(&data);
+    free(emu, proc_data, virtual_ptr);
     return new_virtual_ptr;
 }
 
+// NOTE: Mappings are aligned to a page, even though nothing actually uses pages, so that they look like the mappings programs expect
+const MMAP_ALIGNMENT: usize = 4096;
+
+// Anonymous mappings are zeroed memory that isn't backed by anything, file mappings start out with a copy of the part of the file at offset
+// NOTE: File mappings have to be private, so writing to them never changes the file
+// FIXME: Support shared file mappings, which would be written back on munmap
+// Source: man mmap
+fn mmap(emu: &mut Emulator, proc_data: &mut ProcessData, length: usize, prot: usize, flags: usize, fd: i32, offset: u64) -> u64 {
+    use rlibc::sys::{MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE};
+
+    // If addr is NULL, then the kernel chooses the (page-aligned) address at which to create the mapping
+    // NOTE: addr is always treated as NULL, as it's only a hint anyways
+    if length == 0 {
+        return MAP_FAILED as u64;
+    }
+    let file = if flags & MAP_ANONYMOUS != 0 {
+        if fd != -1 {
+            return MAP_FAILED as u64;
+        }
+        None
+    } else {
+        // offset must be a multiple of the page size
+        if flags & MAP_PRIVATE == 0 || offset % MMAP_ALIGNMENT as u64 != 0 {
+            return MAP_FAILED as u64;
+        }
+        let Some(file) = get_mappable_file(proc_data, fd) else {
+            return MAP_FAILED as u64;
+        };
+        Some(file)
+    };
+
+    let Ok(layout) = core::alloc::Layout::from_size_align(length, MMAP_ALIGNMENT) else {
+        return MAP_FAILED as u64;
+    };
+
+    // The contents of a mapping are initialized to zero
+    let mut physical_allocation = Vec::new_in(&allocator::PROGRAM_ALLOCATOR);
+    physical_allocation.clear();
+    physical_allocation.resize(length, 0u8);
+
+    // The part of the mapping that is past the end of the file stays zeroed
+    if let Some(file) = file.filter(|file| file.borrow().get_size() > offset) {
+        let file = file.borrow();
+        let file_len = (file.get_size() - offset).min(length as u64) as usize;
+        let Some(data) = file.read(offset, file_len) else {
+            return MAP_FAILED as u64;
+        };
+        physical_allocation[..data.len()].copy_from_slice(&data);
+    }
+
+    let virtual_ptr = proc_data.virtual_allocator.alloc(layout) as u64;
+    if virtual_ptr == virtmem::USERSPACE_NULL_PTR { return MAP_FAILED as u64; }
+
+    let permissions = virtmem::RegionPermissions {
+        readable: prot & PROT_READ != 0,
+        writable: prot & PROT_WRITE != 0,
+        executable: prot & PROT_EXEC != 0,
+    };
+    if emu.memory.add_region_with_permissions(virtual_ptr, physical_allocation, permissions).is_none() {
+        proc_data.virtual_allocator.dealloc(virtual_ptr as *mut u8, layout);
+        return MAP_FAILED as u64;
+    }
+    proc_data.mappings.insert(virtual_ptr, length);
+    virtual_ptr
+}
+
+// Returns: The file fd refers to, None if fd isn't open for reading or isn't a regular file, since character devices and pipes have nothing to map
+fn get_mappable_file(proc_data: &ProcessData, fd: i32) -> Option<Rc<RefCell<dyn vfs::IFile>>> {
+    let Some(FdMapping::Regular(node_index)) = proc_data.fd_mappings.get(usize::try_from(fd).ok()?)?.as_ref() else {
+        return None;
+    };
+    let node = proc_data.open_nodes.get(*node_index)?.as_ref()?;
+    if node.flags & O_RDONLY == 0 {
+        return None;
+    }
+    match &node.vfs_node {
+        vfs::Node::File(f) if !f.borrow().is_character_device() => Some(f.clone()),
+        _ => None,
+    }
+}
+
+// NOTE: Only whole mappings can be removed, unlike on linux where any range of pages can be
+fn munmap(emu: &mut Emulator, proc_data: &mut ProcessData, virtual_ptr: u64, length: usize) -> isize {
+    if proc_data.mappings.get(&virtual_ptr) != Some(&length) {
+        return -1;
+    }
+
+    let Some((_, mapping_info)) = emu.memory.try_map(virtual_ptr) else {
+        return -1;
+    };
+    emu.memory.remove_region(mapping_info.region_index);
+    proc_data.mappings.remove(&virtual_ptr);
+
+    let Ok(layout) = core::alloc::Layout::from_size_align(length, MMAP_ALIGNMENT) else {
+        return -1;
+    };
+    proc_data.virtual_allocator.dealloc(virtual_ptr as *mut u8, layout);
+    0
+}
+
 fn getcwd(emu: &mut Emulator, proc_data: &mut ProcessData, virtual_ptr: virtmem::UserPointer<[u8]>, buf_size: usize) -> u64 {
     // On failure, these functions return NULL
     // Source: man getcwd
@@ -624,6 +864,11 @@ fn fchdir(proc_data: &mut ProcessData, fd: usize) -> isize {
     match node_mapping {
         FdMapping::Regular(node_index) => {
             let node = proc_data.open_nodes[node_index].as_mut().unwrap();
+            // ENOTDIR fd does not refer to a directory.
+            // Source: man fchdir
+            if !matches!(node.vfs_node, vfs::Node::Folder(_)) {
+                return -1;
+            }
             proc_data.cwd = node.path.clone();
             return 0;
         }
@@ -725,6 +970,36 @@ fn dup2(proc_data: &mut ProcessData, oldfd: usize, newfd: usize) -> isize {
     newfd as isize
 }
 
+// Source: man signal, man sigaction
+// Returns: The previous handler, SIG_DFL and SIG_IGN stand for the default disposition and for ignoring the signal, SIG_ERR on failure
+fn sigaction(proc_data: &mut ProcessData, signum: usize, handler: u64, restorer: u64) -> u64 {
+    let Some(signal_type) = u8::try_from(signum).ok().and_then(SignalType::from_number) else {
+        return SIG_ERR as u64;
+    };
+    if !(ProcessSignal { signal_type }).can_be_handled() {
+        return SIG_ERR as u64;
+    }
+
+    let disposition = match handler as usize {
+        SIG_DFL => SignalDisposition::DEFAULT,
+        SIG_IGN => SignalDisposition::IGNORE,
+        _ => SignalDisposition::HANDLER { handler, restorer },
+    };
+    match proc_data.signal_dispositions.insert(signum as u8, disposition).unwrap_or(SignalDisposition::DEFAULT) {
+        SignalDisposition::DEFAULT => SIG_DFL as u64,
+        SignalDisposition::IGNORE => SIG_IGN as u64,
+        SignalDisposition::HANDLER { handler, restorer: _ } => handler,
+    }
+}
+
+// Returns: None if no signal handler is running
+fn sigreturn(emu: &mut Emulator, proc_data: &mut ProcessData) -> Option<()> {
+    let frame = proc_data.signal_frames.pop()?;
+    emu.restore_context(frame.context);
+    proc_data.state = frame.state;
+    Some(())
+}
+
 fn fork(emu: &mut Emulator, proc_data: &mut ProcessData) -> usize {
     match proc_data.state {
         ProcessState::RUNNING_NEW_CHILD_JUST_FORKED => {
@@ -852,7 +1127,8 @@ pub fn exec(emu: &mut Emulator, proc_data: &mut ProcessData, node: vfs::Node, no
             return Err(-1);
         }
 
-        if elf.header.elf_type != elf_header::ElfType::EXECUTABLE {
+        // NOTE: SHARED is what position-independent executables use
+        if elf.header.elf_type != elf_header::ElfType::EXECUTABLE && elf.header.elf_type != elf_header::ElfType::SHARED {
             return Err(-1);
         }
 
@@ -861,7 +1137,7 @@ pub fn exec(emu: &mut Emulator, proc_data: &mut ProcessData, node: vfs::Node, no
         // First reset ourselves, past this point returning -1 is useless as the program would crash anyways
         // FIXME: Don't crash the program if we fail to load the new program
         emu.memory.clear_regions();
-        emu.reset_registers(elf.header.program_entry);
+        emu.reset_registers(Process::get_elf_load_base(&elf) + elf.header.program_entry);
 
         let Some(lower_virt_addr) = Process::load_elf_into_virtual_memory(&elf, &file_bytes, &mut emu.memory) else {
             exit( proc_data, 0xDED);
@@ -874,9 +1150,10 @@ pub fn exec(emu: &mut Emulator, proc_data: &mut ProcessData, node: vfs::Node, no
         program_stack.resize(PROGRAM_STACK_SIZE as usize, 0u8);
 
         // Add 8kb of stack space at the end of the virtual address space
-        let did_create_stack_region =  emu.memory.add_region(
+        let did_create_stack_region =  emu.memory.add_region_with_permissions(
             u64::MAX - (PROGRAM_STACK_SIZE) + 1,     /* +1 because the address itself is included in the region */
             program_stack, // NOTE: We don't use [] because that would allocate 1MB on the stack, then move it to the heap, which might overflow the stack
+            Process::get_stack_permissions(&elf),
         );
         if did_create_stack_region.is_none() { // We failed to add a stack region
             exit( proc_data, 0xDED);
@@ -886,6 +1163,10 @@ pub fn exec(emu: &mut Emulator, proc_data: &mut ProcessData, node: vfs::Node, no
         // Create virtual allocator for the heap, this manages the locations of allocations on the heap in the virtual space
         // Or just generally the location of segments in virtual space, this can't be done for some segments like the elf regions and the stack
         // as they require certain addresses
+        proc_data.mappings.clear();
+        // Handlers were part of the old program, so their signals go back to the default, ignored signals stay ignored, like on linux
+        proc_data.signal_dispositions.retain(|_, disposition| *disposition == SignalDisposition::IGNORE);
+        proc_data.signal_frames.clear();
         proc_data.virtual_allocator = BasicAlloc::from(lower_virt_addr as *mut u8, (u64::MAX - (PROGRAM_STACK_SIZE + lower_virt_addr)) as usize, true);
 
 
@@ -910,6 +1191,7 @@ pub fn exec(emu: &mut Emulator, proc_data: &mut ProcessData, node: vfs::Node, no
         };
 
         proc_data.env = prog_env;
+        proc_data.name = args.first().cloned().unwrap_or_default();
         
 
         // Setup argc and argv
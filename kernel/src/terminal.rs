@@ -3,9 +3,9 @@ use core::fmt::{Debug, Write};
 use alloc::{string::String, collections::VecDeque};
 
 use crate::{
-    char_device::CharDevice,
+    char_device::{CharDevice, Font, FONT},
     framebuffer::{FrameBuffer, Pixel},
-    primitives::{LazyInitialised, Mutex}, hio::{KeyboardKey, standard_usa_qwerty}, ps2_8042::KeyboardModifiers,
+    primitives::{LazyInitialised, Mutex}, hio::{self, KeyboardKey}, ps2_8042::KeyboardModifiers, pty::LineDisciplineSettings, UART,
 };
 
 pub static TERMINAL: Mutex<LazyInitialised<Terminal<'static>>> = Mutex::from(LazyInitialised::uninit());
@@ -15,7 +15,11 @@ pub struct Terminal<'a> {
     cursor_pos: (usize, usize),
     cursor_char: char,
     color: Pixel,
-    pub line_buffer: VecDeque<char>
+    pub line_buffer: VecDeque<char>,
+    // NOTE: The terminal has no notion of a foreground process, so Ctrl+C is turned into a signal by whoever passes it keys, see pty::pass_key_to_kernel_terminal
+    pub line_discipline: LineDisciplineSettings,
+    // Everything shown is also sent to the uart and input is also read from it, so the terminal can be used over a serial console
+    pub serial_console: bool,
 }
 
 impl Debug for Terminal<'_> {
@@ -24,6 +28,8 @@ impl Debug for Terminal<'_> {
             .field("cursor_pos", &self.cursor_pos)
             .field("cursor_char", &self.cursor_char)
             .field("color", &self.color)
+            .field("line_discipline", &self.line_discipline)
+            .field("serial_console", &self.serial_console)
             .finish()
     }
 }
@@ -37,7 +43,7 @@ impl<'a> Write for Terminal<'a> {
 
 impl<'a> Terminal<'a> {
     pub fn new(fb: &'a mut dyn FrameBuffer, color: Pixel) -> Self {
-        Terminal { fb, cursor_pos: (0, 0), cursor_char: ' ', color, line_buffer: VecDeque::new() }
+        Terminal { fb, cursor_pos: (0, 0), cursor_char: ' ', color, line_buffer: VecDeque::new(), line_discipline: LineDisciplineSettings::cooked(), serial_console: false }
     }
 
     pub fn clear(&mut self) {
@@ -47,6 +53,19 @@ impl<'a> Terminal<'a> {
             }
         }
         self.cursor_pos = (0, 0);
+        self.fb.present();
+    }
+
+    // NOTE: The screen is cleared, since what's on it was laid out for the old size of the characters
+    // Returns: None if not even one character of the font fits on the screen, in which case the font isn't changed
+    pub fn set_font(&mut self, font: Font) -> Option<()> {
+        if self.fb.get_width() < font.cell_width() || self.fb.get_height() < font.cell_height() {
+            return None;
+        }
+        *FONT.lock() = font;
+        self.clear();
+        self.update_visual_cursor();
+        Some(())
     }
 
     pub fn cursor_up(&mut self) {
@@ -107,15 +126,24 @@ impl<'a> Terminal<'a> {
         self.update_visual_cursor();
     }
 
+    // NOTE: Drawing the cursor is always the last thing a change to the terminal does, so this is also where what was drawn is presented
     fn update_visual_cursor(&mut self) {
         self.fb.write_char(self.cursor_pos.0, self.cursor_pos.1, '_', self.color);
+        self.fb.present();
     }
 
     fn erase_visual_cursor(&mut self) {
         self.fb.write_char(self.cursor_pos.0, self.cursor_pos.1, self.cursor_char, self.color);
     }
 
+    fn mirror_to_serial(&self, s: &str) {
+        if self.serial_console {
+            write!(UART.lock(), "{}", s).unwrap();
+        }
+    }
+
     pub fn write_char(&mut self, c: char) {
+        self.mirror_to_serial(c.encode_utf8(&mut [0; 4]));
         self.erase_visual_cursor(); // erase current cursor
         match c {
             '\n' => {
@@ -126,6 +154,8 @@ impl<'a> Terminal<'a> {
                 self.cursor_pos.0 = 0;
             }
 
+            '\x08' /* backspace */ => self.cursor_left(),
+
             _ => {
                 self.fb.write_char(self.cursor_pos.0, self.cursor_pos.1, c, self.color);
                 self.cursor_right();
@@ -135,7 +165,7 @@ impl<'a> Terminal<'a> {
     }
 
     pub fn read_char(&mut self) -> Option<char> {
-        if self.line_buffer.contains(&'\n') {
+        if !self.line_discipline.canonical || self.line_buffer.contains(&'\n') {
             return self.line_buffer.pop_front()
         }else{
             return None
@@ -143,10 +173,24 @@ impl<'a> Terminal<'a> {
     }
 
     pub fn recive_key(&mut self, key: KeyboardKey, modifiers: KeyboardModifiers) {
-        self.erase_visual_cursor(); // erase current cursor
         match key {
-            KeyboardKey::Enter => {
+            KeyboardKey::Enter => self.recive_char('\n'),
+            KeyboardKey::Backspace => self.recive_char('\x08'),
+            _ => {
+                let Ok(c) = hio::parse_key(key, modifiers) else { return; };
+                self.recive_char(c);
+            }
+        }
+    }
+
+    // Handles a character typed by the user, '\n' ends the line and '\x08' deletes the last character of it
+    pub fn recive_char(&mut self, c: char) {
+        self.erase_visual_cursor(); // erase current cursor
+        match c {
+            // NOTE: The newline is shown even if echo is off, otherwise whatever comes next would end up on the line that was typed on
+            '\n' => {
                 self.line_buffer.push_back('\n');
+                self.mirror_to_serial("\n");
                 self.cursor_down();
                 for x in 0..self.fb.get_cols() {
                     self.fb.write_char(x, self.cursor_pos.1, ' ', self.color);
@@ -154,21 +198,56 @@ impl<'a> Terminal<'a> {
                 self.cursor_pos.0 = 0;
             }
 
-            KeyboardKey::Backspace => {
-                self.cursor_left();
+            '\x08' /* backspace */ => {
                 // Make sure we can't delete previous lines
-                if self.line_buffer.pop_back() == Some('\n') {
-                    self.line_buffer.push_back('\n');
+                let deleted = match self.line_buffer.pop_back() {
+                    Some('\n') => {
+                        self.line_buffer.push_back('\n');
+                        false
+                    }
+                    deleted => deleted.is_some(),
+                };
+                if self.line_discipline.echo {
+                    self.cursor_left();
+                    if deleted {
+                        // A serial terminal only moves its cursor back on a backspace, so the character has to be overwritten too
+                        self.mirror_to_serial("\x08 \x08");
+                    }
                 }
             }
 
             _ => {
-                let Ok(c) = standard_usa_qwerty::parse_key(key, modifiers) else { return; };
                 self.line_buffer.push_back(c);
-                self.fb.write_char(self.cursor_pos.0, self.cursor_pos.1, c, self.color);
-                self.cursor_right();
+                if self.line_discipline.echo {
+                    self.mirror_to_serial(c.encode_utf8(&mut [0; 4]));
+                    self.fb.write_char(self.cursor_pos.0, self.cursor_pos.1, c, self.color);
+                    self.cursor_right();
+                }
             }
         }
         self.update_visual_cursor();
     }
+
+    // Reads a byte from the uart, if this is a serial console and there is one
+    // Returns: The character that was typed, None if there was nothing to read or the byte isn't something that can be typed
+    pub fn try_read_serial_char(&self) -> Option<char> {
+        if !self.serial_console {
+            return None;
+        }
+        let byte = UART.lock().read_byte()?;
+        match byte {
+            // Serial terminals usually send a carriage return for enter, and delete for backspace
+            b'\r' | b'\n' => Some('\n'),
+            0x08 | 0x7F => Some('\x08'),
+            b'\t' | b' '..=b'~' => Some(byte as char),
+            _ => None,
+        }
+    }
+
+    // Same as try_read_serial_char, but the character is also handled like a typed character
+    pub fn try_recive_serial_input(&mut self) -> Option<char> {
+        let c = self.try_read_serial_char()?;
+        self.recive_char(c);
+        Some(c)
+    }
 }
@@ -0,0 +1,141 @@
+use core::{any::Any, cell::RefCell, convert::TryFrom};
+
+use alloc::{collections::BTreeMap, rc::Rc, string::String, vec::Vec};
+
+use crate::vfs::{IFile, IFolder, Node, NodeType};
+
+// Note: This file defines tmpfs, a filesystem that lives entirely in memory, so everything in it is gone once it's unmounted,
+// it's useful as scratch space or for trying out the vfs without a disk
+// NOTE: Folders only point to their children, never back to their parent, so once nothing points to the root anymore the whole tree is freed
+
+pub struct TmpFSFolder {
+    children: BTreeMap<String, Node>,
+}
+
+impl TmpFSFolder {
+    pub fn new() -> Self {
+        Self { children: BTreeMap::new() }
+    }
+}
+
+pub struct TmpFSFile {
+    data: Vec<u8>,
+}
+
+impl IFile for TmpFSFile {
+    // Returns: None if the range goes past the end of the file
+    fn read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(len)?;
+        Some(self.data.get(start..end)?.to_vec())
+    }
+
+    // NOTE: Writing doesn't change the size of the file, so writes that don't fit fail, use resize to make room first
+    fn write(&mut self, offset: u64, data: &[u8]) -> Option<usize> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(data.len())?;
+        self.data.get_mut(start..end)?.copy_from_slice(data);
+        Some(data.len())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    // NOTE: New space is zeroed, and space that is cut off is given back to the heap
+    fn resize(&mut self, new_size: u64) -> Option<()> {
+        let new_size = usize::try_from(new_size).ok()?;
+        self.data.resize(new_size, 0);
+        if new_size < self.data.capacity() / 2 {
+            self.data.shrink_to_fit();
+        }
+        Some(())
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
+
+impl IFolder for TmpFSFolder {
+    fn get_children(&self) -> Vec<(String, Node)> {
+        self.children.iter().map(|(name, node)| (name.clone(), node.clone())).collect()
+    }
+
+    // Returns: None if there already is a child called name
+    fn create_empty_child(&mut self, name: &str, typ: NodeType) -> Option<Node> {
+        if name.is_empty() || name.contains('/') || self.children.contains_key(name) {
+            return None;
+        }
+        let node = match typ {
+            NodeType::File => Node::File(Rc::new(RefCell::new(TmpFSFile { data: Vec::new() }))),
+            NodeType::Folder => Node::Folder(Rc::new(RefCell::new(TmpFSFolder::new()))),
+        };
+        self.children.insert(name.into(), node.clone());
+        Some(node)
+    }
+
+    // NOTE: A file's contents are only freed once nothing uses it anymore, so files that are still open can be read after being deleted, like on unix
+    fn unlink_or_delete_empty_child(&mut self, name: &str) -> Option<()> {
+        if let Node::Folder(folder) = self.children.get(name)? {
+            if !folder.borrow().get_children().is_empty() {
+                return None;
+            }
+        }
+        self.children.remove(name)?;
+        Some(())
+    }
+
+    // NOTE: Anything can be moved between tmpfs folders, even ones mounted separately, since moving only changes which folder points to the child
+    fn can_rename_children_to(&mut self, new_parent: Option<&mut dyn IFolder>) -> bool {
+        match new_parent {
+            Some(new_parent) => new_parent.as_any_mut().is_some_and(|new_parent| new_parent.is::<TmpFSFolder>()),
+            None => true,
+        }
+    }
+
+    fn rename_child(&mut self, old_name: &str, new_parent: Option<&mut dyn IFolder>, new_name: &str) -> Option<()> {
+        let new_parent = match new_parent {
+            Some(new_parent) => Some(new_parent.as_any_mut()?.downcast_mut::<TmpFSFolder>()?),
+            None => None,
+        };
+        if new_name.is_empty() || new_name.contains('/') || !self.children.contains_key(old_name) {
+            return None;
+        }
+
+        match new_parent {
+            Some(new_parent) => {
+                if new_parent.children.contains_key(new_name) {
+                    return None;
+                }
+                let node = self.children.remove(old_name)?;
+                new_parent.children.insert(new_name.into(), node);
+            }
+            None => {
+                if self.children.contains_key(new_name) {
+                    return None;
+                }
+                let node = self.children.remove(old_name)?;
+                self.children.insert(new_name.into(), node);
+            }
+        }
+        Some(())
+    }
+
+    fn link_child(&mut self, existing: &Node, new_name: &str) -> Option<()> {
+        let Node::File(file) = existing else {
+            return None;
+        };
+        // Links can only be made to tmpfs files
+        file.borrow_mut().as_any_mut()?.downcast_mut::<TmpFSFile>()?;
+        if new_name.is_empty() || new_name.contains('/') || self.children.contains_key(new_name) {
+            return None;
+        }
+        self.children.insert(new_name.into(), existing.clone());
+        Some(())
+    }
+
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        Some(self)
+    }
+}
@@ -172,12 +172,33 @@ impl UARTDevice {
         }
     }
 
-    pub fn receive(&self) -> u8 {
-        unsafe {
-            wait_for!(self.line_sts().input_full);
-            self.data.read()
+    // Returns: The byte that was received, or None if nothing was received yet
+    // NOTE: With the fifo enabled input_full stays set until the fifo is empty, so this can be called until it returns None to drain it
+    // NOTE: Bytes that arrived with a parity or framing error are dropped, since they aren't what was sent
+    pub fn read_byte(&self) -> Option<u8> {
+        loop {
+            // The error bits are for the byte at the front of the fifo ( or the only byte, without the fifo ), and reading the line status clears them
+            let status = self.line_sts();
+            if !status.input_full {
+                return None;
+            }
+            let byte = unsafe { self.data.read() };
+            if status.parity_error || status.framing_error {
+                continue;
+            }
+            // NOTE: An overrun error means bytes were lost because they weren't read in time, but this byte is still fine
+            return Some(byte);
         }
     }
+
+    pub fn read_byte_blocking(&self) -> u8 {
+        let mut res;
+        wait_for!({
+            res = self.read_byte();
+            res.is_some()
+        });
+        res.unwrap()
+    }
 }
 
 impl Debug for UARTDevice {
@@ -1,4 +1,5 @@
 use core::{
+    any::Any,
     cell::RefCell,
     convert::TryFrom,
     fmt::{Debug, Display},
@@ -18,10 +19,88 @@ pub enum NodeType {
     Folder,
 }
 
+// What kind of file a file node is, only used to show it to the user, how the node behaves is still decided by the IFile methods
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    CharacterDevice,
+    BlockDevice,
+    Fifo,
+}
+
 pub trait IFolder {
     fn get_children(&self) -> Vec<(String, Node)>;
     fn create_empty_child(&mut self, name: &str, typ: NodeType) -> Option<Node>;
     fn unlink_or_delete_empty_child(&mut self, name: &str) -> Option<()>;
+
+    // Returns: Whether rename_child can move children to new_parent at all, for ex. false if new_parent is on another filesystem, if new_parent is None it's about renaming in this folder
+    // NOTE: By default nothing can be moved, so callers have to be ready to copy instead, see rename
+    fn can_rename_children_to(&mut self, _new_parent: Option<&mut dyn IFolder>) -> bool {
+        false
+    }
+
+    // Moves the child named old_name to new_parent under the name new_name, without copying it, if new_parent is None the child stays in this folder
+    // Returns: None if the child couldn't be moved, for ex. because new_name is already taken, or because can_rename_children_to(new_parent) is false
+    fn rename_child(&mut self, _old_name: &str, _new_parent: Option<&mut dyn IFolder>, _new_name: &str) -> Option<()> {
+        None
+    }
+
+    // Adds another name for existing, a file that's on the same filesystem, like a hard link
+    // Returns: None if the link couldn't be made, for ex. because existing is on another filesystem, or new_name is already taken
+    // NOTE: By default nothing can be linked
+    fn link_child(&mut self, _existing: &Node, _new_name: &str) -> Option<()> {
+        None
+    }
+
+    // Lets a filesystem find out if another folder is one of its own, for ex. to move things between them
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+
+    // Returns: How much space the filesystem the folder is on has, like statfs, or None if the filesystem doesn't keep track of that
+    fn get_fs_stats(&self) -> Option<FileSystemStats> {
+        None
+    }
+
+    // Makes sure everything that was written to the folder ( and the filesystem it's on ) actually reached the disk, like fsync
+    // NOTE: By default there is nothing cached, so there is nothing to do
+    fn sync(&mut self) -> Option<()> {
+        Some(())
+    }
+
+    // Returns: What the filesystem knows about the folder, like stat, or None if it doesn't keep any metadata
+    fn get_metadata(&self) -> Option<NodeMetadata> {
+        None
+    }
+
+    // Changes the permission bits ( the bottom 12 bits of mode ), like chmod
+    // Returns: None if they couldn't be changed, for ex. because the filesystem doesn't have permissions
+    fn set_mode(&mut self, _mode: u32) -> Option<()> {
+        None
+    }
+}
+
+// NOTE: The type and size of a node are known from the Node itself, this is everything else a filesystem might keep track of
+#[derive(Debug, Clone, Copy)]
+pub struct NodeMetadata {
+    pub inode_number: u64,
+    pub hard_links: u64,
+    pub mode: u32, // The type and permission bits, like st_mode
+    pub user_id: u32,
+    pub group_id: u32,
+    pub last_access_unix_timestamp: u64,
+    pub last_modif_unix_timestamp: u64,
+    pub last_change_unix_timestamp: u64, // When the metadata ( not the contents ) last changed
+}
+
+// NOTE: Sizes are in blocks of block_size bytes, filesystems without inodes report 0 of them
+#[derive(Debug, Clone, Copy)]
+pub struct FileSystemStats {
+    pub block_size: u64,
+    pub total_blocks: u64,
+    pub free_blocks: u64,
+    pub total_inodes: u64,
+    pub free_inodes: u64,
 }
 
 type BytesWritten = Option<usize>;
@@ -31,6 +110,71 @@ pub trait IFile {
     fn write(&mut self, offset: u64, data: &[u8]) -> BytesWritten;
     fn get_size(&self) -> u64;
     fn resize(&mut self, new_size: u64) -> Option<()>;
+
+    // Writes data at the end of the file, growing it to make room
+    // NOTE: Character devices have no end, so for them it's the same as write
+    fn append(&mut self, data: &[u8]) -> BytesWritten {
+        if self.is_character_device() {
+            return self.write(0, data);
+        }
+        let size = self.get_size();
+        self.resize(size.checked_add(data.len() as u64)?)?;
+        self.write(size, data)
+    }
+
+    // NOTE: Character devices ( like terminals ) are streams, they ignore offsets and have no real size,
+    // so reads and writes to them shouldn't be bounded by get_size
+    fn is_character_device(&self) -> bool {
+        false
+    }
+
+    fn get_file_type(&self) -> FileType {
+        if self.is_character_device() {
+            FileType::CharacterDevice
+        } else {
+            FileType::Regular
+        }
+    }
+
+    // Hints that the range will probably be read soon, so anything that caches data can load it ahead of time
+    // NOTE: Most files have nothing to cache, so by default this does nothing
+    fn prefetch(&self, _offset: u64, _len: usize) {}
+
+    // Same as read, but for character devices it doesn't treat having nothing to read yet as a successful empty read
+    // Returns: None if there is nothing to read right now ( or if the read failed )
+    fn try_read(&self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        self.read(offset, len).filter(|data| !(self.is_character_device() && data.is_empty() && len != 0))
+    }
+
+    // Returns: The block on the underlying device that each block of the file is stored in, 0 for holes,
+    // or None if the file isn't stored in blocks ( or that information isn't available )
+    // NOTE: Only meant for debugging the filesystems
+    fn get_block_map(&self) -> Option<Vec<u32>> {
+        None
+    }
+
+    // Lets a filesystem find out if a file is one of its own, for ex. to link to it
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        None
+    }
+
+    // Makes sure everything that was written to the file actually reached the disk, like fsync
+    // NOTE: By default there is nothing cached, so there is nothing to do
+    fn sync(&mut self) -> Option<()> {
+        Some(())
+    }
+
+    // Returns: What the filesystem knows about the file, like stat, or None if it doesn't keep any metadata
+    fn get_metadata(&self) -> Option<NodeMetadata> {
+        None
+    }
+
+    // Changes the permission bits ( the bottom 12 bits of mode ), like chmod
+    // NOTE: Filesystems with permissions refuse to write to or resize files without the owner's write bit
+    // Returns: None if they couldn't be changed, for ex. because the filesystem doesn't have permissions
+    fn set_mode(&mut self, _mode: u32) -> Option<()> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -69,25 +213,31 @@ impl Path {
         self.inner.split("/").filter(|val|!val.is_empty()).last()
     }
 
-    // Removes .. and replaces // with /
-    pub fn canonicalize(self) -> Self {
+    // Returns: path with . and .. removed ( .. at the root stays at the root ) and // replaced with /
+    // NOTE: path has to start with a /, .. always removes the part before it, even if that's a file
+    fn normalize(path: &str) -> String {
         let mut canonical_path = Vec::new();
         let mut parts_to_skip = 0; 
         // Walk the path backwards splitting at /
-        for part in self.inner.rsplit("/") {
+        for part in path.rsplit("/") {
             if part == "." || part == "" /* for // */ { continue; }
             if part == ".." { parts_to_skip += 1; continue; }
             if parts_to_skip > 0 { parts_to_skip -= 1; continue; }
             canonical_path.push(part);
         }
 
-        if canonical_path.is_empty() { 
-            return Path::root(); 
+        if canonical_path.is_empty() {
+            String::from("/")
         } else {
-            return Path::try_from(canonical_path.iter().rev().map(|part| ["/", part].concat()).collect::<String>()).unwrap();
+            canonical_path.iter().rev().map(|part| ["/", part].concat()).collect::<String>()
         }
     }
 
+    // Removes . and .. and replaces // with /
+    pub fn canonicalize(self) -> Self {
+        Path { inner: Self::normalize(&self.inner) }
+    }
+
     // NOTE: Won't delete initial /
     pub fn del_last(&mut self) -> &mut Self {
         loop {
@@ -106,11 +256,13 @@ impl Path {
         }
     }
 
+    // NOTE: subnode can have more than one part, and . and .. in it, for ex. "../a/./b"
     pub fn append_str(&mut self, subnode: &str) {
         if !self.inner.ends_with('/') {
             self.inner.push('/');
         }
         self.inner.push_str(subnode);
+        self.inner = Self::normalize(&self.inner);
     }
 
     pub fn into_inner(self) -> String {
@@ -209,19 +361,11 @@ impl TryFrom<&str> for Path {
     type Error = ();
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if value == "/" {
-            return Ok(Self::root());
-        }
-
         if !value.starts_with("/") {
             return Err(());
         }
 
-        if value.ends_with('/'){
-            Ok(Path {inner: String::from(&value[..value.len()-1])})
-        }else{
-            Ok(Path { inner: String::from(value) })
-        }
+        Ok(Path { inner: Path::normalize(value) })
     }
 }
 
@@ -232,11 +376,102 @@ impl TryFrom<String> for Path {
         if !value.starts_with("/") {
             return Err(());
         }
-        if !value.contains("/") {
-            return Err(());
+        Ok(Path { inner: Path::normalize(&value) })
+    }
+}
+
+// Returns: arg as a path, relative to cur_dir unless it starts with a /, or Err if it isn't a valid path
+pub fn resolve(cur_dir: &Path, arg: &str) -> Result<Path, ()> {
+    if arg.starts_with('/') {
+        Path::try_from(arg)
+    } else {
+        let mut path = cur_dir.clone();
+        path.append_str(arg);
+        Ok(path)
+    }
+}
+
+// Returns: The folder at path, for mountpoints that's the root of the mounted filesystem instead of the root fs node covering it
+fn get_folder_below_mountpoint(path: &Path) -> Option<Rc<RefCell<dyn IFolder>>> {
+    if let Some(mountpoint) = path.get_rootfs_node().and_then(|node| node.borrow().mountpoint.clone()) {
+        return Some(mountpoint);
+    }
+    match path.get_node()? {
+        Node::Folder(folder) => Some(folder),
+        Node::File(_) => None,
+    }
+}
+
+// Moves the node at from to to, which can be in another folder or even on another filesystem
+// NOTE: If the filesystem can't move the node itself ( for ex. because to is on another filesystem ), files are copied and then deleted instead,
+// but if it can and the move fails nothing else is tried, since the move could have gotten partway
+// FIXME: Copy folders too
+// Returns: None if from doesn't exist, to already exists, or the move failed
+pub fn rename(from: &Path, to: &Path) -> Option<()> {
+    let from = from.clone().canonicalize();
+    let to = to.clone().canonicalize();
+    if from == to {
+        return Some(());
+    }
+    // A folder can't be moved inside of itself
+    if to.starts_with(&[&*from, "/"].concat()) {
+        return None;
+    }
+    let old_name = from.last()?.to_owned();
+    let new_name = to.last()?.to_owned();
+    if to.get_node().is_some() {
+        return None;
+    }
+    let node = from.get_node()?;
+
+    let mut from_parent_path = from.clone();
+    from_parent_path.del_last();
+    let mut to_parent_path = to.clone();
+    to_parent_path.del_last();
+    let from_parent = get_folder_below_mountpoint(&from_parent_path)?;
+    let to_parent = get_folder_below_mountpoint(&to_parent_path)?;
+
+    if from_parent_path == to_parent_path {
+        if from_parent.borrow_mut().can_rename_children_to(None) {
+            return from_parent.borrow_mut().rename_child(&old_name, None, &new_name);
         }
-        Ok(Path { inner: String::from(value) })
+    } else if from_parent.borrow_mut().can_rename_children_to(Some(&mut *to_parent.borrow_mut())) {
+        return from_parent.borrow_mut().rename_child(&old_name, Some(&mut *to_parent.borrow_mut()), &new_name);
+    }
+
+    let Node::File(file) = node else {
+        return None;
+    };
+    let Node::File(new_file) = to_parent.borrow_mut().create_empty_child(&new_name, NodeType::File)? else {
+        return None;
+    };
+    let size = file.borrow().get_size();
+    let data = file.borrow().read(0, usize::try_from(size).ok()?)?;
+    new_file.borrow_mut().resize(size)?;
+    if new_file.borrow_mut().write(0, &data)? != data.len() {
+        return None;
+    }
+    from_parent.borrow_mut().unlink_or_delete_empty_child(&old_name)?;
+    Some(())
+}
+
+// Makes new another name for the file at existing, they have to be on the same filesystem
+// Returns: None if existing isn't a file, new already exists, or the filesystem can't link them
+pub fn link(existing: &Path, new: &Path) -> Option<()> {
+    let new_name = new.last()?.to_owned();
+    if new.get_node().is_some() {
+        return None;
+    }
+    let node = existing.get_node()?;
+    if let Node::Folder(_) = node {
+        return None;
     }
+
+    let mut new_parent_path = new.clone();
+    new_parent_path.del_last();
+    let new_parent = get_folder_below_mountpoint(&new_parent_path)?;
+    new_parent.borrow_mut().link_child(&node, &new_name)?;
+    Some(())
 }
 
 #[derive(Clone)]
@@ -305,6 +540,21 @@ impl RootFSNode {
     pub fn get_path(&self) -> &Path {
         &self.path
     }
+
+    // Returns: Every root fs node that has something mounted on it, with what is mounted there
+    pub fn get_mounted_folders(slf: Rc<RefCell<RootFSNode>>) -> Vec<(Path, Rc<RefCell<dyn IFolder>>)> {
+        let mut res = Vec::new();
+        // NOTE: Uses a stack instead of recursion, so deep trees can't overflow the kernel stack
+        let mut to_visit = alloc::vec![slf];
+        while let Some(node) = to_visit.pop() {
+            let node = node.borrow();
+            if let Some(mountpoint) = &node.mountpoint {
+                res.push((node.path.clone(), mountpoint.clone()));
+            }
+            to_visit.extend(node.children.iter().rev().cloned());
+        }
+        res
+    }
 }
 
 impl IFolder for RootFSNode {
@@ -345,4 +595,23 @@ impl IFolder for RootFSNode {
             return None;
         }
     }
+
+    fn get_fs_stats(&self) -> Option<FileSystemStats> {
+        (*self.mountpoint.as_ref()?).borrow().get_fs_stats()
+    }
+
+    fn get_metadata(&self) -> Option<NodeMetadata> {
+        (*self.mountpoint.as_ref()?).borrow().get_metadata()
+    }
+
+    fn set_mode(&mut self, mode: u32) -> Option<()> {
+        (*self.mountpoint.as_ref()?).borrow_mut().set_mode(mode)
+    }
+
+    fn sync(&mut self) -> Option<()> {
+        match &self.mountpoint {
+            Some(mnt) => (*mnt).borrow_mut().sync(),
+            None => Some(()),
+        }
+    }
 }
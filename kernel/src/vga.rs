@@ -1200,4 +1200,10 @@ impl<STATE: MixedRegisterState> Vga<Color256, STATE> {
         let vram: KernPointer<u8> = core::mem::transmute(self.video_ram);
         vram.offset((y * 320 + x) as isize).write(pixel_color);
     }
+
+    #[inline(always)]
+    pub unsafe fn read(&self, x: usize, y: usize) -> u8 {
+        let vram: KernPointer<u8> = core::mem::transmute(self.video_ram);
+        vram.offset((y * 320 + x) as isize).read()
+    }
 }
@@ -4,11 +4,27 @@ use core::{arch::asm, marker::PhantomData, alloc::Allocator, convert::TryInto};
 
 use crate::emulator::EmulatorMemory;
 
+// NOTE: Only enforced for the program running in the emulator, the kernel can always read and write any region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionPermissions {
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl RegionPermissions {
+    // Permissions for regions that are just data, like the heap or the arguments of a program
+    pub fn data() -> Self {
+        Self { readable: true, writable: true, executable: false }
+    }
+}
+
 #[derive(Debug, Clone)]
 
 pub struct VirtRegion<A: Allocator> {
     virtual_start: u64, // Note: virtual_start "points" to the beginning of the region, not one after or one before
     pub backing_storage: Vec<u8, A>,
+    pub permissions: RegionPermissions,
 }
 
 impl<A: Allocator> VirtRegion<A> {
@@ -66,7 +82,10 @@ pub trait VirtualMemory {
     fn try_map(&self, virt_addr: u64) -> Option<(&VirtRegion<Self::A>, MappingInfo)>;
     fn try_map_mut(&mut self, virt_addr: u64) -> Option<(&mut VirtRegion<Self::A>, MappingInfo)>;
 
-    fn add_region(&mut self, virt_addr: u64, data: Vec<u8, Self::A>) -> Option<()>;
+    fn add_region_with_permissions(&mut self, virt_addr: u64, data: Vec<u8, Self::A>, permissions: RegionPermissions) -> Option<()>;
+    fn add_region(&mut self, virt_addr: u64, data: Vec<u8, Self::A>) -> Option<()> {
+        self.add_region_with_permissions(virt_addr, data, RegionPermissions::data())
+    }
     fn remove_region(&mut self, region_index: usize);
     fn clear_regions(&mut self); // Remove all regions
 }
@@ -105,8 +124,8 @@ impl<A: Allocator> VirtualMemory for LittleEndianVirtualMemory<A> {
                  )
     }
 
-    fn add_region(&mut self, virt_addr: u64, data: Vec<u8, A>) -> Option<()> {
-        let new_range = VirtRegion { virtual_start: virt_addr, backing_storage: data };
+    fn add_region_with_permissions(&mut self, virt_addr: u64, data: Vec<u8, A>, permissions: RegionPermissions) -> Option<()> {
+        let new_range = VirtRegion { virtual_start: virt_addr, backing_storage: data, permissions };
 
         // First check for overlap in the virtual space
         // (Overlap in the physical space is fine, as long as one virtual address only maps to one physical address it's fine)
@@ -130,75 +149,83 @@ impl<A: Allocator> VirtualMemory for LittleEndianVirtualMemory<A> {
     }
 }
 
+// Returns: The N bytes starting at virt_addr, or None if they aren't all mapped
+// FIXME: Reading/writing more than 1 byte across a region boundary is not supported
+fn try_read_bytes<const N: usize>(virtual_memory: &impl VirtualMemory, virt_addr: u64) -> Option<[u8; N]> {
+    let (region, mapping) = virtual_memory.try_map(virt_addr)?;
+    let end = mapping.offset_in_region.checked_add(N)?;
+    region.backing_storage.get(mapping.offset_in_region..end)?.try_into().ok()
+}
+
+// Returns: None if the bytes don't all fit in mapped memory, in which case nothing is written
+fn try_write_bytes(virtual_memory: &mut impl VirtualMemory, virt_addr: u64, bytes: &[u8]) -> Option<()> {
+    let (region, mapping) = virtual_memory.try_map_mut(virt_addr)?;
+    let end = mapping.offset_in_region.checked_add(bytes.len())?;
+    region.backing_storage.get_mut(mapping.offset_in_region..end)?.copy_from_slice(bytes);
+    Some(())
+}
+
 impl<T> EmulatorMemory for T
 where
     T: VirtualMemory,
 {
-    fn read_u8_ne(&self, addr: u64) -> u8 {
-        let region =
-            if let Some(val) = self.try_map(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        region.0.backing_storage[region.1.offset_in_region]
+    fn read_u8_ne(&self, addr: u64) -> Option<u8> {
+        try_read_bytes(self, addr).map(u8::from_le_bytes)
     }
 
-    fn write_u8_ne(&mut self, addr: u64, val: u8) {
-        let region =
-            if let Some(val) = self.try_map_mut(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        region.0.backing_storage[region.1.offset_in_region] = val;
+    fn write_u8_ne(&mut self, addr: u64, val: u8) -> Option<()> {
+        try_write_bytes(self, addr, &val.to_le_bytes())
     }
 
-    // FIXME: Reading/writing more than 1 byte across a region boundary is not supported
-    fn read_u16_ne(&self, addr: u64) -> u16 {
-        let region =
-            if let Some(val) = self.try_map(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        u16::from_le_bytes(region.0.backing_storage[region.1.offset_in_region..region.1.offset_in_region + core::mem::size_of::<u16>()].try_into().unwrap())
+    fn read_u16_ne(&self, addr: u64) -> Option<u16> {
+        try_read_bytes(self, addr).map(u16::from_le_bytes)
     }
 
-    fn write_u16_ne(&mut self, addr: u64, val: u16) {
-        let region =
-            if let Some(val) = self.try_map_mut(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        let mut indx = 0;
-        for byte in val.to_le_bytes() {
-            region.0.backing_storage[region.1.offset_in_region + indx] = byte;
-            indx += 1;
-        }
+    fn write_u16_ne(&mut self, addr: u64, val: u16) -> Option<()> {
+        try_write_bytes(self, addr, &val.to_le_bytes())
     }
 
-    fn read_u32_ne(&self, addr: u64) -> u32 {
-        let region =
-            if let Some(val) = self.try_map(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        u32::from_le_bytes(region.0.backing_storage[region.1.offset_in_region..region.1.offset_in_region + core::mem::size_of::<u32>()].try_into().unwrap())
+    fn read_u32_ne(&self, addr: u64) -> Option<u32> {
+        try_read_bytes(self, addr).map(u32::from_le_bytes)
     }
 
-    fn write_u32_ne(&mut self, addr: u64, val: u32) {
-        let region =
-            if let Some(val) = self.try_map_mut(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        let mut indx = 0;
-        for byte in val.to_le_bytes() {
-            region.0.backing_storage[region.1.offset_in_region + indx] = byte;
-            indx += 1;
-        }
+    fn write_u32_ne(&mut self, addr: u64, val: u32) -> Option<()> {
+        try_write_bytes(self, addr, &val.to_le_bytes())
     }
 
-    fn read_u64_ne(&self, addr: u64) -> u64 {
-        let region =
-            if let Some(val) = self.try_map(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        u64::from_le_bytes(region.0.backing_storage[region.1.offset_in_region..region.1.offset_in_region + core::mem::size_of::<u64>()].try_into().unwrap())
+    fn read_u64_ne(&self, addr: u64) -> Option<u64> {
+        try_read_bytes(self, addr).map(u64::from_le_bytes)
     }
 
-    fn write_u64_ne(&mut self, addr: u64, val: u64) {
-        let region =
-            if let Some(val) = self.try_map_mut(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        let mut indx = 0;
-        for byte in val.to_le_bytes() {
-            region.0.backing_storage[region.1.offset_in_region + indx] = byte;
-            indx += 1;
-        }
+    fn write_u64_ne(&mut self, addr: u64, val: u64) -> Option<()> {
+        try_write_bytes(self, addr, &val.to_le_bytes())
+    }
+
+    fn read_u16_le(&self, addr: u64) -> Option<u16> {
+        try_read_bytes(self, addr).map(u16::from_le_bytes)
     }
 
-    fn read_u32_le(&self, addr: u64) -> u32 {
-        let region =
-            if let Some(val) = self.try_map(addr) { val } else { panic!("Virtual address: {} should be mapped!", addr) };
-        u32::from_le_bytes(region.0.backing_storage[region.1.offset_in_region..region.1.offset_in_region + core::mem::size_of::<u32>()].try_into().unwrap())
+    fn read_u32_le(&self, addr: u64) -> Option<u32> {
+        try_read_bytes(self, addr).map(u32::from_le_bytes)
+    }
+
+    // NOTE: Unmapped memory is neither readable, writable nor executable
+    fn is_readable(&self, addr: u64, len: usize) -> bool {
+        let Some(last_addr) = addr.checked_add(len.saturating_sub(1) as u64) else {
+            return false;
+        };
+        [addr, last_addr].iter().all(|addr| self.try_map(*addr).map(|(region, _)| region.permissions.readable).unwrap_or(false))
+    }
+
+    fn is_writable(&self, addr: u64, len: usize) -> bool {
+        let Some(last_addr) = addr.checked_add(len.saturating_sub(1) as u64) else {
+            return false;
+        };
+        [addr, last_addr].iter().all(|addr| self.try_map(*addr).map(|(region, _)| region.permissions.writable).unwrap_or(false))
+    }
+
+    fn is_executable(&self, addr: u64) -> bool {
+        self.try_map(addr).map(|(region, _)| region.permissions.executable).unwrap_or(false)
     }
 }
 
@@ -253,6 +280,20 @@ unsafe fn port_inh(addr: u16) -> u16 {
     return res;
 }
 
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+unsafe fn port_outw(addr: u16, val: u32) {
+    asm!("out dx, eax", in("eax") val, in("dx") addr, options(nostack, nomem));
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+unsafe fn port_inw(addr: u16) -> u32 {
+    let mut res: u32;
+    asm!("in eax, dx", out("eax") res, in("dx") addr, options(nostack, nomem));
+    return res;
+}
+
 #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
 #[inline(always)]
 unsafe fn port_outb(addr: u16, val: u8) {
@@ -277,6 +318,18 @@ unsafe fn port_inh(addr: u16) -> u16 {
     unimplemented!("The port_inh function is either not avilable on your architecture or your architecture is not supported.");
 }
 
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+#[inline(always)]
+unsafe fn port_outw(addr: u16, val: u32) {
+    unimplemented!("The port_outw function is either not avilable on your architecture or your architecture is not supported.");
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+#[inline(always)]
+unsafe fn port_inw(addr: u16) -> u32 {
+    unimplemented!("The port_inw function is either not avilable on your architecture or your architecture is not supported.");
+}
+
 impl<T> KernPointer<T>
 where
     T: Sized,
@@ -359,6 +412,34 @@ impl KernPointer<u16> {
     }
 }
 
+impl KernPointer<u32> {
+    // SAFTEY: Constructors assume address is in correct space
+    pub unsafe fn from_mem(addr: *mut u32) -> Self {
+        Self { inner: addr, is_port: false }
+    }
+
+    pub unsafe fn from_port(port: u16) -> Self {
+        Self { inner: port as *mut u32, is_port: true }
+    }
+
+    #[inline(always)]
+    pub unsafe fn write(&mut self, val: u32) {
+        if self.is_port {
+            port_outw(self.inner as u16, val);
+        } else {
+            core::ptr::write_volatile(self.inner, val);
+        }
+    }
+
+    #[inline(always)]
+    pub unsafe fn read(&self) -> u32 {
+        if self.is_port {
+            port_inw(self.inner as u16)
+        } else {
+            *self.inner
+        }
+    }
+}
 
 // NOTE: The order of first .add'ing the pointer before casting is correct since the offset is in bytes, and add offsets in units of T which is u8 since backing_storage is a vec of u8
 
@@ -5,5 +5,6 @@
 #define O_APPEND 0b00100
 #define O_CREAT  0b01000
 #define O_TRUNC  0b10000
+#define O_NONBLOCK 0b100000
 
 extern int open(const char* pathname, int flags);
@@ -1,2 +1,13 @@
+#pragma once
+#define SIGINT 2
 #define SIGILL 4
-#define SIGKILL 9
\ No newline at end of file
+#define SIGKILL 9
+#define SIGSEGV 11
+#define SIGTERM 15
+
+typedef void (*sighandler_t)(int);
+#define SIG_DFL ((sighandler_t)0)
+#define SIG_IGN ((sighandler_t)1)
+#define SIG_ERR ((sighandler_t)-1)
+
+extern sighandler_t signal(int signum, sighandler_t handler);
@@ -11,13 +11,9 @@ typedef struct{
 } FILE;
 
 
-FILE stdin_struct = {STDIN_FILENO};
-FILE stdout_struct = {STDOUT_FILENO};
-FILE stderr_struct = {STDERR_FILENO};
-
-#define stdin (&stdin_struct)
-#define stdout (&stdout_struct)
-#define stderr (&stderr_struct)
+extern FILE* stdin;
+extern FILE* stdout;
+extern FILE* stderr;
 
 
 extern FILE* fopen(const char* filename, const char* mode);
@@ -28,23 +24,11 @@ extern int fseek(FILE* f, long offset, int origin);
 
 extern int vfprintf(FILE* out_stream, const char* format, va_list vlist);
 int vprintf(const char* format, va_list vlist) { return vfprintf(stdout, format, vlist); } 
+extern int vsprintf(char* buffer, const char* format, va_list vlist);
 
-
-int printf(const char* format, ...) {
-    va_list args;
-    va_start(args, format);
-    int res = vprintf(format, args);
-    va_end(args);
-    return res;
-}
-
-int fprintf(FILE* out_stream, const char* format, ...) {
-    va_list args;
-    va_start(args, format);
-    int res = vfprintf(out_stream, format, args);
-    va_end(args);
-    return res;
-}
+extern int printf(const char* format, ...);
+extern int fprintf(FILE* out_stream, const char* format, ...);
+extern int sprintf(char* buffer, const char* format, ...);
 
 extern int vfscanf(FILE* in_stream, const char* format, va_list vlist);
 int vscanf(const char* format, va_list vlist) { return vfscanf(stdin, format, vlist); }
@@ -6,5 +6,13 @@
 extern void  exit(unsigned int code);
 extern void* malloc(size_t size);
 extern void* realloc(void* ptr, size_t new_size);
+extern void* calloc(size_t nmemb, size_t size);
+extern void* aligned_alloc(size_t alignment, size_t size);
+extern int   posix_memalign(void** memptr, size_t alignment, size_t size);
 extern void  free(void* ptr);
 extern char* getenv(const char* name);
+
+extern long strtol(const char* nptr, char** endptr, int base);
+extern unsigned long strtoul(const char* nptr, char** endptr, int base);
+extern long atol(const char* str);
+extern int atoi(const char* str);
@@ -2,6 +2,7 @@
 #include "stddef.h"
 extern size_t strlen(const char* str);
 extern int strcmp(const char* str1, const char* str2);
+extern int strncmp(const char* str1, const char* str2, size_t count);
 extern char* strstr(const char* str, const char* substr);
 extern char* strcat(char* dest, const char* src);
 extern char* strcpy(char* dest, const char* src);
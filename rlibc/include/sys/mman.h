@@ -0,0 +1,15 @@
+#pragma once
+#include "stddef.h"
+#include "sys/types.h"
+
+#define PROT_NONE  0b000
+#define PROT_READ  0b001
+#define PROT_WRITE 0b010
+#define PROT_EXEC  0b100
+
+#define MAP_PRIVATE   0b01
+#define MAP_ANONYMOUS 0b10
+#define MAP_FAILED ((void*)-1)
+
+extern void* mmap(void* addr, size_t length, int prot, int flags, int fd, off_t offset);
+extern int munmap(void* addr, size_t length);
\ No newline at end of file
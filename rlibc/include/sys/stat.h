@@ -0,0 +1,21 @@
+#pragma once
+#include "sys/types.h"
+
+#define S_IFMT  0170000
+#define S_IFIFO 0010000
+#define S_IFCHR 0020000
+#define S_IFDIR 0040000
+#define S_IFREG 0100000
+
+#define S_ISFIFO(m) (((m) & S_IFMT) == S_IFIFO)
+#define S_ISCHR(m)  (((m) & S_IFMT) == S_IFCHR)
+#define S_ISDIR(m)  (((m) & S_IFMT) == S_IFDIR)
+#define S_ISREG(m)  (((m) & S_IFMT) == S_IFREG)
+
+struct stat {
+    mode_t st_mode;
+    unsigned int st_reserved;
+    off_t st_size;
+};
+
+extern int fstat(int fd, struct stat* statbuf);
\ No newline at end of file
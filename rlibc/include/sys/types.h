@@ -1,3 +1,4 @@
 #pragma once
 typedef long off_t;
-typedef int pid_t;
\ No newline at end of file
+typedef int pid_t;
+typedef unsigned int mode_t;
\ No newline at end of file
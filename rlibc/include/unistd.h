@@ -8,7 +8,7 @@
 
 #define SEEK_CUR 0
 #define SEEK_SET 1
-#define SEEK_END 3
+#define SEEK_END 2
 
 extern ssize_t write(int fd, const void* buf, size_t count);
 extern ssize_t read(int fd, void* buf, size_t count);
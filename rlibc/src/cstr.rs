@@ -1,7 +1,9 @@
-use core::{cmp::min, ptr::null_mut};
+use core::ptr::null_mut;
 
 use crate::mem::{memcmp, memcpy, memset};
 
+/// # Safety
+/// str must point to a null-terminated byte string.
 #[no_mangle]
 pub unsafe extern "C" fn strchr(str: *const core::ffi::c_char, ch: core::ffi::c_int) -> *mut core::ffi::c_char {
     let ch: core::ffi::c_char = ch as core::ffi::c_char;
@@ -22,12 +24,15 @@ pub unsafe extern "C" fn strchr(str: *const core::ffi::c_char, ch: core::ffi::c_
 // Strtok is specifically *not* thread safe, so modifying a global without synchronization is fine
 static mut STRTOK_STR: *mut core::ffi::c_char = null_mut();
 
+/// # Safety
+/// str must be null or point to a writable null-terminated byte string, which is modified and remembered for later calls with a null str,
+/// so it must stay valid until the last of those calls. delim must point to a null-terminated byte string.
 #[no_mangle]
 pub unsafe extern "C" fn strtok(mut str: *mut core::ffi::c_char, delim: *const core::ffi::c_char) -> *mut core::ffi::c_char {
     // Returns: Pointer to the beginning of the next token or a nullptr if there are no more tokens. 
 
-    if str == null_mut() {
-        if STRTOK_STR != null_mut() {
+    if str.is_null() {
+        if !STRTOK_STR.is_null() {
             str = STRTOK_STR;
         }else{
             // Both STRTOK_STR is null and str is null
@@ -89,6 +94,8 @@ pub unsafe extern "C" fn strtok(mut str: *mut core::ffi::c_char, delim: *const c
 }
 
 
+/// # Safety
+/// str must point to a null-terminated byte string.
 #[no_mangle]
 pub unsafe extern "C" fn strlen(str: *const core::ffi::c_char) -> core::ffi::c_ulong {
     let mut size: core::ffi::c_ulong = 0;
@@ -98,21 +105,40 @@ pub unsafe extern "C" fn strlen(str: *const core::ffi::c_char) -> core::ffi::c_u
     size
 }
 
+/// # Safety
+/// str1 and str2 must both point to null-terminated byte strings.
 #[no_mangle]
 pub unsafe extern "C" fn strcmp(str1: *const core::ffi::c_char, str2: *const core::ffi::c_char) -> core::ffi::c_int {
-    let len_1 = strlen(str1);
-    let len_2 = strlen(str2);
-    // min(len_1, len_2) would be 0 in these cases which would cause memcmp to return 0, but we should not return 0, because the string are *NOT* equal
-    if len_1 == 0 && len_2 != 0 {
-        return -1;
-    }
-    if len_1 != 0 && len_2 == 0 {
-        return -1;
-    }
+    // Compares two null-terminated byte strings lexicographically.
+    // The sign of the result is the sign of the difference between the values of the first pair of characters (both interpreted as unsigned char) that differ in the strings being compared.
+    // Returns: Negative value if str1 appears before str2 in lexicographical order, zero if they compare equal, positive value if str1 appears after str2.
+    // Source: https://en.cppreference.com/w/c/string/byte/strcmp
+    strncmp(str1, str2, core::ffi::c_size_t::MAX)
+}
+
+/// # Safety
+/// str1 and str2 must both be readable up to count bytes or up to and including their null terminator, whichever comes first.
+#[no_mangle]
+pub unsafe extern "C" fn strncmp(str1: *const core::ffi::c_char, str2: *const core::ffi::c_char, count: core::ffi::c_size_t) -> core::ffi::c_int {
+    // Compares at most count characters of two possibly null-terminated arrays lexicographically. Characters that follow the null character are not compared.
+    // Returns: Same as strcmp
+    // Source: https://en.cppreference.com/w/c/string/byte/strncmp
 
-    memcmp(str1, str2, min(len_1, len_2) as usize)
+    // NOTE: The null terminator takes part in the comparison, that's what makes a prefix compare before the longer string
+    for i in 0..count {
+        let (c1, c2) = (*str1.add(i) as u8, *str2.add(i) as u8);
+        if c1 != c2 {
+            return c1 as core::ffi::c_int - c2 as core::ffi::c_int;
+        }
+        if c1 == b'\0' {
+            break;
+        }
+    }
+    0
 }
 
+/// # Safety
+/// str and substr must both point to null-terminated byte strings.
 #[no_mangle]
 pub unsafe extern "C" fn strstr(str: *const core::ffi::c_char, substr: *const core::ffi::c_char) -> *mut core::ffi::c_char {
     // Finds the first occurrence of the null-terminated byte string pointed to by substr in the null-terminated byte string pointed to by str. 
@@ -138,6 +164,9 @@ pub unsafe extern "C" fn strstr(str: *const core::ffi::c_char, substr: *const co
     return null_mut();
 }
 
+/// # Safety
+/// src must be readable up to count bytes or up to and including its null terminator, dest must be writable for count bytes,
+/// and the two must not overlap.
 #[no_mangle]
 pub unsafe extern "C" fn strncpy(dest: *mut core::ffi::c_char, src: *const core::ffi::c_char, count: core::ffi::c_size_t) -> *mut core::ffi::c_char {
     // Copies at most count characters of the character array pointed to by src (including the terminating null character, but not any of the characters that follow the null character) to character array pointed to by dest.
@@ -158,6 +187,8 @@ pub unsafe extern "C" fn strncpy(dest: *mut core::ffi::c_char, src: *const core:
     return dest;
 }
 
+/// # Safety
+/// src must point to a null-terminated byte string, dest must have room for all of it including the null terminator, and the two must not overlap.
 #[no_mangle]
 pub unsafe extern "C" fn strcpy(dest: *mut core::ffi::c_char, src: *const core::ffi::c_char) -> *mut core::ffi::c_char {
     // Copies the null-terminated byte string pointed to by src, including the null terminator, to the character array whose first element is pointed to by dest.
@@ -166,6 +197,9 @@ pub unsafe extern "C" fn strcpy(dest: *mut core::ffi::c_char, src: *const core::
     memcpy(dest, src, strlen(src) as usize + 1 /* also copy the null-terminator from the src string */)
 }
 
+/// # Safety
+/// dest and src must both point to null-terminated byte strings, dest must have room for both strings and one null terminator,
+/// and the two must not overlap.
 #[no_mangle]
 pub unsafe extern "C" fn strcat(dest: *mut core::ffi::c_char, src: *const core::ffi::c_char) -> *mut core::ffi::c_char {
     // Appends a copy of the null-terminated byte string pointed to by src to the end of the null-terminated byte string pointed to by dest. The character src[0] replaces the null terminator at the end of dest. The resulting byte string is null-terminated.
@@ -174,6 +208,121 @@ pub unsafe extern "C" fn strcat(dest: *mut core::ffi::c_char, src: *const core::
     strcpy(dest.add(strlen(dest) as usize), src)
 }
 
+// Parses the part that strtol and strtoul have in common
+// Returns: The magnitude of the number ( saturated at u64::MAX ), whether it had a minus sign, and a pointer to the char after the number,
+// or None if there is no number to parse
+unsafe fn parse_integer(nptr: *const core::ffi::c_char, base: core::ffi::c_int) -> Option<(u64, bool, *const core::ffi::c_char)> {
+    fn char_to_digit(c: u8) -> Option<u32> {
+        (c as char).to_digit(36)
+    }
+
+    if base != 0 && !(2..=36).contains(&base) {
+        return None;
+    }
+    let mut base = base as u32;
+    let mut pos = nptr as *const u8;
+
+    // Discards any whitespace characters (as identified by calling isspace) until the first non-whitespace character is found
+    while isspace(*pos as core::ffi::c_int) != 0 {
+        pos = pos.add(1);
+    }
+
+    let mut negative = false;
+    if *pos == b'+' || *pos == b'-' {
+        negative = *pos == b'-';
+        pos = pos.add(1);
+    }
+
+    // NOTE: "0x" is only a prefix if a hex digit follows it, otherwise the number is just the 0 and the 'x' is left unparsed
+    if (base == 0 || base == 16) && *pos == b'0' && (*pos.add(1) == b'x' || *pos.add(1) == b'X') && char_to_digit(*pos.add(2)).is_some_and(|digit| digit < 16) {
+        base = 16;
+        pos = pos.add(2);
+    } else if base == 0 && *pos == b'0' {
+        base = 8;
+    } else if base == 0 {
+        base = 10;
+    }
+
+    let mut n: u64 = 0;
+    let mut digits_read = 0;
+    while let Some(digit) = char_to_digit(*pos).filter(|digit| *digit < base) {
+        n = n.saturating_mul(base as u64).saturating_add(digit as u64);
+        digits_read += 1;
+        pos = pos.add(1);
+    }
+
+    if digits_read == 0 {
+        return None;
+    }
+    Some((n, negative, pos as *const core::ffi::c_char))
+}
+
+/// # Safety
+/// nptr must point to a null-terminated byte string and endptr must be null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn strtol(nptr: *const core::ffi::c_char, endptr: *mut *mut core::ffi::c_char, base: core::ffi::c_int) -> core::ffi::c_long {
+    // Interprets an integer value in a byte string pointed to by nptr.
+    // Discards any whitespace characters until the first non-whitespace character is found, then takes as many characters as possible to form a valid base-n integer number representation and converts them to an integer value.
+    // If base is 0, the numeric base is auto-detected: "0x"/"0X" means hexadecimal, a leading 0 means octal, and anything else means decimal.
+    // The functions sets the pointer pointed to by endptr to point to the character past the last character interpreted. If endptr is a null pointer, it is ignored.
+    // Returns: Integer value corresponding to the contents of nptr on success. If no conversion can be performed, 0 is returned.
+    // If the converted value falls out of range of corresponding return type, LONG_MAX or LONG_MIN is returned.
+    // Source: https://en.cppreference.com/w/c/string/byte/strtol
+    // FIXME: There is no errno, so a range error can't be reported as ERANGE
+    let Some((n, negative, end)) = parse_integer(nptr, base) else {
+        // If no conversion can be performed, nptr is stored in *endptr
+        if !endptr.is_null() { *endptr = nptr as *mut core::ffi::c_char; }
+        return 0;
+    };
+    if !endptr.is_null() { *endptr = end as *mut core::ffi::c_char; }
+
+    if negative {
+        if n > core::ffi::c_long::MIN.unsigned_abs() { core::ffi::c_long::MIN } else { (n as core::ffi::c_long).wrapping_neg() }
+    } else {
+        if n > core::ffi::c_long::MAX as u64 { core::ffi::c_long::MAX } else { n as core::ffi::c_long }
+    }
+}
+
+/// # Safety
+/// Same as strtol.
+#[no_mangle]
+pub unsafe extern "C" fn strtoul(nptr: *const core::ffi::c_char, endptr: *mut *mut core::ffi::c_char, base: core::ffi::c_int) -> core::ffi::c_ulong {
+    // Same as strtol, except that the result is unsigned, a minus sign negates the result in the unsigned type ( so "-1" is ULONG_MAX ),
+    // and if the converted value falls out of range ULONG_MAX is returned.
+    // Source: https://en.cppreference.com/w/c/string/byte/strtoul
+    // FIXME: There is no errno, so a range error can't be reported as ERANGE
+    let Some((n, negative, end)) = parse_integer(nptr, base) else {
+        if !endptr.is_null() { *endptr = nptr as *mut core::ffi::c_char; }
+        return 0;
+    };
+    if !endptr.is_null() { *endptr = end as *mut core::ffi::c_char; }
+
+    // NOTE: parse_integer saturates at u64::MAX, which is ULONG_MAX, so values that are too big already come back as ULONG_MAX
+    if negative { n.wrapping_neg() } else { n }
+}
+
+/// # Safety
+/// str must point to a null-terminated byte string.
+#[no_mangle]
+pub unsafe extern "C" fn atol(str: *const core::ffi::c_char) -> core::ffi::c_long {
+    // Interprets an integer value in a byte string pointed to by str, the implied radix is always 10.
+    // Returns: Integer value corresponding to the contents of str on success. If no conversion can be performed, 0 is returned.
+    // Source: https://en.cppreference.com/w/c/string/byte/atoi
+    strtol(str, null_mut(), 10)
+}
+
+/// # Safety
+/// Same as atol.
+#[no_mangle]
+pub unsafe extern "C" fn atoi(str: *const core::ffi::c_char) -> core::ffi::c_int {
+    // Same as atol but for an int
+    // NOTE: The behavior is undefined if the value doesn't fit in an int, here it's just truncated
+    // Source: https://en.cppreference.com/w/c/string/byte/atoi
+    atol(str) as core::ffi::c_int
+}
+
+/// # Safety
+/// Any ch is fine, it's only unsafe to match the rest of the C functions.
 #[no_mangle]
 pub unsafe extern "C" fn isspace(ch: core::ffi::c_int) -> core::ffi::c_int {
     // Checks if the given character is a whitespace character, i.e. 
@@ -187,6 +336,8 @@ pub unsafe extern "C" fn isspace(ch: core::ffi::c_int) -> core::ffi::c_int {
     }
 }
 
+/// # Safety
+/// Any ch is fine, values that aren't an unsigned char or EOF are truncated to one.
 #[no_mangle]
 pub unsafe extern "C" fn isdigit(ch: core::ffi::c_int) -> core::ffi::c_int {
     // Checks if the given character is a numeric character (0123456789). 
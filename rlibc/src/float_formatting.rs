@@ -0,0 +1,265 @@
+// Note: This file turns doubles into decimal digits for the float specifiers of printf, and back for the ones of scanf
+// NOTE: Doubles are scaled using floating point math, so the digits after the first ~15 significant ones can be off, which is fine for printing
+
+// A double has at most 17 significant decimal digits, more than that are always 0
+pub const MAX_SIGNIFICANT_DIGITS: usize = 17;
+
+// 10^(2^i), used to scale numbers in as few steps as possible, since every step can round
+const POWERS_OF_TEN: [f64; 9] = [1e1, 1e2, 1e4, 1e8, 1e16, 1e32, 1e64, 1e128, 1e256];
+
+pub struct DecimalDigits {
+    digits: [u8; MAX_SIGNIFICANT_DIGITS],
+    len: usize,
+    pub exponent: i32, // The power of ten of the first digit
+}
+
+impl DecimalDigits {
+    fn zero() -> Self {
+        Self { digits: [0; MAX_SIGNIFICANT_DIGITS], len: 0, exponent: 0 }
+    }
+
+    // Returns: The digit #index ( starting from the most significant one ), digits past the ones that were computed are 0
+    pub fn digit(&self, index: usize) -> u8 {
+        if index < self.len { self.digits[index] } else { 0 }
+    }
+
+    // Returns: The digit that is multiplied by 10^power
+    fn digit_at_power(&self, power: i32) -> u8 {
+        if power > self.exponent { 0 } else { self.digit((self.exponent - power) as usize) }
+    }
+
+    // Returns: How many of the first count digits are zeroes at the end
+    fn trailing_zeroes(&self, count: usize) -> usize {
+        (0..count).rev().take_while(|i| self.digit(*i) == 0).count()
+    }
+}
+
+// Returns: x scaled to be in [1, 10), and the power of ten it was scaled by
+// NOTE: x has to be positive and finite
+fn scale_to_one_integer_digit(mut x: f64) -> (f64, i32) {
+    let mut exponent = 0;
+    // Going from the biggest power to the smallest one means that each one has to be used at most once
+    for (i, power) in POWERS_OF_TEN.iter().enumerate().rev() {
+        if x >= *power {
+            x /= *power;
+            exponent += 1 << i;
+        }
+    }
+    for (i, power) in POWERS_OF_TEN.iter().enumerate().rev() {
+        if x * *power < 10.0 {
+            x *= *power;
+            exponent -= 1 << i;
+        }
+    }
+
+    // Rounding errors can leave x just outside of the range
+    if x >= 10.0 {
+        x /= 10.0;
+        exponent += 1;
+    } else if x < 1.0 {
+        x *= 10.0;
+        exponent -= 1;
+    }
+    (x, exponent)
+}
+
+// Returns: x * 10^exponent
+// NOTE: Dividing by an exact power of ten rounds less than multiplying by an inexact negative one, so negative exponents divide
+pub fn scale_by_power_of_ten(mut x: f64, exponent: i32) -> f64 {
+    let power = exponent.unsigned_abs();
+    for (i, power_of_ten) in POWERS_OF_TEN.iter().enumerate() {
+        if power & (1 << i) != 0 {
+            if exponent < 0 {
+                x /= *power_of_ten;
+            } else {
+                x *= *power_of_ten;
+            }
+        }
+    }
+    // Anything bigger than what the table covers is infinity or 0 anyways, unless x is 0
+    if power >= 1 << POWERS_OF_TEN.len() && x != 0.0 {
+        x = if exponent < 0 { 0.0 } else { f64::INFINITY };
+    }
+    x
+}
+
+// Returns: The first count significant digits of x ( rounded half up ), x has to be positive and finite
+pub fn to_decimal_digits(x: f64, count: usize) -> DecimalDigits {
+    let mut res = DecimalDigits::zero();
+    if x == 0.0 || count == 0 {
+        return res;
+    }
+
+    let len = core::cmp::min(count, MAX_SIGNIFICANT_DIGITS);
+    let (x, mut exponent) = scale_to_one_integer_digit(x);
+    // NOTE: Every power of ten up to 10^22 is exact as a double, so this only rounds once
+    let scale = (1..len).fold(1.0, |acc, _| acc * 10.0);
+    let mut n = (x * scale + 0.5) as u64;
+
+    // Rounding up can add a digit, for ex. 9.99 to 2 digits is 10
+    if n >= 10u64.pow(len as u32) {
+        n /= 10;
+        exponent += 1;
+    }
+
+    for i in (0..len).rev() {
+        res.digits[i] = (n % 10) as u8;
+        n /= 10;
+    }
+    res.len = len;
+    res.exponent = exponent;
+    res
+}
+
+// Returns: The digits of x rounded to precision digits after the point, x has to be positive and finite
+fn to_fixed_decimal_digits(x: f64, precision: usize) -> DecimalDigits {
+    if x == 0.0 {
+        return DecimalDigits::zero();
+    }
+    let exponent = scale_to_one_integer_digit(x).1;
+    let count = i64::from(exponent) + 1 + precision as i64;
+    if count > 0 {
+        return to_decimal_digits(x, count as usize);
+    }
+
+    // None of the digits are shown, but the first one can still round up to the last digit that is
+    let first_digit = to_decimal_digits(x, 1);
+    if count == 0 && first_digit.exponent == exponent && first_digit.digit(0) >= 5 {
+        let mut res = DecimalDigits::zero();
+        res.digits[0] = 1;
+        res.len = 1;
+        res.exponent = -(precision as i32);
+        return res;
+    }
+    DecimalDigits::zero()
+}
+
+// Calls output and keeps count of how many bytes were written
+struct CountingWriter<'a> {
+    output: &'a mut dyn FnMut(&[u8]) -> bool,
+    written: usize,
+}
+
+impl<'a> CountingWriter<'a> {
+    fn write(&mut self, bytes: &[u8]) -> Option<()> {
+        if !(self.output)(bytes) {
+            return None;
+        }
+        self.written += bytes.len();
+        Some(())
+    }
+
+    // Writes the sign, and if x is infinite or nan writes that too
+    // Returns: true if x was completly written
+    fn write_sign_and_non_finite(&mut self, x: f64, uppercase: bool) -> Option<bool> {
+        if x.is_sign_negative() {
+            self.write(b"-")?;
+        }
+        if x.is_finite() {
+            return Some(false);
+        }
+        let name: &[u8] = match (x.is_nan(), uppercase) {
+            (true, false) => b"nan",
+            (true, true) => b"NAN",
+            (false, false) => b"inf",
+            (false, true) => b"INF",
+        };
+        self.write(name)?;
+        Some(true)
+    }
+}
+
+// Writes x in the style of %f ( [-]ddd.ddd ), with precision digits after the point
+// NOTE: The point is only written if there are digits after it, unless always_write_point is set ( the '#' flag )
+// Returns: The number of bytes written, or None if output failed
+pub fn write_fixed(
+    x: f64,
+    precision: usize,
+    uppercase: bool,
+    always_write_point: bool,
+    output: &mut dyn FnMut(&[u8]) -> bool,
+) -> Option<usize> {
+    let mut writer = CountingWriter { output, written: 0 };
+    if writer.write_sign_and_non_finite(x, uppercase)? {
+        return Some(writer.written);
+    }
+
+    let digits = to_fixed_decimal_digits(x.abs(), precision);
+    for power in (0..=core::cmp::max(digits.exponent, 0)).rev() {
+        writer.write(&[b'0' + digits.digit_at_power(power)])?;
+    }
+    if precision > 0 || always_write_point {
+        writer.write(b".")?;
+    }
+    for power in 1..=precision as i32 {
+        writer.write(&[b'0' + digits.digit_at_power(-power)])?;
+    }
+    Some(writer.written)
+}
+
+// Writes x in the style of %e ( [-]d.ddde±dd ), with precision digits after the point
+// NOTE: The point is only written if there are digits after it, unless always_write_point is set ( the '#' flag )
+// Returns: The number of bytes written, or None if output failed
+pub fn write_scientific(
+    x: f64,
+    precision: usize,
+    uppercase: bool,
+    always_write_point: bool,
+    output: &mut dyn FnMut(&[u8]) -> bool,
+) -> Option<usize> {
+    let mut writer = CountingWriter { output, written: 0 };
+    if writer.write_sign_and_non_finite(x, uppercase)? {
+        return Some(writer.written);
+    }
+    let mut write = |bytes: &[u8]| writer.write(bytes);
+
+    let digits = to_decimal_digits(x.abs(), precision + 1);
+    write(&[b'0' + digits.digit(0)])?;
+    if precision > 0 || always_write_point {
+        write(b".")?;
+    }
+    for i in 1..=precision {
+        write(&[b'0' + digits.digit(i)])?;
+    }
+
+    write(if uppercase { b"E" } else { b"e" })?;
+    write(if digits.exponent < 0 { b"-" } else { b"+" })?;
+    // The exponent always has at least two digits
+    let exponent = digits.exponent.unsigned_abs();
+    if exponent >= 100 {
+        write(&[b'0' + (exponent / 100) as u8])?;
+    }
+    write(&[b'0' + (exponent / 10 % 10) as u8, b'0' + (exponent % 10) as u8])?;
+    Some(writer.written)
+}
+
+// Writes x in the style of %g, which is the style of %e if the exponent is < -4 or >= precision, and of %f otherwise,
+// where precision is the number of significant digits
+// NOTE: Trailing zeroes ( and the point if nothing is left after it ) are removed, unless keep_trailing_zeroes is set ( the '#' flag )
+// Source: https://en.cppreference.com/w/c/io/fprintf
+// Returns: The number of bytes written, or None if output failed
+pub fn write_shortest(
+    x: f64,
+    precision: usize,
+    uppercase: bool,
+    keep_trailing_zeroes: bool,
+    output: &mut dyn FnMut(&[u8]) -> bool,
+) -> Option<usize> {
+    let precision = core::cmp::max(precision, 1);
+    if !x.is_finite() {
+        return write_fixed(x, 0, uppercase, false, output);
+    }
+
+    // The exponent has to be the one x would have in the style of %e, so after rounding
+    let digits = to_decimal_digits(x.abs(), precision);
+    let exponent = if x == 0.0 { 0 } else { digits.exponent };
+    let removed_zeroes = if keep_trailing_zeroes { 0 } else { digits.trailing_zeroes(precision) };
+
+    if exponent < -4 || exponent >= precision as i32 {
+        let precision = (precision - 1).saturating_sub(removed_zeroes);
+        write_scientific(x, precision, uppercase, keep_trailing_zeroes, output)
+    } else {
+        let precision = ((precision as i32 - 1 - exponent) as usize).saturating_sub(removed_zeroes);
+        write_fixed(x, precision, uppercase, keep_trailing_zeroes, output)
+    }
+}
@@ -5,6 +5,7 @@
 use core::{ptr::null_mut, ffi::VaList, ops::{DivAssign, Rem}};
 
 pub mod cstr;
+mod float_formatting;
 pub mod mem;
 pub mod sys;
 pub mod specifier_parsing;
@@ -51,8 +52,102 @@ extern "C" {
 }
 
 
-#[no_mangle]
-pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_char, mut args: VaList) -> core::ffi::c_int {
+// The most arguments a format string can refer to by position ( like in %2$d ), named like the posix limit
+const NL_ARGMAX: usize = 32;
+
+// An argument of the printf family that had to be read ahead of time, because the format string refers to arguments by position
+#[derive(Clone, Copy)]
+enum PositionalArgument {
+    Unused,
+    Int(core::ffi::c_int), // NOTE: Also used for unsigned ints, so that the same argument can be formatted as both ( like %1$d %1$x )
+    Double(core::ffi::c_double),
+    Pointer(*mut core::ffi::c_void),
+}
+
+// Arguments can only be read from a VaList in order, so to format %2$s %1$s the types of all the arguments have to be known first,
+// then they are all read up front, in order
+// NOTE: Arguments no conversion uses are assumed to be pointers, leaving gaps is undefined behaviour anyways
+// Returns: None if no conversion in the format string has a position
+unsafe fn read_printf_positional_arguments(format_str: *const core::ffi::c_char, args: &mut VaList) -> Option<[PositionalArgument; NL_ARGMAX]> {
+    use specifier_parsing::*;
+    // Until the arguments are read the values are just placeholders, only the variants matter
+    let mut positional_arguments = [PositionalArgument::Unused; NL_ARGMAX];
+    let mut number_of_arguments = 0;
+    let mut specification_under_construction: Option<UnfinishedPrintfConversionSpecification> = None;
+    for i in 0..strlen(format_str) {
+        let format_char = *format_str.add(i as usize) as u8;
+        specification_under_construction = match specification_under_construction {
+            None if format_char == b'%' => Some(UnfinishedPrintfConversionSpecification::default()),
+            None => None,
+            Some(unfinished) => match add_char_to_printf_specification(unfinished, format_char) {
+                Err(unfinished) => Some(unfinished),
+                Ok(specification) => {
+                    let argument = match specification.specifier {
+                        ConversionSpecifier::SignedInteger | ConversionSpecifier::SignedDecimalInteger | ConversionSpecifier::Character
+                        | ConversionSpecifier::UnsignedDecimalInteger | ConversionSpecifier::UnsignedOctalInteger
+                        | ConversionSpecifier::UnsignedHexIntegerLowerCase | ConversionSpecifier::UnsignedHexIntegerUpperCase => PositionalArgument::Int(0),
+                        ConversionSpecifier::String | ConversionSpecifier::Pointer | ConversionSpecifier::Meta => PositionalArgument::Pointer(null_mut()),
+                        ConversionSpecifier::Escape | ConversionSpecifier::Unparsed => PositionalArgument::Unused,
+                        _ => PositionalArgument::Double(0.0),
+                    };
+                    if let Some(position) = specification.position.filter(|position| (1..=NL_ARGMAX).contains(position)) {
+                        positional_arguments[position - 1] = argument;
+                        number_of_arguments = core::cmp::max(number_of_arguments, position);
+                    }
+                    None
+                }
+            },
+        };
+    }
+
+    if number_of_arguments == 0 {
+        return None;
+    }
+    for argument in positional_arguments[..number_of_arguments].iter_mut() {
+        *argument = match argument {
+            PositionalArgument::Int(_) => PositionalArgument::Int(args.arg::<core::ffi::c_int>()),
+            PositionalArgument::Double(_) => PositionalArgument::Double(args.arg::<core::ffi::c_double>()),
+            PositionalArgument::Pointer(_) | PositionalArgument::Unused => PositionalArgument::Pointer(args.arg::<*mut core::ffi::c_void>()),
+        };
+    }
+    Some(positional_arguments)
+}
+
+// Same as read_printf_positional_arguments, but for the scanf family, where every argument is a pointer
+unsafe fn read_scanf_positional_arguments(format_str: *const core::ffi::c_char, args: &mut VaList) -> Option<[*mut core::ffi::c_void; NL_ARGMAX]> {
+    use specifier_parsing::*;
+    let mut number_of_arguments = 0;
+    let mut specification_under_construction: Option<UnfinishedScanfConversionSpecification> = None;
+    for i in 0..strlen(format_str) {
+        let format_char = *format_str.add(i as usize) as u8;
+        specification_under_construction = match specification_under_construction {
+            None if format_char == b'%' => Some(UnfinishedScanfConversionSpecification::default()),
+            None => None,
+            Some(unfinished) => match add_char_to_scanf_specification(unfinished, format_char) {
+                Err(unfinished) => Some(unfinished),
+                Ok(specification) => {
+                    if let Some(position) = specification.position.filter(|position| (1..=NL_ARGMAX).contains(position)) {
+                        number_of_arguments = core::cmp::max(number_of_arguments, position);
+                    }
+                    None
+                }
+            },
+        };
+    }
+
+    if number_of_arguments == 0 {
+        return None;
+    }
+    let mut positional_arguments = [null_mut(); NL_ARGMAX];
+    for argument in positional_arguments[..number_of_arguments].iter_mut() {
+        *argument = args.arg::<*mut core::ffi::c_void>();
+    }
+    Some(positional_arguments)
+}
+
+// Does the actual formatting for the printf family, output is called with each piece of the formatted string,
+// and has to return how many bytes of it were written ( or a negative value on error ), just like write
+unsafe fn format_to(output: &mut dyn FnMut(*const core::ffi::c_char, core::ffi::c_size_t) -> core::ffi::c_ssize_t, format_str: *const core::ffi::c_char, mut args: VaList) -> core::ffi::c_int {
     // The format string consists of ordinary multibyte characters (except %), which are copied unchanged into the output stream, and conversion specifications
     // Returns:
     // number of characters transmitted to the output stream or negative value if an output error or an encoding error (for string and character conversion specifiers) occurred
@@ -63,6 +158,7 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
     let mut parsing_conversion_specification = false;
     let mut parsed_specification: Option<PrintfConversionSpecification> = None;
     let mut specification_under_construction  = UnfinishedPrintfConversionSpecification::default();
+    let positional_arguments = read_printf_positional_arguments(format_str, &mut args);
 
     for i in 0..format_str_len {
         let format_char = *format_str.add(i as usize) as u8;
@@ -76,7 +172,7 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                     specification_under_construction = UnfinishedPrintfConversionSpecification::default();
                 },
                 _ => {
-                    let bytes_written = write((*f).fileno, format_str.add(i as usize), 1);
+                    let bytes_written = output(format_str.add(i as usize), 1);
                     if bytes_written < 1 {
                         return -1;
                     }else{
@@ -104,6 +200,21 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
             // FIXME: Implement width, flags and precision and finish all specifiers
             enum Casing { Lower, Upper}
 
+            // Gets the argument to format, either the next one, or if the format string uses positions the one at the specification's position
+            // NOTE: Mixing conversions with and without positions is undefined behaviour, so it's treated as an error
+            macro_rules! next_arg {
+                ($variant: ident, $typ: ty) => {
+                    match (&positional_arguments, specification.position) {
+                        (None, None) => args.arg::<$typ>(),
+                        (Some(positional_arguments), Some(position)) => match positional_arguments.get(position.wrapping_sub(1)) {
+                            Some(PositionalArgument::$variant(val)) => *val as $typ,
+                            _ => return -1,
+                        },
+                        _ => return -1,
+                    }
+                };
+            }
+
             // Writes "n" to "output_str", in radix specified by "base"
             // SAFTEY: Assumes that "output_str" is big enough to contain all the digits of "n"
             // Returns: the index of the left-most digit - 1
@@ -112,6 +223,11 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                   u8: TryFrom<<T as Rem>::Output> {
                 // We start with the last digit ( the digit most to the right )
                 let mut ind = output_str.len()-1;
+                // Zero still has one digit
+                if n == T::from(0) {
+                    output_str[ind] = b'0';
+                    return ind - 1;
+                }
                 while n > T::from(0) {
                     // Maps the last digit of the number to a character
                     let last_digit_char = 
@@ -135,7 +251,7 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
     
             match specification.specifier {
                 ConversionSpecifier::SignedDecimalInteger | ConversionSpecifier::SignedInteger => { // 'd' or 'i'
-                    let mut n = args.arg::<core::ffi::c_int>();
+                    let mut n = next_arg!(Int, core::ffi::c_int);
                     let is_negative = n < 0;
                     n = n.abs(); // We will always parse the number as if it is positive and then put the sign afterwards
 
@@ -148,7 +264,7 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                     if is_negative { output_str[ind] = b'-'; ind -= 1; /* make sure we keep ind one to the left of the beginning, as that is how it will be if there is no sign */ }
 
                     let amount_of_str_to_write = output_str.len()-(ind+1);
-                    let bytes_written = write((*f).fileno, (output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
+                    let bytes_written = output((output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
                     if bytes_written < amount_of_str_to_write as isize {
                         return -1;
                     }else{
@@ -157,13 +273,13 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::UnsignedDecimalInteger => { // 'u'
-                    let n = args.arg::<core::ffi::c_uint>();
+                    let n = next_arg!(Int, core::ffi::c_uint);
                     // 3.32192809488736234 = log2(10)
                     let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_uint>()*8) as f64/3.32192809488736234f64) as usize + 1];
                     let ind = number_to_string_in_radix(&mut output_str, n, 10, Casing::Lower/*irrelevant for any base <= 10*/);
                     
                     let amount_of_str_to_write = output_str.len()-(ind+1);
-                    let bytes_written = write((*f).fileno, (output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
+                    let bytes_written = output((output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
                     if bytes_written < amount_of_str_to_write as isize {
                         return -1;
                     }else{
@@ -172,13 +288,18 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::UnsignedOctalInteger => { // 'o'
-                    let n = args.arg::<core::ffi::c_uint>();
-                    // 3 = log2(8)
-                    let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_uint>()*8)/3) as usize + 1];
-                    let ind = number_to_string_in_radix(&mut output_str, n, 8, Casing::Lower /*irrelevant for any base <= 10*/);
+                    let n = next_arg!(Int, core::ffi::c_uint);
+                    // 3 = log2(8), rounded up, plus one because the index ends up one to the left of the first digit
+                    let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_uint>()*8 + 2)/3) as usize + 1 + 1 /* for the 0 */];
+                    let mut ind = number_to_string_in_radix(&mut output_str, n, 8, Casing::Lower /*irrelevant for any base <= 10*/);
+                    // The alternative form makes sure the number starts with a 0, which only zero already does
+                    if specification.flags.contains(conversion_flag::PRECEED_WITH_BASE_MARKING) && n != 0 {
+                        output_str[ind] = b'0';
+                        ind -= 1;
+                    }
 
                     let amount_of_str_to_write = output_str.len()-(ind+1);
-                    let bytes_written = write((*f).fileno, (output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
+                    let bytes_written = output((output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
                     if bytes_written < amount_of_str_to_write as isize {
                         return -1;
                     }else{
@@ -187,13 +308,20 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::UnsignedHexIntegerLowerCase => { // 'x'
-                    let n = args.arg::<core::ffi::c_uint>();
+                    let n = next_arg!(Int, core::ffi::c_uint);
                     // 4 = log2(16)
-                    let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_uint>()*8)/4) as usize + 1];
-                    let ind = number_to_string_in_radix(&mut output_str, n, 16, Casing::Lower);
+                    let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_uint>()*8)/4) as usize + 1 + 2 /* for the 0x */];
+                    let mut ind = number_to_string_in_radix(&mut output_str, n, 16, Casing::Lower);
+                    // The alternative form adds 0x, just like 'p' does, except to zero
+                    if specification.flags.contains(conversion_flag::PRECEED_WITH_BASE_MARKING) && n != 0 {
+                        output_str[ind] = b'x';
+                        ind -= 1;
+                        output_str[ind] = b'0';
+                        ind -= 1;
+                    }
 
                     let amount_of_str_to_write = output_str.len()-(ind+1);
-                    let bytes_written = write((*f).fileno, (output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
+                    let bytes_written = output((output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
                     if bytes_written < amount_of_str_to_write as isize {
                         return -1;
                     }else{
@@ -202,13 +330,20 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::UnsignedHexIntegerUpperCase => { // 'X'
-                    let n = args.arg::<core::ffi::c_uint>();
+                    let n = next_arg!(Int, core::ffi::c_uint);
                     // 4 = log2(16)
-                    let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_uint>()*8)/4) as usize + 1 /* ceil */];
-                    let ind = number_to_string_in_radix(&mut output_str, n, 16, Casing::Upper);
+                    let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_uint>()*8)/4) as usize + 1 /* ceil */ + 2 /* for the 0X */];
+                    let mut ind = number_to_string_in_radix(&mut output_str, n, 16, Casing::Upper);
+                    // Same as for 'x', but with 0X
+                    if specification.flags.contains(conversion_flag::PRECEED_WITH_BASE_MARKING) && n != 0 {
+                        output_str[ind] = b'X';
+                        ind -= 1;
+                        output_str[ind] = b'0';
+                        ind -= 1;
+                    }
 
                     let amount_of_str_to_write = output_str.len()-(ind+1);
-                    let bytes_written = write((*f).fileno, (output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
+                    let bytes_written = output((output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
                     if bytes_written < amount_of_str_to_write as isize {
                         return -1;
                     }else{
@@ -217,8 +352,8 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::Character => { // 'c'
-                    let character_arg = args.arg::<core::ffi::c_char>();
-                    let bytes_written = write((*f).fileno, &character_arg, 1);
+                    let character_arg = next_arg!(Int, core::ffi::c_int) as core::ffi::c_char;
+                    let bytes_written = output(&character_arg, 1);
                     if bytes_written < 1 {
                         return -1;
                     }else{
@@ -227,9 +362,9 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::String => { // 's'
-                    let string_arg = args.arg::<*mut core::ffi::c_char>();
+                    let string_arg = next_arg!(Pointer, *mut core::ffi::c_char);
                     let string_arg_len = strlen(string_arg);
-                    let bytes_written = write((*f).fileno, string_arg, string_arg_len as usize);
+                    let bytes_written = output(string_arg, string_arg_len as usize);
                     if bytes_written < string_arg_len as isize {
                         return -1;
                     }else{
@@ -238,7 +373,7 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::Pointer => { // 'p'
-                    let n = args.arg::<*const core::ffi::c_void>();
+                    let n = next_arg!(Pointer, *const core::ffi::c_void);
                     // 4 = log2(16)
                     let mut output_str = [b'?'; ((core::mem::size_of::<core::ffi::c_size_t>()*8)/4) as usize + 1 + 2 /* for the 0x */];
                     let mut ind = number_to_string_in_radix(&mut output_str, n as usize, 16, Casing::Lower);
@@ -248,7 +383,7 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                     ind -= 1;
 
                     let amount_of_str_to_write = output_str.len()-(ind+1);
-                    let bytes_written = write((*f).fileno, (output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
+                    let bytes_written = output((output_str.as_ptr() as *const core::ffi::c_char).add(ind+1), amount_of_str_to_write);
                     if bytes_written < amount_of_str_to_write as isize {
                         return -1;
                     }else{
@@ -257,7 +392,7 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::Escape => { // '%'
-                    let bytes_written = write((*f).fileno, "%".as_ptr() as *const core::ffi::c_char, 1);
+                    let bytes_written = output("%".as_ptr() as *const core::ffi::c_char, 1);
                     if bytes_written < 1 {
                         return -1;
                     }else{
@@ -266,15 +401,39 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
                 },
 
                 ConversionSpecifier::Meta => { // 'n'
-                    *args.arg::<*mut core::ffi::c_int>() = characters_transmitted;
+                    *next_arg!(Pointer, *mut core::ffi::c_int) = characters_transmitted;
                 }
 
-                ConversionSpecifier::DecimalFloatLowerCase => unimplemented!("Implement printf specification 'f'!"),
-                ConversionSpecifier::DeicmalFloatUpperCase => unimplemented!("Implement printf specification 'F'!"),
-                ConversionSpecifier::ScientificNotationLowerCase => unimplemented!("Implement printf specification 'e'!"),
-                ConversionSpecifier::ScientificNotationUpperCase => unimplemented!("Implement printf specification 'E'!"),
-                ConversionSpecifier::ShortestFloatLowerCase => unimplemented!("Implement printf specification 'g'!"),
-                ConversionSpecifier::ShortestFloatUpperCase => unimplemented!("Implement printf specification 'G'!"),
+                ConversionSpecifier::DecimalFloatLowerCase | ConversionSpecifier::DeicmalFloatUpperCase
+                | ConversionSpecifier::ScientificNotationLowerCase | ConversionSpecifier::ScientificNotationUpperCase
+                | ConversionSpecifier::ShortestFloatLowerCase | ConversionSpecifier::ShortestFloatUpperCase => { // 'f', 'F', 'e', 'E', 'g' or 'G'
+                    let precision = match specification.precision {
+                        ConversionPrecision::Number(precision) => precision,
+                        // FIXME: Support taking the precision from a position too ( like in %1$.*2$f ), for now it's ignored
+                        ConversionPrecision::Meta if positional_arguments.is_some() => 6,
+                        // A negative precision is taken as if the precision were omitted
+                        ConversionPrecision::Meta => usize::try_from(args.arg::<core::ffi::c_int>()).unwrap_or(6),
+                        ConversionPrecision::None => 6,
+                    };
+                    let n = next_arg!(Double, core::ffi::c_double);
+                    let uppercase = matches!(specification.specifier, ConversionSpecifier::DeicmalFloatUpperCase | ConversionSpecifier::ScientificNotationUpperCase | ConversionSpecifier::ShortestFloatUpperCase);
+                    let alternative_form = specification.flags.contains(conversion_flag::PRECEED_WITH_BASE_MARKING);
+
+                    let mut write_bytes = |bytes: &[u8]| output(bytes.as_ptr() as *const core::ffi::c_char, bytes.len()) == bytes.len() as core::ffi::c_ssize_t;
+                    let bytes_written = match specification.specifier {
+                        ConversionSpecifier::DecimalFloatLowerCase | ConversionSpecifier::DeicmalFloatUpperCase =>
+                            float_formatting::write_fixed(n, precision, uppercase, alternative_form, &mut write_bytes),
+                        ConversionSpecifier::ScientificNotationLowerCase | ConversionSpecifier::ScientificNotationUpperCase =>
+                            float_formatting::write_scientific(n, precision, uppercase, alternative_form, &mut write_bytes),
+                        _ => float_formatting::write_shortest(n, precision, uppercase, alternative_form, &mut write_bytes),
+                    };
+                    if let Some(bytes_written) = bytes_written {
+                        characters_transmitted += bytes_written as core::ffi::c_int;
+                    }else{
+                        return -1;
+                    }
+                },
+
                 ConversionSpecifier::HexFloatLowerCase => unimplemented!("Implement printf specification 'a'!"),
                 ConversionSpecifier::HexFloatUpperCase => unimplemented!("Implement printf specification 'A'!"),
                 ConversionSpecifier::Unparsed => panic!("Impossible printf state, conversion specifer is still unparsed even though the parsing finished!"),
@@ -287,6 +446,41 @@ pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_
     return characters_transmitted;
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn vfprintf(f: *mut FILE, format_str: *const core::ffi::c_char, args: VaList) -> core::ffi::c_int {
+    format_to(&mut |buf, count| write((*f).fileno, buf, count), format_str, args)
+}
+
+// NOTE: The buffer is assumed to be big enough, which is why snprintf should be used instead if possible
+// Source: https://en.cppreference.com/w/c/io/vfprintf
+#[no_mangle]
+pub unsafe extern "C" fn vsprintf(buffer: *mut core::ffi::c_char, format_str: *const core::ffi::c_char, args: VaList) -> core::ffi::c_int {
+    let mut bytes_written = 0;
+    let res = format_to(&mut |buf, count| {
+        core::ptr::copy_nonoverlapping(buf, buffer.add(bytes_written), count);
+        bytes_written += count;
+        count as core::ffi::c_ssize_t
+    }, format_str, args);
+    // The output is always null terminated, even if there was an error
+    *buffer.add(bytes_written) = 0;
+    res
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn printf(format_str: *const core::ffi::c_char, mut args: ...) -> core::ffi::c_int {
+    vfprintf(stdout, format_str, args.as_va_list())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fprintf(f: *mut FILE, format_str: *const core::ffi::c_char, mut args: ...) -> core::ffi::c_int {
+    vfprintf(f, format_str, args.as_va_list())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sprintf(buffer: *mut core::ffi::c_char, format_str: *const core::ffi::c_char, mut args: ...) -> core::ffi::c_int {
+    vsprintf(buffer, format_str, args.as_va_list())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_char, mut args: VaList) -> core::ffi::c_int {
     // Returns: Number of receiving arguments successfully assigned, or EOF if read failure occurs before the first receiving argument was assigned.
@@ -297,6 +491,7 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
     let mut parsing_conversion_specification = false;
     let mut parsed_specification: Option<ScanfConversionSpecification> = None;
     let mut specification_under_construction  = UnfinishedScanfConversionSpecification::default();
+    let positional_arguments = read_scanf_positional_arguments(format_str, &mut args);
 
     // The format string consists of:
     // - non-whitespace multibyte characters except %: each such character in the format string consumes exactly one identical character from the input stream, or causes the function to fail if the next character on the stream does not compare equal.
@@ -349,6 +544,20 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
             // Do the actual formatting
             // FIXME: Implement precision and finish all specifiers
 
+            // Gets the argument to assign to, same as in format_to
+            macro_rules! next_arg {
+                ($typ: ty) => {
+                    match (&positional_arguments, specification.position) {
+                        (None, None) => args.arg::<$typ>(),
+                        (Some(positional_arguments), Some(position)) => match positional_arguments.get(position.wrapping_sub(1)) {
+                            Some(val) => *val as $typ,
+                            None => return arguments_assigned.unwrap_or(0),
+                        },
+                        _ => return arguments_assigned.unwrap_or(0),
+                    }
+                };
+            }
+
             #[derive(PartialEq)]
             enum ParsedSign {
                 POSITIVE,
@@ -384,6 +593,19 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
                 })
             }
 
+            // Reads decimal digits until stream_char is no longer one, calling on_digit with every digit
+            // NOTE: This always over-reads, the char that isn't a digit is left in stream_char for whoever parses next
+            // Returns: The number of digits read, or None if a read failed
+            unsafe fn read_decimal_digits(f: *mut FILE, stream_char: &mut u8, characters_read: &mut usize, on_digit: &mut dyn FnMut(u8)) -> Option<usize> {
+                let mut digits_read = 0;
+                while stream_char.is_ascii_digit() {
+                    on_digit(char_to_digit(*stream_char).unwrap());
+                    digits_read += 1;
+                    if read((*f).fileno, stream_char as *mut u8 as *mut core::ffi::c_char, 1) < 0 { return None; } else { *characters_read += 1;}
+                }
+                Some(digits_read)
+            }
+
             match specification.specifier {
                 ConversionSpecifier::Escape => { // '%'
                     if stream_char != b'%' {
@@ -393,7 +615,7 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
 
                 ConversionSpecifier::Character => { // 'c'
                     // FIXME: Maybe don't allow the opportunity to write to null, but to be fair right now the only alternative that i can think of is duplicating the entire logic which also seems iffy
-                    let c = if !specification.assignment_suppression { args.arg::<*mut core::ffi::c_char>() } else { core::ptr::null_mut() };
+                    let c = if !specification.assignment_suppression { next_arg!(*mut core::ffi::c_char) } else { core::ptr::null_mut() };
                     if let ScanfConversionWidth::Number(len) = specification.width {
                         if !specification.assignment_suppression { *c = stream_char as i8; }
                         for i in 1..len {
@@ -414,7 +636,7 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
                     }
                 
                     // FIXME: Maybe don't allow the opportunity to write to null, but to be fair right now the only alternative that i can think of is duplicating the entire logic which also seems iffy
-                    let s = if !specification.assignment_suppression{ args.arg::<*mut core::ffi::c_char>() } else { core::ptr::null_mut() };
+                    let s = if !specification.assignment_suppression{ next_arg!(*mut core::ffi::c_char) } else { core::ptr::null_mut() };
                     let mut s_pos = 0;
                     if let ScanfConversionWidth::Number(len) = specification.width {
                         if !specification.assignment_suppression { *(s.add(s_pos)) = stream_char as i8; }
@@ -464,19 +686,11 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
                     }
 
                     let mut parsed_n = None;
-
-                    while stream_char == b'0' || stream_char == b'1' || stream_char == b'2' || stream_char == b'3' || stream_char == b'4' || stream_char == b'5' || stream_char == b'6' || stream_char == b'7' || stream_char == b'8' || stream_char == b'9' {
-                        if let Some(val) = parsed_n {
-                            parsed_n = Some(val*10 + char_to_digit(stream_char).unwrap() as i32);
-                        } else {
-                            parsed_n = Some(char_to_digit(stream_char).unwrap() as i32);
-                        }
-                        if read((*f).fileno, (&mut stream_char) as *mut u8 as *mut core::ffi::c_char, 1) < 0 { return arguments_assigned.unwrap_or(EOF); } else { characters_read += 1;}
-                    }
+                    if read_decimal_digits(f, &mut stream_char, &mut characters_read, &mut |digit| parsed_n = Some(parsed_n.unwrap_or(0)*10 + digit as i32)).is_none() { return arguments_assigned.unwrap_or(EOF); }
 
                     if number_sign == ParsedSign::NEGATIVE { parsed_n = parsed_n.map(|val| -val);}
                     should_advance_stream = false; // We read until stream_char is no loner a digit, but we still need to parse the non-digit we over-read
-                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *args.arg::<*mut core::ffi::c_int>() = val; } } else { return arguments_assigned.unwrap_or(0); }
+                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *next_arg!(*mut core::ffi::c_int) = val; } } else { return arguments_assigned.unwrap_or(0); }
                     arguments_assigned = if let Some(val) = arguments_assigned { Some(val+1) } else { Some(1) };
                 }
 
@@ -542,7 +756,7 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
                         
                     if number_sign == ParsedSign::NEGATIVE { parsed_n = parsed_n.map(|val| -val); }
                     should_advance_stream = false; // We read until stream_char is no loner a digit, but we still need to parse the non-digit we over-read
-                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *args.arg::<*mut core::ffi::c_int>() = val; } } else { return arguments_assigned.unwrap_or(0); }
+                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *next_arg!(*mut core::ffi::c_int) = val; } } else { return arguments_assigned.unwrap_or(0); }
                     arguments_assigned = if let Some(val) = arguments_assigned { Some(val+1) } else { Some(1) };
                 }
 
@@ -578,7 +792,7 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
 
                     if number_sign == ParsedSign::NEGATIVE { parsed_n = parsed_n.map(|val| -val); }
                     should_advance_stream = false; // We read until stream_char is no loner a digit, but we still need to parse the non-digit we over-read
-                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *args.arg::<*mut core::ffi::c_int>() = val; } } else { return arguments_assigned.unwrap_or(0); }
+                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *next_arg!(*mut core::ffi::c_int) = val; } } else { return arguments_assigned.unwrap_or(0); }
                     arguments_assigned = if let Some(val) = arguments_assigned { Some(val+1) } else { Some(1) };
                 }
 
@@ -615,7 +829,7 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
                     }
 
                     should_advance_stream = false; // We read until stream_char is no loner a digit, but we still need to parse the non-digit we over-read
-                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *args.arg::<*mut *mut core::ffi::c_void>() = val as *mut core::ffi::c_void; } } else { return arguments_assigned.unwrap_or(0); }
+                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *next_arg!(*mut *mut core::ffi::c_void) = val as *mut core::ffi::c_void; } } else { return arguments_assigned.unwrap_or(0); }
                     arguments_assigned = if let Some(val) = arguments_assigned { Some(val+1) } else { Some(1) };
                 }
 
@@ -657,23 +871,94 @@ pub unsafe extern "C" fn vfscanf(f: *mut FILE, format_str: *const core::ffi::c_c
 
                     if number_sign == ParsedSign::NEGATIVE { parsed_n = parsed_n.map(|val| -val); }
                     should_advance_stream = false; // We read until stream_char is no loner a digit, but we still need to parse the non-digit we over-read
-                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *args.arg::<*mut core::ffi::c_int>() = val; } } else { return arguments_assigned.unwrap_or(0); }
+                    if let Some(val) = parsed_n { if !specification.assignment_suppression { *next_arg!(*mut core::ffi::c_int) = val; } } else { return arguments_assigned.unwrap_or(0); }
                     arguments_assigned = if let Some(val) = arguments_assigned { Some(val+1) } else { Some(1) };
                 }
 
                 ConversionSpecifier::Meta => { // 'n'
                     if !specification.assignment_suppression {
-                        *args.arg::<*mut core::ffi::c_uint>() = characters_read as u32; 
+                        *next_arg!(*mut core::ffi::c_uint) = characters_read as u32; 
                     }
                     should_advance_stream = false; // Meta doesn't consume anything
                 }
 
-                ConversionSpecifier::DecimalFloatLowerCase => unimplemented!("Implement scanf specification 'f'!"),
-                ConversionSpecifier::DeicmalFloatUpperCase => unimplemented!("Implement scanf specification 'F'!"),
-                ConversionSpecifier::ScientificNotationLowerCase => unimplemented!("Implement scanf specification 'e'!"),
-                ConversionSpecifier::ScientificNotationUpperCase => unimplemented!("Implement scanf specification 'E'!"),
-                ConversionSpecifier::ShortestFloatLowerCase => unimplemented!("Implement scanf specification 'g'!"),
-                ConversionSpecifier::ShortestFloatUpperCase => unimplemented!("Implement scanf specification 'G'!"),
+                ConversionSpecifier::DecimalFloatLowerCase | ConversionSpecifier::DeicmalFloatUpperCase 
+                | ConversionSpecifier::ScientificNotationLowerCase | ConversionSpecifier::ScientificNotationUpperCase 
+                | ConversionSpecifier::ShortestFloatLowerCase | ConversionSpecifier::ShortestFloatUpperCase => { // 'f' / 'F' / 'e' / 'E' / 'g' / 'G'
+                    // Read a number with optional + or -, an optional fractional part and an optional exponent, all of the specifiers accept the same input
+                    // FIXME: Parse "inf", "nan" and hex floats
+                    
+                    // Read until stream_char is no longer whitespace
+                    while isspace(stream_char as core::ffi::c_int) != 0 {
+                        if read((*f).fileno, (&mut stream_char) as *mut u8 as *mut core::ffi::c_char, 1) < 0 { return arguments_assigned.unwrap_or(EOF); } else { characters_read += 1;}
+                    }
+
+                    let mut number_sign = ParsedSign::POSITIVE;
+                    if stream_char == b'+' || stream_char == b'-' {
+                        if stream_char == b'+' { number_sign = ParsedSign::POSITIVE; } else if stream_char == b'-' { number_sign = ParsedSign::NEGATIVE; }
+                        if read((*f).fileno, (&mut stream_char) as *mut u8 as *mut core::ffi::c_char, 1) < 0 { return arguments_assigned.unwrap_or(EOF); } else { characters_read += 1;}
+                    }
+
+                    // The digits are gathered into an integer and the point is accounted for in the exponent, so 3.14 is 314e-2
+                    // NOTE: Digits past the ones a double can hold don't change the result, but they still move the point
+                    let mut significant_digits: u64 = 0;
+                    let mut significant_digits_read = 0;
+                    let mut exponent: i32 = 0;
+
+                    let mut on_integer_digit = |digit: u8| {
+                        if significant_digits_read < float_formatting::MAX_SIGNIFICANT_DIGITS {
+                            if significant_digits != 0 || digit != 0 { significant_digits_read += 1; }
+                            significant_digits = significant_digits*10 + digit as u64;
+                        } else {
+                            exponent = exponent.saturating_add(1);
+                        }
+                    };
+                    let integer_digits_read = if let Some(val) = read_decimal_digits(f, &mut stream_char, &mut characters_read, &mut on_integer_digit) { val } else { return arguments_assigned.unwrap_or(EOF); };
+
+                    let mut fraction_digits_read = 0;
+                    if stream_char == b'.' {
+                        if read((*f).fileno, (&mut stream_char) as *mut u8 as *mut core::ffi::c_char, 1) < 0 { return arguments_assigned.unwrap_or(EOF); } else { characters_read += 1;}
+                        let mut on_fraction_digit = |digit: u8| {
+                            if significant_digits_read < float_formatting::MAX_SIGNIFICANT_DIGITS {
+                                if significant_digits != 0 || digit != 0 { significant_digits_read += 1; }
+                                significant_digits = significant_digits*10 + digit as u64;
+                                exponent = exponent.saturating_sub(1);
+                            }
+                        };
+                        fraction_digits_read = if let Some(val) = read_decimal_digits(f, &mut stream_char, &mut characters_read, &mut on_fraction_digit) { val } else { return arguments_assigned.unwrap_or(EOF); };
+                    }
+
+                    // A lone "." or sign is not a number
+                    if integer_digits_read == 0 && fraction_digits_read == 0 { return arguments_assigned.unwrap_or(0); }
+
+                    if stream_char == b'e' || stream_char == b'E' {
+                        // NOTE: We can only push back one char, so if there are no digits after the 'e' it's consumed anyways and the exponent is just 0, instead of failing to match like it should
+                        if read((*f).fileno, (&mut stream_char) as *mut u8 as *mut core::ffi::c_char, 1) < 0 { return arguments_assigned.unwrap_or(EOF); } else { characters_read += 1;}
+                        let mut exponent_sign = ParsedSign::POSITIVE;
+                        if stream_char == b'+' || stream_char == b'-' {
+                            if stream_char == b'+' { exponent_sign = ParsedSign::POSITIVE; } else if stream_char == b'-' { exponent_sign = ParsedSign::NEGATIVE; }
+                            if read((*f).fileno, (&mut stream_char) as *mut u8 as *mut core::ffi::c_char, 1) < 0 { return arguments_assigned.unwrap_or(EOF); } else { characters_read += 1;}
+                        }
+                        let mut parsed_exponent: i32 = 0;
+                        if read_decimal_digits(f, &mut stream_char, &mut characters_read, &mut |digit| parsed_exponent = parsed_exponent.saturating_mul(10).saturating_add(digit as i32)).is_none() { return arguments_assigned.unwrap_or(EOF); }
+                        if exponent_sign == ParsedSign::NEGATIVE { parsed_exponent = -parsed_exponent; }
+                        exponent = exponent.saturating_add(parsed_exponent);
+                    }
+
+                    let mut parsed_n = float_formatting::scale_by_power_of_ten(significant_digits as f64, exponent);
+                    if number_sign == ParsedSign::NEGATIVE { parsed_n = -parsed_n; }
+                    should_advance_stream = false; // We read until stream_char is no loner a part of the number, but we still need to parse the char we over-read
+                    if !specification.assignment_suppression {
+                        // NOTE: Rust has no long double, so 'L' is treated like 'l'
+                        if specification.length == ConversionLength::Long || specification.length == ConversionLength::Double {
+                            *next_arg!(*mut core::ffi::c_double) = parsed_n;
+                        } else {
+                            *next_arg!(*mut core::ffi::c_float) = parsed_n as core::ffi::c_float;
+                        }
+                    }
+                    arguments_assigned = if let Some(val) = arguments_assigned { Some(val+1) } else { Some(1) };
+                }
+
                 ConversionSpecifier::HexFloatLowerCase => unimplemented!("Implement scanf specification 'a'!"),
                 ConversionSpecifier::HexFloatUpperCase => unimplemented!("Implement scanf specification 'A'!"),
                 ConversionSpecifier::Unparsed => panic!("Impossible scanf state, conversion specifer is still unparsed even though the parsing finished!"),
@@ -741,36 +1026,20 @@ pub unsafe extern "C" fn puts(str: *const core::ffi::c_char) -> core::ffi::c_int
     // On failure, returns EOF and sets the error indicator (see ferror()) on stream
     // Source: https://en.cppreference.com/w/c/io/puts
 
-    let res = write(sys::STDOUT_FILENO as core::ffi::c_int, str, strlen(str) as core::ffi::c_size_t);
-    if res < 0 {
-        return EOF;
-    }
-    
-    let res = write(sys::STDOUT_FILENO as core::ffi::c_int, (&"\n").as_ptr() as *const core::ffi::c_char, 1);
-    if res < 0 {
+    if fputs(str, stdout) == EOF || fputc(b'\n' as core::ffi::c_int, stdout) == EOF {
         return EOF;
     }
 
-    return 1;
+    1
 }
 
 // FIXME: Doesn't print errno
 #[no_mangle]
 pub unsafe extern "C" fn perror(str: *const core::ffi::c_char) -> core::ffi::c_int {
-    let mut t = 0;
-    let res = write(sys::STDERR_FILENO as core::ffi::c_int, str, strlen(str) as core::ffi::c_size_t);
-    if res < 0 {
-        return res as core::ffi::c_int;
-    } else {
-        t += res;
-    }
-    let res = write(sys::STDERR_FILENO as core::ffi::c_int, (&"\n").as_ptr() as *const core::ffi::c_char, 1);
-    if res < 0 {
-        return res as core::ffi::c_int;
-    } else {
-        t += res;
+    if fputs(str, stderr) == EOF || fputc(b'\n' as core::ffi::c_int, stderr) == EOF {
+        return EOF;
     }
-    t as core::ffi::c_int
+    1
 }
 
 #[repr(C)]
@@ -778,6 +1047,21 @@ pub struct FILE {
     fileno: core::ffi::c_int,
 }
 
+static mut STDIN_FILE: FILE = FILE { fileno: sys::STDIN_FILENO as core::ffi::c_int };
+static mut STDOUT_FILE: FILE = FILE { fileno: sys::STDOUT_FILENO as core::ffi::c_int };
+static mut STDERR_FILE: FILE = FILE { fileno: sys::STDERR_FILENO as core::ffi::c_int };
+
+// NOTE: These are pointers and not the FILEs themselves because that's how C programs expect them to be
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut stdin: *mut FILE = core::ptr::addr_of_mut!(STDIN_FILE);
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut stdout: *mut FILE = core::ptr::addr_of_mut!(STDOUT_FILE);
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut stderr: *mut FILE = core::ptr::addr_of_mut!(STDERR_FILE);
+
 #[no_mangle]
 pub unsafe extern "C" fn fopen(filename: *const core::ffi::c_char, mode: *const core::ffi::c_char) -> *mut FILE {
     let mode = core::ffi::CStr::from_ptr(mode as *const i8);
@@ -861,7 +1145,7 @@ pub unsafe extern "C" fn fread(
 
 #[no_mangle]
 pub unsafe extern "C" fn fseek(f: *mut FILE, offset: core::ffi::c_long, origin: core::ffi::c_int) -> core::ffi::c_int {
-    if lseek(unsafe { &*f }.fileno, offset, origin) > 0 {
+    if lseek(unsafe { &*f }.fileno, offset, origin) >= 0 {
         return 0;
     } else {
         return -1;
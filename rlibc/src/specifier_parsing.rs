@@ -11,6 +11,12 @@ impl BitOr for ConversionFlags {
     }
 }
 
+impl ConversionFlags {
+    pub fn contains(&self, flags: ConversionFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
 impl BitOrAssign for ConversionFlags {
     fn bitor_assign(&mut self, rhs: Self) {
         *self = *self | rhs;
@@ -133,6 +139,7 @@ pub enum ConversionLength {
 }
 
 pub struct PrintfConversionSpecification {
+    pub position: Option<usize>, // 'N$', which argument to format, starting from 1, instead of the next one
     pub flags: ConversionFlags,
     pub width: PrintfConversionWidth,
     pub precision: ConversionPrecision,
@@ -141,6 +148,7 @@ pub struct PrintfConversionSpecification {
 }
 
 pub struct ScanfConversionSpecification {
+    pub position: Option<usize>, // 'N$', which argument to assign to, starting from 1, instead of the next one
     pub assignment_suppression: bool,
     pub width: ScanfConversionWidth,
     pub length: ConversionLength,
@@ -158,6 +166,7 @@ impl Default for UnfinishedPrintfConversionSpecification {
     fn default() -> Self {
         Self { 
             conversion_under_construction: PrintfConversionSpecification{
+                position: None,
                 flags: conversion_flag::NONE,
                 width: PrintfConversionWidth::None,
                 precision: ConversionPrecision::None,
@@ -181,6 +190,7 @@ impl Default for UnfinishedScanfConversionSpecification {
     fn default() -> Self {
         Self { 
             conversion_under_construction: ScanfConversionSpecification {
+                position: None,
                 assignment_suppression: false,
                 width: ScanfConversionWidth::None,
                 length: ConversionLength::None,
@@ -215,6 +225,15 @@ pub fn add_char_to_printf_specification(mut initial: UnfinishedPrintfConversionS
                 }
                 return Err(initial); // We parsed this character, move on
             }
+            b'$' => {
+                // The number wasn't the width, it was the position of the argument ( like in %2$d ), the width can still come after it
+                if let PrintfConversionWidth::Number(position) = initial.conversion_under_construction.width {
+                    initial.conversion_under_construction.position = Some(position);
+                }
+                initial.conversion_under_construction.width = PrintfConversionWidth::None;
+                initial.parsing_width = false;
+                return Err(initial); // We parsed this character, move on
+            }
             _ => {
                 // Encountered non digit character, finish parsing conversion width number
                 initial.parsing_width = false;
@@ -369,6 +388,16 @@ pub fn add_char_to_scanf_specification(mut initial: UnfinishedScanfConversionSpe
                 return Err(initial);
             }
 
+            b'$' => {
+                // The number wasn't the width, it was the position of the argument ( like in %2$d ), the width can still come after it
+                if let ScanfConversionWidth::Number(position) = initial.conversion_under_construction.width {
+                    initial.conversion_under_construction.position = Some(position);
+                }
+                initial.conversion_under_construction.width = ScanfConversionWidth::None;
+                initial.parsing_width = false;
+                return Err(initial);
+            }
+
             _ => {
                 // Encountered non digit character, finish parsing conversion width number
                 initial.parsing_width = false;
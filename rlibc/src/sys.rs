@@ -12,11 +12,42 @@ pub const O_RDWR: usize = O_RDONLY | O_WRONLY;
 pub const O_APPEND: usize = 0b00100;
 pub const O_CREAT: usize = 0b01000;
 pub const O_TRUNC: usize = 0b10000;
+// Reads from character devices return -1 instead of waiting if there is nothing to read yet
+pub const O_NONBLOCK: usize = 0b100000;
 
 pub const SEEK_CUR: usize = 0;
 pub const SEEK_SET: usize = 1;
 pub const SEEK_END: usize = 2;
 
+pub const PROT_NONE: usize = 0b000;
+pub const PROT_READ: usize = 0b001;
+pub const PROT_WRITE: usize = 0b010;
+pub const PROT_EXEC: usize = 0b100;
+pub const MAP_PRIVATE: usize = 0b01;
+pub const MAP_ANONYMOUS: usize = 0b10;
+pub const MAP_FAILED: usize = usize::MAX; // (void*)-1
+
+// NOTE: These are used in place of a handler, so they can't be addresses a handler could be at
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+pub const SIG_ERR: usize = usize::MAX; // (sighandler_t)-1
+
+pub const S_IFMT: core::ffi::c_uint = 0o170000;
+pub const S_IFIFO: core::ffi::c_uint = 0o010000;
+pub const S_IFCHR: core::ffi::c_uint = 0o020000;
+pub const S_IFDIR: core::ffi::c_uint = 0o040000;
+pub const S_IFREG: core::ffi::c_uint = 0o100000;
+
+// NOTE: Has to match sys/stat.h, the reserved field is there so that the struct has no padding
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub struct stat {
+    pub st_mode: core::ffi::c_uint,
+    pub st_reserved: core::ffi::c_uint,
+    pub st_size: core::ffi::c_long,
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn exit(code: core::ffi::c_int) -> ! {
     load_syscall_argument_1(code as usize);
@@ -66,6 +97,14 @@ pub unsafe extern "C" fn lseek(fd: core::ffi::c_int, offset: core::ffi::c_long,
     read_syscall_return() as core::ffi::c_long
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn fstat(fd: core::ffi::c_int, statbuf: *mut stat) -> core::ffi::c_int {
+    load_syscall_argument_1(fd as usize);
+    load_syscall_argument_2(statbuf as usize);
+    syscall(SyscallNumber::Fstat);
+    read_syscall_return() as core::ffi::c_int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn malloc(size: core::ffi::c_size_t) -> *mut core::ffi::c_char {
     load_syscall_argument_1(size as usize);
@@ -82,11 +121,53 @@ pub unsafe extern "C" fn free(ptr: *mut core::ffi::c_char) {
 #[no_mangle]
 pub unsafe extern "C" fn realloc(ptr: *mut core::ffi::c_char, new_size: core::ffi::c_size_t) -> *mut core::ffi::c_char {
     load_syscall_argument_1(ptr as usize);
-    load_syscall_argument_3(new_size as usize);
+    load_syscall_argument_2(new_size as usize);
     syscall(SyscallNumber::Realloc);
     read_syscall_return() as *mut core::ffi::c_char
 }
 
+// NOTE: Memory from aligned_alloc can be given to free and realloc just like memory from malloc, but realloc doesn't keep the alignment
+// Returns: NULL if alignment isn't a power of two
+// Source: man aligned_alloc
+#[no_mangle]
+pub unsafe extern "C" fn aligned_alloc(alignment: core::ffi::c_size_t, size: core::ffi::c_size_t) -> *mut core::ffi::c_void {
+    load_syscall_argument_1(alignment as usize);
+    load_syscall_argument_2(size as usize);
+    syscall(SyscallNumber::AlignedAlloc);
+    read_syscall_return() as *mut core::ffi::c_void
+}
+
+// Returns: 0 on success, EINVAL if alignment isn't a power of two multiple of sizeof(void *), ENOMEM if there was no memory, *memptr is only changed on success
+// Source: man posix_memalign
+#[no_mangle]
+pub unsafe extern "C" fn posix_memalign(memptr: *mut *mut core::ffi::c_void, alignment: core::ffi::c_size_t, size: core::ffi::c_size_t) -> core::ffi::c_int {
+    const EINVAL: core::ffi::c_int = 22;
+    const ENOMEM: core::ffi::c_int = 12;
+    if !alignment.is_power_of_two() || alignment % core::mem::size_of::<*mut core::ffi::c_void>() != 0 {
+        return EINVAL;
+    }
+    let ptr = aligned_alloc(alignment, size);
+    if ptr.is_null() {
+        return ENOMEM;
+    }
+    *memptr = ptr;
+    0
+}
+
+// Returns: NULL if nmemb * size doesn't fit in a size_t, instead of allocating the wrapped around ( much smaller ) size
+// Source: man calloc
+#[no_mangle]
+pub unsafe extern "C" fn calloc(nmemb: core::ffi::c_size_t, size: core::ffi::c_size_t) -> *mut core::ffi::c_char {
+    let Some(total_size) = nmemb.checked_mul(size) else {
+        return core::ptr::null_mut();
+    };
+    let ptr = malloc(total_size);
+    if !ptr.is_null() {
+        crate::mem::memset(ptr, 0, total_size);
+    }
+    ptr
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn getcwd(buf: *mut core::ffi::c_char, size: core::ffi::c_size_t) -> *mut core::ffi::c_char {
     load_syscall_argument_1(buf as usize);
@@ -191,29 +272,79 @@ pub unsafe extern "C" fn pipe(fds: *const core::ffi::c_int) -> core::ffi::c_int
     read_syscall_return() as core::ffi::c_int
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn mmap(addr: *mut core::ffi::c_void, length: core::ffi::c_size_t, prot: core::ffi::c_int, flags: core::ffi::c_int, fd: core::ffi::c_int, offset: core::ffi::c_long) -> *mut core::ffi::c_void {
+    load_syscall_argument_1(addr as usize);
+    load_syscall_argument_2(length as usize);
+    load_syscall_argument_3(prot as usize);
+    load_syscall_argument_4(flags as usize);
+    load_syscall_argument_5(fd as usize);
+    load_syscall_argument_6(offset as usize);
+    syscall(SyscallNumber::Mmap);
+    read_syscall_return() as *mut core::ffi::c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn munmap(addr: *mut core::ffi::c_void, length: core::ffi::c_size_t) -> core::ffi::c_int {
+    load_syscall_argument_1(addr as usize);
+    load_syscall_argument_2(length as usize);
+    syscall(SyscallNumber::Munmap);
+    read_syscall_return() as core::ffi::c_int
+}
+
+// The handler returns here, which puts the program back to where it was when the signal came
+unsafe extern "C" fn signal_restorer() -> ! {
+    syscall(SyscallNumber::Sigreturn);
+    // Sigreturn doesn't return, unless there was no handler running
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+// NOTE: Only SIGINT and SIGTERM can be handled or ignored, the handler is called with the signal number
+// Returns: The previous handler, or SIG_ERR if the signal can't be handled
+// Source: man signal
+#[no_mangle]
+pub unsafe extern "C" fn signal(signum: core::ffi::c_int, handler: usize) -> usize {
+    load_syscall_argument_1(signum as usize);
+    load_syscall_argument_2(handler);
+    load_syscall_argument_3(signal_restorer as unsafe extern "C" fn() -> ! as usize);
+    syscall(SyscallNumber::Sigaction);
+    read_syscall_return()
+}
+
+// NOTE: The abi is the same as linux's, the syscall number goes in a7, the arguments in a0-a5 and the return value in a0,
+// but the numbers themselves are our own, failures are always reported by returning -1 ( or null for pointers )
+// Source: man syscall
 #[derive(Debug, Clone, Copy)]
 #[repr(usize)]
 pub enum SyscallNumber {
-    Exit = 0,
-    Read = 1,
-    Write = 2,
-    Open = 3,
-    Close = 4,
-    LSeek = 5,
-    Malloc = 6,
-    Free = 7,
-    Realloc = 8,
-    Getcwd = 9,
-    Getenv = 10,
-    Fchdir = 11,
-    Dup = 12,
-    Dup2 = 13,
-    Fork = 14,
-    Waitpid = 15,
-    Fexecve = 16,
-    Execve = 17,
-    Execvpe = 18,
-    Pipe = 19,
+    Exit = 0,     // (exit_code) -> never returns
+    Read = 1,     // (fd, buf, count) -> bytes read, 0 means end of file, blocks until there is something to read
+    Write = 2,    // (fd, buf, count) -> bytes written, fds 1 and 2 go to the terminal
+    Open = 3,     // (pathname, flags) -> fd, relative paths start from the cwd
+    Close = 4,    // (fd) -> 0
+    LSeek = 5,    // (fd, offset, whence) -> the new offset
+    Malloc = 6,   // (size) -> pointer
+    Free = 7,     // (pointer) -> nothing
+    Realloc = 8,  // (pointer, new_size) -> pointer
+    Getcwd = 9,   // (buf, size) -> buf
+    Getenv = 10,  // (name) -> pointer to the value
+    Fchdir = 11,  // (fd) -> 0
+    Dup = 12,     // (oldfd) -> newfd
+    Dup2 = 13,    // (oldfd, newfd) -> newfd
+    Fork = 14,    // () -> the child's pid in the parent and 0 in the child
+    Waitpid = 15, // (pid, wstatus, options) -> pid of the child that changed state
+    Fexecve = 16, // (fd, argv, envp) -> only returns on failure
+    Execve = 17,  // (pathname, argv, envp) -> only returns on failure
+    Execvpe = 18, // (file, argv, envp) -> only returns on failure, file is searched for in PATH
+    Pipe = 19,    // (fds) -> 0, fds[0] is the read end and fds[1] is the write end
+    Fstat = 20,   // (fd, statbuf) -> 0
+    Mmap = 21,    // (addr, length, prot, flags, fd, offset) -> pointer to the mapping, MAP_FAILED on failure, addr is ignored, files can only be mapped with MAP_PRIVATE
+    Munmap = 22,  // (addr, length) -> 0, only whole mappings can be unmapped
+    AlignedAlloc = 23, // (alignment, size) -> pointer, alignment has to be a power of two
+    Sigaction = 24, // (signum, handler, restorer) -> the previous handler, SIG_ERR on failure, the handler returns into the restorer, which has to call sigreturn
+    Sigreturn = 25, // () -> only returns on failure, puts back what the program was doing before the signal handler was called
     MaxValue,
 }
 
@@ -221,8 +352,37 @@ pub enum SyscallNumber {
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum SignalType {
+    SIGINT = 2,
     SIGILL = 4,
     SIGKILL = 9,
+    SIGSEGV = 11,
+    SIGTERM = 15,
+}
+
+impl SignalType {
+    pub const ALL: [SignalType; 5] = [SignalType::SIGINT, SignalType::SIGILL, SignalType::SIGKILL, SignalType::SIGSEGV, SignalType::SIGTERM];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignalType::SIGINT => "SIGINT",
+            SignalType::SIGILL => "SIGILL",
+            SignalType::SIGKILL => "SIGKILL",
+            SignalType::SIGSEGV => "SIGSEGV",
+            SignalType::SIGTERM => "SIGTERM",
+        }
+    }
+
+    // Returns: None if there is no signal with that number
+    pub fn from_number(number: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|signal| u8::from(*signal) == number)
+    }
+
+    // Accepts names both with and without the SIG prefix, for ex. "SIGTERM" and "TERM"
+    // Returns: None if there is no signal with that name
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.strip_prefix("SIG").unwrap_or(name);
+        Self::ALL.iter().copied().find(|signal| signal.name()[3..] == *name)
+    }
 }
 
 impl From<SignalType> for u8  {
@@ -268,6 +428,27 @@ unsafe fn load_syscall_argument_3(value: usize) {
     asm!("", in("a2") value);
 }
 
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+unsafe fn load_syscall_argument_4(value: usize) {
+    // NOTE: Uses linux abi
+    asm!("", in("a3") value);
+}
+
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+unsafe fn load_syscall_argument_5(value: usize) {
+    // NOTE: Uses linux abi
+    asm!("", in("a4") value);
+}
+
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+unsafe fn load_syscall_argument_6(value: usize) {
+    // NOTE: Uses linux abi
+    asm!("", in("a5") value);
+}
+
 #[cfg(target_arch = "riscv64")]
 #[inline(always)]
 unsafe fn read_syscall_return() -> usize {
@@ -327,6 +508,21 @@ unsafe fn load_syscall_argument_3(_value: usize) {
     unimplemented!("No syscall argument 3 loading function defined in c library for your architecture!");
 }
 
+#[cfg(not(target_arch = "riscv64"))]
+unsafe fn load_syscall_argument_4(_value: usize) {
+    unimplemented!("No syscall argument 4 loading function defined in c library for your architecture!");
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+unsafe fn load_syscall_argument_5(_value: usize) {
+    unimplemented!("No syscall argument 5 loading function defined in c library for your architecture!");
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+unsafe fn load_syscall_argument_6(_value: usize) {
+    unimplemented!("No syscall argument 6 loading function defined in c library for your architecture!");
+}
+
 #[cfg(not(target_arch = "riscv64"))]
 unsafe fn read_syscall_return() -> usize {
     unimplemented!("No syscall return reading function defined in c library for your architecture!");